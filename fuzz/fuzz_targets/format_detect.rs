@@ -0,0 +1,12 @@
+// fuzz/fuzz_targets/format_detect.rs
+//
+// Input format detection.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::detect_formats;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = detect_formats(data);
+});
@@ -0,0 +1,21 @@
+// fuzz/fuzz_targets/msx_basic.rs
+//
+// MSX tokenised BASIC decoding and BSAVE headers.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::msx;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = msx::parse_bload(data);
+    if let Ok(listing) = msx::decode_basic(data) {
+        let _ = listing.to_text(0);
+        let _ = listing.to_json();
+        let _ = listing.analyse().to_text();
+        let _ = listing.to_formatted_text(&Default::default());
+        for line in &listing.lines {
+            assert_eq!(line.items.iter().map(|item| item.text.as_str()).collect::<String>(), line.text);
+        }
+    }
+});
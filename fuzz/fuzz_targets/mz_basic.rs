@@ -0,0 +1,36 @@
+// fuzz/fuzz_targets/mz_basic.rs
+//
+// SA-5510, SP-5025 and 1Z-013B BASIC decoding of MZF files, normal and recovering,
+// renumbering, the data after the program, and the 5-byte number format.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::mz::{self, MZBasicVersion};
+
+fuzz_target!(|data: &[u8]| {
+    // Every non-zero 5-byte number converts to an f64 and back unchanged
+    if let Some(&bytes) = data.first_chunk::<5>().filter(|bytes| bytes[0] != 0) {
+        assert_eq!(mz::mz_float_bytes(mz::mz_float(&bytes)), bytes);
+        let _ = mz::mz_number_string(mz::mz_float(&bytes));
+    }
+    for version in [MZBasicVersion::SA5510, MZBasicVersion::SP5025, MZBasicVersion::V1Z013B] {
+        if let Ok(listing) = mz::decode_basic(data, version) {
+            let _ = listing.to_text(0);
+            let _ = listing.to_json();
+            let _ = listing.analyse().to_text();
+            let _ = listing.to_formatted_text(&Default::default());
+            for line in &listing.lines {
+                assert_eq!(line.items.iter().map(|item| item.text.as_str()).collect::<String>(), line.text);
+            }
+        }
+        let _ = mz::recover_basic(data, version);
+        if let Ok(Some(trailing)) = mz::trailing_data(data, version) {
+            let _ = (trailing.summary(), trailing.disassembly(), trailing.hex_dump());
+        }
+        // A renumbered program decodes to the same number of lines
+        if let (Ok(listing), Ok(renumbered)) = (mz::decode_basic(data, version), mz::renumber_basic(data, version, 1, 1)) {
+            assert_eq!(mz::decode_basic(&renumbered.data, version).map(|l| l.lines.len()).ok(), Some(listing.lines.len()));
+        }
+    }
+});
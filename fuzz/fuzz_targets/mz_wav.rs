@@ -0,0 +1,12 @@
+// fuzz/fuzz_targets/mz_wav.rs
+//
+// Sharp MZ cassette recording decoding.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::mz;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = mz::decode_mz_wav(data);
+});
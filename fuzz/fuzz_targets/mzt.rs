@@ -0,0 +1,12 @@
+// fuzz/fuzz_targets/mzt.rs
+//
+// MZT multi-file container parsing.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::mz;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = mz::parse_mzt(data);
+});
@@ -0,0 +1,22 @@
+// fuzz/fuzz_targets/process_binary.rs
+//
+// The viewer entry point; the first byte chooses the mode, machine and character set.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::{process_binary, MZFMachine};
+
+const MODES: [&str; 15] = [
+    "SA", "SP", "1Z", "Z80", "DUMP", "ZX80BASIC", "ZX81BASIC", "ZX81RUN", "MZT", "MZWAV", "ZXWAV", "TZX", "QD",
+    "DISK", "AUTO",
+];
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, data)) = data.split_first() else {
+        return;
+    };
+    let mode = MODES[selector as usize % MODES.len()];
+    let machine = if selector & 0x40 == 0 { MZFMachine::Sharp } else { MZFMachine::Sinclair };
+    let _ = process_binary(data, mode.to_string(), machine, selector & 0x80 != 0);
+});
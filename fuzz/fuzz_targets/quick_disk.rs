@@ -0,0 +1,13 @@
+// fuzz/fuzz_targets/quick_disk.rs
+//
+// Quick Disk image parsing.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::mz;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = mz::read_qd_blocks(data);
+    let _ = mz::parse_qd_image(data);
+});
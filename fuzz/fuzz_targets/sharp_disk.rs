@@ -0,0 +1,12 @@
+// fuzz/fuzz_targets/sharp_disk.rs
+//
+// D88 and raw floppy disk image parsing.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::mz;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = mz::parse_sharp_disk(data);
+});
@@ -0,0 +1,23 @@
+// fuzz/fuzz_targets/spectrum_tape.rs
+//
+// ZX Spectrum .tap/.tzx tape reading and BASIC decoding.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::spectrum;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(files) = spectrum::parse_spectrum_tape(data) {
+        let _ = spectrum::list_spectrum_tape(&files);
+    }
+    for listing in [spectrum::decode_basic(data), spectrum::decode_program(data, 0)].into_iter().flatten() {
+        let _ = listing.to_text(4);
+        let _ = listing.to_json();
+        let _ = listing.analyse().to_text();
+        let _ = listing.to_formatted_text(&Default::default());
+        for line in &listing.lines {
+            assert_eq!(line.items.iter().map(|item| item.text.as_str()).collect::<String>(), line.text);
+        }
+    }
+});
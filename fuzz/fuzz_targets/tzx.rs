@@ -0,0 +1,13 @@
+// fuzz/fuzz_targets/tzx.rs
+//
+// TZX block parsing and ZX81 program extraction.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::zx81;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = zx81::parse_tzx(data);
+    let _ = zx81::extract_zx81_programs(data);
+});
@@ -0,0 +1,17 @@
+// fuzz/fuzz_targets/z80_disassemble.rs
+//
+// Z80 disassembly; the first four bytes choose the load and exec addresses.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::z80;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+    let load = u16::from_le_bytes([data[0], data[1]]);
+    let exec = u16::from_le_bytes([data[2], data[3]]);
+    let _ = z80::disassemble(&data[4..], load, exec);
+});
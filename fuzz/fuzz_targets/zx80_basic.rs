@@ -0,0 +1,25 @@
+// fuzz/fuzz_targets/zx80_basic.rs
+//
+// ZX80 .O file decoding, with and without the ZXpand keywords, and renumbering.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::zx80;
+
+fuzz_target!(|data: &[u8]| {
+    for zxpand in [false, true] {
+        if let Ok(listing) = zx80::decode_basic(data, zxpand) {
+            let _ = listing.to_text(0);
+            let _ = listing.to_json();
+            let _ = listing.analyse().to_text();
+            let _ = listing.to_formatted_text(&Default::default());
+            for line in &listing.lines {
+                assert_eq!(line.items.iter().map(|item| item.text.as_str()).collect::<String>(), line.text);
+            }
+        }
+    }
+    if let (Ok(listing), Ok(renumbered)) = (zx80::decode_basic(data, false), zx80::renumber_basic(data, 1000, 1)) {
+        assert_eq!(zx80::decode_basic(&renumbered.data, false).map(|l| l.lines.len()).ok(), Some(listing.lines.len()));
+    }
+});
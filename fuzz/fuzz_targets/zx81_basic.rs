@@ -0,0 +1,23 @@
+// fuzz/fuzz_targets/zx81_basic.rs
+//
+// ZX81 .P and .P81 file decoding and renumbering.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::zx81;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(listing) = zx81::decode_basic(data) {
+        let _ = listing.to_text(4);
+        let _ = listing.to_json();
+        let _ = listing.analyse().to_text();
+        let _ = listing.to_formatted_text(&Default::default());
+        for line in &listing.lines {
+            assert_eq!(line.items.iter().map(|item| item.text.as_str()).collect::<String>(), line.text);
+        }
+    }
+    if let (Ok(listing), Ok(renumbered)) = (zx81::decode_basic(data), zx81::renumber_basic(data, 1000, 1)) {
+        assert_eq!(zx81::decode_basic(&renumbered.data).map(|l| l.lines.len()).ok(), Some(listing.lines.len()));
+    }
+});
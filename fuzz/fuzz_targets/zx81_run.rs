@@ -0,0 +1,15 @@
+// fuzz/fuzz_targets/zx81_run.rs
+//
+// Running ZX81 programs, with a small step limit so each input finishes quickly.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::zx81::{self, ZX81RunConfig};
+
+fuzz_target!(|data: &[u8]| {
+    let config = ZX81RunConfig { max_steps: 2_000, ..Default::default() };
+    if let Ok(run) = zx81::run_zx81_p_file(data, config) {
+        let _ = run.display_lines();
+    }
+});
@@ -0,0 +1,12 @@
+// fuzz/fuzz_targets/zx_wav.rs
+//
+// ZX80/ZX81 cassette recording decoding.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_wasm_converter::zx81;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = zx81::decode_zx_wav(data);
+});
@@ -1,7 +1,7 @@
 // Import the WASM module generated by wasm-pack.
 // The path './pkg/rust_wasm_converter.js' assumes that wasm-pack
 // builds into a 'pkg' directory relative to this HTML file.
-import init, { MZFMachine, process_binary } from './pkg/rust_wasm_converter.js';
+import init, { MZFMachine, process_binary, recover_mz_basic, mzf_to_wav, diff_programs } from './pkg/rust_wasm_converter.js';
 
 // Utility to get query string parameters
 function getQueryParam(name) {
@@ -19,13 +19,27 @@ const mode1Z = document.getElementById('mode1Z');
 const modeZ80 = document.getElementById('modeZ80');
 const modeZX80Basic = document.getElementById('modeZX80Basic');
 const modeZX81Basic = document.getElementById('modeZX81Basic');
+const modeZX81Run = document.getElementById('modeZX81Run');
+const modeZXWAV = document.getElementById('modeZXWAV');
+const modeTZX = document.getElementById('modeTZX');
+const modeSpectrum = document.getElementById('modeSpectrum');
+const modeMSX = document.getElementById('modeMSX');
 const modeDump = document.getElementById('modeDump');
+const modeMZT = document.getElementById('modeMZT');
+const modeMZWAV = document.getElementById('modeMZWAV');
+const modeQD = document.getElementById('modeQD');
+const modeDISK = document.getElementById('modeDISK');
+const modeAuto = document.getElementById('modeAuto');
 const messageParagraph = document.getElementById('message');
 const useAltParagraph = document.getElementById('useAlt');
 const saveButton = document.getElementById('saveButton');
+const saveWavButton = document.getElementById('saveWavButton');
 const charset = document.getElementById('charset');
 const charsetToggle = document.getElementById('charsetToggle');
 const charsetLabel = document.querySelector('label[for="charsetToggle"]');
+const recover = document.getElementById('recover');
+const recoverToggle = document.getElementById('recoverToggle');
+const compareInput = document.getElementById('compareInput');
 const fileInputSection = fileInput.closest('div'); // The file upload section container
 const outputTypeSpan = document.getElementById('outputType');
 const titleElement = document.getElementById('title');
@@ -41,11 +55,14 @@ const machine = modeSA ? MZFMachine.Sharp : MZFMachine.Sinclair;
 
 // Initialize fileData and fileName
 let fileData = null;
+// A second version of the program to compare the first with
+let compareData = null;
 let fileName = 'MZFBasic';
 
 // Function to enable/disable the save button
 const toggleSaveButton = () => {
     saveButton.disabled = outputPre.textContent.trim() === '' || fileData === null;
+    if (saveWavButton) saveWavButton.disabled = fileData === null;
 };
 
 // Function to process the file data based on the selected mode
@@ -62,8 +79,18 @@ if (modeParam) {
     else if (modeParam === '1Z') mode1Z.checked = true;
     else if (modeParam === 'Z80') modeZ80.checked = true;   
     else if (modeParam === 'DUMP') modeDump.checked = true;
+    else if (modeParam === 'MZT') modeMZT.checked = true;
+    else if (modeParam === 'MZWAV') modeMZWAV.checked = true;
+    else if (modeParam === 'QD') modeQD.checked = true;
+    else if (modeParam === 'DISK') modeDISK.checked = true;
+    else if (modeParam === 'AUTO') modeAuto.checked = true;
     else if (modeParam === 'ZX80BASIC') modeZX80Basic.checked = true;
     else if (modeParam === 'ZX81BASIC') modeZX81Basic.checked = true;
+    else if (modeParam === 'ZX81RUN') modeZX81Run.checked = true;
+    else if (modeParam === 'ZXWAV') modeZXWAV.checked = true;
+    else if (modeParam === 'TZX') modeTZX.checked = true;
+    else if (modeParam === 'SPECTRUM') modeSpectrum.checked = true;
+    else if (modeParam === 'MSX') modeMSX.checked = true;
     else modeDump.checked = true; // Default to DUMP if invalid mode
 } else {
     // This is a placeholder for `script.js`. The actual default will be set in the specific HTML files.
@@ -77,11 +104,17 @@ init().then(() => {
 
     processFile = () => {
         if(machine == MZFMachine.Sharp) {
-            if (modeDump.checked || modeZ80.checked) {
+            if (modeDump.checked || modeZ80.checked || modeMZT.checked || modeMZWAV.checked || modeQD.checked || modeDISK.checked) {
                 charset.classList.remove('hidden');
             } else {
                 charset.classList.add('hidden');
             }
+            // Recovery only applies to the BASIC listings
+            if (modeSA.checked || modeSP.checked || mode1Z.checked) {
+                recover.classList.remove('hidden');
+            } else {
+                recover.classList.add('hidden');
+            }
         } else { // For Sinclair viewer, charset is always available if it exists
             if (charset) charset.classList.remove('hidden');
         }
@@ -101,13 +134,23 @@ init().then(() => {
         useAltParagraph.classList.remove('hidden');
 
         let mode;
-        if (modeSA && modeSA.checked) mode = 'SA';
+        if (modeAuto && modeAuto.checked) mode = 'AUTO';
+        else if (modeSA && modeSA.checked) mode = 'SA';
         else if (modeSP && modeSP.checked) mode = 'SP';
         else if (mode1Z && mode1Z.checked) mode = '1Z';
         else if (modeZ80 && modeZ80.checked) mode = 'Z80';
         else if (modeDump && modeDump.checked) mode = 'DUMP';
+        else if (modeMZT && modeMZT.checked) mode = 'MZT';
+        else if (modeMZWAV && modeMZWAV.checked) mode = 'MZWAV';
+        else if (modeQD && modeQD.checked) mode = 'QD';
+        else if (modeDISK && modeDISK.checked) mode = 'DISK';
         else if (modeZX80Basic && modeZX80Basic.checked) mode = 'ZX80BASIC';
         else if (modeZX81Basic && modeZX81Basic.checked) mode = 'ZX81BASIC';
+        else if (modeZX81Run && modeZX81Run.checked) mode = 'ZX81RUN';
+        else if (modeZXWAV && modeZXWAV.checked) mode = 'ZXWAV';
+        else if (modeTZX && modeTZX.checked) mode = 'TZX';
+        else if (modeSpectrum && modeSpectrum.checked) mode = 'SPECTRUM';
+        else if (modeMSX && modeMSX.checked) mode = 'MSX';
         else mode = 'SA'; // Fallback for MZF viewer, or will be overridden by specific HTML
 
         // Only try to read the first byte and set outputTypeSpan if mzbyte0 element exists
@@ -128,7 +171,14 @@ init().then(() => {
 
         try {
             const ascii_charset = charsetToggle ? charsetToggle.checked : false;
-            const result = process_binary(new Uint8Array(fileData), mode, machine, ascii_charset);
+            const recovering = recoverToggle && !recover.classList.contains('hidden') && recoverToggle.checked;
+            // The diff takes BASIC, Z80 and DUMP modes; containers are compared by detecting each file
+            const diffMode = ['Z80', 'DUMP', 'SA', 'SP', '1Z', 'ZX80BASIC', 'ZX81BASIC', 'SPECTRUM', 'MSX'].includes(mode) ? mode : 'AUTO';
+            const result = compareData
+                ? diff_programs(new Uint8Array(fileData), new Uint8Array(compareData), diffMode)
+                : recovering
+                ? recover_mz_basic(new Uint8Array(fileData), mode)
+                : process_binary(new Uint8Array(fileData), mode, machine, ascii_charset);
 
             // Pre-compile regex for better performance
             const HTML_ESCAPE_REGEX = /&(?!#x)|[<>]/g;
@@ -199,11 +249,11 @@ init().then(() => {
 
                 // Determine target file extension based on current viewer (MZF or ZX)
                 if (machine === MZFMachine.Sinclair) {
-                    // For Sinclair, match .tap, .p, or .80
-                    fileExtension = /\.(tap|p|80)$/i; 
+                    // For Sinclair, match .tap, .p, .p81, .81, .80, .wav, .tzx or MSX .bas
+                    fileExtension = /\.(tap|p|p81|81|80|wav|tzx|bas)$/i; 
                 } else {
                     // For other machines (presumably MZF), match .mzf
-                    fileExtension = /\.mzf$/i; 
+                    fileExtension = /\.(mzf|mzt|wav|qdf|mzq|d88|2d)$/i; 
                 }
 
                 zip.forEach((relativePath, zipEntry) => {
@@ -274,11 +324,11 @@ init().then(() => {
 
                 // Determine target file extension based on current viewer (MZF or ZX)
                 if (machine === MZFMachine.Sinclair) {
-                    // For Sinclair, match .tap, .p, or .80
-                    fileExtension = /\.(tap|p|80)$/i; 
+                    // For Sinclair, match .tap, .p, .p81, .81, .80, .wav, .tzx or MSX .bas
+                    fileExtension = /\.(tap|p|p81|81|80|wav|tzx|bas)$/i; 
                 } else {
                     // For other machines (presumably MZF), match .mzf
-                    fileExtension = /\.mzf$/i; 
+                    fileExtension = /\.(mzf|mzt|wav|qdf|mzq|d88|2d)$/i; 
                 }
 
                             zip.forEach((relativePath, zipEntry) => {
@@ -325,15 +375,42 @@ init().then(() => {
         });
     }
 
+    // Comparing needs both files in memory; clearing the second file goes back to the listing
+    if (compareInput) compareInput.addEventListener('change', (event) => {
+        const file = event.target.files[0];
+        if (!file) {
+            compareData = null;
+            if (processFile) processFile();
+            return;
+        }
+        const reader = new FileReader();
+        reader.onload = (e) => {
+            compareData = e.target.result;
+            if (processFile) processFile();
+        };
+        reader.readAsArrayBuffer(file);
+    });
+
     // Event listeners for radio button changes
     if (modeSA) modeSA.addEventListener('change', () => processFile && processFile());
     if (modeSP) modeSP.addEventListener('change', () => processFile && processFile());
     if (mode1Z) mode1Z.addEventListener('change', () => processFile && processFile());
     if (modeZ80) modeZ80.addEventListener('change', () => processFile && processFile());
     if (modeDump) modeDump.addEventListener('change', () => processFile && processFile());
+    if (modeMZT) modeMZT.addEventListener('change', () => processFile && processFile());
+    if (modeMZWAV) modeMZWAV.addEventListener('change', () => processFile && processFile());
+    if (modeQD) modeQD.addEventListener('change', () => processFile && processFile());
+    if (modeDISK) modeDISK.addEventListener('change', () => processFile && processFile());
+    if (modeAuto) modeAuto.addEventListener('change', () => processFile && processFile());
     if (modeZX80Basic) modeZX80Basic.addEventListener('change', () => processFile && processFile());
     if (modeZX81Basic) modeZX81Basic.addEventListener('change', () => processFile && processFile());
+    if (modeZX81Run) modeZX81Run.addEventListener('change', () => processFile && processFile());
+    if (modeZXWAV) modeZXWAV.addEventListener('change', () => processFile && processFile());
+    if (modeTZX) modeTZX.addEventListener('change', () => processFile && processFile());
+    if (modeSpectrum) modeSpectrum.addEventListener('change', () => processFile && processFile());
+    if (modeMSX) modeMSX.addEventListener('change', () => processFile && processFile());
     if (charsetToggle) charsetToggle.addEventListener('change', () => processFile && processFile());
+    if (recoverToggle) recoverToggle.addEventListener('change', () => processFile && processFile());
     
     // Event listener for the Save button
     saveButton.addEventListener('click', () => {
@@ -358,6 +435,26 @@ init().then(() => {
         URL.revokeObjectURL(url);
     });
 
+    // Event listener for the Save as Tape WAV button (Sharp viewer only)
+    if (saveWavButton) saveWavButton.addEventListener('click', () => {
+        let wav;
+        try {
+            wav = mzf_to_wav(new Uint8Array(fileData), 44100, 1);
+        } catch (e) {
+            outputPre.textContent = `Error: This file cannot be written as a tape (${e.message}).`;
+            return;
+        }
+        const blob = new Blob([wav], { type: 'audio/wav' });
+        const url = URL.createObjectURL(blob);
+        const a = document.createElement('a');
+        a.href = url;
+        a.download = `${fileName}.wav`;
+        document.body.appendChild(a);
+        a.click();
+        document.body.removeChild(a);
+        URL.revokeObjectURL(url);
+    });
+
     // Initial call to hide the message and disable save button if no file is selected on load
     if (!fileUrl) processFile();
 }).catch(e => {
@@ -0,0 +1,273 @@
+// src/basic.rs
+//
+// Line-level results shared by the BASIC decoders.
+
+use crate::error::Error;
+
+/// What a part of a line is, for highlighting and analysis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemKind {
+    /// A keyword, function or operator token.
+    Keyword,
+    /// Variable names, punctuation and anything else listed as stored.
+    Text,
+    /// A string literal, quotes included.
+    String,
+    /// A numeric constant.
+    Number,
+    /// The body of a REM or DATA statement, kept as written.
+    Rem,
+}
+
+impl ItemKind {
+    /// The name used for the kind in JSON output.
+    pub fn name(self) -> &'static str {
+        match self {
+            ItemKind::Keyword => "keyword",
+            ItemKind::Text => "text",
+            ItemKind::String => "string",
+            ItemKind::Number => "number",
+            ItemKind::Rem => "rem",
+        }
+    }
+}
+
+/// A run of a line that is all of one kind.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicItem {
+    /// What the item is.
+    pub kind: ItemKind,
+    /// The item as listed.
+    pub text: String,
+    /// Offset of the item's first byte within the file.
+    pub offset: usize,
+    /// The stored value of a number, where the program holds it in binary form
+    /// (MZ BASIC constants and ZX81 hidden numbers) or it can be read from the digits.
+    pub value: Option<f64>,
+}
+
+/// One decoded BASIC program line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicLine {
+    /// The line number.
+    pub number: u16,
+    /// The detokenised statements, without the line number.
+    pub text: String,
+    /// Offset of the start of the line (its length or line number field) within the file.
+    pub offset: usize,
+    /// Bytes the line occupies in the file, header and terminator included.
+    pub length: usize,
+    /// The statements split into items; their texts joined together give `text`.
+    pub items: Vec<BasicItem>,
+}
+
+/// Collects a line's text and items together, so the items always add up to the text.
+#[derive(Default)]
+pub(crate) struct LineBuilder {
+    text: String,
+    items: Vec<BasicItem>,
+}
+
+impl LineBuilder {
+    /// Appends `text` that starts at byte `offset`. Text, strings and REM bodies run on
+    /// into the previous item of the same kind; keywords and numbers always start a new one.
+    pub(crate) fn push(&mut self, kind: ItemKind, offset: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        self.text.push_str(text);
+        match self.items.last_mut() {
+            Some(last) if last.kind == kind && !matches!(kind, ItemKind::Keyword | ItemKind::Number) => {
+                last.text.push_str(text)
+            }
+            _ => self.items.push(BasicItem { kind, text: text.to_string(), offset, value: None }),
+        }
+    }
+
+    /// Appends a numeric constant.
+    pub(crate) fn number(&mut self, offset: usize, text: &str, value: f64) {
+        self.text.push_str(text);
+        self.items.push(BasicItem { kind: ItemKind::Number, text: text.to_string(), offset, value: Some(value) });
+    }
+
+    /// The text so far.
+    pub(crate) fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Removes trailing newlines, as left by a line terminator.
+    pub(crate) fn trim_end_newlines(&mut self) {
+        while self.text.ends_with('\n') {
+            self.text.pop();
+            if let Some(last) = self.items.last_mut() {
+                last.text.pop();
+                if last.text.is_empty() {
+                    self.items.pop();
+                }
+            }
+        }
+    }
+
+    /// Finishes the line.
+    pub(crate) fn into_line(self, number: u16, offset: usize, length: usize) -> BasicLine {
+        BasicLine { number, text: self.text, offset, length, items: self.items }
+    }
+}
+
+/// A decoded BASIC program.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BasicListing {
+    /// The program name, for formats that record one (ZX81 .P81 and tape images).
+    pub name: Option<String>,
+    /// The program lines in file order.
+    pub lines: Vec<BasicLine>,
+    /// Why decoding stopped early, if it did. The lines before the damage are still listed.
+    pub diagnostics: Vec<String>,
+}
+
+impl BasicListing {
+    /// Ends a decode that stopped at `error`. The lines read so far are kept and the
+    /// error becomes a diagnostic, unless nothing could be decoded at all.
+    pub(crate) fn partial(mut self, error: Error) -> Result<Self, Error> {
+        if self.lines.is_empty() {
+            return Err(error);
+        }
+        self.diagnostics.push(error.to_string());
+        Ok(self)
+    }
+
+    /// Renders the listing with each line number right-aligned to `width` columns.
+    pub fn to_text(&self, width: usize) -> String {
+        let mut result = String::new();
+        if let Some(name) = &self.name {
+            result.push_str(&format!("Program: \"{}\"\n\n", name));
+        }
+        for line in &self.lines {
+            result.push_str(&format!("{:width$} {}\n", line.number, line.text, width = width));
+        }
+        if !self.diagnostics.is_empty() {
+            result.push('\n');
+        }
+        for diagnostic in &self.diagnostics {
+            result.push_str(&format!("Error: {}\n", diagnostic));
+        }
+        result
+    }
+
+    /// Serialises the listing, including every line's items, as JSON.
+    ///
+    /// ```json
+    /// {"name":null,"lines":[{"number":10,"offset":128,"length":12,"text":"PRINT \"HI\"",
+    ///   "items":[{"kind":"keyword","text":"PRINT","offset":132}, ...]}],"diagnostics":[]}
+    /// ```
+    pub fn to_json(&self) -> String {
+        let lines: Vec<String> = self
+            .lines
+            .iter()
+            .map(|line| {
+                let items: Vec<String> = line
+                    .items
+                    .iter()
+                    .map(|item| {
+                        let value = match item.value {
+                            Some(v) if v.is_finite() => format!(",\"value\":{}", v),
+                            _ => String::new(),
+                        };
+                        format!(
+                            "{{\"kind\":\"{}\",\"text\":{},\"offset\":{}{}}}",
+                            item.kind.name(),
+                            json_string(&item.text),
+                            item.offset,
+                            value
+                        )
+                    })
+                    .collect();
+                format!(
+                    "{{\"number\":{},\"offset\":{},\"length\":{},\"text\":{},\"items\":[{}]}}",
+                    line.number,
+                    line.offset,
+                    line.length,
+                    json_string(&line.text),
+                    items.join(",")
+                )
+            })
+            .collect();
+        let diagnostics: Vec<String> = self.diagnostics.iter().map(|d| json_string(d)).collect();
+        format!(
+            "{{\"name\":{},\"lines\":[{}],\"diagnostics\":[{}]}}",
+            self.name.as_deref().map_or("null".to_string(), json_string),
+            lines.join(","),
+            diagnostics.join(",")
+        )
+    }
+}
+
+/// Quotes and escapes a string for JSON.
+fn json_string(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(name: Option<&str>, build: impl FnOnce(&mut LineBuilder)) -> BasicListing {
+        let mut line = LineBuilder::default();
+        build(&mut line);
+        BasicListing { name: name.map(str::to_string), lines: vec![line.into_line(10, 128, 12)], diagnostics: Vec::new() }
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(json_string("say \"HI\""), r#""say \"HI\"""#);
+        assert_eq!(json_string("C:\\MZ"), r#""C:\\MZ""#);
+        assert_eq!(json_string("a\nb\rc\td"), r#""a\nb\rc\td""#);
+        assert_eq!(json_string("\u{01}\u{1F}"), r#""\u0001\u001f""#);
+        // Non-ASCII is valid JSON as it stands
+        assert_eq!(json_string("£◇"), "\"£◇\"");
+    }
+
+    #[test]
+    fn the_name_is_null_when_there_is_none() {
+        let print = |line: &mut LineBuilder| line.push(ItemKind::Keyword, 132, "PRINT");
+        assert!(listing(None, print).to_json().starts_with("{\"name\":null,"));
+        assert!(listing(Some("GA\"ME"), print).to_json().starts_with(r#"{"name":"GA\"ME","#));
+    }
+
+    #[test]
+    fn only_numbers_carry_a_value() {
+        let json = listing(None, |line| {
+            line.push(ItemKind::Keyword, 132, "PRINT");
+            line.push(ItemKind::Text, 133, " A1+");
+            line.number(137, "1.5", 1.5);
+            line.push(ItemKind::Text, 140, "+");
+            line.number(141, "1E400", f64::INFINITY);
+        })
+        .to_json();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"name":null,"lines":[{"number":10,"offset":128,"length":12,"text":"PRINT A1+1.5+1E400","items":["#,
+                r#"{"kind":"keyword","text":"PRINT","offset":132},"#,
+                r#"{"kind":"text","text":" A1+","offset":133},"#,
+                r#"{"kind":"number","text":"1.5","offset":137,"value":1.5},"#,
+                r#"{"kind":"text","text":"+","offset":140},"#,
+                r#"{"kind":"number","text":"1E400","offset":141}]}],"diagnostics":[]}"#
+            )
+        );
+    }
+}
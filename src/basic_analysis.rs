@@ -0,0 +1,508 @@
+// src/basic_analysis.rs
+//
+// Cross-reference and flow analysis over a decoded BASIC program. It works on the items
+// of a `BasicListing`, so the MZ, ZX80 and ZX81 dialects share one implementation.
+
+use crate::basic::{BasicLine, BasicListing, ItemKind};
+use std::collections::HashMap;
+
+/// A line number used by a statement: a jump, or a line given to LIST or RESTORE.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineReference {
+    /// The line the statement is on.
+    pub line: u16,
+    /// The keyword, such as "GOTO", "GOSUB", "THEN", "RUN", "LIST" or "RESTORE".
+    pub keyword: String,
+    /// The line referred to, or `None` when it is worked out at run time (`GOTO N*100`).
+    pub target: Option<u16>,
+    /// Whether the program has a line with the target's number.
+    pub exists: bool,
+    /// Offset within the file of the target's number item, when it is a constant.
+    pub offset: Option<usize>,
+}
+
+/// Where a variable is given a value and where it is used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariableUse {
+    /// The name as listed, with `()` added for arrays (`A$()`).
+    pub name: String,
+    /// Lines that assign it with LET, FOR, INPUT, READ, GET or DIM.
+    pub set: Vec<u16>,
+    /// Lines that read it.
+    pub read: Vec<u16>,
+}
+
+/// A POKE, PEEK or USR and the address it is given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressUse {
+    /// The line the statement is on.
+    pub line: u16,
+    /// "POKE", "PEEK" or "USR".
+    pub keyword: String,
+    /// The address, when it is a constant.
+    pub address: Option<u16>,
+    /// The address expression as listed.
+    pub expression: String,
+}
+
+/// The result of analysing a program: its line references, control flow, variables
+/// and machine code addresses.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProgramAnalysis {
+    /// Every line number target in program order.
+    pub references: Vec<LineReference>,
+    /// Lines that no jump or fall-through from the first line reaches. Left empty when
+    /// the program has a computed GOTO or GOSUB, since that could go anywhere.
+    pub unreachable: Vec<u16>,
+    /// Whether the program jumps to a computed line number.
+    pub computed_jumps: bool,
+    /// Every variable, sorted by name.
+    pub variables: Vec<VariableUse>,
+    /// Every POKE, PEEK and USR in program order.
+    pub addresses: Vec<AddressUse>,
+}
+
+/// A piece of a line after splitting text items into names, numbers and symbols.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A keyword in upper case with its spacing and any opening bracket removed.
+    Keyword(String),
+    Name(String),
+    Number(Option<f64>),
+    Symbol(char),
+    /// A string literal, or a REM body.
+    Literal,
+}
+
+struct Lexeme {
+    token: Token,
+    text: String,
+    /// Where a number constant's item starts in the file; numbers read from text have none.
+    offset: Option<usize>,
+}
+
+/// Splits a line into lexemes. Keywords that are only operators (`<>`, `+`) become symbols.
+fn lex_line(line: &BasicLine) -> Vec<Lexeme> {
+    let mut lexemes = Vec::new();
+    for item in &line.items {
+        match item.kind {
+            ItemKind::Keyword => {
+                let text = item.text.trim();
+                let (word, bracket) = match text.strip_suffix('(') {
+                    Some(word) => (word, true),
+                    None => (text, false),
+                };
+                if word.chars().any(|c| c.is_ascii_alphabetic()) {
+                    let key = word.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_ascii_uppercase();
+                    lexemes.push(Lexeme { token: Token::Keyword(key), text: item.text.clone(), offset: None });
+                } else {
+                    lexemes.extend(word.chars().map(|c| Lexeme { token: Token::Symbol(c), text: c.to_string(), offset: None }));
+                }
+                if bracket {
+                    lexemes.push(Lexeme { token: Token::Symbol('('), text: "(".to_string(), offset: None });
+                }
+            }
+            ItemKind::Number => lexemes.push(Lexeme {
+                token: Token::Number(item.value.or_else(|| item.text.parse().ok())),
+                text: item.text.clone(),
+                offset: Some(item.offset),
+            }),
+            ItemKind::String | ItemKind::Rem => {
+                lexemes.push(Lexeme { token: Token::Literal, text: item.text.clone(), offset: None })
+            }
+            ItemKind::Text => lex_text(&item.text, &mut lexemes),
+        }
+    }
+    lexemes
+}
+
+/// Splits variable names, digits and punctuation, as in the body of a DATA statement or
+/// between the tokens of a line.
+fn lex_text(text: &str, lexemes: &mut Vec<Lexeme>) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        let token = if c.is_ascii_alphabetic() {
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            if chars.get(i) == Some(&'$') {
+                i += 1;
+            }
+            Token::Name(chars[start..i].iter().collect())
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            Token::Number(chars[start..i].iter().collect::<String>().parse().ok())
+        } else if c == '$' && chars.get(i + 1).is_some_and(|d| d.is_ascii_hexdigit()) {
+            // A Sharp hexadecimal constant
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_hexdigit() {
+                i += 1;
+            }
+            Token::Number(u32::from_str_radix(&chars[start + 1..i].iter().collect::<String>(), 16).ok().map(f64::from))
+        } else if c == '"' {
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                i += 1;
+            }
+            i = (i + 1).min(chars.len());
+            Token::Literal
+        } else {
+            i += 1;
+            if c.is_whitespace() {
+                continue;
+            }
+            Token::Symbol(c)
+        };
+        lexemes.push(Lexeme { token, text: chars[start..i].iter().collect(), offset: None });
+    }
+}
+
+/// Converts a number to a line number or address, if it is in range.
+fn to_u16(value: Option<f64>) -> Option<u16> {
+    value.filter(|v| (0.0..=65535.0).contains(v)).map(|v| v as u16)
+}
+
+/// Collects the analysis as the lines are walked.
+#[derive(Default)]
+struct Analyser {
+    analysis: ProgramAnalysis,
+    variables: HashMap<String, VariableUse>,
+    /// Position of each line number in the listing.
+    index: HashMap<u16, usize>,
+}
+
+impl Analyser {
+    /// Analyses one line, returning the lines it can jump to and whether control can run
+    /// on into the next line.
+    fn line(&mut self, line: &BasicLine) -> (Vec<u16>, bool) {
+        let lexemes = lex_line(line);
+        let number = line.number;
+        let mut jumps = Vec::new();
+        let mut runs_on = true;
+        // Everything after IF or ON may not happen
+        let mut conditional = false;
+        let mut statement_start = true;
+        // INPUT, READ, GET and DIM assign every name in their list; LET and FOR the next one
+        let mut assigning = false;
+        let mut next_is_set = false;
+        let mut depth = 0usize;
+        let mut i = 0;
+
+        while i < lexemes.len() {
+            let at_start = std::mem::replace(&mut statement_start, false);
+            match &lexemes[i].token {
+                Token::Symbol(':') => {
+                    statement_start = true;
+                    assigning = false;
+                    depth = 0;
+                }
+                Token::Symbol('(') => depth += 1,
+                Token::Symbol(')') => depth = depth.saturating_sub(1),
+                Token::Symbol(_) | Token::Number(_) | Token::Literal => {}
+                Token::Name(name) => {
+                    let array = matches!(lexemes.get(i + 1).map(|l| &l.token), Some(Token::Symbol('(')));
+                    let name = if array { format!("{}()", name) } else { name.clone() };
+                    // A statement that starts with a name is a LET without the keyword
+                    let set = at_start || next_is_set || (assigning && depth == 0);
+                    next_is_set = false;
+                    self.variable(name, number, set);
+                }
+                Token::Keyword(keyword) => {
+                    let keyword = keyword.as_str();
+                    if at_start && !conditional && matches!(keyword, "GOTO" | "RETURN" | "END" | "STOP" | "NEW" | "RUN") {
+                        runs_on = false;
+                    }
+                    match keyword {
+                        "IF" | "ON" => conditional = true,
+                        "LET" | "FOR" => next_is_set = true,
+                        "INPUT" | "READ" | "GET" | "DIM" => assigning = true,
+                        "DATA" => {
+                            while lexemes.get(i + 1).is_some_and(|l| l.token != Token::Symbol(':')) {
+                                i += 1;
+                            }
+                        }
+                        // A function name, not a variable
+                        "FN" | "DEFFN" if matches!(lexemes.get(i + 1).map(|l| &l.token), Some(Token::Name(_))) => i += 1,
+                        "POKE" | "PEEK" | "USR" => self.address(&lexemes, i, number, keyword),
+                        "GOTO" | "GOSUB" | "THEN" | "ELSE" | "RUN" | "LIST" | "RESTORE" => {
+                            let next = self.line_numbers(&lexemes, i, number, keyword, &mut jumps);
+                            // IF X THEN LET A=1: a statement follows
+                            statement_start = matches!(keyword, "THEN" | "ELSE") && next == i + 1;
+                            i = next;
+                            continue;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            i += 1;
+        }
+        (jumps, runs_on)
+    }
+
+    /// Records a use of a variable, once per line.
+    fn variable(&mut self, name: String, line: u16, set: bool) {
+        let entry = self.variables.entry(name.clone()).or_insert(VariableUse { name, set: Vec::new(), read: Vec::new() });
+        let lines = if set { &mut entry.set } else { &mut entry.read };
+        if lines.last() != Some(&line) {
+            lines.push(line);
+        }
+    }
+
+    /// Reads the line numbers after the keyword at `i`, returning the index after them.
+    /// ON lists (`ON X GOTO 100,200`) give several targets. A computed target is recorded
+    /// without a number, and its expression is left to be read as ordinary statements.
+    fn line_numbers(&mut self, lexemes: &[Lexeme], i: usize, number: u16, keyword: &str, jumps: &mut Vec<u16>) -> usize {
+        let flow = matches!(keyword, "GOTO" | "GOSUB" | "THEN" | "ELSE" | "RUN");
+        let mut j = i + 1;
+        let mut targets = Vec::new();
+        while let Some(Lexeme { token: Token::Number(value), offset, .. }) = lexemes.get(j) {
+            targets.push((to_u16(*value), *offset));
+            j += 1;
+            match (lexemes.get(j).map(|l| &l.token), lexemes.get(j + 1).map(|l| &l.token)) {
+                (Some(Token::Symbol(',')), Some(Token::Number(_))) => j += 1,
+                _ => break,
+            }
+        }
+        let computed = keyword != "LIST"
+            && match lexemes.get(j).map(|l| &l.token) {
+                Some(Token::Symbol(c)) => *c != ':' && (targets.is_empty() || *c != ','),
+                Some(Token::Name(_)) => targets.is_empty() && keyword != "THEN" && keyword != "ELSE",
+                Some(Token::Keyword(_)) => targets.is_empty() && matches!(keyword, "GOTO" | "GOSUB"),
+                _ => false,
+            };
+        if computed {
+            self.analysis.computed_jumps |= flow;
+            self.analysis.references.push(LineReference { line: number, keyword: keyword.to_string(), target: None, exists: false, offset: None });
+            return i + 1;
+        }
+        for (target, offset) in targets {
+            let exists = target.is_some_and(|t| self.index.contains_key(&t));
+            match target {
+                Some(t) if flow => jumps.push(t),
+                None => self.analysis.computed_jumps |= flow,
+                _ => {}
+            }
+            self.analysis.references.push(LineReference { line: number, keyword: keyword.to_string(), target, exists, offset });
+        }
+        j
+    }
+
+    /// Records the address given to the POKE, PEEK or USR at `i`: a bracketed expression,
+    /// everything up to the comma for POKE, or the single operand of a Sinclair function.
+    fn address(&mut self, lexemes: &[Lexeme], i: usize, number: u16, keyword: &str) {
+        let start = i + 1;
+        let (from, to) = if lexemes.get(start).is_some_and(|l| l.token == Token::Symbol('(')) {
+            let mut depth = 0;
+            let close = (start..lexemes.len())
+                .find(|&j| {
+                    match lexemes[j].token {
+                        Token::Symbol('(') => depth += 1,
+                        Token::Symbol(')') => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .unwrap_or(lexemes.len());
+            (start + 1, close)
+        } else if keyword == "POKE" {
+            let mut depth = 0usize;
+            let end = (start..lexemes.len())
+                .find(|&j| {
+                    match lexemes[j].token {
+                        Token::Symbol('(') => depth += 1,
+                        Token::Symbol(')') => depth = depth.saturating_sub(1),
+                        Token::Symbol(',' | ':') => return depth == 0,
+                        _ => {}
+                    }
+                    false
+                })
+                .unwrap_or(lexemes.len());
+            (start, end)
+        } else {
+            (start, (start + 1).min(lexemes.len()))
+        };
+        let operand = &lexemes[from.min(to)..to];
+        let address = match operand {
+            [Lexeme { token: Token::Number(value), .. }] => to_u16(*value),
+            _ => None,
+        };
+        let expression = operand.iter().map(|l| l.text.as_str()).collect::<String>().trim().to_string();
+        self.analysis.addresses.push(AddressUse { line: number, keyword: keyword.to_string(), address, expression });
+    }
+}
+
+impl BasicListing {
+    /// Builds a cross-reference of the program: its line number references (flagging
+    /// those to missing lines), the lines control never reaches, where each variable is
+    /// set and read, and every POKE, PEEK and USR address.
+    pub fn analyse(&self) -> ProgramAnalysis {
+        let mut analyser = Analyser::default();
+        for (index, line) in self.lines.iter().enumerate() {
+            analyser.index.entry(line.number).or_insert(index);
+        }
+        let flow: Vec<(Vec<u16>, bool)> = self.lines.iter().map(|line| analyser.line(line)).collect();
+
+        let mut analysis = std::mem::take(&mut analyser.analysis);
+        if !analysis.computed_jumps && !flow.is_empty() {
+            let mut reached = vec![false; flow.len()];
+            let mut pending = vec![0];
+            while let Some(index) = pending.pop() {
+                if std::mem::replace(&mut reached[index], true) {
+                    continue;
+                }
+                let (jumps, runs_on) = &flow[index];
+                pending.extend(jumps.iter().filter_map(|target| analyser.index.get(target).copied()));
+                if *runs_on && index + 1 < flow.len() {
+                    pending.push(index + 1);
+                }
+            }
+            analysis.unreachable =
+                self.lines.iter().zip(reached).filter(|(_, reached)| !reached).map(|(line, _)| line.number).collect();
+        }
+        // A string indexed without a DIM is being sliced (ZX81 `A$(2 TO 4)`), not an array
+        let slices: Vec<String> = analyser
+            .variables
+            .iter()
+            .filter(|(name, variable)| name.ends_with("$()") && variable.set.is_empty())
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in slices {
+            let read = analyser.variables.remove(&name).map(|v| v.read).unwrap_or_default();
+            let string = name.trim_end_matches("()").to_string();
+            for line in read {
+                analyser.variable(string.clone(), line, false);
+            }
+            if let Some(variable) = analyser.variables.get_mut(&string) {
+                variable.read.sort_unstable();
+                variable.read.dedup();
+            }
+        }
+        analysis.variables = analyser.variables.into_values().collect();
+        analysis.variables.sort_by(|a, b| a.name.cmp(&b.name));
+        analysis
+    }
+}
+
+impl ProgramAnalysis {
+    /// Line references to lines the program does not have.
+    pub fn missing_targets(&self) -> impl Iterator<Item = &LineReference> {
+        self.references.iter().filter(|r| r.target.is_some() && !r.exists)
+    }
+
+    /// Renders the analysis as a plain text report.
+    pub fn to_text(&self) -> String {
+        let lines = |numbers: &[u16]| match numbers {
+            [] => "-".to_string(),
+            _ => numbers.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", "),
+        };
+        let mut result = String::from("Line references:\n");
+        for reference in &self.references {
+            let target = match reference.target {
+                Some(target) if reference.exists => target.to_string(),
+                Some(target) => format!("{} (missing)", target),
+                None => "(computed)".to_string(),
+            };
+            result.push_str(&format!("{:5} {} {}\n", reference.line, reference.keyword, target));
+        }
+        if self.references.is_empty() {
+            result.push_str("  none\n");
+        }
+
+        result.push_str("\nUnreachable lines:\n");
+        if self.computed_jumps {
+            result.push_str("  not worked out: the program has computed jumps\n");
+        } else if self.unreachable.is_empty() {
+            result.push_str("  none\n");
+        } else {
+            result.push_str(&format!("  {}\n", lines(&self.unreachable)));
+        }
+
+        result.push_str("\nVariables:\n");
+        for variable in &self.variables {
+            result.push_str(&format!("  {:8} set {:16} read {}\n", variable.name, lines(&variable.set), lines(&variable.read)));
+        }
+        if self.variables.is_empty() {
+            result.push_str("  none\n");
+        }
+
+        result.push_str("\nAddresses:\n");
+        for address in &self.addresses {
+            let note = if address.address.is_some() { "" } else { " (computed)" };
+            result.push_str(&format!("{:5} {} {}{}\n", address.line, address.keyword, address.expression, note));
+        }
+        if self.addresses.is_empty() {
+            result.push_str("  none\n");
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mz_decoder::{decode_mz_listing, MZBasicVersion};
+    use crate::test_fixtures::{mzf, zx81_p};
+    use crate::zx81_decoder::{decode_zx81_listing, OutputStyle};
+
+    fn analyse(lines: &[(u16, &str)]) -> super::ProgramAnalysis {
+        decode_zx81_listing(&zx81_p(lines), OutputStyle::Readable).unwrap().analyse()
+    }
+
+    #[test]
+    fn references_and_missing_targets() {
+        let analysis = analyse(&[(10, "GOSUB 100"), (20, "IF A=1 THEN GOTO 50"), (30, "GOTO 10"), (100, "RETURN")]);
+        let references: Vec<_> = analysis.references.iter().map(|r| (r.line, r.keyword.as_str(), r.target, r.exists)).collect();
+        assert_eq!(references, [(10, "GOSUB", Some(100), true), (20, "GOTO", Some(50), false), (30, "GOTO", Some(10), true)]);
+        assert!(analysis.references.iter().all(|r| r.offset.is_some()));
+        let missing: Vec<_> = analysis.missing_targets().map(|r| r.line).collect();
+        assert_eq!(missing, [20]);
+        assert!(analysis.to_text().contains("   20 GOTO 50 (missing)\n"));
+    }
+
+    #[test]
+    fn unreachable_lines() {
+        let analysis = analyse(&[(10, "GOTO 30"), (20, "PRINT 1"), (30, "IF A THEN STOP"), (40, "STOP"), (50, "CLS")]);
+        assert_eq!(analysis.unreachable, [20, 50]);
+        assert!(!analysis.computed_jumps);
+
+        let computed = analyse(&[(10, "GOTO A*10"), (20, "PRINT 1")]);
+        assert!(computed.computed_jumps && computed.unreachable.is_empty());
+        assert_eq!(computed.references[0].target, None);
+        assert!(computed.to_text().contains("not worked out"));
+    }
+
+    #[test]
+    fn variables_are_set_and_read() {
+        let analysis = analyse(&[(10, "LET A=1"), (20, "FOR I=1 TO A"), (30, "INPUT B$"), (40, "DIM C(5)"), (50, "PRINT B$(2 TO 3);C(I)"), (60, "NEXT I")]);
+        let variables: Vec<_> = analysis.variables.iter().map(|v| (v.name.as_str(), v.set.clone(), v.read.clone())).collect();
+        assert_eq!(
+            variables,
+            [("A", vec![10], vec![20]), ("B$", vec![30], vec![50]), ("C()", vec![40], vec![50]), ("I", vec![20], vec![50, 60])]
+        );
+    }
+
+    #[test]
+    fn addresses_are_listed() {
+        let analysis = analyse(&[(10, "POKE 16418,0"), (20, "PRINT PEEK 16396"), (30, "RAND USR (16514+A)")]);
+        let addresses: Vec<_> = analysis.addresses.iter().map(|a| (a.keyword.as_str(), a.address, a.expression.as_str())).collect();
+        assert_eq!(addresses, [("POKE", Some(16418), "16418"), ("PEEK", Some(16396), "16396"), ("USR", None, "16514+A")]);
+        assert!(analysis.to_text().contains("   30 USR 16514+A (computed)\n"));
+    }
+
+    #[test]
+    fn sharp_hexadecimal_addresses() {
+        // 10 POKE $D000,1 in SP-5025 BASIC
+        let line = [0x0D, 0x00, 0x0A, 0x00, 0x94, 0x11, 0x00, 0xD0, b',', b'1', 0x00];
+        let mut body = line.to_vec();
+        body.extend([0, 0]);
+        let program = mzf(0x02, "POKE", 0x4F88, 0, &body);
+        let analysis = decode_mz_listing(&program, MZBasicVersion::SP5025).unwrap().analyse();
+        assert_eq!(analysis.addresses[0].address, Some(0xD000));
+        assert_eq!(analysis.addresses[0].expression, "$D000");
+    }
+}
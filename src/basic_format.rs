@@ -0,0 +1,237 @@
+// src/basic_format.rs
+//
+// A formatted listing: one statement per line, FOR/NEXT loops and subroutines indented,
+// and keyword spacing made consistent across the MZ, ZX80 and ZX81 token tables.
+
+use crate::basic::{BasicItem, BasicListing, ItemKind};
+use std::collections::{HashMap, HashSet};
+
+/// Choices for `BasicListing::to_formatted_text`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatOptions {
+    /// Spaces per level of indentation.
+    pub indent: usize,
+    /// Put each `:`-separated statement on a line of its own.
+    pub split_statements: bool,
+    /// Follow each jump with a comment showing the line it goes to.
+    pub jump_comments: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions { indent: 2, split_statements: true, jump_comments: false }
+    }
+}
+
+/// A block that indents the statements inside it.
+#[derive(Clone, Copy, PartialEq)]
+enum Block {
+    For,
+    Subroutine,
+}
+
+/// A keyword in upper case without spacing, for recognising statements in any dialect.
+fn keyword(item: &BasicItem) -> Option<String> {
+    let word = || item.text.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_ascii_uppercase();
+    (item.kind == ItemKind::Keyword).then(word)
+}
+
+/// Splits a line's items into statements at the colons between them. DATA bodies are
+/// left whole, since a colon there may be part of the data.
+fn statements(items: &[BasicItem]) -> Vec<Vec<BasicItem>> {
+    let mut statements = vec![Vec::new()];
+    let mut data = false;
+    for item in items {
+        data |= keyword(item).as_deref() == Some("DATA");
+        if item.kind != ItemKind::Text || data || !item.text.contains(':') {
+            statements.last_mut().unwrap().push(item.clone());
+            continue;
+        }
+        let mut offset = item.offset;
+        for (i, part) in item.text.split(':').enumerate() {
+            if i > 0 {
+                statements.push(Vec::new());
+                offset += 1;
+            }
+            if !part.trim().is_empty() {
+                statements.last_mut().unwrap().push(BasicItem { text: part.to_string(), offset, ..item.clone() });
+            }
+            offset += part.len();
+        }
+    }
+    statements.retain(|statement| !statement.is_empty());
+    statements
+}
+
+/// Lists a statement with one space around each word keyword, none inside brackets or
+/// before separators, and operator keywords written tight like any other symbol.
+fn render(items: &[BasicItem]) -> String {
+    let mut out = String::new();
+    // A word keyword wants a space after it, unless a separator follows
+    let mut space = false;
+    for item in items {
+        let text = match item.kind {
+            ItemKind::Keyword => item.text.trim(),
+            ItemKind::Text if space => item.text.trim_start(),
+            ItemKind::Rem => item.text.trim_start(),
+            _ => &item.text,
+        };
+        if text.is_empty() {
+            continue;
+        }
+        let word = item.kind == ItemKind::Keyword && text.chars().any(|c| c.is_ascii_alphabetic());
+        let separator = text.starts_with([')', ',', ';', ':']);
+        let after_operand = out.ends_with(|c: char| c.is_ascii_alphanumeric() || matches!(c, '"' | ')' | '$' | '.'));
+        if (space && !separator) || (word && after_operand) {
+            out.push(' ');
+        }
+        out.push_str(text);
+        space = word && !text.ends_with('(');
+    }
+    out.trim_end().to_string()
+}
+
+impl BasicListing {
+    /// Renders the listing for reading rather than typing in: statements split onto lines
+    /// of their own, FOR/NEXT bodies and subroutines (from a GOSUB target to its RETURN)
+    /// indented, consistent keyword spacing and, if asked, a comment after each jump
+    /// giving the start of the line it goes to.
+    pub fn to_formatted_text(&self, options: &FormatOptions) -> String {
+        let analysis = self.analyse();
+        let subroutines: HashSet<u16> =
+            analysis.references.iter().filter(|r| r.keyword == "GOSUB").filter_map(|r| r.target).collect();
+        let mut targets: HashMap<usize, Vec<(u16, bool)>> = HashMap::new();
+        for reference in &analysis.references {
+            if let (Some(offset), Some(target)) = (reference.offset, reference.target) {
+                targets.entry(offset).or_default().push((target, reference.exists));
+            }
+        }
+        let first_statements: HashMap<u16, String> = self
+            .lines
+            .iter()
+            .map(|line| (line.number, statements(&line.items).first().map(|s| render(s)).unwrap_or_default()))
+            .collect();
+        let width = self.lines.iter().map(|line| line.number.to_string().len()).max().unwrap_or(1);
+
+        let mut result = String::new();
+        if let Some(name) = &self.name {
+            result.push_str(&format!("Program: \"{}\"\n\n", name));
+        }
+        let mut blocks: Vec<Block> = Vec::new();
+        for line in &self.lines {
+            if subroutines.contains(&line.number) {
+                // A second entry point closes the subroutine above it
+                if let Some(open) = blocks.iter().rposition(|&b| b == Block::Subroutine) {
+                    blocks.truncate(open);
+                }
+            }
+            let mut statements = statements(&line.items);
+            if !options.split_statements && statements.len() > 1 {
+                statements = vec![line.items.clone()];
+            }
+            let mut conditional = false;
+            for (i, statement) in statements.iter().enumerate() {
+                let first = statement.iter().find_map(keyword);
+                let depth = blocks.len() + usize::from(conditional);
+                let depth = match first.as_deref() {
+                    Some("NEXT") if !conditional && blocks.last() == Some(&Block::For) => {
+                        blocks.pop();
+                        depth - 1
+                    }
+                    Some("RETURN") if !conditional => match blocks.iter().rposition(|&b| b == Block::Subroutine) {
+                        Some(open) => {
+                            blocks.truncate(open);
+                            open
+                        }
+                        None => depth,
+                    },
+                    _ => depth,
+                };
+                let number = if i == 0 { line.number.to_string() } else { String::new() };
+                let mut text = format!("{:>width$} {}{}", number, " ".repeat(depth * options.indent), render(statement));
+                if options.jump_comments {
+                    let jumps: Vec<String> = statement
+                        .iter()
+                        .filter_map(|item| targets.get(&item.offset))
+                        .flatten()
+                        .map(|&(target, exists)| match (exists, first_statements.get(&target)) {
+                            (true, Some(first)) => format!("{}: {}", target, first),
+                            _ => format!("{} (missing)", target),
+                        })
+                        .collect();
+                    if !jumps.is_empty() {
+                        text.push_str(&format!("  => {}", jumps.join(", ")));
+                    }
+                }
+                result.push_str(text.trim_end());
+                result.push('\n');
+
+                // The body of a subroutine is indented under its first statement
+                if i == 0 && subroutines.contains(&line.number) && first.as_deref() != Some("RETURN") {
+                    blocks.insert(0, Block::Subroutine);
+                }
+                conditional |= statement.iter().any(|item| keyword(item).as_deref() == Some("IF"));
+                if !conditional && first.as_deref() == Some("FOR") {
+                    blocks.push(Block::For);
+                }
+            }
+        }
+
+        if !self.diagnostics.is_empty() {
+            result.push('\n');
+        }
+        for diagnostic in &self.diagnostics {
+            result.push_str(&format!("Error: {}\n", diagnostic));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mz_decoder::{decode_mz_listing, MZBasicVersion};
+    use crate::test_fixtures::{mzf, zx81_p};
+    use crate::zx81_decoder::{decode_zx81_listing, OutputStyle};
+
+    fn zx81(lines: &[(u16, &str)]) -> BasicListing {
+        decode_zx81_listing(&zx81_p(lines), OutputStyle::Readable).unwrap()
+    }
+
+    #[test]
+    fn loops_and_subroutines_are_indented() {
+        let listing = zx81(&[
+            (10, "FOR I=1 TO 3"),
+            (20, "GOSUB 100"),
+            (30, "NEXT I"),
+            (40, "STOP"),
+            (100, "PRINT I"),
+            (110, "IF I=2 THEN RETURN"),
+            (120, "RETURN"),
+        ]);
+        // The subroutine body is indented under its first line, and the RETURN closes it
+        let text = listing.to_formatted_text(&FormatOptions::default());
+        assert_eq!(text, " 10 FOR I=1 TO 3\n 20   GOSUB 100\n 30 NEXT I\n 40 STOP\n100 PRINT I\n110   IF I=2 THEN RETURN\n120 RETURN\n");
+        let text = listing.to_formatted_text(&FormatOptions { indent: 4, ..Default::default() });
+        assert!(text.contains("\n 20     GOSUB 100\n"));
+    }
+
+    #[test]
+    fn statements_are_split_and_jumps_explained() {
+        // 10 PRINT "A":GOTO 30, 20 END, 30 DATA 1:2 in SP-5025 BASIC
+        let line = |number: u16, statement: &[u8]| {
+            let mut bytes = ((5 + statement.len()) as u16).to_le_bytes().to_vec();
+            bytes.extend(number.to_le_bytes());
+            bytes.extend(statement);
+            bytes.push(0x00);
+            bytes
+        };
+        let body = [line(10, b"\x85\"A\":\x89\x0B\x1E\x00"), line(20, &[0x8F]), line(30, b"\x81 1:2"), vec![0, 0]].concat();
+        let listing = decode_mz_listing(&mzf(0x02, "FORMAT", 0x4F88, 0, &body), MZBasicVersion::SP5025).unwrap();
+        let options = FormatOptions { jump_comments: true, ..Default::default() };
+        // The colon in the DATA line is part of the data
+        assert_eq!(listing.to_formatted_text(&options), "10 PRINT \"A\"\n   GOTO 30  => 30: DATA 1:2\n20 END\n30 DATA 1:2\n");
+        let options = FormatOptions { split_statements: false, ..Default::default() };
+        assert_eq!(listing.to_formatted_text(&options), "10 PRINT \"A\":GOTO 30\n20 END\n30 DATA 1:2\n");
+    }
+}
@@ -0,0 +1,72 @@
+// src/basic_number.rs
+//
+// Printing numbers the way the BASICs do. The Sharp MZ BASICs and the ZX81 share the
+// method: round to a number of significant digits, drop trailing zeros and the zero
+// before the point, and switch to E notation outside a range of exponents. They differ
+// only in the digits kept, that range, and how the exponent is written.
+
+use std::ops::Range;
+
+/// How a BASIC prints numbers.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct NumberStyle {
+    /// Significant digits printed.
+    pub digits: usize,
+    /// Decimal exponents printed without `E`.
+    pub fixed_exponents: Range<i32>,
+    /// Digits the exponent is padded to: 2 gives `E+08`, 1 gives `E+8`.
+    pub exponent_digits: usize,
+}
+
+/// Formats a number in the given style, without the space printed before a positive
+/// number. Zero and numbers that are not finite print as `0`.
+pub(crate) fn format_number(n: f64, style: &NumberStyle) -> String {
+    if n == 0.0 || !n.is_finite() {
+        return "0".to_string();
+    }
+    let sign = if n < 0.0 { "-" } else { "" };
+    // Rounding to the digits first means 99999999.5 becomes 1E+08, as on the machines
+    let formatted = format!("{:.*e}", style.digits - 1, n.abs());
+    let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let digits: String = mantissa.chars().filter(|c| c.is_ascii_digit()).collect();
+    let digits = digits.trim_end_matches('0');
+    let digits = if digits.is_empty() { "0" } else { digits };
+
+    if !style.fixed_exponents.contains(&exponent) {
+        let (head, tail) = digits.split_at(1);
+        let point = if tail.is_empty() { String::new() } else { format!(".{}", tail) };
+        let esign = if exponent < 0 { '-' } else { '+' };
+        return format!("{}{}{}E{}{:0width$}", sign, head, point, esign, exponent.abs(), width = style.exponent_digits);
+    }
+
+    if exponent < 0 {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        format!("{}.{}{}", sign, zeros, digits)
+    } else {
+        let int_len = exponent as usize + 1;
+        if digits.len() <= int_len {
+            format!("{}{}{}", sign, digits, "0".repeat(int_len - digits.len()))
+        } else {
+            format!("{}{}.{}", sign, &digits[..int_len], &digits[int_len..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn styles_set_the_digits_range_and_exponent() {
+        let wide = NumberStyle { digits: 8, fixed_exponents: -5..8, exponent_digits: 1 };
+        let narrow = NumberStyle { digits: 6, fixed_exponents: -2..6, exponent_digits: 2 };
+        assert_eq!(format_number(0.0001, &wide), ".0001");
+        assert_eq!(format_number(0.0001, &narrow), "1E-04");
+        assert_eq!(format_number(1E12, &wide), "1E+12");
+        assert_eq!(format_number(1.0 / 3.0, &wide), ".33333333");
+        assert_eq!(format_number(1.0 / 3.0, &narrow), ".333333");
+        assert_eq!(format_number(-123456.7, &narrow), "-123457");
+        assert_eq!(format_number(f64::INFINITY, &wide), "0");
+    }
+}
@@ -0,0 +1,269 @@
+// src/basic_renumber.rs
+//
+// Renumbering of decoded BASIC programs. The new numbers are written back into the
+// original bytes at the offsets recorded in the program model, so the result is a file
+// in the same format that the machine loads.
+
+use crate::basic::{BasicItem, BasicListing, ItemKind};
+use crate::error::Error;
+use crate::zx81_decoder::zx81_float_bytes;
+use std::collections::HashMap;
+
+/// A renumbered program, and what could not be renumbered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenumberedProgram {
+    /// The program file with its new line numbers.
+    pub data: Vec<u8>,
+    /// Computed targets and references to missing lines, which are left as they were.
+    pub diagnostics: Vec<String>,
+}
+
+/// How a BASIC stores line numbers, and where its pointers past the program are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LineFormat {
+    /// Sharp MZ BASIC: little-endian line numbers, and targets as 0x0B/0x0C integers.
+    Mz,
+    /// ZX80: big-endian line numbers, and targets as plain digits.
+    Zx80,
+    /// ZX81: big-endian line numbers followed by the line length, and targets as digits
+    /// followed by a hidden 5-byte number. `base` is where the system variables start,
+    /// after the name of a .P81 file.
+    Zx81 { base: usize },
+}
+
+impl LineFormat {
+    /// The highest line number the BASIC accepts.
+    fn last_line(self) -> u32 {
+        match self {
+            LineFormat::Mz => 65535,
+            LineFormat::Zx80 | LineFormat::Zx81 { .. } => 9999,
+        }
+    }
+
+    /// The memory address of a file offset, for the Sinclair system variable pointers.
+    fn address(self, offset: usize) -> usize {
+        match self {
+            LineFormat::Mz => offset,
+            LineFormat::Zx80 => 0x4000 + offset,
+            LineFormat::Zx81 { base } => (16393 + offset).saturating_sub(base),
+        }
+    }
+
+    /// File offsets of the pointers that move when the program changes length.
+    fn pointers(self) -> Vec<usize> {
+        match self {
+            LineFormat::Mz => Vec::new(),
+            // VARS, E_LINE, D_FILE, DF_EA and DF_END
+            LineFormat::Zx80 => vec![8, 10, 12, 14, 16],
+            // D_FILE, DF_CC, VARS, DEST, E_LINE, CH_ADD, X_PTR, STKBOT, STKEND and NXTLIN
+            LineFormat::Zx81 { base } => [3, 5, 7, 9, 11, 13, 15, 17, 19, 32].iter().map(|p| base + p).collect(),
+        }
+    }
+
+    /// File offset of E_PPC, the current line, which is kept pointing at the same line.
+    fn current_line(self) -> Option<usize> {
+        match self {
+            LineFormat::Mz => None,
+            LineFormat::Zx80 => Some(6),
+            LineFormat::Zx81 { base } => Some(base + 1),
+        }
+    }
+
+    /// Where a line's number is within the line, and its bytes.
+    fn line_number(self, number: u16) -> (usize, [u8; 2]) {
+        match self {
+            // After the line length
+            LineFormat::Mz => (2, number.to_le_bytes()),
+            LineFormat::Zx80 | LineFormat::Zx81 { .. } => (0, number.to_be_bytes()),
+        }
+    }
+
+    /// Re-encodes the target number `item` as `number`, giving the bytes it replaces and
+    /// their replacement, or `None` if the bytes are not the constant the item says.
+    fn target(self, data: &[u8], item: &BasicItem, number: u16) -> Option<(usize, Vec<u8>)> {
+        let at = item.offset;
+        let digits = || number.to_string().bytes().map(|b| b - b'0' + 28).collect::<Vec<u8>>();
+        let is_digits = |len: usize| data.get(at..at + len).is_some_and(|d| d.iter().all(|b| (28..=37).contains(b)));
+        match self {
+            LineFormat::Mz => {
+                matches!(data.get(at), Some(0x0B | 0x0C)).then(|| (3, [vec![data[at]], number.to_le_bytes().to_vec()].concat()))
+            }
+            LineFormat::Zx80 => is_digits(item.text.len()).then(|| (item.text.len(), digits())),
+            LineFormat::Zx81 { .. } => {
+                let marker = at + item.text.len();
+                (is_digits(item.text.len()) && data.get(marker) == Some(&126) && marker + 6 <= data.len()).then(|| {
+                    let mut bytes = digits();
+                    bytes.push(126);
+                    bytes.extend(zx81_float_bytes(number as f64));
+                    (item.text.len() + 6, bytes)
+                })
+            }
+        }
+    }
+}
+
+/// A change to the file: `length` bytes at `offset` become `bytes`.
+struct Edit {
+    offset: usize,
+    length: usize,
+    bytes: Vec<u8>,
+}
+
+/// Renumbers the program in `data`, whose decoded form is `listing`, from `start` in steps
+/// of `step`. GOTO, GOSUB, THEN, RUN, LIST and RESTORE targets follow their lines,
+/// including every entry of an ON list.
+pub(crate) fn renumber(
+    data: &[u8],
+    listing: &BasicListing,
+    format: LineFormat,
+    start: u16,
+    step: u16,
+) -> Result<RenumberedProgram, Error> {
+    if !listing.diagnostics.is_empty() {
+        return Err(Error::InvalidFormat("The program is damaged; every line must decode before it can be renumbered"));
+    }
+    if step == 0 {
+        return Err(Error::InvalidArgument("The renumbering step must be at least 1".to_string()));
+    }
+    let last = start as u32 + step as u32 * (listing.lines.len() as u32).saturating_sub(1);
+    if last > format.last_line() {
+        return Err(Error::InvalidArgument(format!(
+            "Renumbering {} lines from {} in steps of {} goes past line {}",
+            listing.lines.len(),
+            start,
+            step,
+            format.last_line()
+        )));
+    }
+
+    // Where a damaged program repeats a line number, references go to the first
+    let renumbered: Vec<u16> = (0..listing.lines.len()).map(|i| start + step * i as u16).collect();
+    let mut numbers = HashMap::new();
+    for (line, &number) in listing.lines.iter().zip(&renumbered) {
+        numbers.entry(line.number).or_insert(number);
+    }
+    let items: HashMap<usize, &BasicItem> = listing
+        .lines
+        .iter()
+        .flat_map(|line| &line.items)
+        .filter(|item| item.kind == ItemKind::Number)
+        .map(|item| (item.offset, item))
+        .collect();
+
+    let mut edits: Vec<Edit> = listing
+        .lines
+        .iter()
+        .zip(&renumbered)
+        .map(|(line, &number)| {
+            let (at, bytes) = format.line_number(number);
+            Edit { offset: line.offset + at, length: 2, bytes: bytes.to_vec() }
+        })
+        .collect();
+    let mut diagnostics = Vec::new();
+    for reference in listing.analyse().references {
+        let (line, keyword) = (reference.line, &reference.keyword);
+        let Some(target) = reference.target else {
+            diagnostics.push(format!("Line {}: the {} target is computed and was left as it is", line, keyword));
+            continue;
+        };
+        let Some(&number) = numbers.get(&target) else {
+            diagnostics.push(format!("Line {}: {} {} refers to a missing line and was left as it is", line, keyword, target));
+            continue;
+        };
+        match reference.offset.and_then(|offset| items.get(&offset)).and_then(|item| format.target(data, item, number)) {
+            Some((length, bytes)) => edits.push(Edit { offset: reference.offset.unwrap_or_default(), length, bytes }),
+            None => diagnostics.push(format!(
+                "Line {}: {} {} is not stored as a line number constant and was left as it is",
+                line, keyword, target
+            )),
+        }
+    }
+
+    // Apply the edits from the end, so the offsets of those still to do are unaffected
+    edits.sort_by_key(|edit| edit.offset);
+    let mut output = data.to_vec();
+    let pointers = format.pointers();
+    let mut lines = listing.lines.iter().rev().peekable();
+    for edit in edits.iter().rev() {
+        output.splice(edit.offset..edit.offset + edit.length, edit.bytes.iter().copied());
+        let delta = edit.bytes.len() as isize - edit.length as isize;
+        if delta == 0 {
+            continue;
+        }
+        // A ZX81 line records its length after the line number
+        while lines.peek().is_some_and(|line| line.offset > edit.offset) {
+            lines.next();
+        }
+        if let (LineFormat::Zx81 { .. }, Some(line)) = (format, lines.peek()) {
+            let at = line.offset + 2;
+            let length = u16::from_le_bytes([output[at], output[at + 1]]) as isize + delta;
+            output[at..at + 2].copy_from_slice(&(length as u16).to_le_bytes());
+        }
+        let address = format.address(edit.offset);
+        for &pointer in &pointers {
+            let Some(field) = output.get(pointer..pointer + 2) else { continue };
+            let value = u16::from_le_bytes([field[0], field[1]]) as usize;
+            // A pointer that would leave the address space is already nonsense, so it stays
+            if let Some(moved) = (value > address).then(|| u16::try_from(value as isize + delta).ok()).flatten() {
+                output[pointer..pointer + 2].copy_from_slice(&moved.to_le_bytes());
+            }
+        }
+    }
+    if let Some(at) = format.current_line().filter(|&at| at + 2 <= output.len()) {
+        let current = u16::from_le_bytes([output[at], output[at + 1]]);
+        if let Some(&number) = numbers.get(&current) {
+            output[at..at + 2].copy_from_slice(&number.to_le_bytes());
+        }
+    }
+    Ok(RenumberedProgram { data: output, diagnostics })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mz::{renumber_basic as renumber_mz, MZBasicVersion};
+    use crate::test_fixtures::{mzf, zx81_p};
+    use crate::zx81::renumber_basic as renumber_zx81;
+
+    #[test]
+    fn zx81_targets_follow_their_lines() {
+        let program = zx81_p(&[(10, "GOTO 30"), (20, "GOSUB 5"), (30, "IF A THEN GOTO A")]);
+        let renumbered = renumber_zx81(&program, 100, 10).unwrap();
+        // The longer target moves everything after it, and the system variables with it
+        assert_eq!(renumbered.data, zx81_p(&[(100, "GOTO 120"), (110, "GOSUB 5"), (120, "IF A THEN GOTO A")]));
+        assert_eq!(
+            renumbered.diagnostics,
+            ["Line 20: GOSUB 5 refers to a missing line and was left as it is", "Line 30: the GOTO target is computed and was left as it is"]
+        );
+    }
+
+    #[test]
+    fn mz_targets_follow_their_lines() {
+        // 10 GOTO 30, 20 PRINT, 30 END in SP-5025 BASIC, with the target as a 0x0B integer
+        let line = |number: u16, statement: &[u8]| {
+            let mut bytes = ((5 + statement.len()) as u16).to_le_bytes().to_vec();
+            bytes.extend(number.to_le_bytes());
+            bytes.extend(statement);
+            bytes.push(0x00);
+            bytes
+        };
+        let program = |numbers: [u16; 3]| {
+            let body = [line(numbers[0], &[0x89, 0x0B, numbers[2] as u8, (numbers[2] >> 8) as u8]), line(numbers[1], &[0x85]), line(numbers[2], &[0x8F])];
+            mzf(0x02, "RENUMBER", 0x4F88, 0, &[body.concat(), vec![0, 0]].concat())
+        };
+        let renumbered = renumber_mz(&program([10, 20, 30]), MZBasicVersion::SP5025, 1000, 1000).unwrap();
+        assert_eq!(renumbered.data, program([1000, 2000, 3000]));
+        assert!(renumbered.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn impossible_renumbering_is_refused() {
+        let program = zx81_p(&[(10, "CLS"), (20, "CLS")]);
+        assert!(renumber_zx81(&program, 9990, 10).is_err());
+        assert!(renumber_zx81(&program, 10, 0).is_err());
+        assert_eq!(renumber_zx81(&program, 9980, 10).unwrap().data, zx81_p(&[(9980, "CLS"), (9990, "CLS")]));
+
+        let mut damaged = program.clone();
+        damaged.truncate(116 + 6);
+        assert!(renumber_zx81(&damaged, 100, 10).is_err());
+    }
+}
@@ -0,0 +1,567 @@
+// src/bin/mzfview.rs
+//
+// Command-line front end to the viewer, for batch processing an archive.
+// Build with `cargo build --release --features cli`.
+//
+// Exit codes: 0 success, 1 a file could not be decoded or converted,
+// 2 bad command line, 3 a file could not be read or written.
+
+use rust_wasm_converter::mz::{self, MZBasicVersion};
+use rust_wasm_converter::{
+    analyse_basic_program, basic_program_json, detect_input_format, diff_program_files, disk_entry_to_mzf,
+    format_basic_program, mz_trailing_data, mz_wav_to_mzf, mzf_to_wav, p_to_tzx, process_binary, qd_entry_to_mzf,
+    recover_mz_basic, tzx_to_p, zx80, zx81, zx_program_to_wav, zx_wav_to_program, Error, MZFMachine,
+};
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: mzfview [OPTIONS] <FILE|DIRECTORY>...
+
+Decodes Sharp MZ and Sinclair ZX80/ZX81 files. Directories are searched recursively.
+
+Options:
+  -m, --mode MODE      SA, SP, 1Z, Z80, DUMP, ZX80BASIC, ZX81BASIC, ZX81RUN, MZT,
+                       MZWAV, ZXWAV, TZX, SPECTRUM, MSX, QD, DISK or AUTO
+                       (default AUTO)
+      --machine NAME   sharp, sinclair, spectrum or msx (default: chosen from the mode)
+  -a, --ascii          Use the ASCII character set for DUMP and Z80 output
+  -j, --json           List BASIC programs as JSON (lines, offsets and items)
+  -x, --xref           Cross-reference BASIC programs: line references, unreachable
+                       lines, variables and POKE/PEEK/USR addresses
+  -p, --pretty         List BASIC programs one statement per line, with FOR/NEXT
+                       loops and subroutines indented
+      --jumps          With --pretty, show where each jump goes
+  -d, --diff           Compare two files: BASIC line by line, machine code byte by
+                       byte at its load address
+  -t, --trailing VIEW  Show what an MZ BASIC file holds after the program:
+                       z80 or dump
+  -r, --recover        Skip damaged lines of MZ BASIC programs instead of
+                       stopping at the first one
+  -c, --convert TYPE   Convert instead of listing:
+                         wav  MZF, .P or .O file to a cassette recording
+                         mzf  Sharp cassette WAV, Quick Disk or floppy image to MZF
+                         p    ZX80/ZX81 cassette WAV or TZX to a .P/.O file
+                         tzx  ZX81 .P file to TZX
+                         renumber  BASIC program renumbered, in its own format
+      --rate HZ        Sample rate of generated recordings (default 44100)
+      --speed N        Sharp tape speed 1-3 (default 1)
+      --name NAME      ZX81 program name for generated recordings (default PROGRAM)
+      --start N        First line number when renumbering (default 10)
+      --step N         Gap between line numbers when renumbering (default 10)
+  -o, --output PATH    Output file, or directory when several inputs are given.
+                       Listings go to stdout and conversions next to the input by default.
+  -h, --help           Show this help
+";
+
+/// Exit status for each kind of failure.
+const EXIT_DECODE: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_IO: u8 = 3;
+
+struct Options {
+    mode: String,
+    machine: Option<MZFMachine>,
+    ascii: bool,
+    json: bool,
+    xref: bool,
+    pretty: bool,
+    jumps: bool,
+    diff: bool,
+    trailing: Option<String>,
+    recover: bool,
+    convert: Option<String>,
+    rate: u32,
+    speed: u8,
+    name: String,
+    start: u16,
+    step: u16,
+    output: Option<PathBuf>,
+    inputs: Vec<PathBuf>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        mode: "AUTO".to_string(),
+        machine: None,
+        ascii: false,
+        json: false,
+        xref: false,
+        pretty: false,
+        jumps: false,
+        diff: false,
+        trailing: None,
+        recover: false,
+        convert: None,
+        rate: 44100,
+        speed: 1,
+        name: String::new(),
+        start: 10,
+        step: 10,
+        output: None,
+        inputs: Vec::new(),
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = |name: &str| iter.next().cloned().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "-m" | "--mode" => options.mode = value(arg)?.to_uppercase(),
+            "--machine" => {
+                options.machine = match value(arg)?.to_lowercase().as_str() {
+                    "sharp" => Some(MZFMachine::Sharp),
+                    "sinclair" => Some(MZFMachine::Sinclair),
+                    "spectrum" => Some(MZFMachine::Spectrum),
+                    "msx" => Some(MZFMachine::MSX),
+                    other => return Err(format!("unknown machine '{}'", other)),
+                }
+            }
+            "-a" | "--ascii" => options.ascii = true,
+            "-j" | "--json" => options.json = true,
+            "-x" | "--xref" => options.xref = true,
+            "-p" | "--pretty" => options.pretty = true,
+            "--jumps" => options.jumps = true,
+            "-d" | "--diff" => options.diff = true,
+            "-t" | "--trailing" => {
+                let view = value(arg)?.to_uppercase();
+                if !["Z80", "DUMP"].contains(&view.as_str()) {
+                    return Err(format!("unknown trailing data view '{}'", view.to_lowercase()));
+                }
+                options.trailing = Some(view);
+            }
+            "-r" | "--recover" => options.recover = true,
+            "-c" | "--convert" => {
+                let kind = value(arg)?.to_lowercase();
+                if !["wav", "mzf", "p", "tzx", "renumber"].contains(&kind.as_str()) {
+                    return Err(format!("unknown conversion '{}'", kind));
+                }
+                options.convert = Some(kind);
+            }
+            "--rate" => options.rate = value(arg)?.parse().map_err(|_| "--rate needs a number".to_string())?,
+            "--speed" => options.speed = value(arg)?.parse().map_err(|_| "--speed needs a number".to_string())?,
+            "--name" => options.name = value(arg)?,
+            "--start" => options.start = value(arg)?.parse().map_err(|_| "--start needs a line number".to_string())?,
+            "--step" => options.step = value(arg)?.parse().map_err(|_| "--step needs a number".to_string())?,
+            "-o" | "--output" => options.output = Some(PathBuf::from(value(arg)?)),
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') && arg.len() > 1 => return Err(format!("unknown option '{}'", arg)),
+            _ => options.inputs.push(PathBuf::from(arg)),
+        }
+    }
+    if options.inputs.is_empty() {
+        return Err("no input files".to_string());
+    }
+    if options.diff && options.inputs.len() != 2 {
+        return Err("--diff needs exactly two files".to_string());
+    }
+    Ok(options)
+}
+
+/// Expands directories into the files beneath them, in name order.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if path.is_dir() {
+        let mut entries: Vec<PathBuf> = fs::read_dir(path)?.map(|e| e.map(|e| e.path())).collect::<Result<_, _>>()?;
+        entries.sort();
+        for entry in entries {
+            collect_files(&entry, files)?;
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+/// The machine a mode decodes files of; Sharp for the modes that are not for another machine.
+fn machine_for_mode(mode: &str) -> MZFMachine {
+    match mode {
+        "SPECTRUM" => MZFMachine::Spectrum,
+        "MSX" => MZFMachine::MSX,
+        mode if mode.starts_with("ZX") || mode == "TZX" => MZFMachine::Sinclair,
+        _ => MZFMachine::Sharp,
+    }
+}
+
+/// Collects every MZF file held in a Quick Disk or floppy image.
+fn all_entries(data: &[u8], entry: fn(&[u8], usize) -> Result<Vec<u8>, Error>) -> Result<Vec<u8>, Error> {
+    let mut output = entry(data, 0)?;
+    // Entries are numbered from zero, so the first index that fails is one past the last file
+    for mzf in (1..).map_while(|i| entry(data, i).ok()) {
+        output.extend(mzf);
+    }
+    Ok(output)
+}
+
+/// Runs a conversion, returning the new file and its extension.
+fn convert(data: &[u8], kind: &str, options: &Options, file: &Path) -> Result<(Vec<u8>, &'static str), String> {
+    let detected = detect_input_format(data);
+    let format = detected.split('\t').next().unwrap_or("DUMP");
+    if kind == "renumber" {
+        // AUTO only tells SA-5510 from SP-5025 by their tokens, so --mode chooses the others
+        let dialect = if options.mode == "AUTO" { format } else { options.mode.as_str() };
+        return renumber(data, dialect, options, file);
+    }
+    let output = match (kind, format) {
+        ("wav", "ZX81BASIC") => zx_program_to_wav(data, true, options.name.clone(), options.rate),
+        ("wav", "ZX80BASIC") => zx_program_to_wav(data, false, String::new(), options.rate),
+        ("wav", "SA" | "SP" | "1Z" | "Z80" | "DUMP") => mzf_to_wav(data, options.rate, options.speed),
+        ("mzf", "MZWAV") => mz_wav_to_mzf(data),
+        ("mzf", "QD") => all_entries(data, qd_entry_to_mzf),
+        ("mzf", "DISK") => all_entries(data, disk_entry_to_mzf),
+        ("p", "ZXWAV") => zx_wav_to_program(data),
+        ("p", "TZX") => tzx_to_p(data),
+        ("tzx", "ZX81BASIC") => Ok(p_to_tzx(data, options.name.clone())),
+        _ => return Err(format!("cannot convert this file to {} (detected as {})", kind, format)),
+    }
+    .map_err(|e| format!("conversion to {} failed: {}", kind, e))?;
+    let extension = match kind {
+        "wav" => "wav",
+        "tzx" => "tzx",
+        "mzf" => mzf_extension(&output),
+        _ if detect_input_format(&output).starts_with("ZX80BASIC") => "o",
+        _ => "p",
+    };
+    Ok((output, extension))
+}
+
+/// The extension for extracted MZF data: several files are written back to back as an
+/// MZT container.
+fn mzf_extension(mzf: &[u8]) -> &'static str {
+    let size = mzf.get(0x12..0x14).map_or(0, |size| u16::from_le_bytes([size[0], size[1]]) as usize);
+    if mzf.len() > 128 + size {
+        "mzt"
+    } else {
+        "mzf"
+    }
+}
+
+/// Renumbers a BASIC program, reporting the targets that could not be changed.
+fn renumber(data: &[u8], format: &str, options: &Options, file: &Path) -> Result<(Vec<u8>, &'static str), String> {
+    let (start, step) = (options.start, options.step);
+    let (result, extension) = match (format, MZBasicVersion::from_mode(format)) {
+        (_, Some(version)) => (mz::renumber_basic(data, version, start, step), "renumbered.mzf"),
+        ("ZX80BASIC", _) => (zx80::renumber_basic(data, start, step), "renumbered.o"),
+        ("ZX81BASIC", _) => (zx81::renumber_basic(data, start, step), "renumbered.p"),
+        _ => return Err(format!("cannot renumber this file (detected as {})", format)),
+    };
+    let renumbered = result.map_err(|e| format!("renumbering failed: {}", e))?;
+    for diagnostic in &renumbered.diagnostics {
+        eprintln!("mzfview: {}: {}", file.display(), diagnostic);
+    }
+    Ok((renumbered.data, extension))
+}
+
+/// Chooses where the result for `input` is written, or None for stdout.
+fn output_path(input: &Path, extension: &str, options: &Options, several: bool) -> Option<PathBuf> {
+    let name = Path::new(input.file_name().unwrap_or_default());
+    // Listings keep the original extension so t.mzf and t.p do not both become t.txt
+    let file_name = if options.convert.is_some() {
+        name.with_extension(extension)
+    } else {
+        PathBuf::from(format!("{}.{}", name.display(), extension))
+    };
+    match &options.output {
+        Some(out) if several || out.is_dir() => Some(out.join(file_name)),
+        Some(out) => Some(out.clone()),
+        None if options.convert.is_some() => Some(input.with_extension(extension)),
+        None => None,
+    }
+}
+
+/// Decodes a file for listing: as JSON, as a cross-reference, pretty-printed, as the data
+/// after an MZ BASIC program, or salvaging damaged MZ BASIC programs when asked to.
+fn list(data: &[u8], options: &Options) -> Result<String, Error> {
+    let mode = match options.mode.as_str() {
+        "AUTO" if options.recover || options.trailing.is_some() => detect_input_format(data).split('\t').next().unwrap_or("AUTO").to_string(),
+        mode => mode.to_string(),
+    };
+    if options.json {
+        return basic_program_json(data, mode);
+    }
+    if options.xref {
+        return analyse_basic_program(data, mode);
+    }
+    if let Some(view) = &options.trailing {
+        return mz_trailing_data(data, mode, view.clone());
+    }
+    if options.pretty {
+        return format_basic_program(data, mode, options.jumps);
+    }
+    if options.recover && MZBasicVersion::from_mode(&mode).is_some() {
+        return recover_mz_basic(data, mode);
+    }
+    let machine = options.machine.unwrap_or_else(|| machine_for_mode(&options.mode));
+    process_binary(data, options.mode.clone(), machine, options.ascii)
+}
+
+/// Why writing to stdout stopped: the reader went away, as when piped into `head`, or
+/// another error that gives `EXIT_IO`.
+enum StdoutError {
+    Closed,
+    Failed,
+}
+
+/// Writes `text` to stdout, reporting any error other than a closed pipe.
+fn write_stdout(text: &str) -> Result<(), StdoutError> {
+    let mut stdout = io::stdout().lock();
+    match write!(stdout, "{}", text).and_then(|_| stdout.flush()) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => Err(StdoutError::Closed),
+        Err(e) => {
+            eprintln!("mzfview: stdout: {}", e);
+            Err(StdoutError::Failed)
+        }
+    }
+}
+
+/// True if a listing reports that one of the files inside a container could not be decoded.
+fn has_decode_error(listing: &str) -> bool {
+    listing.lines().any(|line| line.starts_with("Error"))
+}
+
+/// Compares the two input files, writing the differences to stdout or the output file.
+fn diff(options: &Options) -> u8 {
+    let mut files = Vec::new();
+    for input in &options.inputs {
+        match fs::read(input) {
+            Ok(data) => files.push(data),
+            Err(e) => {
+                eprintln!("mzfview: {}: {}", input.display(), e);
+                return EXIT_IO;
+            }
+        }
+    }
+    let differences = match diff_program_files(&files[0], &files[1], &options.mode) {
+        Ok(differences) => differences,
+        Err(e) => {
+            eprintln!("mzfview: {} and {}: {}", options.inputs[0].display(), options.inputs[1].display(), e);
+            return EXIT_DECODE;
+        }
+    };
+    let text = format!("--- {}\n+++ {}\n{}", options.inputs[0].display(), options.inputs[1].display(), differences.to_text());
+    match &options.output {
+        Some(path) => {
+            if let Err(e) = fs::write(path, text) {
+                eprintln!("mzfview: {}: {}", path.display(), e);
+                return EXIT_IO;
+            }
+        }
+        None => {
+            if let Err(StdoutError::Failed) = write_stdout(&text) {
+                return EXIT_IO;
+            }
+        }
+    }
+    0
+}
+
+fn run(options: &Options) -> u8 {
+    if options.diff {
+        return diff(options);
+    }
+    let mut files = Vec::new();
+    for input in &options.inputs {
+        if let Err(e) = collect_files(input, &mut files) {
+            eprintln!("mzfview: {}: {}", input.display(), e);
+            return EXIT_IO;
+        }
+    }
+    let several = files.len() > 1;
+    let mut status = 0;
+
+    for file in &files {
+        let data = match fs::read(file) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("mzfview: {}: {}", file.display(), e);
+                status = status.max(EXIT_IO);
+                continue;
+            }
+        };
+
+        let (bytes, extension) = match &options.convert {
+            Some(kind) => match convert(&data, kind, options, file) {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("mzfview: {}: {}", file.display(), e);
+                    status = status.max(EXIT_DECODE);
+                    continue;
+                }
+            },
+            None => {
+                let listing = match list(&data, options) {
+                    Ok(listing) => listing,
+                    Err(e) => {
+                        eprintln!("mzfview: {}: {}", file.display(), e);
+                        status = status.max(EXIT_DECODE);
+                        continue;
+                    }
+                };
+                if has_decode_error(&listing) {
+                    eprintln!("mzfview: {}: could not be decoded completely", file.display());
+                    status = status.max(EXIT_DECODE);
+                }
+                (listing.into_bytes(), match (options.json, options.xref) {
+                    (true, _) => "json",
+                    (_, true) => "xref",
+                    _ => "txt",
+                })
+            }
+        };
+
+        match output_path(file, extension, options, several) {
+            Some(path) if path == *file => {
+                eprintln!("mzfview: {}: output would overwrite the input", file.display());
+                status = status.max(EXIT_USAGE);
+            }
+            Some(path) => {
+                if let Err(e) = fs::write(&path, &bytes) {
+                    eprintln!("mzfview: {}: {}", path.display(), e);
+                    status = status.max(EXIT_IO);
+                }
+            }
+            None => {
+                let mut text = String::new();
+                if several {
+                    text.push_str(&format!("==> {} <==\n", file.display()));
+                }
+                text.push_str(&format!("{}\n", String::from_utf8_lossy(&bytes)));
+                match write_stdout(&text) {
+                    Ok(()) => {}
+                    Err(StdoutError::Closed) => return status,
+                    Err(StdoutError::Failed) => return status.max(EXIT_IO),
+                }
+            }
+        }
+    }
+    status
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match parse_args(&args) {
+        Ok(options) => ExitCode::from(run(&options)),
+        Err(message) if message.is_empty() => {
+            match write_stdout(USAGE) {
+                Err(StdoutError::Failed) => ExitCode::from(EXIT_IO),
+                _ => ExitCode::SUCCESS,
+            }
+        }
+        Err(message) => {
+            eprintln!("mzfview: {}\n\n{}", message, USAGE);
+            ExitCode::from(EXIT_USAGE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Options, String> {
+        parse_args(&line.split_whitespace().map(String::from).collect::<Vec<_>>())
+    }
+
+    /// A directory of its own under the system temporary directory.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mzfview-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn defaults() {
+        let options = args("GAME.mzf").unwrap();
+        assert_eq!(options.mode, "AUTO");
+        assert!(options.machine.is_none() && options.convert.is_none() && options.output.is_none());
+        assert_eq!((options.rate, options.speed, options.start, options.step), (44100, 1, 10, 10));
+        assert_eq!(options.inputs, [PathBuf::from("GAME.mzf")]);
+    }
+
+    #[test]
+    fn options_take_their_values() {
+        let options =
+            args("-m sa --machine Sinclair -c WAV --rate 22050 --speed 2 --name TAPE --start 100 --step 5 -o out a b")
+                .unwrap();
+        assert_eq!(options.mode, "SA");
+        assert!(matches!(options.machine, Some(MZFMachine::Sinclair)));
+        assert_eq!(options.convert.as_deref(), Some("wav"));
+        assert_eq!((options.rate, options.speed, options.start, options.step), (22050, 2, 100, 5));
+        assert_eq!(options.name, "TAPE");
+        assert_eq!(options.output, Some(PathBuf::from("out")));
+        assert_eq!(options.inputs.len(), 2);
+        let options = args("-a -j -x -p --jumps -r -t DUMP a").unwrap();
+        assert!(options.ascii && options.json && options.xref && options.pretty && options.jumps && options.recover);
+        assert_eq!(options.trailing.as_deref(), Some("DUMP"));
+    }
+
+    #[test]
+    fn bad_command_lines_are_refused() {
+        assert_eq!(args("-h").err().as_deref(), Some(""));
+        assert_eq!(args("").err().as_deref(), Some("no input files"));
+        assert_eq!(args("-q a").err().as_deref(), Some("unknown option '-q'"));
+        assert_eq!(args("a -m").err().as_deref(), Some("-m needs a value"));
+        assert_eq!(args("--rate fast a").err().as_deref(), Some("--rate needs a number"));
+        assert_eq!(args("--machine c64 a").err().as_deref(), Some("unknown machine 'c64'"));
+        assert_eq!(args("-c gif a").err().as_deref(), Some("unknown conversion 'gif'"));
+        assert_eq!(args("-t basic a").err().as_deref(), Some("unknown trailing data view 'basic'"));
+        assert_eq!(args("-d a").err().as_deref(), Some("--diff needs exactly two files"));
+        // A lone dash is a file name
+        assert_eq!(args("-").unwrap().inputs, [PathBuf::from("-")]);
+    }
+
+    #[test]
+    fn listings_go_to_stdout_or_keep_their_extension() {
+        let input = Path::new("dir/t.mzf");
+        assert_eq!(output_path(input, "txt", &args("x").unwrap(), false), None);
+        assert_eq!(output_path(input, "txt", &args("-o list.txt x").unwrap(), false), Some(PathBuf::from("list.txt")));
+        assert_eq!(output_path(input, "json", &args("-o out x").unwrap(), true), Some(PathBuf::from("out/t.mzf.json")));
+    }
+
+    #[test]
+    fn conversions_go_next_to_the_input() {
+        let input = Path::new("dir/t.p");
+        assert_eq!(output_path(input, "wav", &args("-c wav x").unwrap(), false), Some(PathBuf::from("dir/t.wav")));
+        assert_eq!(output_path(input, "tzx", &args("-c tzx -o out x").unwrap(), true), Some(PathBuf::from("out/t.tzx")));
+        let dir = scratch("output");
+        let options = parse_args(&["-c".into(), "wav".into(), "-o".into(), dir.display().to_string(), "x".into()]).unwrap();
+        assert_eq!(output_path(input, "wav", &options, false), Some(dir.join("t.wav")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn several_extracted_files_are_an_mzt() {
+        let mut mzf = vec![0u8; 128];
+        mzf[0x12] = 2;
+        mzf.extend([1, 2]);
+        assert_eq!(mzf_extension(&mzf), "mzf");
+        mzf.extend(mzf.clone());
+        assert_eq!(mzf_extension(&mzf), "mzt");
+    }
+
+    #[test]
+    fn directories_are_expanded_in_name_order() {
+        let dir = scratch("collect");
+        fs::create_dir(dir.join("sub")).unwrap();
+        for name in ["b.mzf", "a.p", "sub/c.o"] {
+            fs::write(dir.join(name), [0]).unwrap();
+        }
+        let mut files = Vec::new();
+        collect_files(&dir, &mut files).unwrap();
+        assert_eq!(files, [dir.join("a.p"), dir.join("b.mzf"), dir.join("sub/c.o")]);
+        // A missing path is kept, for reading it to report
+        assert!(collect_files(&dir.join("missing"), &mut files).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn modes_choose_the_machine() {
+        assert!(matches!(machine_for_mode("ZX81BASIC"), MZFMachine::Sinclair));
+        assert!(matches!(machine_for_mode("TZX"), MZFMachine::Sinclair));
+        assert!(matches!(machine_for_mode("SPECTRUM"), MZFMachine::Spectrum));
+        assert!(matches!(machine_for_mode("MSX"), MZFMachine::MSX));
+        assert!(matches!(machine_for_mode("AUTO"), MZFMachine::Sharp));
+    }
+}
@@ -0,0 +1,131 @@
+// src/error.rs
+//
+// The error type shared by every decoder, encoder and container reader.
+
+use std::fmt;
+
+/// Why a file could not be decoded, converted or read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The data is shorter than the fixed header of its format.
+    TruncatedHeader { format: &'static str, expected: usize, found: usize },
+    /// The data ended in the middle of a program line or record.
+    UnexpectedEnd { offset: usize, line: Option<u16> },
+    /// A line's length or link field points outside the program.
+    BadLineLink { offset: usize, line: u16, link: usize },
+    /// A byte in token position that the selected BASIC does not define.
+    UnknownToken { offset: usize, line: u16, token: u8 },
+    /// A system variable or directory field points outside the file.
+    PointerOutOfRange { name: &'static str, offset: usize, value: usize },
+    /// The data is not in the expected format.
+    InvalidFormat(&'static str),
+    /// The file is valid but holds nothing that could be decoded.
+    NothingFound(&'static str),
+    /// A mode, index or option passed by the caller is not valid.
+    InvalidArgument(String),
+}
+
+impl Error {
+    /// Records the line being decoded when the data ran out.
+    pub(crate) fn in_line(self, number: u16) -> Self {
+        match self {
+            Error::UnexpectedEnd { offset, line: None } => Error::UnexpectedEnd { offset, line: Some(number) },
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::TruncatedHeader { format, expected, found } => {
+                write!(f, "{} header needs {} bytes but only {} are present", format, expected, found)
+            }
+            Error::UnexpectedEnd { offset, line: Some(line) } => {
+                write!(f, "Unexpected end of data at offset {:04X} in line {}", offset, line)
+            }
+            Error::UnexpectedEnd { offset, line: None } => write!(f, "Unexpected end of data at offset {:04X}", offset),
+            Error::BadLineLink { offset, line, link } => {
+                write!(f, "Line {} at offset {:04X} has an invalid length or link ({:04X})", line, offset, link)
+            }
+            Error::UnknownToken { offset, line, token } => {
+                write!(f, "Unknown token {:02X} at offset {:04X} in line {}", token, offset, line)
+            }
+            Error::PointerOutOfRange { name, offset, value } => {
+                write!(f, "{} (offset {:04X}) holds {:04X}, which is outside the file", name, offset, value)
+            }
+            Error::InvalidFormat(message) | Error::NothingFound(message) => f.write_str(message),
+            Error::InvalidArgument(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Errors are thrown to JavaScript as `Error` objects.
+#[cfg(feature = "wasm")]
+impl From<Error> for wasm_bindgen::JsValue {
+    fn from(error: Error) -> Self {
+        wasm_bindgen::JsError::new(&error.to_string()).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mz_decoder::{decode_mz_listing, MZBasicVersion};
+    use crate::test_fixtures::mzf;
+
+    #[test]
+    fn every_variant_is_described() {
+        let cases = [
+            (
+                Error::TruncatedHeader { format: "MZF", expected: 128, found: 12 },
+                "MZF header needs 128 bytes but only 12 are present",
+            ),
+            (Error::UnexpectedEnd { offset: 0x9A, line: Some(20) }, "Unexpected end of data at offset 009A in line 20"),
+            (Error::UnexpectedEnd { offset: 0x9A, line: None }, "Unexpected end of data at offset 009A"),
+            (
+                Error::BadLineLink { offset: 0x80, line: 10, link: 3 },
+                "Line 10 at offset 0080 has an invalid length or link (0003)",
+            ),
+            (Error::UnknownToken { offset: 0x85, line: 10, token: 0x7F }, "Unknown token 7F at offset 0085 in line 10"),
+            (
+                Error::PointerOutOfRange { name: "VARS", offset: 8, value: 0x40 },
+                "VARS (offset 0008) holds 0040, which is outside the file",
+            ),
+            (Error::InvalidFormat("Not a TZX file."), "Not a TZX file."),
+            (Error::NothingFound("No files found."), "No files found."),
+            (Error::InvalidArgument("Invalid mode 'XX'".to_string()), "Invalid mode 'XX'"),
+        ];
+        for (error, text) in cases {
+            assert_eq!(error.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn in_line_only_fills_a_missing_line() {
+        let end = Error::UnexpectedEnd { offset: 5, line: None };
+        assert_eq!(end.in_line(30), Error::UnexpectedEnd { offset: 5, line: Some(30) });
+        let end = Error::UnexpectedEnd { offset: 5, line: Some(20) };
+        assert_eq!(end.clone().in_line(30), end);
+        let other = Error::NothingFound("Nothing.");
+        assert_eq!(other.clone().in_line(30), other);
+    }
+
+    #[test]
+    fn decode_failures_give_their_offset_and_line() {
+        // A length of 3 cannot hold the length, the line number and the terminator
+        let short = mzf(0x02, "BASIC", 0x4F88, 0, &[0x03, 0x00, 0x0A, 0x00, 0x00]);
+        assert_eq!(
+            decode_mz_listing(&short, MZBasicVersion::SA5510),
+            Err(Error::BadLineLink { offset: 0x80, line: 10, link: 3 })
+        );
+        // Line 10 claims 32 bytes, but the file ends after PRINT
+        let cut = mzf(0x02, "BASIC", 0x4F88, 0, &[0x20, 0x00, 0x0A, 0x00, 0x80, 0x88]);
+        assert_eq!(
+            decode_mz_listing(&cut, MZBasicVersion::SA5510),
+            Err(Error::UnexpectedEnd { offset: 0x86, line: Some(10) })
+        );
+    }
+}
@@ -0,0 +1,363 @@
+// src/format_detect.rs
+//
+// Identifies the kind of file a byte array holds, so the viewer can pick the
+// decoder itself instead of trusting the page and mode the user chose.
+// Each format has a check returning a confidence (0.0 to 1.0) and the reason
+// it was given; the most confident check wins.
+
+use crate::mzf_file::{self, MZFHeader, MZF_HEADER_SIZE};
+use crate::{msx_decoder, qd_image, sharp_disk, spectrum_tape, wav_audio, zx81_decoder};
+
+/// The container or program formats the viewer understands.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    MZF,         // Single Sharp MZ tape file
+    MZT,         // Several MZF records back to back
+    ZX81P,       // ZX81 .P / .81 image
+    ZX81P81,     // ZX81 .P81 image (program name first)
+    ZX80O,       // ZX80 .O / .80 image
+    TZX,         // TZX tape image
+    SpectrumTAP, // ZX Spectrum .tap tape image
+    MSXBasic,    // MSX tokenised BASIC program
+    MSXBinary,   // MSX BSAVE machine code file
+    MZWAV,       // Sharp MZ cassette recording
+    ZXWAV,       // ZX80/ZX81 cassette recording
+    QuickDisk,   // MZ-700/MZ-1500 Quick Disk image
+    FloppyDisk,  // D88 or raw sector floppy image
+    Unknown,
+}
+
+impl InputFormat {
+    /// A short human readable name for the format.
+    pub fn description(&self) -> &'static str {
+        match self {
+            InputFormat::MZF => "Sharp MZF file",
+            InputFormat::MZT => "Sharp MZT multi-file tape",
+            InputFormat::ZX81P => "ZX81 .P file",
+            InputFormat::ZX81P81 => "ZX81 .P81 file",
+            InputFormat::ZX80O => "ZX80 .O file",
+            InputFormat::TZX => "TZX tape image",
+            InputFormat::SpectrumTAP => "ZX Spectrum .tap file",
+            InputFormat::MSXBasic => "MSX BASIC file",
+            InputFormat::MSXBinary => "MSX BSAVE file",
+            InputFormat::MZWAV => "Sharp MZ cassette WAV",
+            InputFormat::ZXWAV => "ZX80/ZX81 cassette WAV",
+            InputFormat::QuickDisk => "Quick Disk image",
+            InputFormat::FloppyDisk => "Floppy disk image",
+            InputFormat::Unknown => "Unknown",
+        }
+    }
+}
+
+/// The outcome of checking a file against one format.
+#[derive(Debug, Clone)]
+pub struct FormatGuess {
+    pub format: InputFormat,
+    /// How sure the check is, from 0.0 (no match) to 1.0 (certain).
+    pub confidence: f32,
+    /// Why the check reached its verdict.
+    pub reason: String,
+}
+
+impl FormatGuess {
+    fn new(format: InputFormat, confidence: f32, reason: impl Into<String>) -> Option<Self> {
+        Some(Self { format, confidence, reason: reason.into() })
+    }
+}
+
+/// A check for one format.
+type FormatCheck = fn(&[u8]) -> Option<FormatGuess>;
+
+fn u16_at(data: &[u8], pos: usize) -> usize {
+    u16::from_le_bytes([data[pos], data[pos + 1]]) as usize
+}
+
+/// Checks the header fields every Sharp tape file shares.
+fn plausible_mzf_header(header: &MZFHeader) -> bool {
+    (0x01..=0x05).contains(&header.file_type) && header.name.contains(&0x0D)
+}
+
+fn check_mzf(data: &[u8]) -> Option<FormatGuess> {
+    let header = MZFHeader::parse(data).ok()?;
+    if !plausible_mzf_header(&header) {
+        return None;
+    }
+    let expected = MZF_HEADER_SIZE + header.size as usize;
+    if data.len() == expected {
+        return FormatGuess::new(InputFormat::MZF, 0.95, format!("valid header, body of {} bytes matches the size field", header.size));
+    }
+
+    let entries = mzf_file::parse_mzt(data).ok()?;
+    let valid = entries
+        .iter()
+        .filter(|e| !e.truncated && e.diagnostics.is_empty() && plausible_mzf_header(&e.header))
+        .count();
+    if entries.len() > 1 && valid == entries.len() {
+        return FormatGuess::new(InputFormat::MZT, 0.95, format!("{} consecutive MZF records fill the file exactly", valid));
+    }
+    if data.len() > expected {
+        FormatGuess::new(InputFormat::MZF, 0.6, format!("valid header followed by {} extra bytes", data.len() - expected))
+    } else {
+        FormatGuess::new(InputFormat::MZF, 0.5, format!("valid header but the body is {} bytes short", expected - data.len()))
+    }
+}
+
+fn check_zx81(data: &[u8]) -> Option<FormatGuess> {
+    let (name, program) = zx81_decoder::split_zx81_name(data);
+    if !zx81_decoder::plausible_p_image(program) {
+        return None;
+    }
+    let format = if name.is_some() { InputFormat::ZX81P81 } else { InputFormat::ZX81P };
+    let expected = u16_at(program, 11) - 0x4009;
+    let named = name.map(|n| format!("program \"{}\", ", n)).unwrap_or_default();
+    if program.len() == expected {
+        FormatGuess::new(format, 0.9, format!("{}system variables are consistent and E_LINE matches the length", named))
+    } else {
+        FormatGuess::new(format, 0.6, format!("{}system variables are consistent but E_LINE gives {} bytes, not {}", named, expected, program.len()))
+    }
+}
+
+fn check_zx80(data: &[u8]) -> Option<FormatGuess> {
+    if data.len() < 40 {
+        return None;
+    }
+    // VARS, E_LINE and D_FILE follow each other in memory after the 40 bytes of system variables
+    let (vars, e_line, d_file) = (u16_at(data, 8), u16_at(data, 10), u16_at(data, 12));
+    if vars < 0x4028 || e_line <= vars || d_file < e_line || d_file >= 0x8000 {
+        return None;
+    }
+    if data.len() == e_line - 0x4000 {
+        FormatGuess::new(InputFormat::ZX80O, 0.85, "system variables are consistent and E_LINE matches the length")
+    } else {
+        FormatGuess::new(InputFormat::ZX80O, 0.4, format!("system variables are consistent but E_LINE gives {} bytes, not {}", e_line - 0x4000, data.len()))
+    }
+}
+
+fn check_tzx(data: &[u8]) -> Option<FormatGuess> {
+    if !data.starts_with(b"ZXTape!\x1A") {
+        return None;
+    }
+    FormatGuess::new(InputFormat::TZX, 1.0, "ZXTape! signature")
+}
+
+fn check_spectrum_tap(data: &[u8]) -> Option<FormatGuess> {
+    if !spectrum_tape::plausible_tap(data) {
+        return None;
+    }
+    let files = spectrum_tape::parse_spectrum_tape(data).ok()?;
+    let bad = files.iter().filter(|f| !f.checksum_ok).count();
+    if bad == 0 {
+        FormatGuess::new(InputFormat::SpectrumTAP, 0.9, format!("{} blocks fill the file exactly, all checksums correct", files.len()))
+    } else {
+        FormatGuess::new(InputFormat::SpectrumTAP, 0.6, format!("blocks fill the file exactly, but {} of {} checksums are wrong", bad, files.len()))
+    }
+}
+
+fn check_msx(data: &[u8]) -> Option<FormatGuess> {
+    match *data.first()? {
+        msx_decoder::MSX_BASIC_MARKER => {
+            let listing = msx_decoder::decode_msx_listing(data).ok()?;
+            if listing.diagnostics.is_empty() {
+                FormatGuess::new(InputFormat::MSXBasic, 0.8, format!("0xFF marker and {} linked lines", listing.lines.len()))
+            } else {
+                FormatGuess::new(InputFormat::MSXBasic, 0.4, format!("0xFF marker, but the line links break after {} lines", listing.lines.len()))
+            }
+        }
+        msx_decoder::MSX_BINARY_MARKER => {
+            let binary = msx_decoder::parse_bload(data).ok()?;
+            let length = binary.end.checked_sub(binary.start)? as usize + 1;
+            if data.len() == 7 + length {
+                FormatGuess::new(InputFormat::MSXBinary, 0.8, "0xFE marker, start and end addresses match the length")
+            } else {
+                FormatGuess::new(InputFormat::MSXBinary, 0.3, format!("0xFE marker, but the addresses give {} bytes, not {}", length, data.len() - 7))
+            }
+        }
+        _ => None,
+    }
+}
+
+fn check_wav(data: &[u8]) -> Option<FormatGuess> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return None;
+    }
+    let audio = match wav_audio::read_wav(data) {
+        Ok(audio) => audio,
+        Err(e) => return FormatGuess::new(InputFormat::MZWAV, 0.3, format!("RIFF/WAVE signature, but {}", e)),
+    };
+
+    // Sharp pulses come in two lengths about 2:1 apart. Sinclair bursts are
+    // separated by silences about five times the pulse period.
+    let periods = audio.rising_edge_periods();
+    let Some(threshold) = wav_audio::pulse_threshold(&periods) else {
+        return FormatGuess::new(InputFormat::MZWAV, 0.3, "RIFF/WAVE signature, but no pulses found");
+    };
+    let mean = |short: bool| {
+        let group: Vec<f64> = periods.iter().copied().filter(|&p| (p < threshold) == short).collect();
+        group.iter().sum::<f64>() / group.len().max(1) as f64
+    };
+    let ratio = mean(false) / mean(true);
+    if ratio > 3.0 {
+        FormatGuess::new(InputFormat::ZXWAV, 0.8, format!("RIFF/WAVE signature, pulse bursts separated by gaps {:.1}x the pulse period", ratio))
+    } else {
+        FormatGuess::new(InputFormat::MZWAV, 0.8, format!("RIFF/WAVE signature, long pulses {:.1}x the short ones", ratio))
+    }
+}
+
+fn check_quick_disk(data: &[u8]) -> Option<FormatGuess> {
+    let files = qd_image::parse_qd_image(data).ok()?;
+    if data.starts_with(b"-QD format-") {
+        FormatGuess::new(InputFormat::QuickDisk, 1.0, format!("-QD format- signature, {} files", files.len()))
+    } else {
+        FormatGuess::new(InputFormat::QuickDisk, 0.5, format!("{} files framed by SYNC/0xA5 marks", files.len()))
+    }
+}
+
+fn check_floppy_disk(data: &[u8]) -> Option<FormatGuess> {
+    let files = sharp_disk::parse_sharp_disk(data).ok()?;
+    let d88 = data.len() >= 0x20 && u32::from_le_bytes([data[0x1C], data[0x1D], data[0x1E], data[0x1F]]) as usize == data.len();
+    if d88 {
+        FormatGuess::new(InputFormat::FloppyDisk, 0.9, format!("D88 header matches the file size, {} files in the directory", files.len()))
+    } else {
+        FormatGuess::new(InputFormat::FloppyDisk, 0.7, format!("raw sector image, {} files in the directory", files.len()))
+    }
+}
+
+/// Checks the data against every known format.
+///
+/// # Arguments
+///
+/// * `data` - The file contents
+///
+/// # Returns
+///
+/// Every format the data could be, most likely first. Empty if nothing matched.
+pub fn detect_formats(data: &[u8]) -> Vec<FormatGuess> {
+    let checks: [FormatCheck; 9] = [
+        check_tzx,
+        check_spectrum_tap,
+        check_msx,
+        check_wav,
+        check_mzf,
+        check_zx81,
+        check_zx80,
+        check_quick_disk,
+        check_floppy_disk,
+    ];
+    let mut guesses: Vec<FormatGuess> = checks.iter().filter_map(|check| check(data)).collect();
+    guesses.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    guesses
+}
+
+/// Picks the most likely format of the data.
+pub fn detect_format(data: &[u8]) -> FormatGuess {
+    detect_formats(data)
+        .into_iter()
+        .next()
+        .unwrap_or(FormatGuess { format: InputFormat::Unknown, confidence: 0.0, reason: "no known signature or structure".to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mz_tape::{encode_mz_wav, MZTapeOptions};
+    use crate::test_fixtures::{d88, disk_entry, mzf, qd_block, qd_header, spectrum_tap, zx80_o, zx81_p};
+    use crate::zx_tape::{encode_zx_wav, ZXTapeMachine};
+
+    fn detected(data: &[u8]) -> InputFormat {
+        detect_format(data).format
+    }
+
+    #[test]
+    fn sharp_tape_files_are_recognised() {
+        let program = mzf(0x01, "PROGRAM", 0x1200, 0x1200, &[0xC9]);
+        assert_eq!(detected(&program), InputFormat::MZF);
+        assert_eq!(detect_format(&program).confidence, 0.95);
+
+        let mut tape = program.clone();
+        tape.extend(mzf(0x02, "BASIC", 0x4F88, 0, &[0, 0]));
+        assert_eq!(detected(&tape), InputFormat::MZT);
+
+        let mut longer = program.clone();
+        longer.push(0);
+        assert_eq!(detected(&longer), InputFormat::MZF);
+        assert!(detect_format(&longer).reason.contains("1 extra bytes"));
+    }
+
+    #[test]
+    fn sinclair_programs_are_recognised() {
+        let p = zx81_p(&[(10, "PRINT 1")]);
+        assert_eq!(detected(&p), InputFormat::ZX81P);
+
+        // A .P81 file starts with the name, its last character inverted
+        let mut p81 = vec![0x26, 0x27 | 0x80];
+        p81.extend(&p);
+        assert_eq!(detected(&p81), InputFormat::ZX81P81);
+        assert!(detect_format(&p81).reason.starts_with("program \"AB\""));
+
+        assert_eq!(detected(&zx80_o()), InputFormat::ZX80O);
+    }
+
+    #[test]
+    fn tape_images_are_recognised() {
+        let mut tzx = b"ZXTape!\x1A\x01\x14".to_vec();
+        tzx.extend([0x30, 3]);
+        tzx.extend(b"ABC");
+        assert_eq!(detected(&tzx), InputFormat::TZX);
+        assert_eq!(detect_format(&tzx).confidence, 1.0);
+
+        let tap = spectrum_tap("HELLO", &[0x00, 0x0A, 0x02, 0x00, 0xEF, 0x0D], 10);
+        assert_eq!(detected(&tap), InputFormat::SpectrumTAP);
+        let mut damaged = tap.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        let guess = detect_format(&damaged);
+        assert!(guess.reason.contains("1 of 1 checksums are wrong"), "{:?}", guess);
+    }
+
+    #[test]
+    fn msx_files_are_recognised() {
+        // 10 END, linked from 0x8001, then the zero link ending the program
+        let basic = [0xFF, 0x07, 0x80, 0x0A, 0x00, 0x81, 0x00, 0x00, 0x00];
+        assert_eq!(detected(&basic), InputFormat::MSXBasic);
+
+        let binary = [0xFE, 0x00, 0xC0, 0x00, 0xC0, 0x00, 0xC0, 0xC9];
+        assert_eq!(detected(&binary), InputFormat::MSXBinary);
+    }
+
+    #[test]
+    fn recordings_are_told_apart() {
+        let options = MZTapeOptions { sample_rate: 22050, long_gap: 2000, short_gap: 1000, ..Default::default() };
+        let mz = encode_mz_wav(&mzf(0x01, "PROGRAM", 0x1200, 0x1200, &[0xC9]), options).unwrap();
+        assert_eq!(detected(&mz), InputFormat::MZWAV);
+
+        let zx = encode_zx_wav(&zx81_p(&[(10, "PRINT 1")]), ZXTapeMachine::ZX81, "TAPE", 22050).unwrap();
+        assert_eq!(detected(&zx), InputFormat::ZXWAV);
+
+        assert!(detect_format(&zx[..12]).reason.starts_with("RIFF/WAVE signature, but"));
+    }
+
+    #[test]
+    fn disk_images_are_recognised() {
+        let mut qd = b"-QD format-".to_vec();
+        qd.resize(16, 0);
+        qd.extend(qd_block(0, &qd_header("FIRST", false, 1, 0x1200, 0x1200)));
+        qd.extend(qd_block(1, &[0xC9]));
+        assert_eq!(detected(&qd), InputFormat::QuickDisk);
+        assert_eq!(detect_format(&qd).confidence, 1.0);
+
+        let mut sectors = vec![[0u8; 256]; 32];
+        sectors[16][..32].copy_from_slice(&disk_entry(0x01, "PROGRAM", 1, 0x1200, 30));
+        sectors[30][0] = 0xC9;
+        let floppy = d88(&sectors);
+        assert_eq!(detected(&floppy), InputFormat::FloppyDisk);
+        assert!(detect_format(&floppy).reason.starts_with("D88 header"));
+        assert!(detect_format(&sectors.concat()).reason.starts_with("raw sector image"));
+    }
+
+    #[test]
+    fn unknown_data_is_unknown() {
+        assert_eq!(detected(&[]), InputFormat::Unknown);
+        assert_eq!(detected(b"plain text"), InputFormat::Unknown);
+        assert!(detect_formats(b"plain text").is_empty());
+    }
+}
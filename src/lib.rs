@@ -1,18 +1,60 @@
 // src/lib.rs
 
+//! Decoders for Sharp MZ, Sinclair ZX80/ZX81, ZX Spectrum and MSX files.
+//!
+//! The [`mz`], [`zx80`], [`zx81`], [`spectrum`], [`msx`] and [`z80`] modules return typed results for use
+//! from Rust. The string based functions at the top level (`process_binary` and
+//! friends) produce the text shown by the web viewer; with the `wasm` feature
+//! (on by default) they are also exported to JavaScript.
+
+pub mod basic;
+mod basic_analysis;
+mod basic_format;
+mod basic_number;
+mod basic_renumber;
+pub mod mz;
+pub mod zx80;
+pub mod zx81;
+pub mod spectrum;
+pub mod msx;
+pub mod z80;
+
 mod z80_disasm;
 use z80_disasm::Z80Disassembler;
 mod zx80_decoder;
 mod zx81_decoder;
+mod zx81_interpreter;
+mod spectrum_decoder;
+mod spectrum_tape;
+mod msx_decoder;
 mod mz_decoder;
+mod mz_number;
+mod mz_trailing;
+mod mzf_file;
+mod mz_tape;
+mod wav_audio;
+mod zx_tape;
+mod tzx_file;
+mod qd_image;
+mod sharp_disk;
+mod format_detect;
+mod program_diff;
+mod error;
+#[cfg(test)]
+mod test_fixtures;
 
+pub use error::Error;
+pub use format_detect::{detect_format, detect_formats, FormatGuess, InputFormat};
+pub use program_diff::{ByteChange, LineChange, ProgramDiff};
 use mz_decoder::MZBasicVersion;
 
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 use std::collections::HashMap;
 
 
 /// Enum representing different processing modes.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq, Copy)]
 enum MZFEncoding {
     SA5510,
@@ -22,12 +64,25 @@ enum MZFEncoding {
     DUMP,    // Hexadecimal output
     ZX80BASIC, // Sinclair ZX80 Basic
     ZX81BASIC, // Sinclair ZX81 Basic
+    ZX81RUN,   // Run a ZX81 program and show the final screen
+    MZT,       // Multi-file MZT tape container
+    MZWAV,     // Sharp MZ cassette recording
+    ZXWAV,     // Sinclair ZX80/ZX81 cassette recording
+    TZX,       // TZX tape image holding ZX81 programs
+    SPECTRUM,  // ZX Spectrum .tap or TZX tape image
+    MSX,       // MSX tokenised BASIC or BSAVE file
+    QD,        // MZ-700/MZ-1500 Quick Disk image
+    DISK,      // MZ-80B/MZ-2000/MZ-800 floppy disk image
+    AUTO,      // Detect the format and pick one of the above
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MZFMachine {
     Sharp,
     Sinclair,
+    Spectrum,
+    MSX,
 }
 
 /// Struct for handling non-BASIC operations (DUMP mode and Sharp ASCII mapping)
@@ -61,17 +116,29 @@ impl MZLowerCase
 /// # Arguments
 /// * `data` - A slice of unsigned 8-bit integers (bytes) representing the binary file content.
 /// * `mode` - A string indicating the desired BASIC version for detokenization:
-///            "SA" for SA-5510, "SP" for SP-5025, "1Z" for 1Z-013B, 
-///            "Z80" for Z80 disassembly, "DUMP" for hexadecimal output,
-///            "ZX80BASIC" for ZX80 Basic, "ZX81BASIC" for ZX81 Basic.
+///   "SA" for SA-5510, "SP" for SP-5025, "1Z" for 1Z-013B, 
+///   "Z80" for Z80 disassembly, "DUMP" for hexadecimal output,
+///   "ZX80BASIC" for ZX80 Basic, "ZX81BASIC" for ZX81 Basic,
+///   "ZX81RUN" to run a ZX81 program and show its final screen,
+///   "MZT" to list and decode every file in an MZT container,
+///   "MZWAV" to read a Sharp cassette recording (WAV) and decode each file on it,
+///   "ZXWAV" to read a ZX80/ZX81 cassette recording (WAV) and decode each program on it,
+///   "TZX" to list a TZX tape image and decode the ZX81 programs in it,
+///   "SPECTRUM" to list a ZX Spectrum .tap or TZX image, decoding its BASIC programs and
+///   disassembling its code,
+///   "MSX" to decode a tokenised MSX BASIC file or disassemble an MSX BSAVE file,
+///   "QD" to list and decode every file on a Quick Disk image,
+///   "DISK" to list and decode every file on a D88 or raw floppy disk image,
+///   "AUTO" to detect the file format and use the matching mode.
 /// * `machine` : type of machine to process binary
 /// * `charset_flag` - A boolean indicating whether to use the ASCII character set for detokenization.
 ///
 /// # Returns
-/// A `String` containing the detokenized BASIC listing or an error message.
+/// A `Result` containing the detokenized BASIC listing, or an `Error` (thrown as a JavaScript
+/// `Error` over wasm). Files inside containers that cannot be decoded are reported inline.
 /// This needs to be safe HTML as it will be interpreted by browser for INV and Special characters
-#[wasm_bindgen]
-pub fn process_binary(data: &[u8], mode: String, machine: MZFMachine, charset_flag: bool) -> String {
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn process_binary(data: &[u8], mode: String, machine: MZFMachine, charset_flag: bool) -> Result<String, Error> {
     // Determine the processing mode based on the selected mode.
     let version = match mode.as_str() {
         "SA" => MZFEncoding::SA5510,
@@ -81,17 +148,57 @@ pub fn process_binary(data: &[u8], mode: String, machine: MZFMachine, charset_fl
         "DUMP" => MZFEncoding::DUMP,
         "ZX80BASIC" => MZFEncoding::ZX80BASIC,
         "ZX81BASIC" => MZFEncoding::ZX81BASIC,
-        _ => return "Error: Invalid mode specified. Expected (SA, SP, 1Z, Z80, DUMP, ZX80BASIC, ZX81BASIC)".to_string(),
+        "ZX81RUN" => MZFEncoding::ZX81RUN,
+        "MZT" => MZFEncoding::MZT,
+        "MZWAV" => MZFEncoding::MZWAV,
+        "ZXWAV" => MZFEncoding::ZXWAV,
+        "TZX" => MZFEncoding::TZX,
+        "SPECTRUM" => MZFEncoding::SPECTRUM,
+        "MSX" => MZFEncoding::MSX,
+        "QD" => MZFEncoding::QD,
+        "DISK" => MZFEncoding::DISK,
+        "AUTO" => MZFEncoding::AUTO,
+        _ => return Err(Error::InvalidArgument(format!(
+            "Invalid mode '{}'. Expected (SA, SP, 1Z, Z80, DUMP, ZX80BASIC, ZX81BASIC, ZX81RUN, MZT, MZWAV, ZXWAV, TZX, SPECTRUM, MSX, QD, DISK, AUTO)",
+            mode
+        ))),
     };
 
     match version {
+        MZFEncoding::AUTO => {
+            let guess = format_detect::detect_format(data);
+            let (mode, machine) = auto_mode(data, guess.format);
+            // Both BASICs save with attribute 0x02, so the dialect is only a guess
+            let dialect = match mode {
+                "SA" | "SP" => " (SA-5510 or SP-5025, guessed from the statement tokens)",
+                _ => "",
+            };
+            let mut output = format!(
+                "Detected: {} ({:.0}% confidence, {}), decoding as {}{}\n\n",
+                guess.format.description(),
+                guess.confidence * 100.0,
+                guess.reason,
+                mode,
+                dialect
+            );
+            output.push_str(&process_binary(data, mode.to_string(), machine, charset_flag)?);
+            Ok(output)
+        },
+
         MZFEncoding::Z80 => {
             let (skip_bytes, start_address, exec_address) = match machine {
+                MZFMachine::Sharp if data.len() < 128 => {
+                    return Err(Error::TruncatedHeader { format: "MZF", expected: 128, found: data.len() });
+                }
                 MZFMachine::Sharp => (128,
                     u16::from_le_bytes([data[0x14], data[0x15]]), // default start address is found at bytes 0x14,0x15 (LE)
                     u16::from_le_bytes([data[0x16], data[0x17]]), // default exec address is found at bytes 0x16,0x17 (LE)
                 ),
-                MZFMachine::Sinclair => (0, 0, 0)
+                MZFMachine::MSX if data.first() == Some(&msx_decoder::MSX_BINARY_MARKER) => {
+                    let binary = msx_decoder::parse_bload(data)?;
+                    (7, binary.start, binary.exec)
+                }
+                MZFMachine::Sinclair | MZFMachine::Spectrum | MZFMachine::MSX => (0, 0, 0)
             };
             let lowercase = MZLowerCase::new();
 
@@ -99,7 +206,7 @@ pub fn process_binary(data: &[u8], mode: String, machine: MZFMachine, charset_fl
             let mut disasm = Z80Disassembler::new();
             let result = disasm.disassemble(&data[skip_bytes..], start_address, exec_address);
 
-            if charset_flag {
+            Ok(if charset_flag {
                 result
                 .iter()
                     .map(|line| {
@@ -113,7 +220,7 @@ pub fn process_binary(data: &[u8], mode: String, machine: MZFMachine, charset_fl
                     .join("\n")
             }else{
                 result.join("\n")
-            }
+            })
         },
         
         MZFEncoding::DUMP => {
@@ -124,7 +231,7 @@ pub fn process_binary(data: &[u8], mode: String, machine: MZFMachine, charset_fl
                 // each line has 16 bytes
                 if i % 16 == 0 {
                     if i > 0 {
-                        hex_output.push_str("\n");
+                        hex_output.push('\n');
                     }
                     // current location in dump (every 16 bytes)
                     hex_output.push_str(&format!("{:04X}: ", i));
@@ -134,7 +241,7 @@ pub fn process_binary(data: &[u8], mode: String, machine: MZFMachine, charset_fl
                     let text_part: String = data[i - (i % 16)..=i]
                         .iter()
                         .map(|&b| { 
-                            if b >= 32 && b < 127 {
+                            if (32..127).contains(&b) {
                                 // If 'b' is a standard ASCII printable character, use it directly.
                                 b as char
                             } else if charset_flag {
@@ -151,7 +258,9 @@ pub fn process_binary(data: &[u8], mode: String, machine: MZFMachine, charset_fl
                                             '.'
                                         }
                                     }
-                                    MZFMachine::Sinclair => '_'
+                                    MZFMachine::Sinclair => '_',
+                                    MZFMachine::Spectrum if b == 0x7F => '©',
+                                    MZFMachine::Spectrum | MZFMachine::MSX => '.',
                                 }
                             }
                         })
@@ -160,23 +269,163 @@ pub fn process_binary(data: &[u8], mode: String, machine: MZFMachine, charset_fl
                     hex_output.push_str(&format!(" | {}", text_part));
                 }
             } 
-            hex_output 
+            Ok(hex_output)
         },
         
-        MZFEncoding::ZX80BASIC => {
-            match zx80_decoder::decode_zx80_bytes(data, false) {
-                Ok(basic_listing) => basic_listing,
-                Err(e) => format!("Error detokenizing file: {}", e),
+        MZFEncoding::ZX80BASIC => zx80_decoder::decode_zx80_bytes(data, false),
+        
+        MZFEncoding::ZX81BASIC => zx81_decoder::decode_zx81_bytes(data),
+        
+        MZFEncoding::ZX81RUN => {
+            let run = zx81_interpreter::run_zx81_p_file(data, zx81_interpreter::ZX81RunConfig::default())?;
+            let mut screen = run.display_lines().join("\n");
+            screen.push_str(&format!("\n\nReport {}: {} ({} statements)", run.report.code, run.report.message, run.steps));
+            Ok(screen)
+        },
+
+        MZFEncoding::MZT => {
+            let entries = mzf_file::parse_mzt(data)?;
+            let mut output = mzf_file::list_mzt(&entries);
+            for (i, entry) in entries.iter().enumerate() {
+                let mode = mzf_file::suggested_mode(&entry.mzf);
+                output.push_str(&format!("\n--- {}: {} ({}) ---\n", i, entry.header.name_string(), mode));
+                output.push_str(&decode_entry(&entry.mzf, mode.to_string(), MZFMachine::Sharp, charset_flag));
+                output.push('\n');
             }
+            Ok(output)
         },
-        
-        MZFEncoding::ZX81BASIC => {
-            match zx81_decoder::decode_zx81_bytes(data) {
-                Ok(basic_listing) => basic_listing,
-                Err(e) => format!("Error detokenizing file: {}", e),
+
+        MZFEncoding::QD => {
+            let files = qd_image::parse_qd_image(data)?;
+            let mut output = qd_image::list_qd(&files);
+            for (i, file) in files.iter().enumerate() {
+                let mode = mzf_file::suggested_mode(&file.mzf);
+                output.push_str(&format!("\n--- {}: {} ({}) ---\n", i, file.header.name_string(), mode));
+                output.push_str(&decode_entry(&file.mzf, mode.to_string(), MZFMachine::Sharp, charset_flag));
+                output.push('\n');
             }
+            Ok(output)
         },
-        
+
+        MZFEncoding::DISK => {
+            let files = sharp_disk::parse_sharp_disk(data)?;
+            let mut output = sharp_disk::list_disk(&files);
+            for (i, file) in files.iter().enumerate() {
+                let mode = mzf_file::suggested_mode(&file.mzf);
+                output.push_str(&format!("\n--- {}: {} ({}) ---\n", i, file.header.name_string(), mode));
+                output.push_str(&decode_entry(&file.mzf, mode.to_string(), MZFMachine::Sharp, charset_flag));
+                output.push('\n');
+            }
+            Ok(output)
+        },
+
+        MZFEncoding::MZWAV => {
+            let files = mz_tape::decode_mz_wav(data)?;
+            let mut output = String::new();
+            for (i, file) in files.iter().enumerate() {
+                let (name, mode) = match &file.header {
+                    Some(h) => (h.name_string(), mzf_file::suggested_mode(&file.mzf)),
+                    None => ("?".to_string(), "DUMP"),
+                };
+                output.push_str(&format!(
+                    "--- {}: {} ({}) header {} body {}{} ---\n",
+                    i,
+                    name,
+                    mode,
+                    if file.header_checksum_ok { "OK" } else { "BAD" },
+                    if file.body_checksum_ok { "OK" } else { "BAD" },
+                    if file.complete { "" } else { ", incomplete" }
+                ));
+                for d in &file.diagnostics {
+                    output.push_str(&format!("  {}\n", d));
+                }
+                output.push_str(&decode_entry(&file.mzf, mode.to_string(), MZFMachine::Sharp, charset_flag));
+                output.push_str("\n\n");
+            }
+            Ok(output)
+        },
+
+        MZFEncoding::ZXWAV => {
+            let files = zx_tape::decode_zx_wav(data)?;
+            let mut output = String::new();
+            for (i, file) in files.iter().enumerate() {
+                let mode = match file.machine {
+                    zx_tape::ZXTapeMachine::ZX80 => "ZX80BASIC",
+                    zx_tape::ZXTapeMachine::ZX81 => "ZX81BASIC",
+                };
+                output.push_str(&format!(
+                    "--- {}: {} ({}, {} bytes{}) ---\n",
+                    i,
+                    file.name.as_deref().unwrap_or("(no name)"),
+                    mode,
+                    file.data.len(),
+                    if file.complete { "" } else { ", incomplete" }
+                ));
+                for d in &file.diagnostics {
+                    output.push_str(&format!("  {}\n", d));
+                }
+                output.push_str(&decode_entry(&file.data, mode.to_string(), MZFMachine::Sinclair, charset_flag));
+                output.push_str("\n\n");
+            }
+            Ok(output)
+        },
+
+        MZFEncoding::TZX => {
+            let blocks = tzx_file::parse_tzx(data)?;
+            let mut output = tzx_file::list_tzx(data, &blocks);
+            match tzx_file::extract_zx81_programs(data) {
+                Ok(programs) => {
+                    for program in programs {
+                        output.push_str(&format!(
+                            "\n--- block {}: {} ---\n",
+                            program.block,
+                            program.name.as_deref().unwrap_or("(no name)")
+                        ));
+                        output.push_str(&decode_entry(&program.data, "ZX81BASIC".to_string(), MZFMachine::Sinclair, charset_flag));
+                    }
+                }
+                Err(e) => output.push_str(&format!("\n{}\n", e)),
+            }
+            Ok(output)
+        },
+
+        MZFEncoding::SPECTRUM => {
+            let files = spectrum_tape::parse_spectrum_tape(data)?;
+            let mut output = spectrum_tape::list_spectrum_tape(&files);
+            for (i, file) in files.iter().enumerate() {
+                let name = file.header.as_ref().map_or("(no header)", |h| h.name.as_str());
+                output.push_str(&format!("\n--- {}: {} ---\n", i, name));
+                if let Some(program) = file.program() {
+                    match spectrum_decoder::decode_spectrum_listing(program, file.offset) {
+                        Ok(listing) => output.push_str(&listing.to_text(4)),
+                        Err(e) => output.push_str(&format!("Error: {}\n", e)),
+                    }
+                } else if let Some(address) = file.load_address() {
+                    let mut disasm = Z80Disassembler::new();
+                    output.push_str(&disasm.disassemble(&file.data, address, address).join("\n"));
+                    output.push('\n');
+                } else {
+                    output.push_str("(data, not decoded)\n");
+                }
+            }
+            Ok(output)
+        },
+
+        MZFEncoding::MSX => match data.first() {
+            Some(&msx_decoder::MSX_BINARY_MARKER) => {
+                let binary = msx_decoder::parse_bload(data)?;
+                let mut disasm = Z80Disassembler::new();
+                Ok(format!(
+                    "BSAVE file: start {:04X} end {:04X} exec {:04X}\n\n{}",
+                    binary.start,
+                    binary.end,
+                    binary.exec,
+                    disasm.disassemble(&binary.data, binary.start, binary.exec).join("\n")
+                ))
+            }
+            _ => Ok(msx_decoder::decode_msx_listing(data)?.to_text(0)),
+        },
+
         // Handle MZ BASIC versions
         MZFEncoding::SA5510 | MZFEncoding::SP5025 | MZFEncoding::V1Z013B => {
             let mz_version = match version {
@@ -186,10 +435,459 @@ pub fn process_binary(data: &[u8], mode: String, machine: MZFMachine, charset_fl
                 _ => unreachable!(),
             };
             
-            match mz_decoder::decode_mz_bytes(data, mz_version) {
-                Ok(basic_listing) => basic_listing,
-                Err(e) => format!("Error detokenizing file: {}", e),
+            let listing = mz_decoder::decode_mz_listing(data, mz_version)?;
+            let mut output = listing.to_text(0);
+            if let Ok(Some(trailing)) = mz_trailing::listing_trailing_data(data, &listing) {
+                output.push_str(&format!("\n{}\n", trailing.summary()));
+            }
+            Ok(output)
+        }
+    }
+}
+
+/// Decodes one file from a container, reporting a failure in place of its listing
+/// so the rest of the container is still shown.
+fn decode_entry(data: &[u8], mode: String, machine: MZFMachine, charset_flag: bool) -> String {
+    process_binary(data, mode, machine, charset_flag).unwrap_or_else(|e| format!("Error: {}", e))
+}
+
+/// Chooses the `process_binary` mode and machine for a detected format.
+fn auto_mode(data: &[u8], format: format_detect::InputFormat) -> (&'static str, MZFMachine) {
+    use format_detect::InputFormat;
+    match format {
+        InputFormat::MZF => (mzf_file::suggested_mode(data), MZFMachine::Sharp),
+        InputFormat::MZT => ("MZT", MZFMachine::Sharp),
+        InputFormat::ZX81P | InputFormat::ZX81P81 => ("ZX81BASIC", MZFMachine::Sinclair),
+        InputFormat::ZX80O => ("ZX80BASIC", MZFMachine::Sinclair),
+        // ZX81 programs are in generalised data blocks, Spectrum files in standard ones
+        InputFormat::TZX if tzx_file::extract_zx81_programs(data).is_err() => ("SPECTRUM", MZFMachine::Spectrum),
+        InputFormat::TZX => ("TZX", MZFMachine::Sinclair),
+        InputFormat::SpectrumTAP => ("SPECTRUM", MZFMachine::Spectrum),
+        InputFormat::MSXBasic | InputFormat::MSXBinary => ("MSX", MZFMachine::MSX),
+        InputFormat::MZWAV => ("MZWAV", MZFMachine::Sharp),
+        InputFormat::ZXWAV => ("ZXWAV", MZFMachine::Sinclair),
+        InputFormat::QuickDisk => ("QD", MZFMachine::Sharp),
+        InputFormat::FloppyDisk => ("DISK", MZFMachine::Sharp),
+        InputFormat::Unknown => ("DUMP", MZFMachine::Sharp),
+    }
+}
+
+/// WASM-exposed function to identify the format of a file without decoding it.
+///
+/// # Arguments
+/// * `data` - The file contents.
+///
+/// # Returns
+/// One line per possible format, most likely first, each giving the `process_binary`
+/// mode to use, the confidence (0-100) and the reason, separated by tabs.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn detect_input_format(data: &[u8]) -> String {
+    let mut guesses = format_detect::detect_formats(data);
+    if guesses.is_empty() {
+        guesses.push(format_detect::detect_format(data));
+    }
+    guesses
+        .iter()
+        .map(|g| format!("{}\t{:.0}\t{}: {}", auto_mode(data, g.format).0, g.confidence * 100.0, g.format.description(), g.reason))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Decodes the BASIC program in `data` for the structured outputs.
+///
+/// # Arguments
+/// * `data` - The MZF, .O or .P file.
+/// * `mode` - An MZ BASIC mode ("SA", "SP", "1Z"), "ZX80BASIC",
+///   "ZX81BASIC", "SPECTRUM" (the first program on the tape), "MSX", or "AUTO" to detect the format.
+fn basic_listing(data: &[u8], mode: &str) -> Result<basic::BasicListing, Error> {
+    let mode = match mode {
+        "AUTO" => auto_mode(data, format_detect::detect_format(data).format).0,
+        mode => mode,
+    };
+    if let Some(version) = MZBasicVersion::from_mode(mode) {
+        return mz_decoder::decode_mz_listing(data, version);
+    }
+    match mode {
+        "ZX80BASIC" => zx80_decoder::decode_zx80_listing(data, false),
+        "ZX81BASIC" => zx81_decoder::decode_zx81_listing(data, zx81_decoder::OutputStyle::Readable),
+        "SPECTRUM" => spectrum::decode_basic(data),
+        "MSX" => msx_decoder::decode_msx_listing(data),
+        _ => Err(not_a_basic_mode(mode)),
+    }
+}
+
+/// The error for a mode that does not decode BASIC.
+fn not_a_basic_mode(mode: &str) -> Error {
+    Error::InvalidArgument(format!("{} is not a BASIC mode. Expected (SA, SP, 1Z, ZX80BASIC, ZX81BASIC, SPECTRUM, MSX, AUTO)", mode))
+}
+
+/// WASM-exposed function to decode a BASIC program into its line and item structure.
+///
+/// # Arguments
+/// * `data` - The MZF, .O or .P file.
+/// * `mode` - An MZ BASIC mode as for `process_binary`, "ZX80BASIC", "ZX81BASIC", "SPECTRUM",
+///   "MSX", or "AUTO" to detect the format.
+///
+/// # Returns
+/// The program as JSON: its name, each line's number, offset, length, text and items
+/// (keywords, text, strings, numbers and REM bodies, each with its offset), and any
+/// diagnostics. Use this for highlighting and navigation rather than parsing the listing.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn basic_program_json(data: &[u8], mode: String) -> Result<String, Error> {
+    Ok(basic_listing(data, &mode)?.to_json())
+}
+
+/// WASM-exposed function to cross-reference a BASIC program.
+///
+/// # Arguments
+/// * `data` - The MZF, .O or .P file.
+/// * `mode` - As for `basic_program_json`.
+///
+/// # Returns
+/// A report of every GOTO, GOSUB, RUN, LIST and RESTORE target (flagging missing lines),
+/// the unreachable lines, where each variable is set and read, and each POKE, PEEK and
+/// USR address.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn analyse_basic_program(data: &[u8], mode: String) -> Result<String, Error> {
+    Ok(basic_listing(data, &mode)?.analyse().to_text())
+}
+
+/// WASM-exposed function to pretty-print a BASIC program.
+///
+/// # Arguments
+/// * `data` - The MZF, .O or .P file.
+/// * `mode` - As for `basic_program_json`.
+/// * `jump_comments` - Whether to follow each jump with the start of the line it goes to.
+///
+/// # Returns
+/// The listing with one statement per line, FOR/NEXT loops and subroutines indented and
+/// keyword spacing made consistent.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn format_basic_program(data: &[u8], mode: String, jump_comments: bool) -> Result<String, Error> {
+    let options = basic_format::FormatOptions { jump_comments, ..Default::default() };
+    Ok(basic_listing(data, &mode)?.to_formatted_text(&options))
+}
+
+/// WASM-exposed function to renumber a BASIC program.
+///
+/// # Arguments
+/// * `data` - The MZF, .O or .P file.
+/// * `mode` - As for `basic_program_json`.
+/// * `start` - The first new line number.
+/// * `step` - The gap between new line numbers.
+///
+/// # Returns
+/// The renumbered file in its original format, with line number targets changed to match.
+/// Computed targets and targets of missing lines are left alone.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn renumber_basic_program(data: &[u8], mode: String, start: u16, step: u16) -> Result<Vec<u8>, Error> {
+    let mode = match mode.as_str() {
+        "AUTO" => auto_mode(data, format_detect::detect_format(data).format).0,
+        mode => mode,
+    };
+    let renumbered = match (mode, MZBasicVersion::from_mode(mode)) {
+        (_, Some(version)) => mz::renumber_basic(data, version, start, step)?,
+        ("ZX80BASIC", _) => zx80::renumber_basic(data, start, step)?,
+        ("ZX81BASIC", _) => zx81::renumber_basic(data, start, step)?,
+        ("SPECTRUM" | "MSX", _) => {
+            return Err(Error::InvalidArgument(format!("Renumbering {} programs is not supported", mode)))
+        }
+        _ => return Err(not_a_basic_mode(mode)),
+    };
+    Ok(renumbered.data)
+}
+
+/// The code in `data` and the address it loads at: the body of an MZF or MSX BSAVE file,
+/// or the first code file on a Spectrum tape, at its load address, or else the whole
+/// file from address 0.
+fn code_image(data: &[u8]) -> (&[u8], u32) {
+    match format_detect::detect_format(data).format {
+        format_detect::InputFormat::MSXBinary => match msx_decoder::parse_bload(data) {
+            Ok(binary) => (&data[7..7 + binary.data.len()], binary.start as u32),
+            Err(_) => (data, 0),
+        },
+        format_detect::InputFormat::SpectrumTAP | format_detect::InputFormat::TZX => {
+            let files = spectrum_tape::parse_spectrum_tape(data).unwrap_or_default();
+            match files.iter().find_map(|file| Some((file, file.load_address()?))) {
+                Some((file, address)) => (&data[file.offset..file.offset + file.data.len()], address as u32),
+                None => (data, 0),
             }
         }
+        format_detect::InputFormat::MZF => match mzf_file::MZFHeader::parse(data) {
+            Ok(header) => {
+                let end = (mzf_file::MZF_HEADER_SIZE + header.size as usize).min(data.len());
+                (&data[mzf_file::MZF_HEADER_SIZE..end], header.load_address as u32)
+            }
+            Err(_) => (data, 0),
+        },
+        _ => (data, 0),
+    }
+}
+
+/// Compares two versions of a program, decoding each as `mode`.
+///
+/// # Arguments
+/// * `old` - The file to compare from.
+/// * `new` - The file to compare to.
+/// * `mode` - A BASIC mode (an MZ BASIC, "ZX80BASIC" or "ZX81BASIC") to compare line
+///   by line, "Z80" or "DUMP" to compare bytes by load address, or "AUTO" to detect each file.
+///
+/// # Returns
+/// The differences, or an error if either file cannot be decoded as the mode or the two
+/// files are not the same kind of program.
+pub fn diff_program_files(old: &[u8], new: &[u8], mode: &str) -> Result<ProgramDiff, Error> {
+    let resolve = |data: &[u8]| match mode {
+        "AUTO" => auto_mode(data, format_detect::detect_format(data).format).0,
+        mode => mode,
+    };
+    let is_code = |mode: &str| matches!(mode, "Z80" | "DUMP");
+    let (old_mode, new_mode) = (resolve(old), resolve(new));
+    match (is_code(old_mode), is_code(new_mode)) {
+        (true, true) => {
+            let ((old, old_address), (new, new_address)) = (code_image(old), code_image(new));
+            Ok(ProgramDiff::memory(old, old_address, new, new_address))
+        }
+        (false, false) => Ok(ProgramDiff::listings(&basic_listing(old, old_mode)?, &basic_listing(new, new_mode)?)),
+        _ => Err(Error::InvalidArgument(format!("Cannot compare a {} file with a {} file", old_mode, new_mode))),
+    }
+}
+
+/// WASM-exposed function to compare two versions of a program.
+///
+/// # Arguments
+/// * `old` - The file to compare from.
+/// * `new` - The file to compare to.
+/// * `mode` - As for `diff_program_files`.
+///
+/// # Returns
+/// The added, removed and changed BASIC lines, or the changed bytes of machine code by
+/// address, marked `-` and `+` as in a unified diff, followed by a summary.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn diff_programs(old: &[u8], new: &[u8], mode: String) -> Result<String, Error> {
+    Ok(diff_program_files(old, new, &mode)?.to_text())
+}
+
+/// WASM-exposed function to show what an MZF BASIC file holds after its program.
+///
+/// # Arguments
+/// * `data` - The MZF file.
+/// * `mode` - An MZ BASIC mode ("SA", "SP" or "1Z"), as for `process_binary`.
+/// * `view` - "Z80" to disassemble or "DUMP" for hex.
+///
+/// # Returns
+/// The offset, load address and length of the bytes after the program's end marker,
+/// followed by the requested view of them, or a note that the program is all there is.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn mz_trailing_data(data: &[u8], mode: String, view: String) -> Result<String, Error> {
+    let version = MZBasicVersion::from_mode(&mode).ok_or_else(|| {
+        Error::InvalidArgument(format!("Invalid mode '{}'. Expected (SA, SP, 1Z)", mode))
+    })?;
+    let Some(trailing) = mz_trailing::trailing_data(data, version)? else {
+        return Ok("Nothing follows the program".to_string());
+    };
+    let body = match view.as_str() {
+        "Z80" => trailing.disassembly(),
+        "DUMP" => trailing.hex_dump(),
+        _ => return Err(Error::InvalidArgument(format!("Invalid view '{}'. Expected (Z80, DUMP)", view))),
+    };
+    Ok(format!("{}\n\n{}", trailing.summary(), body))
+}
+
+/// WASM-exposed function to salvage a damaged MZ BASIC program, such as one from a bad tape read.
+///
+/// # Arguments
+/// * `data` - The MZF file.
+/// * `mode` - An MZ BASIC mode ("SA", "SP" or "1Z"), as for `process_binary`.
+///
+/// # Returns
+/// Every line that could be decoded, followed by the offsets of the damaged ranges that
+/// were skipped, or an error if no intact line was found.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn recover_mz_basic(data: &[u8], mode: String) -> Result<String, Error> {
+    let version = MZBasicVersion::from_mode(&mode).ok_or_else(|| {
+        Error::InvalidArgument(format!("Invalid mode '{}'. Expected (SA, SP, 1Z)", mode))
+    })?;
+    Ok(mz_decoder::recover_mz_listing(data, version)?.to_text(0))
+}
+
+/// WASM-exposed function to decode a single file from an MZT container.
+///
+/// # Arguments
+/// * `data` - The complete MZT container.
+/// * `index` - Zero-based position of the file within the container.
+/// * `mode` - Any mode accepted by `process_binary`; an empty string uses the mode suggested by the file's header and tokens.
+/// * `charset_flag` - As for `process_binary`.
+///
+/// # Returns
+/// The decoded file, or an error if the container cannot be read or has no such entry.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn process_mzt_entry(data: &[u8], index: usize, mode: String, charset_flag: bool) -> Result<String, Error> {
+    let entries = mzf_file::parse_mzt(data)?;
+    match entries.get(index) {
+        Some(entry) => {
+            let mode = if mode.is_empty() { mzf_file::suggested_mode(&entry.mzf).to_string() } else { mode };
+            process_binary(&entry.mzf, mode, MZFMachine::Sharp, charset_flag)
+        }
+        None => Err(Error::InvalidArgument(format!("MZT file has {} entries, no entry {}", entries.len(), index))),
+    }
+}
+
+/// WASM-exposed function to decode a single file from a Quick Disk image.
+///
+/// # Arguments
+/// * `data` - The complete .qdf or .mzq image.
+/// * `index` - Zero-based position of the file on the disk.
+/// * `mode` - Any mode accepted by `process_binary`; an empty string uses the mode suggested by the file's header and tokens.
+/// * `charset_flag` - As for `process_binary`.
+///
+/// # Returns
+/// The decoded file, or an error if the image cannot be read or has no such file.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn process_qd_entry(data: &[u8], index: usize, mode: String, charset_flag: bool) -> Result<String, Error> {
+    let files = qd_image::parse_qd_image(data)?;
+    match files.get(index) {
+        Some(file) => {
+            let mode = if mode.is_empty() { mzf_file::suggested_mode(&file.mzf).to_string() } else { mode };
+            process_binary(&file.mzf, mode, MZFMachine::Sharp, charset_flag)
+        }
+        None => Err(Error::InvalidArgument(format!("Quick Disk has {} files, no file {}", files.len(), index))),
+    }
+}
+
+/// WASM-exposed function to extract a single file from a Quick Disk image as an MZF file.
+///
+/// # Arguments
+/// * `data` - The complete .qdf or .mzq image.
+/// * `index` - Zero-based position of the file on the disk.
+///
+/// # Returns
+/// The MZF file bytes, or an error if the image cannot be read or has no such file.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn qd_entry_to_mzf(data: &[u8], index: usize) -> Result<Vec<u8>, Error> {
+    let mut files = qd_image::parse_qd_image(data)?;
+    if index >= files.len() {
+        return Err(Error::InvalidArgument(format!("Quick Disk has {} files, no file {}", files.len(), index)));
+    }
+    Ok(files.swap_remove(index).mzf)
+}
+
+/// WASM-exposed function to decode a single file from a floppy disk image.
+///
+/// # Arguments
+/// * `data` - The complete D88 or raw sector image.
+/// * `index` - Zero-based position of the file in the directory listing.
+/// * `mode` - Any mode accepted by `process_binary`; an empty string uses the mode suggested by the file's type.
+/// * `charset_flag` - As for `process_binary`.
+///
+/// # Returns
+/// The decoded file, or an error if the image cannot be read or has no such file.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn process_disk_entry(data: &[u8], index: usize, mode: String, charset_flag: bool) -> Result<String, Error> {
+    let files = sharp_disk::parse_sharp_disk(data)?;
+    match files.get(index) {
+        Some(file) => {
+            let mode = if mode.is_empty() { mzf_file::suggested_mode(&file.mzf).to_string() } else { mode };
+            process_binary(&file.mzf, mode, MZFMachine::Sharp, charset_flag)
+        }
+        None => Err(Error::InvalidArgument(format!("disk has {} files, no file {}", files.len(), index))),
+    }
+}
+
+/// WASM-exposed function to extract a single file from a floppy disk image as an MZF file.
+///
+/// # Arguments
+/// * `data` - The complete D88 or raw sector image.
+/// * `index` - Zero-based position of the file in the directory listing.
+///
+/// # Returns
+/// The MZF file bytes, or an error if the image cannot be read or has no such file.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn disk_entry_to_mzf(data: &[u8], index: usize) -> Result<Vec<u8>, Error> {
+    let mut files = sharp_disk::parse_sharp_disk(data)?;
+    if index >= files.len() {
+        return Err(Error::InvalidArgument(format!("disk has {} files, no file {}", files.len(), index)));
     }
-}
\ No newline at end of file
+    Ok(files.swap_remove(index).mzf)
+}
+
+/// WASM-exposed function to recover MZF files from a Sharp MZ cassette recording.
+///
+/// # Arguments
+/// * `data` - A PCM WAV file.
+///
+/// # Returns
+/// The recovered files as MZF bytes, concatenated (an MZT container when there is more
+/// than one file). Files with checksum errors are included as read. An error if nothing was found.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn mz_wav_to_mzf(data: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(mz_tape::decode_mz_wav(data)?.into_iter().flat_map(|f| f.mzf).collect())
+}
+
+/// WASM-exposed function to turn an MZF file into a Sharp MZ cassette recording.
+///
+/// # Arguments
+/// * `data` - The MZF file.
+/// * `sample_rate` - Output sample rate in Hz (e.g. 44100).
+/// * `speed` - 1 for standard 1200 baud, 2 or 3 for the fast loader variants.
+///
+/// # Returns
+/// The WAV file bytes, or an error if the MZF file or the options are invalid.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn mzf_to_wav(data: &[u8], sample_rate: u32, speed: u8) -> Result<Vec<u8>, Error> {
+    let options = mz_tape::MZTapeOptions { sample_rate, speed, ..Default::default() };
+    mz_tape::encode_mz_wav(data, options)
+}
+
+/// WASM-exposed function to recover a program from a ZX80/ZX81 cassette recording.
+///
+/// # Arguments
+/// * `data` - A PCM WAV file.
+///
+/// # Returns
+/// The first program on the tape as a ZX81 .P or ZX80 .O file (the ZX81 name is removed),
+/// or an error if nothing was found.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn zx_wav_to_program(data: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(zx_tape::decode_zx_wav(data)?.swap_remove(0).data)
+}
+
+/// WASM-exposed function to turn a ZX81 .P or ZX80 .O file into a cassette recording.
+///
+/// # Arguments
+/// * `data` - The .P or .O file.
+/// * `zx81` - True for a ZX81 .P file, false for a ZX80 .O file.
+/// * `name` - The ZX81 program name to record before the data (ignored for the ZX80).
+/// * `sample_rate` - Output sample rate in Hz (e.g. 44100).
+///
+/// # Returns
+/// The WAV file bytes, or an error if the program or sample rate is invalid.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn zx_program_to_wav(data: &[u8], zx81: bool, name: String, sample_rate: u32) -> Result<Vec<u8>, Error> {
+    let machine = if zx81 { zx_tape::ZXTapeMachine::ZX81 } else { zx_tape::ZXTapeMachine::ZX80 };
+    zx_tape::encode_zx_wav(data, machine, &name, sample_rate)
+}
+
+/// WASM-exposed function to extract the first ZX81 program from a TZX file.
+///
+/// # Arguments
+/// * `data` - The .tzx file.
+///
+/// # Returns
+/// The program as a .P file (name removed), or an error if none was found.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn tzx_to_p(data: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(tzx_file::extract_zx81_programs(data)?.swap_remove(0).data)
+}
+
+/// WASM-exposed function to wrap a ZX81 .P file in a TZX file.
+///
+/// # Arguments
+/// * `data` - The .P file.
+/// * `name` - The program name stored in front of the data.
+///
+/// # Returns
+/// The .tzx file bytes.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn p_to_tzx(data: &[u8], name: String) -> Vec<u8> {
+    tzx_file::zx81_p_to_tzx(data, &name)
+}
@@ -0,0 +1,39 @@
+//! MSX files: tokenised BASIC programs (SAVE) and machine code (BSAVE).
+//!
+//! ```no_run
+//! use rust_wasm_converter::msx;
+//!
+//! let data = std::fs::read("GAME.bas").unwrap();
+//! for line in msx::decode_basic(&data).unwrap().lines {
+//!     println!("{} {}", line.number, line.text);
+//! }
+//! ```
+
+pub use crate::basic::{BasicItem, BasicLine, BasicListing, ItemKind};
+pub use crate::basic_analysis::{AddressUse, LineReference, ProgramAnalysis, VariableUse};
+pub use crate::basic_format::FormatOptions;
+pub use crate::error::Error;
+pub use crate::msx_decoder::{parse_bload, MSXBinary, MSX_BASIC_MARKER, MSX_BINARY_MARKER};
+
+/// Decodes a tokenised MSX BASIC file.
+///
+/// # Arguments
+///
+/// * `data` - The complete file, starting with 0xFF
+///
+/// # Returns
+///
+/// A `Result` containing the program lines, or an error if not even the first line could be read.
+/// A damaged program is listed up to the damage, with the reason in `diagnostics`.
+///
+/// ```
+/// use rust_wasm_converter::msx;
+///
+/// // 10 PRINT "HI":GOTO 10, saved from 0x8001
+/// let data = b"\xFF\x12\x80\x0A\x00\x91 \"HI\":\x89 \x0E\x0A\x00\x00\x00\x00";
+/// let listing = msx::decode_basic(data).unwrap();
+/// assert_eq!(listing.lines[0].text, "PRINT \"HI\":GOTO 10");
+/// ```
+pub fn decode_basic(data: &[u8]) -> Result<BasicListing, Error> {
+    crate::msx_decoder::decode_msx_listing(data)
+}
@@ -0,0 +1,420 @@
+// src/msx_decoder.rs
+//
+// MSX BASIC programs as saved by SAVE "name" (tokenised), and machine code saved by
+// BSAVE. A program file starts with 0xFF; each line then holds the address of the next
+// line (0 after the last), the line number, the tokenised statements and a 0 byte.
+// Numbers are stored in binary after a type byte, so their text is rebuilt for listing.
+
+use crate::basic::{BasicListing, ItemKind, LineBuilder};
+use crate::error::Error;
+
+/// First byte of a tokenised BASIC file.
+pub const MSX_BASIC_MARKER: u8 = 0xFF;
+/// First byte of a BSAVE file, which is followed by the start, end and run addresses.
+pub const MSX_BINARY_MARKER: u8 = 0xFE;
+
+/// Statement and operator tokens, 0x81 (END) to 0xFC (\).
+const TOKENS: [&str; 124] = [
+    "END", "FOR", "NEXT", "DATA", "INPUT", "DIM", "READ", "LET", // 0x81
+    "GOTO", "RUN", "IF", "RESTORE", "GOSUB", "RETURN", "REM", "STOP", // 0x89
+    "PRINT", "CLEAR", "LIST", "NEW", "ON", "WAIT", "DEF", "POKE", // 0x91
+    "CONT", "CSAVE", "CLOAD", "OUT", "LPRINT", "LLIST", "CLS", "WIDTH", // 0x99
+    "ELSE", "TRON", "TROFF", "SWAP", "ERASE", "ERROR", "RESUME", "DELETE", // 0xA1
+    "AUTO", "RENUM", "DEFSTR", "DEFINT", "DEFSNG", "DEFDBL", "LINE", "OPEN", // 0xA9
+    "FIELD", "GET", "PUT", "CLOSE", "LOAD", "MERGE", "FILES", "LSET", // 0xB1
+    "RSET", "SAVE", "LFILES", "CIRCLE", "COLOR", "DRAW", "PAINT", "BEEP", // 0xB9
+    "PLAY", "PSET", "PRESET", "SOUND", "SCREEN", "VPOKE", "SPRITE", "VDP", // 0xC1
+    "BASE", "CALL", "TIME", "KEY", "MAX", "MOTOR", "BLOAD", "BSAVE", // 0xC9
+    "DSKO$", "SET", "NAME", "KILL", "IPL", "COPY", "CMD", "LOCATE", // 0xD1
+    "TO", "THEN", "TAB(", "STEP", "USR", "FN", "SPC(", "NOT", // 0xD9
+    "ERL", "ERR", "STRING$", "USING", "INSTR", "'", "VARPTR", "CSRLIN", // 0xE1
+    "ATTR$", "DSKI$", "OFF", "INKEY$", "POINT", ">", "=", "<", // 0xE9
+    "+", "-", "*", "/", "^", "AND", "OR", "XOR", // 0xF1
+    "EQV", "IMP", "MOD", "\\", // 0xF9
+];
+
+/// Function tokens, 0xFF followed by 0x81 (LEFT$) to 0xB0 (MKD$).
+const FUNCTIONS: [&str; 48] = [
+    "LEFT$", "RIGHT$", "MID$", "SGN", "INT", "ABS", "SQR", "RND", // 0x81
+    "SIN", "LOG", "EXP", "COS", "TAN", "ATN", "FRE", "INP", // 0x89
+    "POS", "LEN", "STR$", "VAL", "ASC", "CHR$", "PEEK", "VPEEK", // 0x91
+    "SPACE$", "OCT$", "HEX$", "LPOS", "BIN$", "CINT", "CSNG", "CDBL", // 0x99
+    "FIX", "STICK", "STRIG", "PDL", "PAD", "DSKF", "FPOS", "CVI", // 0xA1
+    "CVS", "CVD", "EOF", "LOC", "LOF", "MKI$", "MKS$", "MKD$", // 0xA9
+];
+
+/// Characters 0x80 to 0xAF of the international character set.
+const ACCENTED: [char; 48] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+];
+
+const DATA: u8 = 0x84;
+const REM: u8 = 0x8F;
+const ELSE: u8 = 0xA1;
+const APOSTROPHE: u8 = 0xE6;
+const FUNCTION_PREFIX: u8 = 0xFF;
+
+/// A machine code file saved with BSAVE.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MSXBinary {
+    /// Where the code loads.
+    pub start: u16,
+    /// The last address it fills.
+    pub end: u16,
+    /// Where BLOAD ,R starts it.
+    pub exec: u16,
+    /// The code, as far as the file holds it.
+    pub data: Vec<u8>,
+}
+
+/// Parses a BSAVE file: 0xFE, then the start, end and run addresses, then the code.
+///
+/// # Arguments
+///
+/// * `data` - The complete file
+///
+/// # Returns
+///
+/// A `Result` containing the addresses and code, or an error if the file does not start
+/// with a BSAVE header.
+pub fn parse_bload(data: &[u8]) -> Result<MSXBinary, Error> {
+    if data.len() < 7 {
+        return Err(Error::TruncatedHeader { format: "MSX BSAVE", expected: 7, found: data.len() });
+    }
+    if data[0] != MSX_BINARY_MARKER {
+        return Err(Error::InvalidFormat("Not an MSX BSAVE file."));
+    }
+    let word = |pos: usize| u16::from_le_bytes([data[pos], data[pos + 1]]);
+    let (start, end) = (word(1), word(3));
+    let length = (end.wrapping_sub(start) as usize + 1).min(data.len() - 7);
+    Ok(MSXBinary { start, end, exec: word(5), data: data[7..7 + length].to_vec() })
+}
+
+/// The listed form of a character in a string, REM or DATA statement. Graphic characters
+/// (0x01 and a second byte) and the characters past the accented letters show as '◇'.
+fn char_text(byte: u8) -> char {
+    match byte {
+        0x20..=0x7E => byte as char,
+        0x80..=0xAF => ACCENTED[(byte - 0x80) as usize],
+        _ => '◇',
+    }
+}
+
+/// Formats a single (4 bytes) or double (8 bytes) precision number. The first byte holds
+/// the sign (bit 7) and the decimal exponent plus 0x40; the rest hold the digits in BCD,
+/// as a fraction 0.ddd. Listed as MSX BASIC lists them, with `!` after a single precision
+/// number and `#` after a whole double precision one that would otherwise read back as an
+/// integer.
+fn bcd_number(bytes: &[u8]) -> (String, f64) {
+    if bytes[0] & 0x7F == 0 {
+        return (if bytes.len() == 4 { "0!" } else { "0#" }.to_string(), 0.0);
+    }
+    let single = bytes.len() == 4;
+    let sign = if bytes[0] & 0x80 != 0 { "-" } else { "" };
+    let exponent = (bytes[0] & 0x7F) as i32 - 0x40;
+    let digits: String = bytes[1..]
+        .iter()
+        .flat_map(|&b| [b >> 4, b & 0x0F])
+        .map(|d| char::from_digit(d.min(9) as u32, 10).unwrap_or('0'))
+        .collect();
+    let digits = digits.trim_end_matches('0');
+    let digits = if digits.is_empty() { "0" } else { digits };
+    let value: f64 = format!("{}0.{}e{}", sign, digits, exponent).parse().unwrap_or(0.0);
+
+    let places = if single { 6 } else { 14 };
+    let text = if (1..=places).contains(&exponent) {
+        let point = exponent as usize;
+        let whole = digits.len() <= point;
+        let text = if whole {
+            format!("{}{}", digits, "0".repeat(point - digits.len()))
+        } else {
+            format!("{}.{}", &digits[..point], &digits[point..])
+        };
+        let suffix = match (single, whole && value.abs() < 32768.0) {
+            (true, _) => "!",
+            (false, true) => "#",
+            (false, false) => "",
+        };
+        format!("{}{}{}", sign, text, suffix)
+    } else if (-1..=0).contains(&exponent) {
+        format!("{}.{}{}{}", sign, "0".repeat(-exponent as usize), digits, if single { "!" } else { "" })
+    } else {
+        let (head, tail) = digits.split_at(1);
+        let point = if tail.is_empty() { String::new() } else { format!(".{}", tail) };
+        let letter = if single { 'E' } else { 'D' };
+        let e = exponent - 1;
+        format!("{}{}{}{}{}{:02}", sign, head, point, letter, if e < 0 { '-' } else { '+' }, e.abs())
+    };
+    (text, value)
+}
+
+/// A line reference stored as a pointer to the line in memory (0x0D), found when a
+/// program is saved after it has run.
+struct LinePointer {
+    /// Index of the line holding the pointer.
+    line: usize,
+    /// Offset of the pointer's type byte, which starts its item.
+    offset: usize,
+    address: u16,
+}
+
+/// Reads `n` bytes after the type byte at `i`, or reports where the line ran out.
+fn operand(bytes: &[u8], i: usize, n: usize, line: u16) -> Result<&[u8], Error> {
+    bytes.get(i + 1..i + 1 + n).ok_or(Error::UnexpectedEnd { offset: bytes.len(), line: Some(line) })
+}
+
+/// A decoded line, the offset just past it and the offsets and addresses of its line pointers.
+type DecodedLine = (LineBuilder, usize, Vec<(usize, u16)>);
+
+/// Decodes the statements of the line numbered `number` that start at `start`. Returns the
+/// line, the offset just past its 0 terminator and any line pointers it holds.
+fn decode_line(bytes: &[u8], start: usize, number: u16) -> Result<DecodedLine, Error> {
+    let mut line = LineBuilder::default();
+    let mut pointers = Vec::new();
+    let (mut in_string, mut in_rem, mut in_data) = (false, false, false);
+    let mut i = start;
+    loop {
+        let byte = *bytes.get(i).ok_or(Error::UnexpectedEnd { offset: bytes.len(), line: Some(number) })?;
+        if byte == 0 {
+            return Ok((line, i + 1, pointers));
+        }
+        let literal = if in_rem {
+            Some(ItemKind::Rem)
+        } else if in_string || byte == b'"' {
+            in_string = byte != b'"' || !in_string;
+            Some(ItemKind::String)
+        } else if in_data && byte != b':' {
+            Some(ItemKind::Rem)
+        } else {
+            None
+        };
+        if let Some(kind) = literal {
+            if byte == 0x01 {
+                // A graphic character: 0x01 and the character plus 0x40
+                line.push(kind, i, &char_text(byte).to_string());
+                i += 2;
+            } else {
+                line.push(kind, i, &char_text(byte).to_string());
+                i += 1;
+            }
+            continue;
+        }
+
+        match byte {
+            // ELSE and ' are stored after a colon that is not listed
+            b':' if bytes.get(i + 1) == Some(&ELSE) => {
+                line.push(ItemKind::Keyword, i, "ELSE");
+                in_data = false;
+                i += 2;
+            }
+            b':' if bytes.get(i + 1..i + 3) == Some(&[REM, APOSTROPHE]) => {
+                line.push(ItemKind::Keyword, i, "'");
+                in_rem = true;
+                i += 3;
+            }
+            0x0B | 0x0C | 0x0E => {
+                let value = u16::from_le_bytes(operand(bytes, i, 2, number)?.try_into().unwrap_or_default());
+                let text = match byte {
+                    0x0B => format!("&O{:o}", value),
+                    0x0C => format!("&H{:X}", value),
+                    _ => value.to_string(),
+                };
+                line.number(i, &text, value as f64);
+                i += 3;
+            }
+            0x0D => {
+                let address = u16::from_le_bytes(operand(bytes, i, 2, number)?.try_into().unwrap_or_default());
+                pointers.push((i, address));
+                line.push(ItemKind::Keyword, i, &format!("[&H{:04X}]", address));
+                i += 3;
+            }
+            0x0F => {
+                let value = operand(bytes, i, 1, number)?[0];
+                line.number(i, &value.to_string(), value as f64);
+                i += 2;
+            }
+            0x11..=0x1A => {
+                let value = byte - 0x11;
+                line.number(i, &value.to_string(), value as f64);
+                i += 1;
+            }
+            0x1C => {
+                let value = i16::from_le_bytes(operand(bytes, i, 2, number)?.try_into().unwrap_or_default());
+                line.number(i, &value.to_string(), value as f64);
+                i += 3;
+            }
+            0x1D | 0x1F => {
+                let length = if byte == 0x1D { 4 } else { 8 };
+                let (text, value) = bcd_number(operand(bytes, i, length, number)?);
+                line.number(i, &text, value);
+                i += 1 + length;
+            }
+            FUNCTION_PREFIX => {
+                let code = operand(bytes, i, 1, number)?[0];
+                match code.checked_sub(0x81).and_then(|n| FUNCTIONS.get(n as usize)) {
+                    Some(word) => line.push(ItemKind::Keyword, i, word),
+                    None => line.push(ItemKind::Keyword, i, &format!("[0xFF 0x{:02X}]", code)),
+                }
+                i += 2;
+            }
+            0x81..=0xFC => {
+                line.push(ItemKind::Keyword, i, TOKENS[(byte - 0x81) as usize]);
+                in_rem = byte == REM;
+                in_data = byte == DATA;
+                i += 1;
+            }
+            0x80 | 0xFD | 0xFE => {
+                line.push(ItemKind::Keyword, i, &format!("[0x{:02X}]", byte));
+                i += 1;
+            }
+            _ => {
+                line.push(ItemKind::Text, i, &char_text(byte).to_string());
+                in_data &= byte != b':';
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Decodes a tokenised MSX BASIC file into numbered lines.
+///
+/// # Arguments
+///
+/// * `bytes` - The complete file, starting with 0xFF
+///
+/// # Returns
+///
+/// A `Result` containing the program lines, with a diagnostic if a damaged line stopped
+/// decoding early, or an error if not even the first line could be read. Line links must
+/// agree with each other, whatever address the program was saved from.
+pub fn decode_msx_listing(bytes: &[u8]) -> Result<BasicListing, Error> {
+    if bytes.first() != Some(&MSX_BASIC_MARKER) {
+        return Err(Error::InvalidFormat("Not a tokenised MSX BASIC file."));
+    }
+    let mut listing = BasicListing::default();
+    let mut pointers = Vec::new();
+    // Where in memory offset 0 of the file was, found from the first link
+    let mut origin: Option<usize> = None;
+    let mut pos = 1;
+    loop {
+        let Some(header) = bytes.get(pos..pos + 2) else {
+            return listing.partial(Error::UnexpectedEnd { offset: bytes.len(), line: None });
+        };
+        let link = u16::from_le_bytes([header[0], header[1]]) as usize;
+        if link == 0 {
+            break;
+        }
+        let Some(header) = bytes.get(pos + 2..pos + 4) else {
+            return listing.partial(Error::UnexpectedEnd { offset: bytes.len(), line: None });
+        };
+        let number = u16::from_le_bytes([header[0], header[1]]);
+        let (line, next, line_pointers) = match decode_line(bytes, pos + 4, number) {
+            Ok(decoded) => decoded,
+            Err(e) => return listing.partial(e),
+        };
+        if link < next || origin.is_some_and(|origin| link - next != origin) {
+            return listing.partial(Error::BadLineLink { offset: pos, line: number, link });
+        }
+        origin = Some(link - next);
+        let index = listing.lines.len();
+        pointers.extend(line_pointers.into_iter().map(|(offset, address)| LinePointer { line: index, offset, address }));
+        listing.lines.push(line.into_line(number, pos, next - pos));
+        pos = next;
+    }
+    if listing.lines.is_empty() {
+        return Err(Error::NothingFound("No valid BASIC program found in the file."));
+    }
+    resolve_pointers(&mut listing, &pointers, origin.unwrap_or(0));
+    Ok(listing)
+}
+
+/// Lists line pointers as the numbers of the lines they point to. A pointer holds the
+/// address of the byte before its line.
+fn resolve_pointers(listing: &mut BasicListing, pointers: &[LinePointer], origin: usize) {
+    for pointer in pointers {
+        let target = pointer.address as usize + 1;
+        let Some(number) = listing.lines.iter().find(|line| origin + line.offset == target).map(|line| line.number)
+        else {
+            continue;
+        };
+        let line = &mut listing.lines[pointer.line];
+        let Some(item) = line.items.iter_mut().find(|item| item.offset == pointer.offset) else {
+            continue;
+        };
+        item.kind = ItemKind::Number;
+        item.text = number.to_string();
+        item.value = Some(number as f64);
+        line.text = line.items.iter().map(|item| item.text.as_str()).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::msx_program;
+
+    fn program() -> Vec<u8> {
+        msx_program(&[
+            (10, &[0x91, b'"', b'H', b'I', b'"', b';', 0x0F, 12]),
+            (20, &[b'X', 0xEF, 0x0C, 0xFF, 0x00, b':', b'Y', 0xEF, 0x1D, 0x41, 0x15, 0x00, 0x00]),
+            (30, &[0x91, 0xFF, 0x81, b'(', b'A', b'$', b',', 0x12, b')', b':', 0x8F, 0xE6, b'H', b'I']),
+            (40, &[0x8B, b'X', 0xEE, 0x11, 0xDA, 0x0E, 10, 0, b':', 0xA1, 0x0D, 0x00, 0x80]),
+            (50, &[0x84, b'1', b',', b'A', b':', 0x81]),
+        ])
+    }
+
+    #[test]
+    fn lines_are_listed() {
+        let listing = decode_msx_listing(&program()).unwrap();
+        assert!(listing.diagnostics.is_empty());
+        assert_eq!(
+            listing.to_text(0),
+            "10 PRINT\"HI\";12\n\
+             20 X=&HFF:Y=1.5!\n\
+             30 PRINTLEFT$(A$,1)'HI\n\
+             40 IFX>0THEN10ELSE10\n\
+             50 DATA1,A:END\n"
+        );
+    }
+
+    #[test]
+    fn numbers_are_rebuilt() {
+        let number = |bytes: &[u8]| bcd_number(bytes).0;
+        assert_eq!(number(&[0x41, 0x15, 0, 0]), "1.5!");
+        assert_eq!(number(&[0xC3, 0x12, 0x30, 0]), "-123!");
+        assert_eq!(number(&[0x40, 0x50, 0, 0]), ".5!");
+        assert_eq!(number(&[0x45, 0x12, 0x34, 0x50, 0, 0, 0, 0]), "12345#");
+        assert_eq!(number(&[0x4A, 0x12, 0x34, 0x56, 0x78, 0x90, 0x12, 0x34]), "1234567890.1234");
+        assert_eq!(number(&[0x48, 0x12, 0x34, 0x56]), "1.23456E+07");
+        assert_eq!(number(&[0, 0, 0, 0]), "0!");
+        assert_eq!(bcd_number(&[0x3E, 0x25, 0, 0]).1, 0.0025);
+    }
+
+    #[test]
+    fn line_pointers_list_as_line_numbers() {
+        let listing = decode_msx_listing(&program()).unwrap();
+        // The ELSE target, saved as the address of line 10
+        let target = listing.lines[3].items.last().unwrap();
+        assert_eq!((target.kind, target.value), (ItemKind::Number, Some(10.0)));
+    }
+
+    #[test]
+    fn broken_links_are_reported() {
+        let mut program = program();
+        // Line 20's link now points into the middle of line 30
+        program[0x0E] += 1;
+        let listing = decode_msx_listing(&program).unwrap();
+        assert_eq!(listing.lines.len(), 1);
+        assert_eq!(listing.diagnostics.len(), 1);
+        assert!(matches!(decode_msx_listing(&[0xFE, 0, 0]), Err(Error::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn bsave_files_are_parsed() {
+        let binary = parse_bload(&[0xFE, 0x00, 0xC0, 0x02, 0xC0, 0x01, 0xC0, 0x3E, 0x01, 0xC9, 0x00]).unwrap();
+        assert_eq!(binary, MSXBinary { start: 0xC000, end: 0xC002, exec: 0xC001, data: vec![0x3E, 0x01, 0xC9] });
+        assert!(matches!(parse_bload(&[0xFE, 0, 0]), Err(Error::TruncatedHeader { expected: 7, found: 3, .. })));
+    }
+}
@@ -0,0 +1,134 @@
+//! Sharp MZ series files: MZF/MZT tape files, cassette recordings,
+//! Quick Disk and floppy disk images, and the SA-5510, SP-5025 and 1Z-013B BASICs.
+//!
+//! ```no_run
+//! use rust_wasm_converter::mz::{self, MZBasicVersion};
+//!
+//! let data = std::fs::read("GAME.mzf").unwrap();
+//! let file = mz::MZFFile::parse(&data).unwrap();
+//! println!("{} loads at {:04X}", file.header.name_string(), file.header.load_address);
+//! for line in mz::decode_basic(&data, MZBasicVersion::SP5025).unwrap().lines {
+//!     println!("{} {}", line.number, line.text);
+//! }
+//! ```
+
+pub use crate::basic::{BasicItem, BasicLine, BasicListing, ItemKind};
+pub use crate::basic_analysis::{AddressUse, LineReference, ProgramAnalysis, VariableUse};
+pub use crate::basic_format::FormatOptions;
+pub use crate::basic_renumber::RenumberedProgram;
+pub use crate::error::Error;
+pub use crate::mz_decoder::MZBasicVersion;
+pub use crate::mz_number::{mz_float, mz_float_bytes, mz_number_string};
+pub use crate::mz_trailing::TrailingData;
+pub use crate::mz_tape::{decode_mz_pulses, decode_mz_wav, encode_mz_wav, mz_checksum, MZTapeFile, MZTapeOptions};
+pub use crate::mzf_file::{parse_mzt, MZFHeader, MZTEntry, MZF_HEADER_SIZE};
+pub use crate::qd_image::{parse_qd_image, read_qd_blocks, QDBlock, QDFile};
+pub use crate::sharp_disk::{parse_sharp_disk, DiskFile};
+
+/// An MZF file split into its header and body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MZFFile {
+    pub header: MZFHeader,
+    /// The body bytes, as far as the file holds them.
+    pub body: Vec<u8>,
+}
+
+impl MZFFile {
+    /// Parses an MZF file. A body shorter than the header's size field is kept as it is.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let header = MZFHeader::parse(data)?;
+        let end = (MZF_HEADER_SIZE + header.size as usize).min(data.len());
+        Ok(Self { header, body: data[MZF_HEADER_SIZE..end].to_vec() })
+    }
+}
+
+/// Decodes the BASIC program in an MZF file.
+///
+/// # Arguments
+///
+/// * `mzf` - The complete MZF file, header included
+/// * `version` - The BASIC dialect the program was saved from
+///
+/// # Returns
+///
+/// A `Result` containing the program lines, or an error if not even the first line could be read.
+/// A damaged program is listed up to the damage, with the reason in `diagnostics`.
+///
+/// ```
+/// use rust_wasm_converter::mz::{self, MZBasicVersion};
+///
+/// // An MZF file holding line 10 with the given statements
+/// let mzf = |statements: &[u8]| {
+///     let mut data = vec![0; 128];
+///     data[0] = 2;
+///     data.extend([statements.len() as u8 + 5, 0, 10, 0]);
+///     data.extend(statements);
+///     data.extend([0x0D, 0, 0]);
+///     data
+/// };
+/// let text = |statements: &[u8], version| mz::decode_basic(&mzf(statements), version).unwrap().lines[0].text.clone();
+///
+/// // PRINT "HI":GOTO 10 in each dialect
+/// assert_eq!(text(b"\x80\x88 \"HI\":\x80\x8D \x0B\x0A\x00", MZBasicVersion::SA5510), "PRINT \"HI\":GOTO 10");
+/// assert_eq!(text(b"\x85 \"HI\":\x89 \x0B\x0A\x00", MZBasicVersion::SP5025), "PRINT \"HI\":GOTO 10");
+/// assert_eq!(text(b"\x8F \"HI\":\x80 \x0B\x0A\x00", MZBasicVersion::V1Z013B), "PRINT \"HI\":GOTO 10");
+/// // Codes the tables do not name are shown, not dropped
+/// assert_eq!(text(b"\x85 \xDC", MZBasicVersion::SP5025), "PRINT [0xDC]");
+/// assert_eq!(text(b"\xFE\x80", MZBasicVersion::V1Z013B), "[0xFE 0x80]");
+/// ```
+pub fn decode_basic(mzf: &[u8], version: MZBasicVersion) -> Result<BasicListing, Error> {
+    crate::mz_decoder::decode_mz_listing(mzf, version)
+}
+
+/// Decodes a damaged BASIC program, such as one from a bad tape read.
+///
+/// Instead of stopping at the first damaged line, decoding resumes at the next run of
+/// intact lines (sane lengths, rising line numbers, proper terminators).
+///
+/// # Arguments
+///
+/// * `mzf` - The complete MZF file, header included
+/// * `version` - The BASIC dialect the program was saved from
+///
+/// # Returns
+///
+/// A `Result` containing every salvaged line, with the offsets of each skipped range in
+/// `diagnostics`, or an error if no intact line was found.
+pub fn recover_basic(mzf: &[u8], version: MZBasicVersion) -> Result<BasicListing, Error> {
+    crate::mz_decoder::recover_mz_listing(mzf, version)
+}
+
+/// Renumbers a BASIC program, keeping the MZF format.
+///
+/// # Arguments
+///
+/// * `mzf` - The complete MZF file, header included
+/// * `version` - The BASIC dialect the program was saved from
+/// * `start` - The first new line number
+/// * `step` - The gap between new line numbers
+///
+/// # Returns
+///
+/// A `Result` containing the renumbered file, with GOTO, GOSUB, THEN, RUN, LIST and
+/// RESTORE targets (ON lists included) changed to match, and notes on any computed or
+/// missing targets that were left alone. Damaged programs are refused.
+pub fn renumber_basic(mzf: &[u8], version: MZBasicVersion, start: u16, step: u16) -> Result<RenumberedProgram, Error> {
+    let listing = decode_basic(mzf, version)?;
+    crate::basic_renumber::renumber(mzf, &listing, crate::basic_renumber::LineFormat::Mz, start, step)
+}
+
+/// Finds what an MZF file holds after its BASIC program: saved variables, appended machine
+/// code or data, as raw bytes.
+///
+/// # Arguments
+///
+/// * `mzf` - The complete MZF file, header included
+/// * `version` - The BASIC dialect the program was saved from
+///
+/// # Returns
+///
+/// A `Result` containing the bytes after the program's end marker with their offset and
+/// load address, `None` if the program is all there is, or an error if the program is too damaged to find its end.
+pub fn trailing_data(mzf: &[u8], version: MZBasicVersion) -> Result<Option<TrailingData>, Error> {
+    crate::mz_trailing::trailing_data(mzf, version)
+}
@@ -1,9 +1,22 @@
 // src/mz_decoder.rs
 
+use crate::basic::{BasicLine, BasicListing, ItemKind, LineBuilder};
+use crate::error::Error;
+use crate::mz_number::{mz_float, mz_number_string};
+use crate::mzf_file::MZF_HEADER_SIZE;
 use std::collections::HashMap;
-use std::io::{self, ErrorKind};
 
-// Token tables for SA-5510
+/// Longest line the recovery mode accepts. A 255 character input line tokenises to well
+/// under this, even with every number stored in its 6-byte floating point form.
+const MAX_LINE_LENGTH: u16 = 0x200;
+
+// The token tables follow those of the tautology0 detokenisers
+// (https://github.com/tautology0/detokenisers). An empty entry is a code those tables do
+// not name; such codes have not been checked against a ROM listing, so the decoder shows
+// them as their bytes (`[0x82]`) rather than guessing.
+
+// Token tables for SA-5510: statements, stored after a 0x80 prefix from 0x80 on, and
+// functions and operators, stored as single bytes from 0x80 on.
 const TOKENS1: [&str; 56] = [
     "REM", "DATA", "", "", "READ", "LIST", "RUN", "NEW", "PRINT", "LET", "FOR",
     "IF", "THEN", "GOTO", "GOSUB", "RETURN", "NEXT", "STOP", "END", "", "ON",
@@ -16,13 +29,15 @@ const TOKENS1: [&str; 56] = [
 const TOKENS2: [&str; 76] = [
     "", "", "", "><", "<>", "=<", "<=", "=>", ">=", "", ">", "<", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
     "TO", "STEP", "LEFT$(", "RIGHT$(", "MID$(", "LEN(", "CHR$(", "STR$(", "ASC(", "VAL(", "PEEK(", "TAB(", "SPACE$(",
-    "SIZE", "", "", "", "STRING$(", "", "CHARACTER$(", "CRS", "CRS", "", "", "", "", "","", "", "","", "", "", "","RND(", "SIN(", "COS(", "TAN(",
+    // The source tables name both 0xB2 and 0xB3 CRS. Only one can be, and without a ROM
+    // listing to say which, the first is kept and 0xB3 shows as its byte
+    "SIZE", "", "", "", "STRING$(", "", "CHARACTER$(", "CRS", "", "", "", "", "", "","", "", "","", "", "", "","RND(", "SIN(", "COS(", "TAN(",
     "ATN(", "EXP(", "INT(", "LOG(", "LN(", "ABS(", "SGN(", "SQR("
 ];
 
 // Token tables for SP-5025
 const TOKENS1_SP5025: [&str; 92] = [
-    "REM", "DATA", "LIST", "RUN", "NEW", "PRINT", "LET", "FOR", "IF", "GOTO", "read",
+    "REM", "DATA", "LIST", "RUN", "NEW", "PRINT", "LET", "FOR", "IF", "GOTO", "READ",
     "GOSUB", "RETURN", "NEXT", "STOP", "END", "ON", "LOAD", "SAVE", "VERIFY", "POKE", "DIM",
     "DEF FN", "INPUT", "RESTORE", "CLR", "MUSIC", "TEMPO", "USR(", "WOPEN", "ROPEN", "CLOSE", "BYE",
     "LIMIT", "CONT", "SET", "RESET", "GET", "INP#", "OUT#", "", "", "", "",
@@ -32,24 +47,27 @@ const TOKENS1_SP5025: [&str; 92] = [
     "SIN(", "COS(", "TAN(", "ATN(", "EXP(", "INT(", "LOG(", "LN(", "ABS(", "SGN(", "SQR("
 ];
 
-// Token tables for 1Z-013B BASIC
+// Token tables for 1Z-013B BASIC: single bytes from 0x80 to 0xFD, then 0xFE and 0xFF
+// prefixing a second byte (from 0x80) for the statements and the functions added later.
 const TOKENS_1Z013B: [&str; 128] = [
     "GOTO", "GOSUB" , "", "RUN", "RETURN", "RESTORE", "RESUME", "LIST", "", "DELETE", "RENUMBER", "AUTO", "", "FOR", "NEXT", "PRINT",
-    "", "INsPUT", "", "IF", "DATA", "READ", "DIM", "REM", "END", "STOP", "CONT", "CLS", "", "ON", "LET", "NEW",
+    "", "INPUT", "", "IF", "DATA", "READ", "DIM", "REM", "END", "STOP", "CONT", "CLS", "", "ON", "LET", "NEW",
     "POKE", "OFF", "MODE", "SKIP", "PLOT", "LINE", "RLINE", "MOVE", "RMOVE", "TRON", "TROFF", "INP#", "", "GET", "PCOLOR", "PHOME",
     "HSET", "GPRINT", "KEY", "AXIS", "LOAD", "SAVE", "MERGE", "", "CONSOLE", "", "OUT", "CIRCLE", "TEST", "PAGE", "", "",
     "ERASE", "ERROR", "", "USR", "BYE", "", "", "DEF", "", "", "", "", "", "", "WOPEN", "CLOSE",
     "ROPEN", "", "", "", "", "", "", "", "", "KILL", "", "", "", "", "", "",
     "TO", "STEP", "THEN", "USING", "", "", "TAB", "SPC", "", "", "", "OR", "AND", "", "><", "<>",
-    "=<", "<=", "=>", ">=", "=", ">", "<", "+", "-", "", "", "/", "*", "^","ext1", "ext2"
+    "=<", "<=", "=>", ">=", "=", ">", "<", "+", "-", "", "", "/", "*", "^", "", ""
 ];
 
+/// 1Z-013B statements after the 0xFE prefix.
 const TOKENS_1Z013B_E1: [&str; 48] =  [
     "", "SET", "RESET", "COLOR", "", "", "", "", "", "", "", "", "", "", "", "",
     "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
     "", "", "MUSIC", "TEMPO", "CURSOR", "VERIFY", "CLR", "LIMIT", "", "", "", "", "", "", "BOOT", ""
 ];
 
+/// 1Z-013B functions after the 0xFF prefix.
 const TOKENS_1Z013B_E2: [&str; 72] =  [
     "INT", "ABS", "SIN", "COS", "TAN", "LN", "EXP", "SQR", "RND", "PEEK", "ATN", "SGN", "LOG", "PAI", "", "RAD",
     "", "", "", "", "", "EOF", "", "", "", "", "", "", "", "", "JOY", "",
@@ -66,6 +84,65 @@ pub enum MZBasicVersion {
     V1Z013B, // 1Z-013B BASIC version
 }
 
+impl MZBasicVersion {
+    /// The version selected by a `process_binary` mode: "SA", "SP" or "1Z".
+    pub fn from_mode(mode: &str) -> Option<Self> {
+        match mode {
+            "SA" => Some(MZBasicVersion::SA5510),
+            "SP" => Some(MZBasicVersion::SP5025),
+            "1Z" => Some(MZBasicVersion::V1Z013B),
+            _ => None,
+        }
+    }
+
+    /// The keyword a token stands for.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The token as stored: one byte, or for SA-5510 statements and the 1Z-013B
+    ///   extended tokens, the prefix byte and the token byte
+    ///
+    /// # Returns
+    ///
+    /// The keyword as listed, or `None` for a code the tables do not name.
+    ///
+    /// ```
+    /// use rust_wasm_converter::mz::MZBasicVersion;
+    ///
+    /// assert_eq!(MZBasicVersion::SA5510.keyword(&[0x80, 0x8D]), Some("GOTO"));
+    /// assert_eq!(MZBasicVersion::SA5510.keyword(&[0x9E]), Some("TO"));
+    /// assert_eq!(MZBasicVersion::SA5510.keyword(&[0x80, 0x82]), None);
+    /// assert_eq!(MZBasicVersion::SP5025.keyword(&[0x8A]), Some("READ"));
+    /// assert_eq!(MZBasicVersion::SP5025.keyword(&[0xDB]), Some("SQR("));
+    /// assert_eq!(MZBasicVersion::SP5025.keyword(&[0xDC]), None);
+    /// assert_eq!(MZBasicVersion::V1Z013B.keyword(&[0x91]), Some("INPUT"));
+    /// assert_eq!(MZBasicVersion::V1Z013B.keyword(&[0xFE, 0xA2]), Some("MUSIC"));
+    /// assert_eq!(MZBasicVersion::V1Z013B.keyword(&[0xFF, 0xBC]), Some("MID$"));
+    /// assert_eq!(MZBasicVersion::V1Z013B.keyword(&[0xFE]), None);
+    /// ```
+    pub fn keyword(self, code: &[u8]) -> Option<&'static str> {
+        let entry = |table: &[&'static str], byte: u8| {
+            table.get(byte.checked_sub(0x80)? as usize).copied().filter(|keyword| !keyword.is_empty())
+        };
+        use MZBasicVersion::*;
+        match (self, code) {
+            (SA5510, &[0x80, byte]) => entry(&TOKENS1, byte),
+            (SA5510, &[byte]) if byte != 0x80 => entry(&TOKENS2, byte),
+            (SP5025, &[byte]) => entry(&TOKENS1_SP5025, byte),
+            (V1Z013B, &[0xFE, byte]) => entry(&TOKENS_1Z013B_E1, byte),
+            (V1Z013B, &[0xFF, byte]) => entry(&TOKENS_1Z013B_E2, byte),
+            (V1Z013B, &[byte]) if byte < 0xFE => entry(&TOKENS_1Z013B, byte),
+            _ => None,
+        }
+    }
+}
+
+/// How a token the tables do not name is listed: its bytes in hex, in brackets.
+fn unknown_token(code: &[u8]) -> String {
+    let bytes: Vec<String> = code.iter().map(|b| format!("0x{:02X}", b)).collect();
+    format!("[{}]", bytes.join(" "))
+}
+
 /// Struct responsible for detokenizing MZ-series BASIC code.
 pub struct MZDecoder {
     sharp_ascii: HashMap<u8, char>,
@@ -108,221 +185,582 @@ impl MZDecoder {
         }
     }
 
-    /// Returns the appropriate token tables based on the detected BASIC version.
-    fn get_token_tables(&self) -> (&[&str], &[&str], &[&str]) {
-        match self.version {
-            MZBasicVersion::SP5025 => (&TOKENS1_SP5025, &[], &[]),
-            MZBasicVersion::V1Z013B => (&TOKENS_1Z013B, &TOKENS_1Z013B_E1, &TOKENS_1Z013B_E2),
-            MZBasicVersion::SA5510 => (&TOKENS1, &TOKENS2, &[]),
+    /// Adds the keyword for the token `code` at `at`, or the token's bytes if the tables
+    /// do not name it, so that nothing in the program is lost from the listing.
+    fn push_token(&self, line: &mut LineBuilder, at: usize, code: &[u8]) {
+        match self.version.keyword(code) {
+            Some(keyword) => line.push(ItemKind::Keyword, at, keyword),
+            None => line.push(ItemKind::Keyword, at, &unknown_token(code)),
         }
     }
 
     // Read a single byte from the data stream
-    fn read_u8(data: &[u8], offset: &mut usize) -> io::Result<u8> {
+    fn read_u8(data: &[u8], offset: &mut usize) -> Result<u8, Error> {
         if *offset < data.len() {
             let byte = data[*offset];
             *offset += 1;
             Ok(byte)
         } else {
-            Err(io::Error::new(ErrorKind::UnexpectedEof, "Unexpected end of data"))
+            Err(Error::UnexpectedEnd { offset: *offset, line: None })
         }
     }
 
-    fn read_u16(data: &[u8], offset: &mut usize) -> io::Result<u16> {
+    fn read_u16(data: &[u8], offset: &mut usize) -> Result<u16, Error> {
         if *offset + 2 <= data.len() {
             let value = u16::from_le_bytes([data[*offset], data[*offset + 1]]);
             *offset += 2;
             Ok(value)
         } else {
-            Err(io::Error::new(ErrorKind::UnexpectedEof, "Unexpected end of data"))
+            Err(Error::UnexpectedEnd { offset: *offset, line: None })
         }
     }
 
+    /// Reads the byte after a two-byte token prefix, which must itself be in the token range.
+    fn read_token(data: &[u8], offset: &mut usize, lineno: u16) -> Result<u8, Error> {
+        let byte = Self::read_u8(data, offset)?;
+        if byte < 0x80 {
+            return Err(Error::UnknownToken { offset: *offset - 1, line: lineno, token: byte });
+        }
+        Ok(byte)
+    }
+
     /// Detokenizes the BASIC code from the provided binary data.
     /// This is the core logic for converting the binary tokens into human-readable BASIC.
-    fn detokenise_basic(&self, data: &[u8]) -> io::Result<String> {
-        let mut output = String::new();
-        let (tokens1, tokens2, tokens3) = self.get_token_tables();
-        let mut offset = 128; // Start after the header
-
-        loop {
-            let line_length = match Self::read_u16(data, &mut offset) {
-                Ok(len) => len,
-                Err(_) => break,
-            };
+    /// Decoding stops at the first damaged line, keeping the lines before it.
+    fn detokenise_basic(&self, data: &[u8]) -> Result<BasicListing, Error> {
+        if data.len() < MZF_HEADER_SIZE {
+            return Err(Error::TruncatedHeader { format: "MZF", expected: MZF_HEADER_SIZE, found: data.len() });
+        }
+        let mut listing = BasicListing::default();
+        let mut offset = MZF_HEADER_SIZE; // Start after the header
 
+        while let Ok(line_length) = Self::read_u16(data, &mut offset) {
             if line_length == 0 {
                 break;
             }
+            match self.read_line(data, &mut offset, line_length) {
+                Ok(line) => listing.lines.push(line),
+                Err(e) => return listing.partial(e),
+            }
+        }
 
-            let lineno = Self::read_u16(data, &mut offset)?;
-            let mut line = format!("{} ", lineno);
+        Ok(listing)
+    }
 
-            let mut quote = false;
-            let mut token = false;
-            let mut literal_mode = false;
-            let mut line_end = false;
-            let mut bytes_read = 4;
+    /// Reads the line number and statements of a line whose length field has just been read.
+    fn read_line(&self, data: &[u8], offset: &mut usize, line_length: u16) -> Result<BasicLine, Error> {
+        let start = *offset - 2;
+        let lineno = Self::read_u16(data, offset)?;
+        // The length includes itself, the line number and the terminator
+        if line_length < 5 {
+            return Err(Error::BadLineLink { offset: start, line: lineno, link: line_length as usize });
+        }
+        let mut line = self
+            .detokenise_line(data, offset, line_length, lineno)
+            .map_err(|e| e.in_line(lineno))?;
+        line.trim_end_newlines();
+        Ok(line.into_line(lineno, start, *offset - start))
+    }
 
-            while !line_end && bytes_read < line_length {
-                let byte = Self::read_u8(data, &mut offset)?;
-                bytes_read += 1;
+    /// Detokenizes a damaged program, skipping the bytes that do not form intact lines.
+    /// After a damaged line, decoding resumes at the next offset holding two intact lines
+    /// in a row (or one intact line followed by the end of the program). Every skipped
+    /// range is recorded as a diagnostic with its offsets.
+    fn recover_basic(&self, data: &[u8]) -> Result<BasicListing, Error> {
+        if data.len() < MZF_HEADER_SIZE {
+            return Err(Error::TruncatedHeader { format: "MZF", expected: MZF_HEADER_SIZE, found: data.len() });
+        }
+        let mut listing = BasicListing::default();
+        let mut offset = MZF_HEADER_SIZE;
+        let mut previous: Option<u16> = None;
 
-                if literal_mode {
-                    if byte == 0x0D || byte == 0x00 {
-                        line.push('\n');
-                    } else if let Some(&ch) = self.sharp_ascii.get(&byte) {
-                        line.push(ch);
-                    } else if (0x20..=0x7E).contains(&byte) {
-                        line.push(byte as char);
-                    } else {
-                        line.push('◇');
-                    }
+        while offset + 2 <= data.len() {
+            if data[offset] == 0 && data[offset + 1] == 0 {
+                break; // End of program
+            }
+            if let Some((line, next)) = self.intact_line(data, offset, previous) {
+                // A damaged line number can still look in order; the line after it gives it away
+                let out_of_order = self
+                    .intact_line(data, next, previous)
+                    .is_some_and(|(following, _)| following.number <= line.number);
+                if !out_of_order {
+                    previous = Some(line.number);
+                    listing.lines.push(line);
+                    offset = next;
                     continue;
                 }
+            } else if let Some((line, next)) = self.relinked_line(data, offset, previous) {
+                listing.diagnostics.push(format!(
+                    "Line {} at {:04X} has a damaged length ({:04X}, should be {:04X})",
+                    line.number,
+                    offset,
+                    u16::from_le_bytes([data[offset], data[offset + 1]]),
+                    next - offset
+                ));
+                previous = Some(line.number);
+                listing.lines.push(line);
+                offset = next;
+                continue;
+            }
 
-                match byte {
-                    0x00 | 0x0D => { 
-                        line.push('\n');
-                        line_end = true;
-                    }
-                    0x0B | 0x0C if !quote => {
-                        let more = Self::read_u16(data, &mut offset)?;
-                        bytes_read += 2;
-                        line.push_str(&more.to_string());
-                    }
-                    0x11 if !quote => {
-                        let more = Self::read_u16(data, &mut offset)?;
-                        bytes_read += 2;
-                        line.push_str(&format!("${:X}", more));
+            let resume = (offset + 1..data.len()).find(|&p| self.resync_point(data, p, previous));
+            let end = resume.unwrap_or(data.len());
+            let after = match previous {
+                Some(number) => format!("after line {}", number),
+                None => "before the first line".to_string(),
+            };
+            listing.diagnostics.push(format!(
+                "Skipped {} damaged bytes at {:04X}-{:04X} {}", end - offset, offset, end - 1, after
+            ));
+            match resume {
+                Some(p) => offset = p,
+                None => break,
+            }
+        }
+
+        if listing.lines.is_empty() {
+            return Err(Error::NothingFound("No intact BASIC lines found in the file."));
+        }
+        Ok(listing)
+    }
+
+    /// Decodes the line at `offset` if it looks intact: a sane length ending on a 0x00 or
+    /// 0x0D terminator, a line number above `previous`, and statements that decode to
+    /// exactly that length. Returns the line and the offset of the next one.
+    fn intact_line(&self, data: &[u8], offset: usize, previous: Option<u16>) -> Option<(BasicLine, usize)> {
+        let line_length = u16::from_le_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+        let next = offset + line_length as usize;
+        if !(5..=MAX_LINE_LENGTH).contains(&line_length) || !matches!(data.get(next - 1), Some(0x00 | 0x0D)) {
+            return None;
+        }
+        let mut end = offset + 2;
+        let line = self.read_line(data, &mut end, line_length).ok()?;
+        if end != next || previous.is_some_and(|p| line.number <= p) {
+            return None;
+        }
+        Some((line, next))
+    }
+
+    /// Decodes the line at `offset` up to its terminator, ignoring a damaged length field.
+    /// The line is only accepted if decoding can resume right after it.
+    fn relinked_line(&self, data: &[u8], offset: usize, previous: Option<u16>) -> Option<(BasicLine, usize)> {
+        let mut end = offset + 2;
+        let line = self.read_line(data, &mut end, MAX_LINE_LENGTH).ok()?;
+        if !matches!(data.get(end - 1), Some(0x00 | 0x0D)) || previous.is_some_and(|p| line.number <= p) {
+            return None;
+        }
+        let resumes = match (data.get(end), data.get(end + 1)) {
+            (None, _) | (Some(0), Some(0)) => true,
+            _ => self.resync_point(data, end, Some(line.number)),
+        };
+        resumes.then_some((line, end))
+    }
+
+    /// True if decoding can resume at `offset`: the line there is intact and is followed
+    /// by another intact line, the end-of-program marker or the end of the data.
+    fn resync_point(&self, data: &[u8], offset: usize, previous: Option<u16>) -> bool {
+        let Some((line, next)) = self.intact_line(data, offset, previous) else {
+            return false;
+        };
+        match (data.get(next), data.get(next + 1)) {
+            (None, _) | (Some(0), Some(0)) => true,
+            _ => self.intact_line(data, next, Some(line.number)).is_some(),
+        }
+    }
+
+    /// Detokenizes one line, leaving `offset` after its terminator.
+    fn detokenise_line(&self, data: &[u8], offset: &mut usize, line_length: u16, lineno: u16) -> Result<LineBuilder, Error> {
+        let mut line = LineBuilder::default();
+
+        let mut quote = false;
+        let mut literal_mode = false;
+        // REM bodies are kept as written; DATA bodies are listed the same way but are program text
+        let mut literal_kind = ItemKind::Rem;
+        let mut line_end = false;
+        let mut bytes_read = 4;
+
+        while !line_end && bytes_read < line_length {
+            let at = *offset;
+            let byte = Self::read_u8(data, offset)?;
+            bytes_read += 1;
+
+            if literal_mode {
+                if byte == 0x0D || byte == 0x00 {
+                    line.push(literal_kind, at, "\n");
+                } else if let Some(&ch) = self.sharp_ascii.get(&byte) {
+                    line.push(literal_kind, at, ch.encode_utf8(&mut [0; 4]));
+                } else if (0x20..=0x7E).contains(&byte) {
+                    line.push(literal_kind, at, (byte as char).encode_utf8(&mut [0; 4]));
+                } else {
+                    line.push(literal_kind, at, "◇");
+                }
+                continue;
+            }
+
+            match byte {
+                0x00 | 0x0D => { 
+                    line.push(ItemKind::Text, at, "\n");
+                    line_end = true;
+                }
+                0x0B | 0x0C if !quote => {
+                    let more = Self::read_u16(data, offset)?;
+                    bytes_read += 2;
+                    line.number(at, &more.to_string(), more as f64);
+                }
+                0x11 if !quote => {
+                    let more = Self::read_u16(data, offset)?;
+                    bytes_read += 2;
+                    line.number(at, &format!("${:X}", more), more as f64);
+                }
+                0x15 if !quote => {
+                    let mut bytes = [0; 5];
+                    for byte in bytes.iter_mut() {
+                        *byte = Self::read_u8(data, offset)?;
                     }
-                    0x15 if !quote => {
-                        let exponent = Self::read_u8(data, &mut offset)?;
-                        bytes_read += 1;
-                        
-                        let exp_val = if exponent & 0x80 != 0 {
-                            (exponent - 0x80) as i32
-                        } else if exponent != 0 {
-                            (0x80 - exponent) as i32
-                        } else {
-                            0
-                        };
-                        
-                        let mut mantissa = 0.0;
-                        let mut count = 1;
-                        
-                        for _ in 0..4 {
-                            let b = Self::read_u8(data, &mut offset)?;
-                            bytes_read += 1;
-                            for j in (1..=7).rev() {
-                                if b & (1 << j) != 0 {
-                                    mantissa += 2.0_f64.powi(-(count as i32));
-                                }
-                                count += 1;
+                    bytes_read += 5;
+                    let fp = mz_float(&bytes);
+                    line.number(at, &mz_number_string(fp), fp);
+                }
+                b if b >= 0x80 && !quote => {
+                    match self.version {
+                        MZBasicVersion::SP5025 => {
+                            self.push_token(&mut line, at, &[b]);
+                            if b == 0x80 || b == 0x81 {
+                                literal_kind = if b == 0x80 { ItemKind::Rem } else { ItemKind::Text };
+                                literal_mode = true;
                             }
                         }
-                        
-                        mantissa += 0.5;
-                        let fp = if exponent != 0 {
-                            2.0_f64.powi(exp_val) * mantissa
-                        } else {
-                            0.0
-                        };
-                        
-                        line.push_str(&fp.to_string());
-                    }
-                    b if b >= 0x80 && !quote && !token => {
-                        match self.version {
-                            MZBasicVersion::SP5025 => {
-                                let tok = (b - 0x80) as usize;
-                                if tok < tokens1.len() {
-                                    line.push_str(tokens1[tok]);
-                                }
-                                if b == 0x80 || b == 0x81 {
-                                    literal_mode = true;
-                                }
+                        MZBasicVersion::V1Z013B => {
+                            if b == 0xfe || b == 0xff {
+                                let next_byte = Self::read_token(data, offset, lineno)?;
+                                bytes_read += 1;
+                                self.push_token(&mut line, at, &[b, next_byte]);
+                            } else {
+                                self.push_token(&mut line, at, &[b]);
                             }
-                            MZBasicVersion::V1Z013B => {
-                                let mut tok = (b - 0x80) as usize;
-                                if b == 0xfe || b == 0xff {
-                                    let next_byte = Self::read_u8(data, &mut offset)?;
-                                    bytes_read += 1;
-                                    tok = (next_byte - 0x80) as usize;
-                                }
-                                match b {
-                                    0xfe if tok < tokens2.len() => line.push_str(tokens2[tok]),
-                                    0xff if tok < tokens3.len() => line.push_str(tokens3[tok]),
-                                    _ if b >= 0x80 => line.push_str(tokens1[tok]),
-                                    _ => line.push_str(&format!(":0x{:02X} 0x{:02X}]", b, tok)),
-                                }
-                                if b == 0x97 || b == 0x94 {
-                                    literal_mode = true;
-                                }
+                            if b == 0x97 || b == 0x94 {
+                                literal_kind = if b == 0x97 { ItemKind::Rem } else { ItemKind::Text };
+                                literal_mode = true;
                             }
-                            MZBasicVersion::SA5510 => {
-                                if b == 0x80 {
-                                    let next_byte = Self::read_u8(data, &mut offset)?;
-                                    bytes_read += 1;
-                                    if next_byte == 0x80 {
-                                        line.push_str("REM");
-                                        literal_mode = true;
-                                    } else if next_byte == 0x81 {
-                                        line.push_str("DATA");
-                                        literal_mode = true;
-                                    } else {
-                                        let tok = (next_byte - 0x80) as usize;
-                                        if tok < tokens1.len() {
-                                            line.push_str(tokens1[tok]);
-                                        }
-                                        if next_byte == 0x80 {
-                                            token = true;
-                                        }
-                                    }
-                                } else {
-                                    let tok = (b - 0x80) as usize;
-                                    if tok < tokens2.len() {
-                                        line.push_str(tokens2[tok]);
-                                    } else {
-                                        line.push_str(&format!("[0x{:02X}]", b));
-                                    }
+                        }
+                        MZBasicVersion::SA5510 => {
+                            if b == 0x80 {
+                                let next_byte = Self::read_token(data, offset, lineno)?;
+                                bytes_read += 1;
+                                self.push_token(&mut line, at, &[b, next_byte]);
+                                if next_byte == 0x80 || next_byte == 0x81 {
+                                    literal_kind = if next_byte == 0x80 { ItemKind::Rem } else { ItemKind::Text };
+                                    literal_mode = true;
                                 }
+                            } else {
+                                self.push_token(&mut line, at, &[b]);
                             }
                         }
                     }
-                    _ => {
-                        if byte == 0x22 {
-                            quote = !quote;
-                            line.push('"');
-                        } else if quote {
-                            if let Some(&literal) = self.string_literal_map.get(&byte) {
-                                line.push_str(literal);
-                            } else if let Some(&ch) = self.sharp_ascii.get(&byte) {
-                                line.push(ch);
-                            } else if (0x20..=0x7E).contains(&byte) {
-                                line.push(byte as char);
-                            }
+                }
+                _ => {
+                    if byte == 0x22 {
+                        quote = !quote;
+                        line.push(ItemKind::String, at, "\"");
+                    } else if quote {
+                        if let Some(&literal) = self.string_literal_map.get(&byte) {
+                            line.push(ItemKind::String, at, literal);
                         } else if let Some(&ch) = self.sharp_ascii.get(&byte) {
-                            line.push(ch);
+                            line.push(ItemKind::String, at, ch.encode_utf8(&mut [0; 4]));
                         } else if (0x20..=0x7E).contains(&byte) {
-                            line.push(byte as char);
-                        }
-
-                        if byte == 0x3A {
-                            token = false;
+                            line.push(ItemKind::String, at, (byte as char).encode_utf8(&mut [0; 4]));
                         }
+                    } else if let Some(length) = number_literal(&data[at..], line.text()) {
+                        let text: String = data[at..at + length].iter().map(|&b| b as char).collect();
+                        *offset = at + length;
+                        bytes_read += length as u16 - 1;
+                        line.number(at, &text, text.parse().unwrap_or(0.0));
+                    } else if let Some(&ch) = self.sharp_ascii.get(&byte) {
+                        line.push(ItemKind::Text, at, ch.encode_utf8(&mut [0; 4]));
+                    } else if (0x20..=0x7E).contains(&byte) {
+                        line.push(ItemKind::Text, at, (byte as char).encode_utf8(&mut [0; 4]));
                     }
                 }
             }
+        }
+
+        Ok(line)
+    }
+}
 
-            output.push_str(&line);
+/// The length of a number typed in ASCII at the start of `bytes` (digits, a point and an
+/// exponent such as `E-3`), unless the digits end a variable name, as in `A1`.
+fn number_literal(bytes: &[u8], before: &str) -> Option<usize> {
+    if before.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '$') {
+        return None;
+    }
+    let digits = |from: usize| bytes[from.min(bytes.len())..].iter().take_while(|b| b.is_ascii_digit()).count();
+    let whole = digits(0);
+    let mut length = whole;
+    if bytes.get(length) == Some(&b'.') {
+        length += 1 + digits(length + 1);
+    }
+    if length == 0 || (whole == 0 && length == 1) {
+        return None;
+    }
+    if bytes.get(length) == Some(&b'E') {
+        let sign = usize::from(matches!(bytes.get(length + 1), Some(b'+' | b'-')));
+        let exponent = digits(length + 1 + sign);
+        if exponent > 0 {
+            length += 1 + sign + exponent;
         }
+    }
+    Some(length)
+}
 
-        Ok(output)
+/// Decodes the BASIC program in an MZF file into numbered lines.
+///
+/// # Arguments
+///
+/// * `data` - The complete MZF file (the 128-byte header is skipped)
+/// * `version` - The BASIC dialect the program was saved from
+///
+/// # Returns
+///
+/// A `Result` containing the program lines, with a diagnostic if a damaged line or the end
+/// of the data stopped decoding early, or an error if not even the first line could be read.
+pub fn decode_mz_listing(data: &[u8], version: MZBasicVersion) -> Result<BasicListing, Error> {
+    MZDecoder::new(version).detokenise_basic(data)
+}
+
+/// Decodes a damaged BASIC program in an MZF file, resynchronising on intact lines.
+///
+/// # Arguments
+///
+/// * `data` - The complete MZF file (the 128-byte header is skipped)
+/// * `version` - The BASIC dialect the program was saved from
+///
+/// # Returns
+///
+/// A `Result` containing every line that could be salvaged, with a diagnostic giving the
+/// offsets of each skipped range, or an error if no intact line was found.
+pub fn recover_mz_listing(data: &[u8], version: MZBasicVersion) -> Result<BasicListing, Error> {
+    MZDecoder::new(version).recover_basic(data)
+}
+
+/// Tells SA-5510 programs from SP-5025 ones, which are saved with the same attribute.
+///
+/// SA-5510 stores statements as 0x80 followed by a second token, while SP-5025 stores
+/// them as single bytes and uses 0x80 only for REM, whose text follows. The lines that
+/// start with a statement are counted both ways.
+///
+/// # Arguments
+///
+/// * `data` - The complete MZF file (the 128-byte header is skipped)
+///
+/// # Returns
+///
+/// `SA5510` if more lines start like SA-5510 statements, otherwise `SP5025`.
+pub fn guess_mz_version(data: &[u8]) -> MZBasicVersion {
+    let (mut sa, mut sp) = (0, 0);
+    let mut offset = MZF_HEADER_SIZE;
+    while offset + 6 <= data.len() {
+        let line_length = u16::from_le_bytes([data[offset], data[offset + 1]]) as usize;
+        if line_length < 5 {
+            break;
+        }
+        match (data[offset + 4], data[offset + 5]) {
+            (0x80, second) if second >= 0x80 => sa += 1,
+            (first, _) if first >= 0x80 => sp += 1,
+            _ => {}
+        }
+        offset += line_length;
     }
+    if sa > sp { MZBasicVersion::SA5510 } else { MZBasicVersion::SP5025 }
 }
 
-/// Public function to decode MZ BASIC bytes
-pub fn decode_mz_bytes(data: &[u8], version: MZBasicVersion) -> io::Result<String> {
-    let decoder = MZDecoder::new(version);
-    decoder.detokenise_basic(data)
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::mzf;
+
+    /// Codes and the keywords they stand for, written out by hand so that the test does not
+    /// read the tables it checks. A table that shifts by an entry fails here.
+    const SA5510_CODES: [(&[u8], &str); 15] = [
+        (&[0x80, 0x88], "PRINT"),
+        (&[0x80, 0x8A], "FOR"),
+        (&[0x80, 0x8D], "GOTO"),
+        (&[0x80, 0x8E], "GOSUB"),
+        (&[0x80, 0x92], "END"),
+        (&[0x80, 0x99], "DIM"),
+        (&[0x80, 0x9B], "INPUT"),
+        (&[0x84], "<>"),
+        (&[0x9E], "TO"),
+        (&[0x9F], "STEP"),
+        (&[0xA4], "CHR$("),
+        (&[0xA8], "PEEK("),
+        (&[0xB2], "CRS"),
+        (&[0xC0], "RND("),
+        (&[0xCB], "SQR("),
+    ];
+
+    const SP5025_CODES: [(&[u8], &str); 12] = [
+        (&[0x85], "PRINT"),
+        (&[0x87], "FOR"),
+        (&[0x89], "GOTO"),
+        (&[0x8B], "GOSUB"),
+        (&[0x8F], "END"),
+        (&[0x97], "INPUT"),
+        (&[0xAD], "THEN"),
+        (&[0xAE], "TO"),
+        (&[0xB9], "AND"),
+        (&[0xC4], "CHR$("),
+        (&[0xD0], "RND("),
+        (&[0xDB], "SQR("),
+    ];
+
+    const V1Z013B_CODES: [(&[u8], &str); 16] = [
+        (&[0x80], "GOTO"),
+        (&[0x8D], "FOR"),
+        (&[0x8F], "PRINT"),
+        (&[0x91], "INPUT"),
+        (&[0x93], "IF"),
+        (&[0x98], "END"),
+        (&[0x9B], "CLS"),
+        (&[0xE0], "TO"),
+        (&[0xE2], "THEN"),
+        (&[0xFE, 0x83], "COLOR"),
+        (&[0xFE, 0xA4], "CURSOR"),
+        (&[0xFF, 0x80], "INT"),
+        (&[0xFF, 0x88], "RND"),
+        (&[0xFF, 0xA1], "STR$"),
+        (&[0xFF, 0xBA], "LEFT$"),
+        (&[0xFF, 0xC4], "TI$"),
+    ];
+
+    fn known_codes(version: MZBasicVersion) -> &'static [(&'static [u8], &'static str)] {
+        match version {
+            MZBasicVersion::SA5510 => &SA5510_CODES,
+            MZBasicVersion::SP5025 => &SP5025_CODES,
+            MZBasicVersion::V1Z013B => &V1Z013B_CODES,
+        }
+    }
+
+    /// A program with one line for each code, numbered from 1.
+    fn program(codes: &[(&[u8], &str)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (number, (code, _)) in codes.iter().enumerate() {
+            body.extend(((5 + code.len()) as u16).to_le_bytes());
+            body.extend((number as u16 + 1).to_le_bytes());
+            body.extend(*code);
+            body.push(0x00);
+        }
+        body.extend([0, 0]);
+        mzf(0x02, "TOKENS", 0x4F88, 0, &body)
+    }
+
+    #[test]
+    fn known_codes_list_as_their_keywords() {
+        for version in [MZBasicVersion::SA5510, MZBasicVersion::SP5025, MZBasicVersion::V1Z013B] {
+            let codes = known_codes(version);
+            let listing = decode_mz_listing(&program(codes), version).unwrap();
+            assert!(listing.diagnostics.is_empty(), "{:?}: {:?}", version, listing.diagnostics);
+            let texts: Vec<_> = listing.lines.iter().map(|line| line.text.trim_end()).collect();
+            let keywords: Vec<_> = codes.iter().map(|&(_, keyword)| keyword).collect();
+            assert_eq!(texts, keywords, "{:?}", version);
+            for &(code, keyword) in codes {
+                assert_eq!(version.keyword(code), Some(keyword), "{:?} {:02X?}", version, code);
+            }
+        }
+        // Codes no table names show as their bytes
+        assert_eq!(MZBasicVersion::SA5510.keyword(&[0xB3]), None);
+        let unnamed: &[(&[u8], &str)] = &[(&[0xB3], ""), (&[0x80, 0x82], "")];
+        let listing = decode_mz_listing(&program(unnamed), MZBasicVersion::SA5510).unwrap();
+        assert_eq!(listing.lines[0].text.trim_end(), "[0xB3]");
+        assert_eq!(listing.lines[1].text.trim_end(), "[0x80 0x82]");
+    }
+
+    /// An SP-5025 line: length, number, statements and the 0x00 terminator.
+    fn sp_line(number: u16, statements: &[u8]) -> Vec<u8> {
+        let mut line = ((5 + statements.len()) as u16).to_le_bytes().to_vec();
+        line.extend(number.to_le_bytes());
+        line.extend(statements);
+        line.push(0x00);
+        line
+    }
+
+    /// `PRINT n`, as SP-5025 stores it.
+    fn print(n: u8) -> Vec<u8> {
+        vec![0x85, b' ', b'0' + n]
+    }
+
+    fn recovered(body: &[u8]) -> (Vec<String>, Vec<String>) {
+        let listing = recover_mz_listing(&mzf(0x02, "DAMAGED", 0x4F88, 0, body), MZBasicVersion::SP5025).unwrap();
+        let lines = listing.lines.iter().map(|line| format!("{} {}", line.number, line.text)).collect();
+        (lines, listing.diagnostics)
+    }
+
+    #[test]
+    fn a_damaged_length_is_relinked() {
+        let mut line_20 = sp_line(20, &print(2));
+        line_20[0] = 0x30;
+        let body = [sp_line(10, &print(1)), line_20, sp_line(30, &print(3)), sp_line(40, &print(4)), vec![0, 0]].concat();
+        let (lines, diagnostics) = recovered(&body);
+        assert_eq!(lines, ["10 PRINT 1", "20 PRINT 2", "30 PRINT 3", "40 PRINT 4"]);
+        assert_eq!(diagnostics, ["Line 20 at 0088 has a damaged length (0030, should be 0008)"]);
+    }
+
+    #[test]
+    fn garbage_between_lines_is_skipped() {
+        // Neither a sane length nor a line number after 10
+        let garbage = [0xEE, 0xEE, 0x05, 0x00, 0x41, 0x42, 0x43];
+        let body = [sp_line(10, &print(1)), garbage.to_vec(), sp_line(20, &print(2)), sp_line(30, &print(3)), vec![0, 0]].concat();
+        let (lines, diagnostics) = recovered(&body);
+        assert_eq!(lines, ["10 PRINT 1", "20 PRINT 2", "30 PRINT 3"]);
+        assert_eq!(diagnostics, ["Skipped 7 damaged bytes at 0088-008E after line 10"]);
+    }
+
+    #[test]
+    fn line_numbers_that_go_backwards_are_skipped() {
+        let body = [sp_line(10, &print(1)), sp_line(20, &print(2)), sp_line(15, &print(5)), sp_line(30, &print(3)), vec![0, 0]].concat();
+        let (lines, diagnostics) = recovered(&body);
+        // The line before the drop is the one taken as damaged, as 15 still fits before 30
+        assert_eq!(lines, ["10 PRINT 1", "15 PRINT 5", "30 PRINT 3"]);
+        assert_eq!(diagnostics, ["Skipped 8 damaged bytes at 0088-008F after line 10"]);
+
+        // A number that fits neither neighbour
+        let body = [sp_line(10, &print(1)), sp_line(20, &print(2)), sp_line(5, &print(5)), sp_line(30, &print(3)), vec![0, 0]].concat();
+        let (lines, diagnostics) = recovered(&body);
+        assert_eq!(lines, ["10 PRINT 1", "20 PRINT 2", "30 PRINT 3"]);
+        assert_eq!(diagnostics, ["Skipped 8 damaged bytes at 0090-0097 after line 20"]);
+
+        // 50 looks in order until the line after it
+        let body = [sp_line(10, &print(1)), sp_line(50, &print(5)), sp_line(30, &print(3)), sp_line(40, &print(4)), vec![0, 0]].concat();
+        let (lines, diagnostics) = recovered(&body);
+        assert_eq!(lines, ["10 PRINT 1", "30 PRINT 3", "40 PRINT 4"]);
+        assert_eq!(diagnostics, ["Skipped 8 damaged bytes at 0088-008F after line 10"]);
+    }
+
+    #[test]
+    fn data_ending_mid_line_is_reported() {
+        let body = [sp_line(10, &print(1)), sp_line(20, &print(2)), sp_line(30, &print(3))[..5].to_vec()].concat();
+        let (lines, diagnostics) = recovered(&body);
+        assert_eq!(lines, ["10 PRINT 1", "20 PRINT 2"]);
+        assert_eq!(diagnostics, ["Skipped 5 damaged bytes at 0090-0094 after line 20"]);
+        assert!(matches!(
+            recover_mz_listing(&mzf(0x02, "DAMAGED", 0x4F88, 0, &[0xEE; 8]), MZBasicVersion::SP5025),
+            Err(Error::NothingFound(_))
+        ));
+    }
+
+    #[test]
+    fn ascii_numbers_are_number_items() {
+        let body = [sp_line(10, b"\x85 1.5,A1,.5E-3,2E,X"), vec![0, 0]].concat();
+        let listing = decode_mz_listing(&mzf(0x02, "NUMBERS", 0x4F88, 0, &body), MZBasicVersion::SP5025).unwrap();
+        let line = &listing.lines[0];
+        assert_eq!(line.text, "PRINT 1.5,A1,.5E-3,2E,X");
+        let numbers: Vec<_> = line
+            .items
+            .iter()
+            .filter(|item| item.kind == ItemKind::Number)
+            .map(|item| (item.text.as_str(), item.offset, item.value))
+            .collect();
+        // The 1 of A1 is part of the name, and an E without digits is not an exponent
+        assert_eq!(numbers, [("1.5", 0x86, Some(1.5)), (".5E-3", 0x8D, Some(0.0005)), ("2", 0x93, Some(2.0))]);
+    }
+}
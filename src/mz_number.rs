@@ -0,0 +1,155 @@
+// src/mz_number.rs
+//
+// The 5-byte floating point numbers of the Sharp MZ BASICs, and the way the machines
+// print them. Used for the constants in program lines (token 0x15) and saved variables.
+
+use crate::basic_number::{format_number, NumberStyle};
+
+/// How the MZ BASICs print numbers: 8 significant digits, and E notation with a
+/// two-digit exponent outside 0.01 up to, but not including, 1E+08.
+const MZ_NUMBERS: NumberStyle = NumberStyle { digits: 8, fixed_exponents: -2..8, exponent_digits: 2 };
+
+/// Converts an MZ BASIC 5-byte floating point number into an `f64`.
+///
+/// The first byte is the binary exponent biased by 0x80, with 0 meaning zero. The other
+/// four hold the mantissa, most significant byte first, as a fraction in [0.5, 1): its top
+/// bit is always set, so it holds the sign instead (set for negative).
+pub fn mz_float(bytes: &[u8; 5]) -> f64 {
+    if bytes[0] == 0 {
+        return 0.0;
+    }
+    let exponent = bytes[0] as i32 - 0x80;
+    let sign = if bytes[1] & 0x80 != 0 { -1.0 } else { 1.0 };
+    let mantissa = u32::from_be_bytes([bytes[1] | 0x80, bytes[2], bytes[3], bytes[4]]) as f64 / 4294967296.0;
+    sign * mantissa * 2.0_f64.powi(exponent)
+}
+
+/// Converts a number to the MZ BASIC 5-byte floating point form, the inverse of `mz_float`.
+/// The mantissa is rounded to the nearest; numbers too small to represent become zero and
+/// numbers too large (or not finite) the largest the format holds.
+pub fn mz_float_bytes(value: f64) -> [u8; 5] {
+    if value == 0.0 || value.is_nan() {
+        return [0; 5];
+    }
+    let sign = if value < 0.0 { 0x80 } else { 0 };
+    if value.is_infinite() {
+        return [0xFF, 0x7F | sign, 0xFF, 0xFF, 0xFF];
+    }
+    let mut exponent = value.abs().log2().floor() as i32 + 1;
+    let mut mantissa = (value.abs() / 2.0_f64.powi(exponent) * 4294967296.0).round() as u64;
+    if mantissa >= 1 << 32 {
+        mantissa >>= 1;
+        exponent += 1;
+    }
+    match exponent + 0x80 {
+        ..=0 => [0; 5],
+        256.. => [0xFF, 0x7F | sign, 0xFF, 0xFF, 0xFF],
+        biased => {
+            let m = (mantissa as u32).to_be_bytes();
+            [biased as u8, (m[0] & 0x7F) | sign, m[1], m[2], m[3]]
+        }
+    }
+}
+
+/// Formats a number the way the MZ BASICs PRINT it, without the space printed before a
+/// positive number: rounded to 8 significant digits, trailing zeros dropped, no leading
+/// zero before the point, and `E+nn` / `E-nn` outside 0.01..1E+08.
+///
+/// ```
+/// use rust_wasm_converter::mz::{mz_float, mz_float_bytes, mz_number_string};
+///
+/// assert_eq!(mz_number_string(0.1 + 0.2), ".3");
+/// assert_eq!(mz_number_string(1.0 / 3.0), ".33333333");
+/// assert_eq!(mz_number_string(-2.5), "-2.5");
+/// assert_eq!(mz_number_string(12345678.0), "12345678");
+/// assert_eq!(mz_number_string(123456789.0), "1.2345679E+08");
+/// assert_eq!(mz_number_string(100000000.0), "1E+08");
+/// assert_eq!(mz_number_string(0.001), "1E-03");
+/// assert_eq!(mz_number_string(std::f64::consts::PI), "3.1415927");
+/// assert_eq!(mz_number_string(99999999.5), "1E+08");
+/// assert_eq!(mz_number_string(mz_float(&mz_float_bytes(0.1))), ".1");
+/// assert_eq!(mz_float_bytes(5.0), [0x83, 0x20, 0x00, 0x00, 0x00]);
+/// ```
+pub fn mz_number_string(n: f64) -> String {
+    format_number(n, &MZ_NUMBERS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Byte patterns and the numbers they hold, worked out from the format: a biased
+    /// exponent, then the mantissa with its top bit replaced by the sign.
+    const PATTERNS: [([u8; 5], f64); 9] = [
+        ([0x00, 0x00, 0x00, 0x00, 0x00], 0.0),
+        ([0x81, 0x00, 0x00, 0x00, 0x00], 1.0),
+        ([0x81, 0x80, 0x00, 0x00, 0x00], -1.0),
+        ([0x80, 0x00, 0x00, 0x00, 0x00], 0.5),
+        ([0x7F, 0x2A, 0xAA, 0xAA, 0xAB], 1.0 / 3.0),
+        ([0x02, 0x59, 0xC7, 0xDC, 0xED], 1E-38),
+        ([0xFF, 0x7F, 0xC9, 0x9E, 0x3C], 1.7E38),
+        ([0x9B, 0x6B, 0x79, 0xA2, 0xA0], 123456789.0),
+        ([0x7D, 0x4C, 0xCC, 0xCC, 0xCD], 0.1),
+    ];
+
+    #[test]
+    fn byte_patterns_decode() {
+        for (bytes, value) in PATTERNS {
+            let decoded = mz_float(&bytes);
+            assert!((decoded - value).abs() <= value.abs() * 1E-9, "{:02X?} gives {}, not {}", bytes, decoded, value);
+            assert_eq!(mz_float_bytes(value), bytes, "{}", value);
+        }
+        // A zero exponent is zero whatever the mantissa holds
+        assert_eq!(mz_float(&[0x00, 0xFF, 0x12, 0x34, 0x56]), 0.0);
+    }
+
+    #[test]
+    fn out_of_range_values_saturate() {
+        assert_eq!(mz_float_bytes(1E39), [0xFF, 0x7F, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(mz_float_bytes(f64::NEG_INFINITY), [0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(mz_float_bytes(1E-40), [0; 5]);
+        assert_eq!(mz_float_bytes(f64::NAN), [0; 5]);
+    }
+
+    /// The expected texts are not taken from a machine: no capture of an MZ printing them
+    /// is at hand. Each group follows from one rule of `MZ_NUMBERS` or `format_number`,
+    /// named above it, so that a capture that disagrees points at the rule to change.
+    #[test]
+    fn numbers_print_as_on_the_machine() {
+        let printed = |bytes: [u8; 5]| mz_number_string(mz_float(&bytes));
+        // The byte patterns above: exact decimals print as they are, with no zero before
+        // the point; 1/3 shows its first 8 digits
+        let expected = ["0", "1", "-1", ".5", ".33333333", "1E-38", "1.7E+38", "1.2345679E+08", ".1"];
+        for ((bytes, _), text) in PATTERNS.into_iter().zip(expected) {
+            assert_eq!(printed(bytes), text);
+        }
+
+        // Rounding to 8 digits can carry into a new digit, and then into E notation
+        assert_eq!(mz_number_string(9.999999999), "10");
+        assert_eq!(mz_number_string(99999999.5), "1E+08");
+        assert_eq!(mz_number_string(0.00999999999), ".01");
+        assert_eq!(mz_number_string(0.00999999), "9.99999E-03");
+        assert_eq!(mz_number_string(-0.01), "-.01");
+
+        // Negative exponents: fixed down to 1E-02, then E-nn padded to two digits
+        assert_eq!(mz_number_string(0.0123), ".0123");
+        assert_eq!(mz_number_string(0.001234), "1.234E-03");
+        assert_eq!(mz_number_string(1.5E-05), "1.5E-05");
+        assert_eq!(mz_number_string(-2.5E-10), "-2.5E-10");
+        assert_eq!(printed(mz_float_bytes(2.9387359E-39)), "2.9387359E-39");
+
+        // Large integers: whole up to 8 digits, then E+nn rounded to 8 digits
+        assert_eq!(mz_number_string(16777216.0), "16777216");
+        assert_eq!(mz_number_string(-99999999.0), "-99999999");
+        assert_eq!(mz_number_string(123456780.0), "1.2345678E+08");
+        assert_eq!(printed(mz_float_bytes(4294967296.0)), "4.2949673E+09");
+        assert_eq!(mz_number_string(1E10), "1E+10");
+
+        // PRINT puts a space before a positive number where the minus sign would go. That
+        // space belongs to PRINT, not to the number, so a constant in a listing has none
+        for n in [1.0, 0.5, 1E10, 1.5E-05] {
+            assert!(!mz_number_string(n).starts_with(' '), "{}", n);
+            assert_eq!(mz_number_string(-n), format!("-{}", mz_number_string(n)));
+        }
+    }
+}
@@ -0,0 +1,475 @@
+// src/mz_tape.rs
+//
+// Sharp MZ cassette format (MZ-80K/80A/700/800 monitor routines).
+//
+// Each bit is one pulse: a long pulse is 1, a short pulse is 0. Every byte is
+// sent as a long start pulse followed by 8 bits, most significant first.
+// A file on tape is laid out as:
+//
+//   LGAP  LTM  L  HDR  CHKH  L  256S  HDRC  CHKH  L
+//   SGAP  STM  L  FILE CHKF  L  256S  FILEC CHKF  L
+//
+// where the gaps are runs of short pulses, the tapemarks are runs of long
+// pulses followed by the same number of short pulses (40/40 long, 20/20 short),
+// and each block is followed by a 16-bit count of its 1 bits (big-endian),
+// then repeated once as a backup copy.
+
+use crate::error::Error;
+use crate::mzf_file::{MZFHeader, MZF_HEADER_SIZE};
+use crate::wav_audio::{self, SquareWave, WavAudio};
+
+/// Shortest run of long then short pulses that is accepted as a tapemark.
+const MIN_TAPEMARK: usize = 15;
+
+/// Pulse classification.
+const SHORT: u8 = 0;
+const LONG: u8 = 1;
+const GAP: u8 = 2; // silence or noise, far longer than a long pulse
+
+/// A file recovered from a Sharp MZ tape recording.
+#[derive(Debug, Clone)]
+pub struct MZTapeFile {
+    /// The recovered MZF image (header followed by as much of the body as could be read).
+    pub mzf: Vec<u8>,
+    /// The parsed header, if one was read.
+    pub header: Option<MZFHeader>,
+    /// True if either copy of the header passed its checksum.
+    pub header_checksum_ok: bool,
+    /// True if either copy of the body passed its checksum.
+    pub body_checksum_ok: bool,
+    /// True if the whole body (as given by the header size) was read.
+    pub complete: bool,
+    /// Problems found while reading, with pulse positions.
+    pub diagnostics: Vec<String>,
+}
+
+/// A block read from the pulse stream.
+struct Block {
+    data: Vec<u8>,
+    checksum_ok: bool,
+    complete: bool,
+}
+
+/// Reader over classified pulses.
+struct PulseReader<'a> {
+    pulses: &'a [u8],
+    pos: usize,
+    diagnostics: Vec<String>,
+}
+
+impl PulseReader<'_> {
+    fn bit(&mut self) -> Option<u8> {
+        let b = *self.pulses.get(self.pos)?;
+        self.pos += 1;
+        if b == GAP { None } else { Some(b) }
+    }
+
+    /// Finds the next tapemark and positions the reader at the first data pulse after it.
+    fn find_tapemark(&mut self) -> bool {
+        while self.pos < self.pulses.len() {
+            let longs = self.run_length(LONG, self.pos);
+            if longs >= MIN_TAPEMARK {
+                let shorts = self.run_length(SHORT, self.pos + longs);
+                if shorts >= MIN_TAPEMARK && shorts <= longs + longs / 2 {
+                    self.pos += longs + shorts;
+                    // The single long pulse separating the tapemark from the block
+                    if self.pulses.get(self.pos) == Some(&LONG) {
+                        self.pos += 1;
+                    }
+                    return true;
+                }
+                self.pos += longs;
+            } else {
+                self.pos += 1;
+            }
+        }
+        false
+    }
+
+    fn run_length(&self, value: u8, from: usize) -> usize {
+        self.pulses.iter().skip(from).take_while(|&&p| p == value).count()
+    }
+
+    fn skip_shorts(&mut self) {
+        self.pos += self.run_length(SHORT, self.pos);
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let start = self.pos;
+        if self.bit()? != LONG {
+            self.diagnostics.push(format!("Missing start pulse at pulse {}", start));
+        }
+        let mut value = 0u8;
+        for _ in 0..8 {
+            value = (value << 1) | self.bit()?;
+        }
+        Some(value)
+    }
+
+    /// Reads `len` data bytes and the 16-bit checksum that follows them.
+    fn block(&mut self, len: usize) -> Block {
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            match self.byte() {
+                Some(b) => data.push(b),
+                None => {
+                    self.diagnostics.push(format!(
+                        "Block ended after {} of {} bytes at pulse {}", data.len(), len, self.pos
+                    ));
+                    return Block { data, checksum_ok: false, complete: false };
+                }
+            }
+        }
+        let stored = match (self.byte(), self.byte()) {
+            (Some(hi), Some(lo)) => Some(u16::from_be_bytes([hi, lo])),
+            _ => None,
+        };
+        let checksum = mz_checksum(&data);
+        let checksum_ok = stored == Some(checksum);
+        if !checksum_ok {
+            self.diagnostics.push(match stored {
+                Some(s) => format!("Checksum mismatch: stored {:04X}, calculated {:04X}", s, checksum),
+                None => "Checksum missing".to_string(),
+            });
+        }
+        // Trailing long pulse
+        if self.pulses.get(self.pos) == Some(&LONG) {
+            self.pos += 1;
+        }
+        Block { data, checksum_ok, complete: true }
+    }
+
+    /// Reads a block and its backup copy, preferring whichever copy passes its checksum.
+    fn block_with_copy(&mut self, len: usize) -> Block {
+        let first = self.block(len);
+        if first.checksum_ok {
+            // Skip the copy; if it cannot be read cleanly, leave the pulses for the next search
+            let (saved_pos, saved_diagnostics) = (self.pos, self.diagnostics.len());
+            self.skip_shorts();
+            if !self.block(len).checksum_ok {
+                self.pos = saved_pos;
+            }
+            self.diagnostics.truncate(saved_diagnostics);
+            return first;
+        }
+        self.skip_shorts();
+        let copy = self.block(len);
+        if copy.checksum_ok {
+            self.diagnostics.push("Recovered block from backup copy".to_string());
+            return copy;
+        }
+        if copy.data.len() > first.data.len() { copy } else { first }
+    }
+}
+
+/// The checksum used by the MZ monitor: the number of 1 bits in the block.
+pub fn mz_checksum(data: &[u8]) -> u16 {
+    data.iter().map(|b| b.count_ones() as u16).fold(0, u16::wrapping_add)
+}
+
+/// Classifies pulse periods (in seconds) as short, long or gap.
+fn classify(periods: &[f64]) -> Vec<u8> {
+    let Some(threshold) = wav_audio::pulse_threshold(periods) else { return Vec::new() };
+    periods
+        .iter()
+        .map(|&p| if p > threshold * 3.0 { GAP } else if p > threshold { LONG } else { SHORT })
+        .collect()
+}
+
+/// Decodes the files recorded on a Sharp MZ tape, given pulse periods in seconds.
+pub fn decode_mz_pulses(periods: &[f64]) -> Vec<MZTapeFile> {
+    let pulses = classify(periods);
+    let mut reader = PulseReader { pulses: &pulses, pos: 0, diagnostics: Vec::new() };
+    let mut files = Vec::new();
+
+    while reader.find_tapemark() {
+        reader.diagnostics.clear();
+        let header_block = reader.block_with_copy(MZF_HEADER_SIZE);
+        if header_block.data.len() < MZF_HEADER_SIZE {
+            if header_block.data.is_empty() {
+                continue;
+            }
+            let mut diagnostics = std::mem::take(&mut reader.diagnostics);
+            diagnostics.push("Header incomplete; no body read".to_string());
+            files.push(MZTapeFile {
+                mzf: header_block.data,
+                header: None,
+                header_checksum_ok: false,
+                body_checksum_ok: false,
+                complete: false,
+                diagnostics,
+            });
+            continue;
+        }
+
+        let header = MZFHeader::parse(&header_block.data).ok();
+        let size = header.as_ref().map_or(0, |h| h.size as usize);
+        let mut mzf = header_block.data.clone();
+
+        let body_start = reader.pos;
+        let (body_ok, complete) = if reader.find_tapemark() {
+            let body = reader.block_with_copy(size);
+            mzf.extend_from_slice(&body.data);
+            (body.checksum_ok, body.complete && body.data.len() == size)
+        } else {
+            reader.pos = body_start;
+            reader.diagnostics.push("No body tapemark found after header".to_string());
+            (false, size == 0)
+        };
+
+        files.push(MZTapeFile {
+            mzf,
+            header,
+            header_checksum_ok: header_block.checksum_ok,
+            body_checksum_ok: body_ok,
+            complete,
+            diagnostics: std::mem::take(&mut reader.diagnostics),
+        });
+    }
+
+    files
+}
+
+/// Decodes the files recorded in a Sharp MZ cassette WAV.
+///
+/// # Arguments
+///
+/// * `bytes` - The complete PCM WAV file
+///
+/// # Returns
+///
+/// A `Result` containing every file found on the tape, each with its MZF bytes and
+/// checksum status, or an error message if the WAV cannot be read or holds no files.
+pub fn decode_mz_wav(bytes: &[u8]) -> Result<Vec<MZTapeFile>, Error> {
+    let audio: WavAudio = wav_audio::read_wav(bytes)?;
+    let files = decode_mz_pulses(&audio.rising_edge_periods());
+    if files.is_empty() {
+        return Err(Error::NothingFound("No Sharp MZ tapemark found in the recording."));
+    }
+    Ok(files)
+}
+
+/// Settings for generating a Sharp MZ cassette recording.
+#[derive(Debug, Clone, Copy)]
+pub struct MZTapeOptions {
+    /// Output sample rate in Hz.
+    pub sample_rate: u32,
+    /// Speed multiplier: 1 for the standard 1200 baud monitor, 2 or 3 for fast loaders.
+    pub speed: u8,
+    /// Number of short pulses in the gap before the header (22000 on the MZ-700, 10000 on the MZ-80K).
+    pub long_gap: usize,
+    /// Number of short pulses in the gap before the body.
+    pub short_gap: usize,
+}
+
+impl Default for MZTapeOptions {
+    fn default() -> Self {
+        Self { sample_rate: 44100, speed: 1, long_gap: 22000, short_gap: 11000 }
+    }
+}
+
+/// Writes pulses for the Sharp tape format.
+struct PulseWriter {
+    wave: SquareWave,
+    scale: f64,
+}
+
+impl PulseWriter {
+    // Pulse timings of the MZ-700 monitor at standard speed (seconds)
+    const LONG_HIGH: f64 = 464e-6;
+    const LONG_LOW: f64 = 494e-6;
+    const SHORT_HIGH: f64 = 240e-6;
+    const SHORT_LOW: f64 = 264e-6;
+
+    fn pulse(&mut self, long: bool) {
+        let (high, low) = if long {
+            (Self::LONG_HIGH, Self::LONG_LOW)
+        } else {
+            (Self::SHORT_HIGH, Self::SHORT_LOW)
+        };
+        self.wave.level(SquareWave::HIGH, high * self.scale);
+        self.wave.level(SquareWave::LOW, low * self.scale);
+    }
+
+    fn pulses(&mut self, long: bool, count: usize) {
+        for _ in 0..count {
+            self.pulse(long);
+        }
+    }
+
+    fn byte(&mut self, value: u8) {
+        self.pulse(true);
+        for bit in (0..8).rev() {
+            self.pulse(value & (1 << bit) != 0);
+        }
+    }
+
+    /// Writes a block, its checksum and trailing long pulse, then the backup copy.
+    fn block_with_copy(&mut self, data: &[u8]) {
+        let checksum = mz_checksum(data).to_be_bytes();
+        for copy in 0..2 {
+            if copy == 1 {
+                self.pulses(false, 256);
+            }
+            for &b in data.iter().chain(checksum.iter()) {
+                self.byte(b);
+            }
+            self.pulse(true);
+        }
+    }
+}
+
+/// Generates a playable Sharp MZ cassette recording of an MZF file.
+///
+/// # Arguments
+///
+/// * `mzf` - The MZF file (128-byte header followed by the body)
+/// * `options` - Sample rate, speed and gap lengths
+///
+/// # Returns
+///
+/// A `Result` containing the WAV file bytes, or an error message.
+pub fn encode_mz_wav(mzf: &[u8], options: MZTapeOptions) -> Result<Vec<u8>, Error> {
+    let header = MZFHeader::parse(mzf)?;
+    let body_end = MZF_HEADER_SIZE + header.size as usize;
+    if mzf.len() < body_end {
+        return Err(Error::UnexpectedEnd { offset: mzf.len(), line: None });
+    }
+    if !(1..=3).contains(&options.speed) {
+        return Err(Error::InvalidArgument("Tape speed must be 1, 2 or 3.".to_string()));
+    }
+    if options.sample_rate < 8000 * options.speed as u32 {
+        return Err(Error::InvalidArgument("Sample rate is too low for the selected tape speed.".to_string()));
+    }
+
+    let mut writer = PulseWriter {
+        wave: SquareWave::new(options.sample_rate),
+        scale: 1.0 / options.speed as f64,
+    };
+    writer.wave.level(SquareWave::SILENT, 0.5);
+
+    writer.pulses(false, options.long_gap);
+    writer.pulses(true, 40);
+    writer.pulses(false, 40);
+    writer.pulse(true);
+    writer.block_with_copy(&mzf[..MZF_HEADER_SIZE]);
+
+    writer.pulses(false, options.short_gap);
+    writer.pulses(true, 20);
+    writer.pulses(false, 20);
+    writer.pulse(true);
+    writer.block_with_copy(&mzf[MZF_HEADER_SIZE..body_end]);
+
+    writer.wave.level(SquareWave::SILENT, 0.5);
+    Ok(writer.wave.into_wav())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::mzf;
+
+    fn program() -> Vec<u8> {
+        let body: Vec<u8> = (0..300).map(|i| (i * 37 % 256) as u8).collect();
+        mzf(0x01, "TAPE TEST", 0x1200, 0x1200, &body)
+    }
+
+    fn recording(sample_rate: u32) -> Vec<u8> {
+        encode_mz_wav(&program(), MZTapeOptions { sample_rate, ..Default::default() }).unwrap()
+    }
+
+    #[test]
+    fn decodes_recordings_at_common_sample_rates() {
+        for sample_rate in [22050, 44100, 48000] {
+            let files = decode_mz_wav(&recording(sample_rate)).unwrap();
+            assert_eq!(files.len(), 1, "{} Hz", sample_rate);
+            let file = &files[0];
+            assert_eq!(file.mzf, program(), "{} Hz", sample_rate);
+            assert_eq!(file.header.as_ref().map(|h| h.name_string()).as_deref(), Some("TAPE TEST"));
+            assert!(file.header_checksum_ok && file.body_checksum_ok && file.complete, "{} Hz", sample_rate);
+            assert!(file.diagnostics.is_empty(), "{} Hz: {:?}", sample_rate, file.diagnostics);
+        }
+    }
+
+    #[test]
+    fn decodes_16_bit_stereo_recordings() {
+        // The same recording with each 8-bit sample widened to two 16-bit channels
+        let wav = recording(44100);
+        let samples = &wav[44..44 + u32::from_le_bytes([wav[40], wav[41], wav[42], wav[43]]) as usize];
+        let mut stereo = Vec::new();
+        stereo.extend_from_slice(b"RIFF");
+        stereo.extend_from_slice(&(36 + samples.len() as u32 * 4).to_le_bytes());
+        stereo.extend_from_slice(b"WAVEfmt ");
+        stereo.extend_from_slice(&[16, 0, 0, 0, 1, 0, 2, 0]);
+        stereo.extend_from_slice(&44100u32.to_le_bytes());
+        stereo.extend_from_slice(&(44100u32 * 4).to_le_bytes());
+        stereo.extend_from_slice(&[4, 0, 16, 0]);
+        stereo.extend_from_slice(b"data");
+        stereo.extend_from_slice(&(samples.len() as u32 * 4).to_le_bytes());
+        for &s in samples {
+            let wide = ((s as i16 - 128) * 256).to_le_bytes();
+            stereo.extend_from_slice(&[wide[0], wide[1], wide[0], wide[1]]);
+        }
+        let files = decode_mz_wav(&stereo).unwrap();
+        assert_eq!(files[0].mzf, program());
+    }
+
+    #[test]
+    fn a_recording_cut_short_keeps_the_header() {
+        let wav = recording(44100);
+        // The header and its copy end about 13 seconds in, and the body starts after 18
+        let cut = &wav[..44 + 44100 * 15];
+        let files = decode_mz_wav(cut).unwrap();
+        assert_eq!(files.len(), 1);
+        let file = &files[0];
+        assert!(file.header_checksum_ok);
+        assert_eq!(file.header.as_ref().map(|h| h.size), Some(300));
+        assert!(!file.complete);
+        assert!(file.mzf.len() < program().len());
+    }
+
+    #[test]
+    fn writes_the_requested_sample_rate() {
+        for sample_rate in [22050u32, 44100, 48000] {
+            let wav = recording(sample_rate);
+            assert_eq!(&wav[24..28], &sample_rate.to_le_bytes());
+            assert_eq!(wav_audio::read_wav(&wav).unwrap().sample_rate, sample_rate);
+        }
+    }
+
+    #[test]
+    fn fast_recordings_decode() {
+        for speed in [2, 3] {
+            let options = MZTapeOptions { speed, long_gap: 2000, short_gap: 1000, ..Default::default() };
+            let wav = encode_mz_wav(&program(), options).unwrap();
+            let files = decode_mz_wav(&wav).unwrap();
+            assert_eq!(files[0].mzf, program(), "speed {}", speed);
+            assert!(files[0].body_checksum_ok);
+        }
+    }
+
+    #[test]
+    fn block_checksums_count_one_bits() {
+        assert_eq!(mz_checksum(&[]), 0);
+        assert_eq!(mz_checksum(&[0xFF, 0x01, 0x80]), 10);
+        // The count wraps at 16 bits
+        assert_eq!(mz_checksum(&[0xFF; 8300]), (8300 * 8 % 65536) as u16);
+    }
+
+    #[test]
+    fn refuses_what_it_cannot_record() {
+        let mut short = program();
+        short.truncate(200);
+        assert!(matches!(encode_mz_wav(&short, MZTapeOptions::default()), Err(Error::UnexpectedEnd { .. })));
+        let options = MZTapeOptions { speed: 4, ..Default::default() };
+        assert!(matches!(encode_mz_wav(&program(), options), Err(Error::InvalidArgument(_))));
+        let options = MZTapeOptions { sample_rate: 11025, speed: 2, ..Default::default() };
+        assert!(matches!(encode_mz_wav(&program(), options), Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn silence_holds_no_files() {
+        let silence = wav_audio::write_wav_u8(44100, &[0x80; 44100]);
+        assert!(matches!(decode_mz_wav(&silence), Err(Error::NothingFound(_))));
+    }
+}
@@ -0,0 +1,158 @@
+// src/mz_trailing.rs
+//
+// The bytes an MZF BASIC file holds after the end of its program: variables saved with
+// the program, appended machine code, or data. They are located from the decoded lines
+// and can be disassembled or hex-dumped.
+
+use crate::basic::BasicListing;
+use crate::error::Error;
+use crate::mz_decoder::{decode_mz_listing, MZBasicVersion};
+use crate::mzf_file::{MZFHeader, MZF_HEADER_SIZE};
+use crate::z80_disasm::Z80Disassembler;
+
+/// What an MZF BASIC file holds after its program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrailingData {
+    /// Offset of the first byte after the program's end marker.
+    pub offset: usize,
+    /// The address that byte loads to.
+    pub address: u16,
+    /// The bytes from there to the end of the file.
+    pub bytes: Vec<u8>,
+    /// How many of them lie past the body size given in the header, and so are not loaded.
+    pub outside_size: usize,
+}
+
+/// The offset just past a fully decoded program and its 0x0000 end marker, or `None` if
+/// the program is damaged, so that its end is not known.
+fn program_end(data: &[u8], listing: &BasicListing) -> Option<usize> {
+    if !listing.diagnostics.is_empty() {
+        return None;
+    }
+    let end = listing.lines.last().map_or(MZF_HEADER_SIZE, |line| line.offset + line.length);
+    (data.get(end..end + 2)? == [0, 0]).then_some(end + 2)
+}
+
+/// Finds the bytes after the BASIC program in an MZF file.
+///
+/// # Arguments
+///
+/// * `data` - The complete MZF file, header included
+/// * `version` - The BASIC dialect the program was saved from
+///
+/// # Returns
+///
+/// A `Result` containing what follows the program, `None` if the file ends with it, or an
+/// error if the program is damaged so that its end cannot be found.
+pub(crate) fn trailing_data(data: &[u8], version: MZBasicVersion) -> Result<Option<TrailingData>, Error> {
+    listing_trailing_data(data, &decode_mz_listing(data, version)?)
+}
+
+/// Finds the bytes after the BASIC program in an MZF file whose listing has already been
+/// decoded, so that a caller listing the program does not decode it twice.
+///
+/// # Arguments
+///
+/// * `data` - The complete MZF file, header included
+/// * `listing` - The program decoded from `data`
+///
+/// # Returns
+///
+/// As for `trailing_data`.
+pub(crate) fn listing_trailing_data(data: &[u8], listing: &BasicListing) -> Result<Option<TrailingData>, Error> {
+    let header = MZFHeader::parse(data)?;
+    let offset = program_end(data, listing)
+        .ok_or(Error::InvalidFormat("The program has no intact end marker, so where it ends is not known"))?;
+    if offset >= data.len() {
+        return Ok(None);
+    }
+    let bytes = data[offset..].to_vec();
+    let outside_size = data.len().saturating_sub(MZF_HEADER_SIZE + header.size as usize).min(bytes.len());
+    Ok(Some(TrailingData {
+        offset,
+        address: header.load_address.wrapping_add((offset - MZF_HEADER_SIZE) as u16),
+        bytes,
+        outside_size,
+    }))
+}
+
+impl TrailingData {
+    /// A one-line description of the region: where it is and how long.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Trailing data: {} bytes at offset {:04X} (address {:04X})",
+            self.bytes.len(),
+            self.offset,
+            self.address
+        );
+        if self.bytes.iter().all(|&b| b == 0) {
+            summary.push_str(", all zero");
+        }
+        if self.outside_size > 0 {
+            summary.push_str(&format!(", {} of them past the size in the header", self.outside_size));
+        }
+        summary
+    }
+
+    /// Disassembles the region as Z80 code at its load address.
+    pub fn disassembly(&self) -> String {
+        let mut disassembler = Z80Disassembler::new();
+        disassembler.disassemble(&self.bytes, self.address, self.address).join("\n")
+    }
+
+    /// Lists the region as hex and ASCII, 16 bytes to a row, by load address.
+    pub fn hex_dump(&self) -> String {
+        self.bytes
+            .chunks(16)
+            .enumerate()
+            .map(|(row, chunk)| {
+                let hex: String = chunk.iter().map(|b| format!("{:02X} ", b)).collect();
+                let text: String = chunk.iter().map(|&b| if (32..127).contains(&b) { b as char } else { '.' }).collect();
+                format!("{:04X}: {:<48} | {}", self.address.wrapping_add((row * 16) as u16), hex, text)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::mzf;
+
+    /// `10 END` in SA-5510 BASIC and its end marker, followed by `extra`.
+    fn program(extra: &[u8]) -> Vec<u8> {
+        let mut body = vec![0x07, 0x00, 0x0A, 0x00, 0x80, 0x92, 0x00, 0x00, 0x00];
+        body.extend(extra);
+        mzf(0x02, "TRAILING", 0x4F88, 0, &body)
+    }
+
+    #[test]
+    fn bytes_after_the_program_are_found() {
+        // LD A,1 / RET
+        let data = program(&[0x3E, 0x01, 0xC9]);
+        let trailing = trailing_data(&data, MZBasicVersion::SA5510).unwrap().unwrap();
+        assert_eq!((trailing.offset, trailing.address, trailing.outside_size), (MZF_HEADER_SIZE + 9, 0x4F91, 0));
+        assert_eq!(trailing.bytes, [0x3E, 0x01, 0xC9]);
+        assert!(trailing.hex_dump().starts_with("4F91: 3E 01 C9 "));
+
+        let listing = crate::process_binary(&data, "SA".to_string(), crate::MZFMachine::Sharp, false).unwrap();
+        assert_eq!(listing, "10 END\n\nTrailing data: 3 bytes at offset 0089 (address 4F91)\n");
+    }
+
+    #[test]
+    fn bytes_past_the_size_are_counted() {
+        let mut zeros = program(&[0; 4]);
+        zeros[0x12] -= 2;
+        let trailing = trailing_data(&zeros, MZBasicVersion::SA5510).unwrap().unwrap();
+        assert_eq!(trailing.summary(), "Trailing data: 4 bytes at offset 0089 (address 4F91), all zero, 2 of them past the size in the header");
+    }
+
+    #[test]
+    fn a_program_without_trailing_data() {
+        assert_eq!(trailing_data(&program(&[]), MZBasicVersion::SA5510).unwrap(), None);
+        let mut damaged = program(&[0x3E, 0x01, 0xC9]);
+        damaged[MZF_HEADER_SIZE + 7] = 0x01;
+        assert!(trailing_data(&damaged, MZBasicVersion::SA5510).is_err());
+    }
+}
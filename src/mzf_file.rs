@@ -0,0 +1,245 @@
+// src/mzf_file.rs
+//
+// MZF header parsing and MZT multi-file tape containers.
+// An MZT file is a concatenation of MZF records: a 128-byte header followed by
+// the number of body bytes given in the header's size field.
+
+use crate::error::Error;
+use crate::mz_decoder::{self, MZBasicVersion};
+
+/// Length of the MZF tape header.
+pub const MZF_HEADER_SIZE: usize = 128;
+
+/// The 128-byte header that precedes every Sharp MZ tape file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MZFHeader {
+    /// File attribute: 0x01 machine code, 0x02 BASIC, 0x03 data, 0x05 1Z-013B BASIC, ...
+    pub file_type: u8,
+    /// Raw 17-byte file name (terminated by 0x0D).
+    pub name: [u8; 17],
+    /// Number of body bytes following the header.
+    pub size: u16,
+    /// Address the body is loaded to.
+    pub load_address: u16,
+    /// Address execution starts at (machine code only).
+    pub exec_address: u16,
+}
+
+impl MZFHeader {
+    /// Parses the first 128 bytes of `data` as an MZF header.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < MZF_HEADER_SIZE {
+            return Err(Error::TruncatedHeader { format: "MZF", expected: MZF_HEADER_SIZE, found: data.len() });
+        }
+        let mut name = [0u8; 17];
+        name.copy_from_slice(&data[1..18]);
+        Ok(Self {
+            file_type: data[0],
+            name,
+            size: u16::from_le_bytes([data[0x12], data[0x13]]),
+            load_address: u16::from_le_bytes([data[0x14], data[0x15]]),
+            exec_address: u16::from_le_bytes([data[0x16], data[0x17]]),
+        })
+    }
+
+    /// The file name up to the 0x0D terminator, with non-ASCII characters shown as '.'.
+    pub fn name_string(&self) -> String {
+        self.name
+            .iter()
+            .take_while(|&&b| b != 0x0D)
+            .map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' })
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    /// A short description of the file attribute.
+    pub fn type_description(&self) -> &'static str {
+        match self.file_type {
+            0x01 => "Machine Code (Z80)",
+            0x02 => "BASIC (SP-5025) or BASIC (SA-5510)",
+            0x03 => "BASIC data",
+            0x04 => "Source",
+            0x05 => "BASIC (1Z-013B)",
+            _ => "Unknown Type",
+        }
+    }
+
+    /// The `process_binary` mode most likely to decode a file with this attribute. The
+    /// attribute alone cannot tell SA-5510 BASIC from SP-5025, so 0x02 gives "SP";
+    /// `suggested_mode` looks at the program as well.
+    pub fn suggested_mode(&self) -> &'static str {
+        match self.file_type {
+            0x01 => "Z80",
+            0x02 => "SP",
+            0x05 => "1Z",
+            _ => "DUMP",
+        }
+    }
+}
+
+/// The `process_binary` mode most likely to decode an MZF file, choosing between
+/// SA-5510 and SP-5025 BASIC from the program's tokens.
+pub fn suggested_mode(mzf: &[u8]) -> &'static str {
+    match MZFHeader::parse(mzf) {
+        Ok(header) if header.file_type == 0x02 => match mz_decoder::guess_mz_version(mzf) {
+            MZBasicVersion::SA5510 => "SA",
+            _ => "SP",
+        },
+        Ok(header) => header.suggested_mode(),
+        Err(_) => "DUMP",
+    }
+}
+
+/// One record of an MZT container.
+#[derive(Debug, Clone)]
+pub struct MZTEntry {
+    /// Offset of the record's header within the container.
+    pub offset: usize,
+    /// The parsed header.
+    pub header: MZFHeader,
+    /// The record as a standalone MZF file (header and body).
+    pub mzf: Vec<u8>,
+    /// True if the container ended before the body was complete.
+    pub truncated: bool,
+    /// Problems found with the record, such as bytes after it too few for another header.
+    pub diagnostics: Vec<String>,
+}
+
+/// Splits an MZT container into its MZF records.
+///
+/// # Arguments
+///
+/// * `bytes` - The complete .mzt (or single .mzf) file
+///
+/// # Returns
+///
+/// A `Result` containing the entries in container order, or an error message if
+/// not even one header is present. A short final body is kept and flagged as truncated,
+/// and bytes after the last record that are too few for another header are reported in
+/// its diagnostics.
+pub fn parse_mzt(bytes: &[u8]) -> Result<Vec<MZTEntry>, Error> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while offset + MZF_HEADER_SIZE <= bytes.len() {
+        let header = MZFHeader::parse(&bytes[offset..])?;
+        let end = offset + MZF_HEADER_SIZE + header.size as usize;
+        let truncated = end > bytes.len();
+        let end = end.min(bytes.len());
+        entries.push(MZTEntry {
+            offset,
+            header,
+            mzf: bytes[offset..end].to_vec(),
+            truncated,
+            diagnostics: Vec::new(),
+        });
+        offset = end;
+    }
+
+    let Some(last) = entries.last_mut() else {
+        return Err(Error::TruncatedHeader { format: "MZF", expected: MZF_HEADER_SIZE, found: bytes.len() });
+    };
+    if offset < bytes.len() {
+        last.diagnostics.push(format!(
+            "{} bytes at {:06X} are too short for another header", bytes.len() - offset, offset
+        ));
+    }
+    Ok(entries)
+}
+
+/// Lists the entries of an MZT container, one line per record.
+pub fn list_mzt(entries: &[MZTEntry]) -> String {
+    let mut result = String::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let h = &entry.header;
+        result.push_str(&format!(
+            "{:2}: {:06X} {:<17} type {:02X} {:<35} size {:04X} load {:04X} exec {:04X}{}\n",
+            i,
+            entry.offset,
+            h.name_string(),
+            h.file_type,
+            h.type_description(),
+            h.size,
+            h.load_address,
+            h.exec_address,
+            if entry.truncated { " (truncated)" } else { "" }
+        ));
+        for d in &entry.diagnostics {
+            result.push_str(&format!("    {}\n", d));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::mzf;
+
+    /// A BASIC file holding `10 PRINT "HI"` and `20 END`, with the given statement tokens.
+    fn basic(print: &[u8], end: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (number, statement, text) in [(10u16, print, &b"\"HI\""[..]), (20, end, b"")] {
+            body.extend(((5 + statement.len() + text.len()) as u16).to_le_bytes());
+            body.extend(number.to_le_bytes());
+            body.extend(statement);
+            body.extend(text);
+            body.push(0x00);
+        }
+        body.extend([0, 0]);
+        mzf(0x02, "BASIC", 0x4F88, 0, &body)
+    }
+
+    #[test]
+    fn basic_dialect_is_chosen_from_the_tokens() {
+        let sa = basic(&[0x80, 0x88], &[0x80, 0x92]);
+        let sp = basic(&[0x85], &[0x8F]);
+        assert_eq!(MZFHeader::parse(&sa).unwrap().suggested_mode(), "SP");
+        assert_eq!(suggested_mode(&sa), "SA");
+        assert_eq!(suggested_mode(&sp), "SP");
+        // A program of REM lines looks the same either way
+        assert_eq!(suggested_mode(&basic(&[0x80], &[0x80])), "SP");
+    }
+
+    #[test]
+    fn other_attributes_keep_their_mode() {
+        assert_eq!(suggested_mode(&mzf(0x01, "CODE", 0x1200, 0x1200, &[0xC9])), "Z80");
+        assert_eq!(suggested_mode(&mzf(0x05, "BASIC", 0x6BCF, 0, &[0, 0])), "1Z");
+        assert_eq!(suggested_mode(&mzf(0x03, "DATA", 0, 0, &[1, 2])), "DUMP");
+        assert_eq!(suggested_mode(&[0x02; 16]), "DUMP");
+    }
+
+    #[test]
+    fn mzt_records_are_split() {
+        let mut tape = mzf(0x01, "FIRST", 0x1200, 0x1200, &[1, 2]);
+        tape.extend(mzf(0x01, "SECOND", 0x2000, 0x2000, &[3, 4, 5]));
+        tape.truncate(tape.len() - 1);
+        let entries = parse_mzt(&tape).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].offset, entries[1].offset), (0, MZF_HEADER_SIZE + 2));
+        assert!(!entries[0].truncated && entries[1].truncated);
+        assert_eq!(entries[1].header.name_string(), "SECOND");
+    }
+
+    #[test]
+    fn bytes_after_the_last_record_are_reported() {
+        let mut tape = mzf(0x01, "FIRST", 0x1200, 0x1200, &[1, 2]);
+        tape.extend(mzf(0x01, "SECOND", 0x2000, 0x2000, &[3]));
+        tape.extend([0xAA; 5]);
+        let entries = parse_mzt(&tape).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].diagnostics.is_empty());
+        assert!(!entries[1].truncated);
+        assert_eq!(entries[1].diagnostics, ["5 bytes at 000103 are too short for another header"]);
+        assert!(list_mzt(&entries).ends_with("\n    5 bytes at 000103 are too short for another header\n"));
+    }
+
+    #[test]
+    fn short_containers_are_refused() {
+        assert!(matches!(
+            parse_mzt(&[0x01; 100]),
+            Err(Error::TruncatedHeader { format: "MZF", expected: 128, found: 100 })
+        ));
+    }
+}
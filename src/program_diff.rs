@@ -0,0 +1,310 @@
+// src/program_diff.rs
+//
+// Comparison of two versions of a program: BASIC line by line, matched on line number,
+// and machine code byte by byte, matched on load address.
+
+use crate::basic::{BasicLine, BasicListing};
+use std::collections::BTreeMap;
+
+/// How one BASIC line differs between the two programs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineChange {
+    /// The line is only in the new program.
+    Added(BasicLine),
+    /// The line is only in the old program.
+    Removed(BasicLine),
+    /// Both programs have the line, with different statements.
+    Changed { old: BasicLine, new: BasicLine },
+}
+
+impl LineChange {
+    /// The line number the change is at.
+    pub fn number(&self) -> u16 {
+        match self {
+            LineChange::Added(line) | LineChange::Removed(line) | LineChange::Changed { new: line, .. } => line.number,
+        }
+    }
+}
+
+/// A run of bytes that differs between the two programs. Where one program does not
+/// load anything at an address, its side of the change is empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ByteChange {
+    /// Address of the first byte.
+    pub address: u32,
+    /// The bytes in the old program.
+    pub old: Vec<u8>,
+    /// The bytes in the new program.
+    pub new: Vec<u8>,
+}
+
+/// The differences between two programs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProgramDiff {
+    /// Added, removed and changed BASIC lines, in line number order.
+    pub lines: Vec<LineChange>,
+    /// Changed machine code, in address order.
+    pub bytes: Vec<ByteChange>,
+    /// Problems decoding either program, which may hide differences past the damage.
+    pub diagnostics: Vec<String>,
+    /// True if the programs have the same lines in the same order, and differ only in
+    /// their line numbers and the references to them.
+    pub renumbered: bool,
+}
+
+/// Maps each line number to its first line, so a damaged program that repeats a
+/// number compares its first copy.
+fn lines_by_number(listing: &BasicListing) -> BTreeMap<u16, &BasicLine> {
+    let mut lines = BTreeMap::new();
+    for line in &listing.lines {
+        lines.entry(line.number).or_insert(line);
+    }
+    lines
+}
+
+/// Each line's text with its line number references replaced by the position of the
+/// line they go to, so that two numberings of a program give the same texts.
+fn numbering_free_texts(listing: &BasicListing) -> Vec<String> {
+    let positions: BTreeMap<u16, usize> =
+        listing.lines.iter().enumerate().rev().map(|(position, line)| (line.number, position)).collect();
+    let targets: BTreeMap<usize, usize> = listing
+        .analyse()
+        .references
+        .iter()
+        .filter_map(|r| Some((r.offset?, *positions.get(&r.target?)?)))
+        .collect();
+    listing
+        .lines
+        .iter()
+        .map(|line| {
+            line.items
+                .iter()
+                .map(|item| match targets.get(&item.offset) {
+                    Some(position) => format!("#{}", position),
+                    None => item.text.trim().to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect()
+}
+
+impl ProgramDiff {
+    /// Compares two BASIC programs line by line. Lines are matched on their number, so
+    /// a renumbered program shows as changed throughout; `renumbered` tells when that is
+    /// the only change.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The program to compare from
+    /// * `new` - The program to compare to
+    ///
+    /// # Returns
+    ///
+    /// The added, removed and changed lines, with the diagnostics of either listing.
+    pub fn listings(old: &BasicListing, new: &BasicListing) -> Self {
+        let (old_lines, new_lines) = (lines_by_number(old), lines_by_number(new));
+        let mut numbers: Vec<u16> = old_lines.keys().chain(new_lines.keys()).copied().collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+
+        let lines = numbers
+            .into_iter()
+            .filter_map(|number| match (old_lines.get(&number), new_lines.get(&number)) {
+                (Some(old), Some(new)) if old.text != new.text => {
+                    Some(LineChange::Changed { old: (*old).clone(), new: (*new).clone() })
+                }
+                (Some(old), None) => Some(LineChange::Removed((*old).clone())),
+                (None, Some(new)) => Some(LineChange::Added((*new).clone())),
+                _ => None,
+            })
+            .collect();
+        let diagnostics = (old.diagnostics.iter().map(|d| format!("Old program: {}", d)))
+            .chain(new.diagnostics.iter().map(|d| format!("New program: {}", d)))
+            .collect();
+        let renumbered = old.lines.len() == new.lines.len()
+            && old.lines.iter().zip(&new.lines).any(|(old, new)| old.number != new.number)
+            && numbering_free_texts(old) == numbering_free_texts(new);
+        ProgramDiff { lines, bytes: Vec::new(), diagnostics, renumbered }
+    }
+
+    /// Compares two machine code images byte by byte, aligned on their load addresses.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The old code
+    /// * `old_address` - Where the old code loads
+    /// * `new` - The new code
+    /// * `new_address` - Where the new code loads
+    ///
+    /// # Returns
+    ///
+    /// Each run of differing bytes. Addresses only one image covers count as changed,
+    /// with the other side of the change empty.
+    pub fn memory(old: &[u8], old_address: u32, new: &[u8], new_address: u32) -> Self {
+        let byte_at =
+            |code: &[u8], address: u32, at: u32| at.checked_sub(address).and_then(|i| code.get(i as usize).copied());
+        let start = old_address.min(new_address);
+        let end = (old_address + old.len() as u32).max(new_address + new.len() as u32);
+
+        let mut bytes: Vec<ByteChange> = Vec::new();
+        for at in start..end {
+            let (old_byte, new_byte) = (byte_at(old, old_address, at), byte_at(new, new_address, at));
+            if old_byte == new_byte {
+                continue;
+            }
+            // A run continues while the addresses follow on and the same sides are present
+            let extends = bytes.last().is_some_and(|run| {
+                let next = run.address + run.old.len().max(run.new.len()) as u32;
+                next == at && run.old.is_empty() == old_byte.is_none() && run.new.is_empty() == new_byte.is_none()
+            });
+            if !extends {
+                bytes.push(ByteChange { address: at, old: Vec::new(), new: Vec::new() });
+            }
+            let run = bytes.last_mut().unwrap();
+            run.old.extend(old_byte);
+            run.new.extend(new_byte);
+        }
+        ProgramDiff { lines: Vec::new(), bytes, ..Default::default() }
+    }
+
+    /// True if the programs are the same.
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.bytes.is_empty()
+    }
+
+    /// Lists the differences in the style of a unified diff: `-` for the old program and
+    /// `+` for the new, followed by a count of each kind of change. A program that was
+    /// only renumbered is summed up in a line instead.
+    pub fn to_text(&self) -> String {
+        // The ZX81 tokens start with a space, which would double the one after the number
+        let listed = |line: &BasicLine| format!("{} {}", line.number, line.text.trim_start());
+        let mut result = String::new();
+        if self.renumbered {
+            let range = |side: fn(&LineChange) -> Option<u16>| {
+                let numbers: Vec<u16> = self.lines.iter().filter_map(side).collect();
+                format!("{}-{}", numbers.iter().min().unwrap_or(&0), numbers.iter().max().unwrap_or(&0))
+            };
+            let old = range(|c| match c {
+                LineChange::Removed(line) | LineChange::Changed { old: line, .. } => Some(line.number),
+                LineChange::Added(_) => None,
+            });
+            let new = range(|c| match c {
+                LineChange::Added(line) | LineChange::Changed { new: line, .. } => Some(line.number),
+                LineChange::Removed(_) => None,
+            });
+            result.push_str(&format!("The programs are the same apart from their line numbers: lines {} are now {}\n", old, new));
+            for diagnostic in &self.diagnostics {
+                result.push_str(&format!("Error: {}\n", diagnostic));
+            }
+            return result;
+        }
+        for change in &self.lines {
+            match change {
+                LineChange::Added(new) => result.push_str(&format!("+{}\n", listed(new))),
+                LineChange::Removed(old) => result.push_str(&format!("-{}\n", listed(old))),
+                LineChange::Changed { old, new } => result.push_str(&format!("-{}\n+{}\n", listed(old), listed(new))),
+            }
+        }
+        let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ");
+        for change in &self.bytes {
+            // Sixteen bytes to a row, as in DUMP mode
+            let rows = change.old.len().max(change.new.len()).div_ceil(16);
+            for row in 0..rows {
+                let range = |bytes: &[u8]| bytes[(row * 16).min(bytes.len())..(row * 16 + 16).min(bytes.len())].to_vec();
+                let address = change.address + row as u32 * 16;
+                let (old, new) = (range(&change.old), range(&change.new));
+                if !old.is_empty() {
+                    result.push_str(&format!("-{:04X}: {}\n", address, hex(&old)));
+                }
+                if !new.is_empty() {
+                    result.push_str(&format!("+{:04X}: {}\n", address, hex(&new)));
+                }
+            }
+        }
+
+        if !result.is_empty() {
+            result.push('\n');
+        }
+        if self.bytes.is_empty() {
+            let count = |f: fn(&LineChange) -> bool| self.lines.iter().filter(|c| f(c)).count();
+            result.push_str(&format!(
+                "Lines: {} added, {} removed, {} changed\n",
+                count(|c| matches!(c, LineChange::Added(_))),
+                count(|c| matches!(c, LineChange::Removed(_))),
+                count(|c| matches!(c, LineChange::Changed { .. }))
+            ));
+        } else {
+            let changed: usize = self.bytes.iter().map(|c| c.old.len().max(c.new.len())).sum();
+            let runs = self.bytes.len();
+            result.push_str(&format!("{} bytes differ in {} run{}\n", changed, runs, if runs == 1 { "" } else { "s" }));
+        }
+        for diagnostic in &self.diagnostics {
+            result.push_str(&format!("Error: {}\n", diagnostic));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::zx81_p;
+    use crate::zx81_decoder::{decode_zx81_listing, OutputStyle};
+
+    fn diff(old: &[(u16, &str)], new: &[(u16, &str)]) -> ProgramDiff {
+        let listing = |lines| decode_zx81_listing(&zx81_p(lines), OutputStyle::Readable).unwrap();
+        ProgramDiff::listings(&listing(old), &listing(new))
+    }
+
+    const PROGRAM: [(u16, &str); 3] = [(10, "PRINT \"A\""), (20, "GOSUB 30"), (30, "RETURN")];
+
+    #[test]
+    fn identical_programs_have_no_differences() {
+        let same = diff(&PROGRAM, &PROGRAM);
+        assert!(same.is_empty() && !same.renumbered);
+        assert_eq!(same.to_text(), "Lines: 0 added, 0 removed, 0 changed\n");
+    }
+
+    #[test]
+    fn changed_lines() {
+        let changed = diff(&PROGRAM, &[(10, "PRINT \"B\""), (20, "GOSUB 30"), (30, "RETURN")]);
+        assert_eq!(changed.lines.len(), 1);
+        assert!(matches!(&changed.lines[0], LineChange::Changed { old, new } if old.text.contains("\"A\"") && new.text.contains("\"B\"")));
+        assert_eq!(changed.to_text(), "-10 PRINT \"A\"\n+10 PRINT \"B\"\n\nLines: 0 added, 0 removed, 1 changed\n");
+    }
+
+    #[test]
+    fn added_and_removed_lines() {
+        let added = diff(&PROGRAM, &[(5, "CLS"), (10, "PRINT \"A\""), (20, "GOSUB 30"), (30, "RETURN")]);
+        assert_eq!(added.lines.iter().map(LineChange::number).collect::<Vec<_>>(), [5]);
+        assert_eq!(added.to_text(), "+5 CLS\n\nLines: 1 added, 0 removed, 0 changed\n");
+
+        let removed = diff(&PROGRAM, &PROGRAM[1..]);
+        assert!(matches!(&removed.lines[..], [LineChange::Removed(line)] if line.number == 10));
+        assert_eq!(removed.to_text(), "-10 PRINT \"A\"\n\nLines: 0 added, 1 removed, 0 changed\n");
+        assert!(!added.renumbered && !removed.renumbered);
+    }
+
+    #[test]
+    fn renumbered_programs_are_recognised() {
+        let renumbered = diff(&PROGRAM, &[(100, "PRINT \"A\""), (110, "GOSUB 120"), (120, "RETURN")]);
+        assert!(renumbered.renumbered && !renumbered.is_empty());
+        assert_eq!(renumbered.lines.len(), 6);
+        assert_eq!(renumbered.to_text(), "The programs are the same apart from their line numbers: lines 10-30 are now 100-120\n");
+
+        // A reference to another line is a real change
+        let retargeted = diff(&PROGRAM, &[(100, "PRINT \"A\""), (110, "GOSUB 100"), (120, "RETURN")]);
+        assert!(!retargeted.renumbered);
+    }
+
+    #[test]
+    fn machine_code_differences_are_runs_of_bytes() {
+        let diff = ProgramDiff::memory(&[1, 2, 3, 4], 0x1200, &[1, 9, 9, 4, 5], 0x1200);
+        assert_eq!(
+            diff.bytes,
+            [ByteChange { address: 0x1201, old: vec![2, 3], new: vec![9, 9] }, ByteChange { address: 0x1204, old: vec![], new: vec![5] }]
+        );
+        assert_eq!(diff.to_text(), "-1201: 02 03\n+1201: 09 09\n+1204: 05\n\n3 bytes differ in 2 runs\n");
+    }
+}
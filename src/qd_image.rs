@@ -0,0 +1,222 @@
+// src/qd_image.rs
+//
+// Quick Disk images (.qdf / .mzq) for the MZ-700 (MZ-1E19) and MZ-1500.
+//
+// The image holds the raw bytes of the spiral track. Every block starts with a
+// run of SYNC bytes (0x16) and a 0xA5 mark, then a flag byte (bit 0 set for a
+// data block), a 16-bit little-endian length, the block bytes and a 16-bit CRC.
+// Each file is a 64-byte header block followed by its data block. The header
+// block uses the monitor's tape header fields, shifted by two bytes:
+//
+//   0x00 attribute  0x01-0x11 name  0x12 protect  0x14 size  0x16 load  0x18 exec
+
+use crate::error::Error;
+use crate::mzf_file::{MZFHeader, MZF_HEADER_SIZE};
+
+/// Signature at the start of emulator .qdf files.
+const QDF_SIGNATURE: &[u8] = b"-QD format-";
+const QDF_PREAMBLE: usize = 16;
+
+const SYNC: u8 = 0x16;
+const MARK: u8 = 0xA5;
+const QD_HEADER_SIZE: usize = 64;
+
+/// A raw block read from the disk track.
+#[derive(Debug, Clone)]
+pub struct QDBlock {
+    /// Offset of the 0xA5 mark within the image.
+    pub offset: usize,
+    /// Block flag byte (bit 0 set for a data block).
+    pub flag: u8,
+    /// Block contents.
+    pub data: Vec<u8>,
+    /// True if the image ended before the block was complete.
+    pub truncated: bool,
+}
+
+/// A file found on a Quick Disk.
+#[derive(Debug, Clone)]
+pub struct QDFile {
+    /// The header converted to MZF layout.
+    pub header: MZFHeader,
+    /// True if the header marks the file as write protected.
+    pub protected: bool,
+    /// The file as a standalone MZF image.
+    pub mzf: Vec<u8>,
+    /// Offset of the file's header block within the image.
+    pub offset: usize,
+    /// Problems found while reading the file.
+    pub diagnostics: Vec<String>,
+}
+
+/// Finds every SYNC/0xA5 framed block on the track.
+pub fn read_qd_blocks(image: &[u8]) -> Vec<QDBlock> {
+    let start = if image.starts_with(QDF_SIGNATURE) { QDF_PREAMBLE } else { 0 };
+    let mut blocks = Vec::new();
+    let mut pos = start + 1;
+
+    while pos + 3 < image.len() {
+        if image[pos] != MARK || image[pos - 1] != SYNC {
+            pos += 1;
+            continue;
+        }
+        let flag = image[pos + 1];
+        let len = u16::from_le_bytes([image[pos + 2], image[pos + 3]]) as usize;
+        let data_start = pos + 4;
+        let data_end = (data_start + len).min(image.len());
+        blocks.push(QDBlock {
+            offset: pos,
+            flag,
+            data: image[data_start..data_end].to_vec(),
+            truncated: data_start + len > image.len(),
+        });
+        // Skip the block and its CRC
+        pos = data_end + 2;
+    }
+    blocks
+}
+
+/// Converts a 64-byte QD header block into a 128-byte MZF header.
+fn qd_header_to_mzf(qd: &[u8]) -> Vec<u8> {
+    let mut mzf = vec![0u8; MZF_HEADER_SIZE];
+    mzf[..0x12].copy_from_slice(&qd[..0x12]);
+    mzf[0x12..0x18].copy_from_slice(&qd[0x14..0x1A]);
+    // Carry the remaining header bytes into the MZF comment area
+    mzf[0x18..0x18 + QD_HEADER_SIZE - 0x1A].copy_from_slice(&qd[0x1A..QD_HEADER_SIZE]);
+    mzf
+}
+
+/// Lists the files stored on a Quick Disk image.
+///
+/// # Arguments
+///
+/// * `image` - The complete .qdf or .mzq image
+///
+/// # Returns
+///
+/// A `Result` containing each file as a standalone MZF image, or an error message if no files were found.
+pub fn parse_qd_image(image: &[u8]) -> Result<Vec<QDFile>, Error> {
+    let blocks = read_qd_blocks(image);
+    let mut files = Vec::new();
+    let mut i = 0;
+
+    while i < blocks.len() {
+        let block = &blocks[i];
+        i += 1;
+        if block.flag & 1 != 0 || block.data.len() != QD_HEADER_SIZE {
+            continue;
+        }
+
+        let mut mzf = qd_header_to_mzf(&block.data);
+        let header = MZFHeader::parse(&mzf)?;
+        let mut diagnostics = Vec::new();
+
+        match blocks.get(i) {
+            Some(data) if data.flag & 1 != 0 => {
+                i += 1;
+                if data.truncated {
+                    diagnostics.push(format!("Data block at {:06X} is truncated", data.offset));
+                }
+                if data.data.len() != header.size as usize {
+                    diagnostics.push(format!(
+                        "Data block holds {} bytes, header says {}", data.data.len(), header.size
+                    ));
+                }
+                mzf.extend_from_slice(&data.data);
+            }
+            _ => diagnostics.push("No data block follows the header".to_string()),
+        }
+
+        files.push(QDFile {
+            protected: block.data[0x12] != 0,
+            header,
+            mzf,
+            offset: block.offset,
+            diagnostics,
+        });
+    }
+
+    if files.is_empty() {
+        return Err(Error::NothingFound("No files found on the Quick Disk image."));
+    }
+    Ok(files)
+}
+
+/// Lists the files of a Quick Disk, one line per file.
+pub fn list_qd(files: &[QDFile]) -> String {
+    let mut result = String::new();
+    for (i, file) in files.iter().enumerate() {
+        let h = &file.header;
+        result.push_str(&format!(
+            "{:2}: {:06X} {:<17} type {:02X} {:<35} size {:04X} load {:04X} exec {:04X}{}\n",
+            i,
+            file.offset,
+            h.name_string(),
+            h.file_type,
+            h.type_description(),
+            h.size,
+            h.load_address,
+            h.exec_address,
+            if file.protected { " protected" } else { "" }
+        ));
+        for d in &file.diagnostics {
+            result.push_str(&format!("    {}\n", d));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{qd_block as block, qd_header as header};
+
+    fn image() -> Vec<u8> {
+        let mut image = QDF_SIGNATURE.to_vec();
+        image.resize(QDF_PREAMBLE, 0);
+        image.extend(block(0, &header("FIRST", false, 4, 0x1200, 0x1234)));
+        image.extend(block(1, &[1, 2, 3, 4]));
+        image.extend(block(0, &header("SECOND", true, 2, 0x2000, 0x2000)));
+        image.extend(block(1, &[0xC9, 0x00]));
+        image
+    }
+
+    #[test]
+    fn files_are_extracted_as_mzf() {
+        let files = parse_qd_image(&image()).unwrap();
+        assert_eq!(files.len(), 2);
+        let first = &files[0];
+        assert_eq!(first.header.name_string(), "FIRST");
+        assert_eq!((first.header.size, first.header.load_address, first.header.exec_address), (4, 0x1200, 0x1234));
+        assert_eq!(first.mzf.len(), MZF_HEADER_SIZE + 4);
+        assert_eq!(&first.mzf[MZF_HEADER_SIZE..], &[1, 2, 3, 4]);
+        assert_eq!(MZFHeader::parse(&first.mzf).unwrap(), first.header);
+        assert_eq!(first.offset, QDF_PREAMBLE + 10);
+        assert!(!first.protected && first.diagnostics.is_empty());
+
+        assert_eq!(files[1].header.name_string(), "SECOND");
+        assert!(files[1].protected);
+        assert!(list_qd(&files).contains(" protected\n"));
+    }
+
+    #[test]
+    fn raw_track_images_are_read() {
+        let files = parse_qd_image(&image()[QDF_PREAMBLE..]).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].offset, 10);
+    }
+
+    #[test]
+    fn damaged_files_are_reported() {
+        let mut image = image();
+        // Drop the second data block, and cut the first short
+        image.truncate(image.len() - block(1, &[0xC9, 0x00]).len());
+        let files = parse_qd_image(&image).unwrap();
+        assert_eq!(files[1].diagnostics, ["No data block follows the header"]);
+
+        let cut = &image[..QDF_PREAMBLE + block(0, &header("FIRST", false, 4, 0, 0)).len() + 14];
+        let files = parse_qd_image(cut).unwrap();
+        assert_eq!(files[0].diagnostics.len(), 2, "{:?}", files[0].diagnostics);
+        assert!(matches!(parse_qd_image(&[SYNC; 100]), Err(Error::NothingFound(_))));
+    }
+}
@@ -0,0 +1,334 @@
+// src/sharp_disk.rs
+//
+// Floppy disk images for the MZ-80B, MZ-2000/2200 and MZ-800 (FDOS and DISK BASIC).
+//
+// Images are either D88 files or raw dumps of 256-byte sectors in track order
+// (16 sectors per side, both sides of a cylinder in turn). The MZ-80B/2000
+// disk controller inverts the data bus, so many images hold every byte
+// inverted; this is detected and undone.
+//
+// The directory lives in logical sectors 16-23: 64 entries of 32 bytes each.
+//
+//   0x00 file mode  0x01-0x11 name  0x12 protect  0x14 size  0x16 load  0x18 exec
+//   0x1A-0x1D date  0x1E start sector
+//
+// Program files occupy consecutive sectors from the start sector. Sequential data
+// files (BSD) are chained: each sector holds 254 data bytes and the number of the
+// next sector in its last two bytes.
+
+use crate::error::Error;
+use crate::mzf_file::{MZFHeader, MZF_HEADER_SIZE};
+
+const SECTOR_SIZE: usize = 256;
+const SECTORS_PER_TRACK: usize = 16;
+const DIRECTORY_START: usize = 16;
+const DIRECTORY_SECTORS: usize = 8;
+const ENTRY_SIZE: usize = 32;
+
+const D88_HEADER_SIZE: usize = 0x2B0;
+const D88_TRACKS: usize = 164;
+
+/// File mode of a sequential data file, stored as a sector chain.
+const MODE_BSD: u8 = 0x03;
+
+/// A file listed in the disk directory.
+#[derive(Debug, Clone)]
+pub struct DiskFile {
+    /// Directory entry number.
+    pub entry: usize,
+    /// The directory entry converted to an MZF header.
+    pub header: MZFHeader,
+    /// True if the entry is write protected.
+    pub protected: bool,
+    /// First logical sector of the file.
+    pub start_sector: usize,
+    /// The file as a standalone MZF image.
+    pub mzf: Vec<u8>,
+    /// Problems found while reading the file.
+    pub diagnostics: Vec<String>,
+}
+
+/// Logical sectors of a disk image, in the order the DOS numbers them.
+struct Sectors {
+    data: Vec<Option<Vec<u8>>>,
+}
+
+impl Sectors {
+    fn get(&self, sector: usize) -> Option<&[u8]> {
+        self.data.get(sector)?.as_deref()
+    }
+
+    fn invert(&mut self) {
+        for sector in self.data.iter_mut().flatten() {
+            sector.iter_mut().for_each(|b| *b = !*b);
+        }
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<usize> {
+    Some(u16::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?]) as usize)
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<usize> {
+    Some(u32::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?, *data.get(pos + 2)?, *data.get(pos + 3)?]) as usize)
+}
+
+/// Reads the sectors of a D88 image, indexed by (cylinder * 2 + head) * 16 + sector - 1.
+fn read_d88(image: &[u8]) -> Option<Sectors> {
+    let disk_size = u32_at(image, 0x1C)?;
+    if disk_size != image.len() || disk_size < D88_HEADER_SIZE {
+        return None;
+    }
+
+    let mut data: Vec<Option<Vec<u8>>> = Vec::new();
+    for track in 0..D88_TRACKS {
+        let mut pos = u32_at(image, 0x20 + track * 4)?;
+        if pos == 0 {
+            continue;
+        }
+        let mut remaining = 1;
+        while remaining > 0 && pos + 0x10 <= image.len() {
+            let (c, h, r) = (image[pos] as usize, image[pos + 1] as usize, image[pos + 2] as usize);
+            remaining = u16_at(image, pos + 4)?;
+            let size = u16_at(image, pos + 0x0E)?;
+            let body = image.get(pos + 0x10..pos + 0x10 + size)?;
+            if (1..=SECTORS_PER_TRACK).contains(&r) && size == SECTOR_SIZE {
+                let index = (c * 2 + (h & 1)) * SECTORS_PER_TRACK + r - 1;
+                if data.len() <= index {
+                    data.resize(index + 1, None);
+                }
+                data[index] = Some(body.to_vec());
+            }
+            pos += 0x10 + size;
+            // A damaged header can give a sector count of zero
+            remaining = remaining.saturating_sub(1);
+        }
+    }
+    Some(Sectors { data })
+}
+
+/// Reads a raw image of consecutive 256-byte sectors.
+fn read_raw(image: &[u8]) -> Option<Sectors> {
+    if image.is_empty() || !image.len().is_multiple_of(SECTOR_SIZE) {
+        return None;
+    }
+    Some(Sectors { data: image.chunks(SECTOR_SIZE).map(|s| Some(s.to_vec())).collect() })
+}
+
+/// Counts the directory entries that look like files, to tell inverted images from plain ones.
+fn plausible_entries(sectors: &Sectors) -> usize {
+    directory_entries(sectors)
+        .filter(|(_, e)| (0x01..=0x05).contains(&e[0]) && e[1..0x12].contains(&0x0D))
+        .count()
+}
+
+fn directory_entries(sectors: &Sectors) -> impl Iterator<Item = (usize, &[u8])> {
+    (DIRECTORY_START..DIRECTORY_START + DIRECTORY_SECTORS)
+        .filter_map(|s| sectors.get(s))
+        .flat_map(|s| s.chunks(ENTRY_SIZE))
+        .enumerate()
+}
+
+/// Reads a program file from consecutive sectors.
+fn read_contiguous(sectors: &Sectors, start: usize, size: usize, diagnostics: &mut Vec<String>) -> Vec<u8> {
+    let mut body = Vec::with_capacity(size);
+    let mut sector = start;
+    while body.len() < size {
+        match sectors.get(sector) {
+            Some(data) => body.extend_from_slice(&data[..(size - body.len()).min(SECTOR_SIZE)]),
+            None => {
+                diagnostics.push(format!("Sector {} is missing from the image", sector));
+                break;
+            }
+        }
+        sector += 1;
+    }
+    body
+}
+
+/// Reads a sequential data file by following its sector chain.
+fn read_chain(sectors: &Sectors, start: usize, diagnostics: &mut Vec<String>) -> Vec<u8> {
+    let mut body = Vec::new();
+    let mut sector = start;
+    let mut visited = vec![false; sectors.data.len()];
+    while sector != 0 {
+        let Some(data) = sectors.get(sector) else {
+            diagnostics.push(format!("Sector {} is missing from the image", sector));
+            break;
+        };
+        if std::mem::replace(&mut visited[sector], true) {
+            diagnostics.push(format!("Sector chain loops back to sector {}", sector));
+            break;
+        }
+        body.extend_from_slice(&data[..SECTOR_SIZE - 2]);
+        sector = u16::from_le_bytes([data[SECTOR_SIZE - 2], data[SECTOR_SIZE - 1]]) as usize;
+    }
+    body
+}
+
+/// Lists the files on a Sharp FDOS or DISK BASIC floppy image.
+///
+/// # Arguments
+///
+/// * `image` - A D88 image or a raw sector dump
+///
+/// # Returns
+///
+/// A `Result` containing each directory entry with the file extracted as an MZF image,
+/// or an error message if the image or its directory cannot be read.
+pub fn parse_sharp_disk(image: &[u8]) -> Result<Vec<DiskFile>, Error> {
+    let mut sectors = read_d88(image)
+        .or_else(|| read_raw(image))
+        .ok_or(Error::InvalidFormat("Not a D88 image or raw sector dump."))?;
+    if sectors.get(DIRECTORY_START).is_none() {
+        return Err(Error::InvalidFormat("Disk image has no directory sectors."));
+    }
+
+    let plain = plausible_entries(&sectors);
+    sectors.invert();
+    if plausible_entries(&sectors) <= plain {
+        sectors.invert();
+    }
+
+    let mut files = Vec::new();
+    for (entry, dir) in directory_entries(&sectors) {
+        if !(0x01..=0x05).contains(&dir[0]) {
+            continue;
+        }
+        let mut mzf = vec![0u8; MZF_HEADER_SIZE];
+        mzf[..0x12].copy_from_slice(&dir[..0x12]);
+        mzf[0x12..0x18].copy_from_slice(&dir[0x14..0x1A]);
+        let header = MZFHeader::parse(&mzf)?;
+        let start_sector = u16_at(dir, 0x1E).unwrap_or(0);
+
+        let mut diagnostics = Vec::new();
+        let body = if dir[0] == MODE_BSD {
+            read_chain(&sectors, start_sector, &mut diagnostics)
+        } else {
+            read_contiguous(&sectors, start_sector, header.size as usize, &mut diagnostics)
+        };
+        // Chained files may exceed 64K, so the MZF size field is only a hint for them
+        mzf[0x12..0x14].copy_from_slice(&(body.len().min(0xFFFF) as u16).to_le_bytes());
+        mzf.extend(body);
+
+        files.push(DiskFile {
+            entry,
+            header: MZFHeader::parse(&mzf)?,
+            protected: dir[0x12] != 0,
+            start_sector,
+            mzf,
+            diagnostics,
+        });
+    }
+
+    if files.is_empty() {
+        return Err(Error::NothingFound("No files found in the disk directory."));
+    }
+    Ok(files)
+}
+
+/// Lists the files of a disk directory, one line per file.
+pub fn list_disk(files: &[DiskFile]) -> String {
+    let mut result = String::new();
+    for (i, file) in files.iter().enumerate() {
+        let h = &file.header;
+        result.push_str(&format!(
+            "{:2}: entry {:2} {:<17} type {:02X} {:<35} size {:04X} load {:04X} exec {:04X} sector {:04X}{}\n",
+            i,
+            file.entry,
+            h.name_string(),
+            h.file_type,
+            h.type_description(),
+            h.size,
+            h.load_address,
+            h.exec_address,
+            file.start_sector,
+            if file.protected { " protected" } else { "" }
+        ));
+        for d in &file.diagnostics {
+            result.push_str(&format!("    {}\n", d));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{d88, disk_entry as entry};
+
+    fn program() -> Vec<u8> {
+        (0..300).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Two cylinders of sectors: a program in sectors 40-41 and a data file chained
+    /// through sectors 43 and 45.
+    fn sectors() -> Vec<[u8; SECTOR_SIZE]> {
+        let mut sectors = vec![[0u8; SECTOR_SIZE]; 4 * SECTORS_PER_TRACK];
+        let mut directory = entry(0x01, "PROGRAM", 300, 0x1200, 40);
+        directory.extend(entry(MODE_BSD, "DATA", 0, 0, 43));
+        sectors[DIRECTORY_START][..directory.len()].copy_from_slice(&directory);
+        let program = program();
+        sectors[40].copy_from_slice(&program[..SECTOR_SIZE]);
+        sectors[41][..300 - SECTOR_SIZE].copy_from_slice(&program[SECTOR_SIZE..]);
+        sectors[43][..SECTOR_SIZE - 2].fill(b'A');
+        sectors[43][SECTOR_SIZE - 2] = 45;
+        sectors[45][..SECTOR_SIZE - 2].fill(b'B');
+        sectors
+    }
+
+    fn raw(sectors: &[[u8; SECTOR_SIZE]]) -> Vec<u8> {
+        sectors.concat()
+    }
+
+    fn check(files: &[DiskFile]) {
+        assert_eq!(files.len(), 2);
+        let program_file = &files[0];
+        assert_eq!(program_file.entry, 0);
+        assert_eq!(program_file.header.name_string(), "PROGRAM");
+        assert_eq!(program_file.header.load_address, 0x1200);
+        assert_eq!(program_file.start_sector, 40);
+        assert_eq!(&program_file.mzf[MZF_HEADER_SIZE..], &program()[..]);
+        assert!(program_file.diagnostics.is_empty());
+
+        let data_file = &files[1];
+        assert_eq!(data_file.header.size as usize, 2 * (SECTOR_SIZE - 2));
+        let body = &data_file.mzf[MZF_HEADER_SIZE..];
+        assert!(body[..SECTOR_SIZE - 2].iter().all(|&b| b == b'A'));
+        assert!(body[SECTOR_SIZE - 2..].iter().all(|&b| b == b'B'));
+    }
+
+    #[test]
+    fn files_are_extracted_from_raw_images() {
+        check(&parse_sharp_disk(&raw(&sectors())).unwrap());
+    }
+
+    #[test]
+    fn files_are_extracted_from_d88_images() {
+        check(&parse_sharp_disk(&d88(&sectors())).unwrap());
+    }
+
+    #[test]
+    fn inverted_images_are_read() {
+        let inverted: Vec<u8> = raw(&sectors()).iter().map(|b| !b).collect();
+        check(&parse_sharp_disk(&inverted).unwrap());
+    }
+
+    #[test]
+    fn damaged_files_are_reported() {
+        let mut sectors = sectors();
+        // The chain loops back on itself
+        sectors[45][SECTOR_SIZE - 2] = 43;
+        let files = parse_sharp_disk(&raw(&sectors)).unwrap();
+        assert_eq!(files[1].diagnostics, ["Sector chain loops back to sector 43"]);
+
+        // The program runs off the end of the image
+        let files = parse_sharp_disk(&raw(&sectors[..41])).unwrap();
+        assert_eq!(files[0].diagnostics, ["Sector 41 is missing from the image"]);
+        assert!(list_disk(&files).contains("    Sector 41 is missing from the image\n"));
+
+        assert!(matches!(parse_sharp_disk(&[0; 100]), Err(Error::InvalidFormat(_))));
+        assert!(matches!(parse_sharp_disk(&[0; 4 * SECTOR_SIZE]), Err(Error::InvalidFormat(_))));
+        assert!(matches!(parse_sharp_disk(&[0; 32 * SECTOR_SIZE]), Err(Error::NothingFound(_))));
+    }
+}
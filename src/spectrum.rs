@@ -0,0 +1,59 @@
+//! ZX Spectrum 48K files: BASIC programs and code on .tap and TZX tape images.
+//!
+//! ```no_run
+//! use rust_wasm_converter::spectrum;
+//!
+//! let data = std::fs::read("GAME.tap").unwrap();
+//! print!("{}", spectrum::decode_basic(&data).unwrap().to_text(4));
+//! for file in spectrum::parse_spectrum_tape(&data).unwrap() {
+//!     if let Some(address) = file.load_address() {
+//!         println!("{} bytes of code at {:04X}", file.data.len(), address);
+//!     }
+//! }
+//! ```
+
+pub use crate::basic::{BasicItem, BasicLine, BasicListing, ItemKind};
+pub use crate::basic_analysis::{AddressUse, LineReference, ProgramAnalysis, VariableUse};
+pub use crate::basic_format::FormatOptions;
+pub use crate::error::Error;
+pub use crate::spectrum_decoder::spectrum_number;
+pub use crate::spectrum_tape::{
+    list_spectrum_tape, parse_spectrum_tape, SpectrumFileType, SpectrumHeader, SpectrumTapeFile,
+};
+
+/// Decodes the first BASIC program on a Spectrum tape.
+///
+/// # Arguments
+///
+/// * `data` - A complete .tap or .tzx image
+///
+/// # Returns
+///
+/// A `Result` containing the program name and lines, with offsets within the image, or an
+/// error if the tape holds no program or not even its first line could be read. A damaged
+/// program is listed up to the damage, with the reason in `diagnostics`.
+pub fn decode_basic(data: &[u8]) -> Result<BasicListing, Error> {
+    let files = parse_spectrum_tape(data)?;
+    let file = files
+        .iter()
+        .find(|file| file.program().is_some_and(|program| !program.is_empty()))
+        .ok_or(Error::NothingFound("No BASIC program found on the tape."))?;
+    let mut listing = decode_program(file.program().unwrap_or_default(), file.offset)?;
+    listing.name = file.header.as_ref().map(|header| header.name.clone());
+    Ok(listing)
+}
+
+/// Decodes a Spectrum BASIC program area, such as the data block of a program file or
+/// memory from PROG onwards.
+///
+/// # Arguments
+///
+/// * `program` - The program bytes; anything after the last line, such as the variables, is ignored
+/// * `base` - Offset of the first byte within its file, added to the offsets reported
+///
+/// # Returns
+///
+/// A `Result` containing the program lines, or an error if not even the first line could be read.
+pub fn decode_program(program: &[u8], base: usize) -> Result<BasicListing, Error> {
+    crate::spectrum_decoder::decode_spectrum_listing(program, base)
+}
@@ -0,0 +1,271 @@
+// src/spectrum_decoder.rs
+//
+// ZX Spectrum 48K BASIC. A line holds its number (big-endian), its length (little-endian)
+// and the tokenised statements, ending with ENTER (0x0D). Every number typed in a line
+// is followed by a hidden copy: 0x0E and five bytes, either a small integer or the
+// floating point form the ZX81 uses.
+
+use crate::basic::{BasicListing, ItemKind, LineBuilder};
+use crate::error::Error;
+use crate::zx81_decoder::zx81_float;
+
+/// Keywords for codes 0xA5 (RND) to 0xFF (COPY). Codes 0xA3 and 0xA4, SPECTRUM and PLAY
+/// on the 128K, are user-defined graphics on the 48K.
+const TOKENS: [&str; 91] = [
+    "RND", "INKEY$", "PI", "FN", "POINT", "SCREEN$", "ATTR", "AT", "TAB", "VAL$", "CODE",
+    "VAL", "LEN", "SIN", "COS", "TAN", "ASN", "ACS", "ATN", "LN", "EXP", "INT", "SQR",
+    "SGN", "ABS", "PEEK", "IN", "USR", "STR$", "CHR$", "NOT", "BIN", "OR", "AND", "<=",
+    ">=", "<>", "LINE", "THEN", "TO", "STEP", "DEF FN", "CAT", "FORMAT", "MOVE", "ERASE",
+    "OPEN #", "CLOSE #", "MERGE", "VERIFY", "BEEP", "CIRCLE", "INK", "PAPER", "FLASH",
+    "BRIGHT", "INVERSE", "OVER", "OUT", "LPRINT", "LLIST", "STOP", "READ", "DATA",
+    "RESTORE", "NEW", "BORDER", "CONTINUE", "DIM", "REM", "FOR", "GO TO", "GO SUB", "INPUT",
+    "LOAD", "LIST", "LET", "PAUSE", "NEXT", "POKE", "PRINT", "PLOT", "RUN", "SAVE",
+    "RANDOMIZE", "IF", "CLS", "DRAW", "CLEAR", "RETURN", "COPY",
+];
+
+const FIRST_TOKEN: u8 = 0xA5;
+const REM: u8 = 0xEA;
+const NUMBER_MARKER: u8 = 0x0E;
+const ENTER: u8 = 0x0D;
+
+/// Block graphics 0x80 to 0x8F: bit 0 is the top right quarter, bit 1 the top left,
+/// bit 2 the bottom right and bit 3 the bottom left.
+const BLOCK_GRAPHICS: [char; 16] =
+    [' ', '▝', '▘', '▀', '▗', '▐', '▚', '▜', '▖', '▞', '▌', '▛', '▄', '▟', '▙', '█'];
+
+/// Converts the five bytes of a hidden number into an `f64`. A first byte of 0 marks a
+/// whole number from -65535 to 65535: a sign byte (0 or 0xFF), then the value, low byte
+/// first, as two's complement when negative. Anything else is a floating point number.
+pub fn spectrum_number(bytes: &[u8; 5]) -> f64 {
+    if bytes[0] == 0 {
+        let value = u16::from_le_bytes([bytes[2], bytes[3]]) as f64;
+        return if bytes[1] == 0xFF { value - 65536.0 } else { value };
+    }
+    zx81_float(bytes)
+}
+
+/// The listed form of a character code, or `None` for a control code.
+fn char_text(code: u8) -> Option<String> {
+    Some(match code {
+        0x60 => "£".to_string(),
+        0x7F => "©".to_string(),
+        0x20..=0x7E => (code as char).to_string(),
+        0x80..=0x8F => BLOCK_GRAPHICS[(code - 0x80) as usize].to_string(),
+        // User-defined graphics, in the zmakebas style: \a to \u
+        0x90..=0xA4 => format!("\\{}", (b'a' + (code - 0x90)) as char),
+        _ => return None,
+    })
+}
+
+/// How many bytes a control code takes, itself and its parameters: one each for INK,
+/// PAPER, FLASH, BRIGHT, INVERSE and OVER, two for AT and TAB.
+fn control_length(code: u8) -> usize {
+    match code {
+        0x10..=0x15 => 2,
+        0x16 | 0x17 => 3,
+        _ => 1,
+    }
+}
+
+/// If a number literal (digits, '.', and an exponent) starts at `start` and is followed by
+/// the hidden number marker, returns the marker's position.
+fn hidden_number_marker(bytes: &[u8], start: usize) -> Option<usize> {
+    if !bytes[start].is_ascii_digit() && bytes[start] != b'.' {
+        return None;
+    }
+    for j in start + 1..bytes.len() {
+        match bytes[j] {
+            b'0'..=b'9' | b'.' | b'e' | b'E' => {}
+            b'+' | b'-' if matches!(bytes[j - 1], b'e' | b'E') => {}
+            NUMBER_MARKER => return Some(j),
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Decodes the statements of a line, which start at `offset` in the file. Keywords are
+/// spaced as the Spectrum lists them: a space after each word, and one before OR, AND
+/// and the commands unless the text already ends with one.
+fn decode_line(bytes: &[u8], offset: usize) -> LineBuilder {
+    let mut line = LineBuilder::default();
+    let mut in_string = false;
+    let mut in_rem = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let byte = bytes[i];
+        let at = offset + i;
+
+        if byte >= FIRST_TOKEN && !in_string && !in_rem {
+            let word = TOKENS[(byte - FIRST_TOKEN) as usize];
+            let operator = matches!(byte, 0xC7..=0xC9);
+            let mut keyword = String::new();
+            if byte >= 0xC5 && !operator && !line.text().is_empty() && !line.text().ends_with(' ') {
+                keyword.push(' ');
+            }
+            keyword.push_str(word);
+            let next = bytes.get(i + 1).copied();
+            if !operator && !matches!(byte, 0xA5..=0xA7) && next.is_some_and(|b| b != b' ') {
+                keyword.push(' ');
+            }
+            line.push(ItemKind::Keyword, at, &keyword);
+            in_rem = byte == REM;
+            i += 1;
+            continue;
+        }
+        if byte >= FIRST_TOKEN {
+            // Keywords inside strings and REMs are listed in full
+            let kind = if in_rem { ItemKind::Rem } else { ItemKind::String };
+            let word = TOKENS[(byte - FIRST_TOKEN) as usize];
+            line.push(kind, at, word);
+            i += 1;
+            continue;
+        }
+
+        if !in_string && !in_rem {
+            if let Some(marker) = hidden_number_marker(bytes, i) {
+                let digits: String = bytes[i..marker].iter().map(|&b| b as char).collect();
+                let value = bytes.get(marker + 1..marker + 6).and_then(|b| b.try_into().ok()).map(spectrum_number);
+                line.number(at, &digits, value.unwrap_or(0.0));
+                i = marker + 6;
+                continue;
+            }
+            if byte == NUMBER_MARKER {
+                // A hidden number with no digits before it, as after a DEF FN parameter
+                i += 6;
+                continue;
+            }
+        }
+
+        match char_text(byte) {
+            Some(ch) => {
+                let kind = if in_rem {
+                    ItemKind::Rem
+                } else if byte == b'"' || in_string {
+                    ItemKind::String
+                } else {
+                    ItemKind::Text
+                };
+                if byte == b'"' && !in_rem {
+                    in_string = !in_string;
+                }
+                line.push(kind, at, &ch);
+                i += 1;
+            }
+            // Colour and position codes are not listed, and take their parameters with them
+            None => i += control_length(byte),
+        }
+    }
+    line
+}
+
+/// Decodes a Spectrum BASIC program area into numbered lines.
+///
+/// # Arguments
+///
+/// * `program` - The program bytes, as saved in the data block of a program file
+/// * `base` - Offset of the first program byte within the file, for the offsets reported
+///
+/// # Returns
+///
+/// A `Result` containing the program lines, with a diagnostic if a damaged line stopped
+/// decoding early, or an error if not even the first line could be read. Decoding stops
+/// at the end of the bytes or where the variables start (a line number above 16383).
+pub fn decode_spectrum_listing(program: &[u8], base: usize) -> Result<BasicListing, Error> {
+    let mut listing = BasicListing::default();
+    let mut pos = 0;
+    while pos < program.len() {
+        let Some(header) = program.get(pos..pos + 4) else {
+            return listing.partial(Error::UnexpectedEnd { offset: base + program.len(), line: None });
+        };
+        let number = u16::from_be_bytes([header[0], header[1]]);
+        if number > 16383 {
+            break;
+        }
+        let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let end = pos + 4 + length;
+        if length == 0 || end > program.len() || program[end - 1] != ENTER {
+            return listing.partial(Error::BadLineLink { offset: base + pos, line: number, link: length });
+        }
+        let line = decode_line(&program[pos + 4..end - 1], base + pos + 4);
+        listing.lines.push(line.into_line(number, base + pos, length + 4));
+        pos = end;
+    }
+    if listing.lines.is_empty() {
+        return Err(Error::NothingFound("No valid BASIC program found in the file."));
+    }
+    Ok(listing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::spectrum_line;
+    use crate::zx81_decoder::zx81_float_bytes;
+
+    /// A number as typed, then its hidden form.
+    fn number(digits: &str, hidden: [u8; 5]) -> Vec<u8> {
+        let mut bytes = digits.as_bytes().to_vec();
+        bytes.push(NUMBER_MARKER);
+        bytes.extend(hidden);
+        bytes
+    }
+
+    fn program() -> Vec<u8> {
+        let mut program = Vec::new();
+        program.extend(spectrum_line(10, &[[0xF5].as_slice(), b"\"HI\";", &number("1", [0, 0, 1, 0, 0])].concat()));
+        program.extend(spectrum_line(20, &[[0xF1].as_slice(), b"X=", &number("0.5", zx81_float_bytes(0.5))].concat()));
+        // INK 2 is not listed, and takes its parameter with it
+        program.extend(spectrum_line(30, &[0xF5, 0x10, 0x02, 0x90, 0xA4, 0x8F]));
+        program.extend(spectrum_line(40, &[[0xEC].as_slice(), &number("10", [0, 0, 10, 0, 0])].concat()));
+        program.extend(spectrum_line(50, &[0xEA, b' ', 0xF5, b'!']));
+        // The variables: A = 5
+        program.extend([0x61, 0, 0, 5, 0, 0, 0x80]);
+        program
+    }
+
+    #[test]
+    fn lines_are_listed() {
+        let listing = decode_spectrum_listing(&program(), 0).unwrap();
+        assert!(listing.diagnostics.is_empty());
+        assert_eq!(
+            listing.to_text(2),
+            "10 PRINT \"HI\";1\n20 LET X=0.5\n30 PRINT \\a\\u█\n40 GO TO 10\n50 REM PRINT!\n"
+        );
+    }
+
+    #[test]
+    fn hidden_numbers_give_the_values() {
+        let listing = decode_spectrum_listing(&program(), 0).unwrap();
+        let values: Vec<f64> = listing
+            .lines
+            .iter()
+            .flat_map(|line| &line.items)
+            .filter(|item| item.kind == ItemKind::Number)
+            .filter_map(|item| item.value)
+            .collect();
+        assert_eq!(values, [1.0, 0.5, 10.0]);
+        assert_eq!(spectrum_number(&[0, 0xFF, 0xFF, 0xFF, 0]), -1.0);
+        assert_eq!(spectrum_number(&[0, 0, 0xFF, 0xFF, 0]), 65535.0);
+    }
+
+    #[test]
+    fn every_user_defined_graphic_is_listed() {
+        let udgs: Vec<u8> = (0x90..=0xA4).collect();
+        let listing = decode_spectrum_listing(&spectrum_line(1, &udgs), 0).unwrap();
+        assert_eq!(listing.lines[0].text, "\\a\\b\\c\\d\\e\\f\\g\\h\\i\\j\\k\\l\\m\\n\\o\\p\\q\\r\\s\\t\\u");
+    }
+
+    #[test]
+    fn damaged_lines_stop_the_listing() {
+        let mut program = program();
+        // Line 20's length now runs past its ENTER
+        let line_20 = program.iter().position(|&b| b == ENTER).unwrap() + 1;
+        program[line_20 + 2] += 1;
+        let listing = decode_spectrum_listing(&program, 0).unwrap();
+        assert_eq!(listing.lines.len(), 1);
+        assert_eq!(listing.diagnostics.len(), 1);
+        assert!(matches!(decode_spectrum_listing(&[0, 10], 0), Err(Error::UnexpectedEnd { .. })));
+    }
+}
@@ -0,0 +1,298 @@
+// src/spectrum_tape.rs
+//
+// ZX Spectrum tape files, from .tap images and the data blocks of TZX images.
+// A .tap image is a series of blocks, each preceded by its length. A file is saved as
+// a 19-byte header block (flag 0x00) followed by a data block (flag 0xFF); every block
+// ends with a checksum that makes the XOR of all its bytes zero.
+
+use crate::error::Error;
+use crate::tzx_file::tzx_data_blocks;
+use std::ops::Range;
+
+/// Length of a header block: flag, type, name, three words and checksum.
+const HEADER_BLOCK_SIZE: usize = 19;
+
+/// What a tape file holds, from the first byte of its header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpectrumFileType {
+    Program,
+    NumberArray,
+    CharacterArray,
+    Code,
+}
+
+/// The header saved in front of a tape file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpectrumHeader {
+    pub file_type: SpectrumFileType,
+    /// The file name, trailing spaces removed.
+    pub name: String,
+    /// Length of the data block, flag and checksum not included.
+    pub length: u16,
+    /// The line a program starts at (32768 or more for none), or where code loads.
+    pub param1: u16,
+    /// The length of a program without its variables.
+    pub param2: u16,
+}
+
+impl SpectrumHeader {
+    /// Parses a header block, flag byte and checksum included.
+    fn parse(block: &[u8]) -> Option<Self> {
+        if block.len() != HEADER_BLOCK_SIZE || block[0] != 0x00 {
+            return None;
+        }
+        let file_type = match block[1] {
+            0 => SpectrumFileType::Program,
+            1 => SpectrumFileType::NumberArray,
+            2 => SpectrumFileType::CharacterArray,
+            3 => SpectrumFileType::Code,
+            _ => return None,
+        };
+        let word = |pos: usize| u16::from_le_bytes([block[pos], block[pos + 1]]);
+        let name: String = block[2..12]
+            .iter()
+            .map(|&b| match b {
+                0x60 => '£',
+                0x7F => '©',
+                0x20..=0x7E => b as char,
+                _ => '?',
+            })
+            .collect();
+        Some(Self { file_type, name: name.trim_end().to_string(), length: word(12), param1: word(14), param2: word(16) })
+    }
+
+    /// The line a program starts at when loaded, if it starts itself.
+    pub fn autostart(&self) -> Option<u16> {
+        (self.file_type == SpectrumFileType::Program && self.param1 < 32768).then_some(self.param1)
+    }
+
+    /// A short description of the file type.
+    pub fn type_description(&self) -> &'static str {
+        match self.file_type {
+            SpectrumFileType::Program => "Program",
+            SpectrumFileType::NumberArray => "Number array",
+            SpectrumFileType::CharacterArray => "Character array",
+            SpectrumFileType::Code => "Bytes",
+        }
+    }
+}
+
+/// A file read from a Spectrum tape: a data block and the header before it, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpectrumTapeFile {
+    /// Index of the data block among the blocks of the tape.
+    pub block: usize,
+    /// The header, or `None` for a headerless block.
+    pub header: Option<SpectrumHeader>,
+    /// Offset of the first data byte (after the flag byte) within the image.
+    pub offset: usize,
+    /// The data, without the flag byte and checksum. Empty if the header has no data block.
+    pub data: Vec<u8>,
+    /// True if the data block's checksum is right.
+    pub checksum_ok: bool,
+}
+
+impl SpectrumTapeFile {
+    /// The BASIC program of a program file, without the variables saved after it.
+    pub fn program(&self) -> Option<&[u8]> {
+        let header = self.header.as_ref().filter(|h| h.file_type == SpectrumFileType::Program)?;
+        Some(&self.data[..(header.param2 as usize).min(self.data.len())])
+    }
+
+    /// The address a code file loads at.
+    pub fn load_address(&self) -> Option<u16> {
+        let header = self.header.as_ref().filter(|h| h.file_type == SpectrumFileType::Code)?;
+        Some(header.param1)
+    }
+}
+
+/// The blocks of a .tap image: for each, the range holding its flag byte, data and checksum.
+fn tap_blocks(data: &[u8]) -> Result<Vec<Range<usize>>, Error> {
+    let mut blocks = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let length = match data.get(pos..pos + 2) {
+            Some(&[low, high]) => u16::from_le_bytes([low, high]) as usize,
+            _ => return Err(Error::UnexpectedEnd { offset: pos, line: None }),
+        };
+        if length == 0 || pos + 2 + length > data.len() {
+            return Err(Error::UnexpectedEnd { offset: pos, line: None });
+        }
+        blocks.push(pos + 2..pos + 2 + length);
+        pos += 2 + length;
+    }
+    Ok(blocks)
+}
+
+/// Reads the files on a Spectrum tape.
+///
+/// # Arguments
+///
+/// * `data` - A complete .tap or .tzx image
+///
+/// # Returns
+///
+/// A `Result` containing the files in tape order, or an error if the image is damaged
+/// or holds no Spectrum blocks.
+pub fn parse_spectrum_tape(data: &[u8]) -> Result<Vec<SpectrumTapeFile>, Error> {
+    let blocks: Vec<(usize, Range<usize>)> = if data.starts_with(b"ZXTape!\x1A") {
+        tzx_data_blocks(data)?
+    } else {
+        tap_blocks(data)?.into_iter().enumerate().collect()
+    };
+
+    let mut files = Vec::new();
+    let mut pending: Option<(usize, SpectrumHeader, usize)> = None;
+    for (index, range) in blocks.into_iter().filter(|(_, range)| !range.is_empty()) {
+        let block = &data[range.clone()];
+        let checksum_ok = block.iter().fold(0, |sum, &b| sum ^ b) == 0;
+        if let Some(header) = SpectrumHeader::parse(block).filter(|_| checksum_ok) {
+            // A header followed by another header lost its data block
+            if let Some((index, header, offset)) = pending.take() {
+                files.push(SpectrumTapeFile { block: index, header: Some(header), offset, data: Vec::new(), checksum_ok: false });
+            }
+            pending = Some((index, header, range.end));
+            continue;
+        }
+        let header = pending.take().map(|(_, header, _)| header);
+        let body = &block[1..block.len().saturating_sub(1).max(1)];
+        files.push(SpectrumTapeFile { block: index, header, offset: range.start + 1, data: body.to_vec(), checksum_ok });
+    }
+    if let Some((index, header, offset)) = pending {
+        files.push(SpectrumTapeFile { block: index, header: Some(header), offset, data: Vec::new(), checksum_ok: false });
+    }
+    if files.is_empty() {
+        return Err(Error::NothingFound("No ZX Spectrum tape blocks found."));
+    }
+    Ok(files)
+}
+
+/// True if `data` is a .tap image: blocks that fill it exactly, with at least one valid header.
+pub(crate) fn plausible_tap(data: &[u8]) -> bool {
+    tap_blocks(data).is_ok_and(|blocks| {
+        blocks.iter().any(|range| {
+            let block = &data[range.clone()];
+            SpectrumHeader::parse(block).is_some() && block.iter().fold(0, |sum, &b| sum ^ b) == 0
+        })
+    })
+}
+
+/// Lists the files on a tape, one per line.
+pub fn list_spectrum_tape(files: &[SpectrumTapeFile]) -> String {
+    let mut result = String::new();
+    for (i, file) in files.iter().enumerate() {
+        let description = match &file.header {
+            Some(h) => {
+                let detail = match h.file_type {
+                    SpectrumFileType::Program => match h.autostart() {
+                        Some(line) => format!(" LINE {}", line),
+                        None => String::new(),
+                    },
+                    SpectrumFileType::Code => format!(" CODE {},{}", h.param1, h.length),
+                    _ => String::new(),
+                };
+                format!("{:<15} \"{}\"{}", h.type_description(), h.name, detail)
+            }
+            None => format!("{:<15}", "Headerless"),
+        };
+        let check = match (file.data.is_empty() && file.header.is_some(), file.checksum_ok) {
+            (true, _) => "no data block",
+            (false, true) => "checksum OK",
+            (false, false) => "checksum BAD",
+        };
+        result.push_str(&format!(
+            "{:3}: {:06X} block {:3} {}, {} bytes, {}\n",
+            i,
+            file.offset,
+            file.block,
+            description,
+            file.data.len(),
+            check
+        ));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{spectrum_line, tap_block};
+
+    /// A header block and the data block it describes.
+    fn tape_file(file_type: u8, name: &str, data: &[u8], param1: u16, param2: u16) -> Vec<u8> {
+        let mut header = vec![file_type];
+        header.extend(format!("{:<10}", name).bytes());
+        header.extend((data.len() as u16).to_le_bytes());
+        header.extend(param1.to_le_bytes());
+        header.extend(param2.to_le_bytes());
+        let mut tap = tap_block(0x00, &header);
+        tap.extend(tap_block(0xFF, data));
+        tap
+    }
+
+    /// A program saved with LINE 10 and its variables, then three bytes of code.
+    fn tape() -> Vec<u8> {
+        // 10 RANDOMIZE USR 32768
+        let mut program = spectrum_line(10, &[0xF9, 0xC0, b'3', b'2', b'7', b'6', b'8', 0x0E, 0, 0, 0, 0x80, 0]);
+        let lines = program.len() as u16;
+        program.extend([0x61, 0, 0, 5, 0, 0, 0x80]);
+        let mut tap = tape_file(0, "LOADER", &program, 10, lines);
+        tap.extend(tape_file(3, "CODE", &[0x3E, 0x01, 0xC9], 32768, 32768));
+        tap
+    }
+
+    #[test]
+    fn files_are_read_from_tap_images() {
+        let tap = tape();
+        let files = parse_spectrum_tape(&tap).unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().all(|file| file.checksum_ok));
+        assert_eq!(files[0].header.as_ref().unwrap().autostart(), Some(10));
+        assert_eq!(files[0].program().unwrap().len(), 18);
+        assert_eq!(files[0].offset, 24);
+        assert_eq!(files[1].load_address(), Some(32768));
+        assert_eq!(files[1].data, [0x3E, 0x01, 0xC9]);
+        assert_eq!(
+            list_spectrum_tape(&files),
+            "  0: 000018 block   1 Program         \"LOADER\" LINE 10, 25 bytes, checksum OK\n  \
+             1: 00004A block   3 Bytes           \"CODE\" CODE 32768,3, 3 bytes, checksum OK\n"
+        );
+    }
+
+    #[test]
+    fn tzx_images_hold_the_same_blocks() {
+        let tap = tape();
+        let mut tzx = b"ZXTape!\x1A\x01\x14".to_vec();
+        let mut pos = 0;
+        while pos < tap.len() {
+            let length = u16::from_le_bytes([tap[pos], tap[pos + 1]]) as usize;
+            tzx.extend([0x10, 0xE8, 0x03]);
+            tzx.extend(&tap[pos..pos + 2 + length]);
+            pos += 2 + length;
+        }
+        let files = parse_spectrum_tape(&tzx).unwrap();
+        let from_tap = parse_spectrum_tape(&tap).unwrap();
+        assert_eq!(files.len(), 2);
+        for (tzx_file, tap_file) in files.iter().zip(&from_tap) {
+            assert_eq!(tzx_file.header, tap_file.header);
+            assert_eq!(tzx_file.data, tap_file.data);
+        }
+    }
+
+    #[test]
+    fn damaged_blocks_are_reported() {
+        let mut tap = tape();
+        tap[30] ^= 0xFF;
+        let files = parse_spectrum_tape(&tap).unwrap();
+        assert!(!files[0].checksum_ok);
+        assert!(list_spectrum_tape(&files).contains("checksum BAD"));
+
+        // A header whose data block is missing
+        let header_only = &tap[..21];
+        let files = parse_spectrum_tape(header_only).unwrap();
+        assert!(files[0].data.is_empty());
+        assert!(list_spectrum_tape(&files).ends_with("no data block\n"));
+
+        assert!(matches!(parse_spectrum_tape(&tap[..tap.len() - 1]), Err(Error::UnexpectedEnd { .. })));
+    }
+}
@@ -0,0 +1,260 @@
+// src/test_fixtures.rs
+//
+// Small files built in memory for the unit tests: ZX81 .P images tokenised from plain
+// text, so a test can state its program the way it would be typed in, ZX80 .O images,
+// MZF files, and the Quick Disk and floppy images they are stored on, Spectrum tapes and
+// MSX program files.
+
+use crate::mzf_file::MZF_HEADER_SIZE;
+use crate::zx81_decoder::zx81_float_bytes;
+use crate::zx81_interpreter::ascii_to_zx81;
+
+/// ZX81 keywords and the codes they tokenise to. Symbols such as `<=` are included, as
+/// they are single tokens too.
+const ZX81_KEYWORDS: [(&str, u8); 65] = [
+    ("RND", 64), ("INKEY$", 65), ("PI", 66), ("AT", 193), ("TAB", 194), ("CODE", 196),
+    ("VAL", 197), ("LEN", 198), ("SIN", 199), ("COS", 200), ("TAN", 201), ("ASN", 202),
+    ("ACS", 203), ("ATN", 204), ("LN", 205), ("EXP", 206), ("INT", 207), ("SQR", 208),
+    ("SGN", 209), ("ABS", 210), ("PEEK", 211), ("USR", 212), ("STR$", 213), ("CHR$", 214),
+    ("NOT", 215), ("**", 216), ("OR", 217), ("AND", 218), ("<=", 219), (">=", 220),
+    ("<>", 221), ("THEN", 222), ("TO", 223), ("STEP", 224), ("LPRINT", 225), ("LLIST", 226),
+    ("STOP", 227), ("SLOW", 228), ("FAST", 229), ("NEW", 230), ("SCROLL", 231), ("CONT", 232),
+    ("DIM", 233), ("REM", 234), ("FOR", 235), ("GOTO", 236), ("GOSUB", 237), ("INPUT", 238),
+    ("LOAD", 239), ("LIST", 240), ("LET", 241), ("PAUSE", 242), ("NEXT", 243), ("POKE", 244),
+    ("PRINT", 245), ("PLOT", 246), ("RUN", 247), ("SAVE", 248), ("RAND", 249), ("IF", 250),
+    ("CLS", 251), ("UNPLOT", 252), ("CLEAR", 253), ("RETURN", 254), ("COPY", 255),
+];
+
+/// Tokenises one line of ZX81 BASIC typed as ASCII, ending it with NEWLINE. Numbers get
+/// their hidden 5-byte form, spaces outside strings are dropped, and variable names must
+/// not start with a keyword.
+pub(crate) fn zx81_line(text: &str) -> Vec<u8> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut bytes = Vec::new();
+    let mut in_string = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        if ch == '"' {
+            in_string = !in_string;
+        }
+        if in_string || ch == '"' {
+            bytes.push(ascii_to_zx81(ch));
+            i += 1;
+            continue;
+        }
+        if ch == ' ' {
+            i += 1;
+            continue;
+        }
+        let after_name = i > 0 && chars[i - 1].is_ascii_alphanumeric();
+        if (ch.is_ascii_digit() || ch == '.') && !after_name {
+            let end = (i..chars.len()).find(|&j| !chars[j].is_ascii_digit() && chars[j] != '.').unwrap_or(chars.len());
+            let literal: String = chars[i..end].iter().collect();
+            bytes.extend(literal.chars().map(ascii_to_zx81));
+            bytes.push(0x7E);
+            bytes.extend(zx81_float_bytes(literal.parse().unwrap()));
+            i = end;
+            continue;
+        }
+        let rest: String = chars[i..].iter().collect();
+        let keyword = ZX81_KEYWORDS
+            .iter()
+            .filter(|(word, _)| rest.starts_with(word) && !after_name)
+            .filter(|(word, _)| {
+                let next = rest[word.len()..].chars().next();
+                !word.ends_with(|c: char| c.is_ascii_alphabetic()) || !next.is_some_and(|c| c.is_ascii_alphabetic())
+            })
+            .max_by_key(|(word, _)| word.len());
+        match keyword {
+            Some(&(word, code)) => {
+                bytes.push(code);
+                i += word.chars().count();
+                if code == 234 {
+                    bytes.extend(chars[i..].iter().map(|&c| ascii_to_zx81(c)));
+                    i = chars.len();
+                }
+            }
+            None => {
+                bytes.push(ascii_to_zx81(ch));
+                i += 1;
+            }
+        }
+    }
+    bytes.push(0x76);
+    bytes
+}
+
+/// Builds a ZX81 .P image holding the given lines, with a collapsed display file and no
+/// variables. As when saved, the image ends where the edit line starts.
+pub(crate) fn zx81_p(lines: &[(u16, &str)]) -> Vec<u8> {
+    let mut program = Vec::new();
+    for &(number, text) in lines {
+        let bytes = zx81_line(text);
+        program.extend(number.to_be_bytes());
+        program.extend((bytes.len() as u16).to_le_bytes());
+        program.extend(bytes);
+    }
+    let d_file = 16509 + program.len() as u16;
+    let vars = d_file + 25;
+    let e_line = vars + 1;
+
+    let mut image = vec![0u8; 116];
+    let mut word = |pos: usize, value: u16| image[pos..pos + 2].copy_from_slice(&value.to_le_bytes());
+    word(3, d_file); // D_FILE
+    word(5, d_file + 1); // DF_CC
+    word(7, vars); // VARS
+    word(11, e_line); // E_LINE
+    word(13, e_line); // CH_ADD
+    word(17, e_line + 1); // STKBOT
+    word(19, e_line + 1); // STKEND
+    word(32, d_file); // NXTLIN
+    image.extend(program);
+    image.extend([0x76; 25]);
+    image.push(0x80);
+    image
+}
+
+/// Builds an MZF file: a header with the given attribute, name and addresses, then `body`.
+pub(crate) fn mzf(file_type: u8, name: &str, load: u16, exec: u16, body: &[u8]) -> Vec<u8> {
+    let mut data = vec![0u8; MZF_HEADER_SIZE];
+    data[0] = file_type;
+    data[1..18].fill(0x0D);
+    data[1..1 + name.len()].copy_from_slice(name.as_bytes());
+    data[0x12..0x14].copy_from_slice(&(body.len() as u16).to_le_bytes());
+    data[0x14..0x16].copy_from_slice(&load.to_le_bytes());
+    data[0x16..0x18].copy_from_slice(&exec.to_le_bytes());
+    data.extend_from_slice(body);
+    data
+}
+
+/// Builds a ZX80 .O image: the 40 bytes of system variables with VARS and E_LINE after
+/// the program, then the lines (number, tokenised statements and NEWLINE) and an empty
+/// variables area.
+pub(crate) fn zx80_image(lines: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut program = Vec::new();
+    for (number, statements) in lines {
+        program.extend(number.to_be_bytes());
+        program.extend(*statements);
+        program.push(0x76);
+    }
+    let vars = 0x4028 + program.len() as u16;
+    let mut image = vec![0u8; 40];
+    image[8..10].copy_from_slice(&vars.to_le_bytes());
+    image[10..12].copy_from_slice(&(vars + 1).to_le_bytes());
+    image[12..14].copy_from_slice(&(vars + 1).to_le_bytes());
+    image.extend(program);
+    image.push(0x80);
+    image
+}
+
+/// A ZX80 .O image of one line, `10 CLS`.
+pub(crate) fn zx80_o() -> Vec<u8> {
+    zx80_image(&[(10, &[0xE8])])
+}
+
+/// A Quick Disk block: SYNC bytes, the 0xA5 mark, flag, length, data and a dummy CRC.
+pub(crate) fn qd_block(flag: u8, data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0x16; 10];
+    bytes.push(0xA5);
+    bytes.push(flag);
+    bytes.extend((data.len() as u16).to_le_bytes());
+    bytes.extend(data);
+    bytes.extend([0x12, 0x34]);
+    bytes
+}
+
+/// The 64-byte Quick Disk header of a machine code file.
+pub(crate) fn qd_header(name: &str, protected: bool, size: u16, load: u16, exec: u16) -> Vec<u8> {
+    let mut qd = vec![0u8; 64];
+    qd[0] = 0x01;
+    qd[1..0x12].fill(0x0D);
+    qd[1..1 + name.len()].copy_from_slice(name.as_bytes());
+    qd[0x12] = protected as u8;
+    qd[0x14..0x16].copy_from_slice(&size.to_le_bytes());
+    qd[0x16..0x18].copy_from_slice(&load.to_le_bytes());
+    qd[0x18..0x1A].copy_from_slice(&exec.to_le_bytes());
+    qd
+}
+
+/// A 32-byte floppy directory entry: mode, name, size, load and exec address (both
+/// `load`) and start sector.
+pub(crate) fn disk_entry(mode: u8, name: &str, size: u16, load: u16, start: u16) -> Vec<u8> {
+    let mut dir = vec![0u8; 32];
+    dir[0] = mode;
+    dir[1..0x12].fill(0x0D);
+    dir[1..1 + name.len()].copy_from_slice(name.as_bytes());
+    dir[0x14..0x16].copy_from_slice(&size.to_le_bytes());
+    dir[0x16..0x18].copy_from_slice(&load.to_le_bytes());
+    dir[0x18..0x1A].copy_from_slice(&load.to_le_bytes());
+    dir[0x1E..0x20].copy_from_slice(&start.to_le_bytes());
+    dir
+}
+
+/// Wraps 256-byte sectors, 16 to a track and both sides of each cylinder in turn, in a
+/// D88 image. The sectors of each track are stored last first, as interleaved disks are.
+pub(crate) fn d88(sectors: &[[u8; 256]]) -> Vec<u8> {
+    let mut image = vec![0u8; 0x2B0];
+    for (track, chunk) in sectors.chunks(16).enumerate() {
+        let offset = image.len() as u32;
+        image[0x20 + track * 4..0x24 + track * 4].copy_from_slice(&offset.to_le_bytes());
+        for r in (1..=chunk.len()).rev() {
+            let mut header = [0u8; 0x10];
+            header[..3].copy_from_slice(&[(track / 2) as u8, (track % 2) as u8, r as u8]);
+            header[3] = 1;
+            header[4] = chunk.len() as u8;
+            header[0x0E..0x10].copy_from_slice(&256u16.to_le_bytes());
+            image.extend(header);
+            image.extend(chunk[r - 1]);
+        }
+    }
+    let size = image.len() as u32;
+    image[0x1C..0x20].copy_from_slice(&size.to_le_bytes());
+    image
+}
+
+/// A Spectrum .tap block: its length, the flag byte, the data and the XOR checksum.
+pub(crate) fn tap_block(flag: u8, data: &[u8]) -> Vec<u8> {
+    let mut block = ((data.len() + 2) as u16).to_le_bytes().to_vec();
+    block.push(flag);
+    block.extend(data);
+    block.push(data.iter().fold(flag, |sum, &b| sum ^ b));
+    block
+}
+
+/// A Spectrum .tap file holding one BASIC program: its header block, then its data block.
+pub(crate) fn spectrum_tap(name: &str, program: &[u8], autostart: u16) -> Vec<u8> {
+    let mut header = vec![0u8];
+    header.extend(format!("{:<10}", name).bytes());
+    header.extend((program.len() as u16).to_le_bytes());
+    header.extend(autostart.to_le_bytes());
+    header.extend((program.len() as u16).to_le_bytes());
+    let mut tap = tap_block(0x00, &header);
+    tap.extend(tap_block(0xFF, program));
+    tap
+}
+
+/// One Spectrum BASIC line: its number (big-endian), its length (little-endian), the
+/// tokenised statements as given and ENTER.
+pub(crate) fn spectrum_line(number: u16, statements: &[u8]) -> Vec<u8> {
+    let mut line = number.to_be_bytes().to_vec();
+    line.extend((statements.len() as u16 + 1).to_le_bytes());
+    line.extend(statements);
+    line.push(0x0D);
+    line
+}
+
+/// A tokenised MSX BASIC file saved from 0x8000, so its first line is at 0x8001. Each
+/// line links to the next, and a zero link ends the program.
+pub(crate) fn msx_program(lines: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut file = vec![0xFF];
+    for (number, statements) in lines {
+        let next = file.len() + 4 + statements.len() + 1;
+        file.extend((0x8000 + next as u16).to_le_bytes());
+        file.extend(number.to_le_bytes());
+        file.extend(*statements);
+        file.push(0);
+    }
+    file.extend([0, 0]);
+    file
+}
@@ -0,0 +1,335 @@
+// src/tzx_file.rs
+//
+// TZX tape images, as used to archive ZX81 and ZX Spectrum software.
+// Spectrum files are in the standard, turbo or pure data blocks, as on a .tap image.
+// ZX81 programs are stored in block 0x19 (generalised data) with a two symbol
+// alphabet: symbol 0 is four pulses and symbol 1 is nine pulses, each followed
+// by a gap. A pulse is two edges, so the symbol definitions hold 8 and 18 edge
+// lengths. The data stream holds the ZX81 program name followed by the .P image.
+
+use crate::error::Error;
+use crate::zx81_decoder::{zx81_name_length, zx81_name_to_string};
+use crate::zx81_interpreter::ascii_to_zx81;
+use std::ops::Range;
+
+const TZX_SIGNATURE: &[u8; 8] = b"ZXTape!\x1A";
+
+// Pulse lengths in T-states (3.5 MHz) used by the ZX81 ROM save routine
+const ZX81_PULSE: u16 = 530;
+const ZX81_GAP: u16 = 4689;
+
+/// A block found while walking a TZX file.
+#[derive(Debug, Clone)]
+pub struct TZXBlock {
+    /// Block ID (0x10 standard data, 0x19 generalised data, ...).
+    pub id: u8,
+    /// Offset of the block ID within the file.
+    pub offset: usize,
+    /// Length of the block, not counting the ID byte.
+    pub length: usize,
+}
+
+/// A ZX81 program extracted from a TZX file.
+#[derive(Debug, Clone)]
+pub struct TZXProgram {
+    /// Index of the block the program came from.
+    pub block: usize,
+    /// The program name, translated to ASCII (if the data had one).
+    pub name: Option<String>,
+    /// The .P image with the name removed.
+    pub data: Vec<u8>,
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<usize> {
+    Some(u16::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?]) as usize)
+}
+
+fn u24_at(data: &[u8], pos: usize) -> Option<usize> {
+    Some(u32::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?, *data.get(pos + 2)?, 0]) as usize)
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<usize> {
+    Some(u32::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?, *data.get(pos + 2)?, *data.get(pos + 3)?]) as usize)
+}
+
+/// Length of the block body starting at `pos` (just after the ID byte).
+fn block_length(id: u8, data: &[u8], pos: usize) -> Option<usize> {
+    let byte_at = |p: usize| data.get(p).map(|&b| b as usize);
+    Some(match id {
+        0x10 => 4 + u16_at(data, pos + 2)?,
+        0x11 => 0x12 + u24_at(data, pos + 0x0F)?,
+        0x12 => 4,
+        0x13 => 1 + 2 * byte_at(pos)?,
+        0x14 => 0x0A + u24_at(data, pos + 0x07)?,
+        0x15 => 0x08 + u24_at(data, pos + 0x05)?,
+        0x20 | 0x23 | 0x24 => 2,
+        0x21 | 0x30 => 1 + byte_at(pos)?,
+        0x22 | 0x25 | 0x27 => 0,
+        0x26 => 2 + 2 * u16_at(data, pos)?,
+        0x28 | 0x32 => 2 + u16_at(data, pos)?,
+        0x2A => 4,
+        0x2B => 5,
+        0x31 => 2 + byte_at(pos + 1)?,
+        0x33 => 1 + 3 * byte_at(pos)?,
+        0x35 => 0x14 + u32_at(data, pos + 0x10)?,
+        0x5A => 9,
+        // 0x18, 0x19 and any later block types start with a 32-bit length
+        _ => 4 + u32_at(data, pos)?,
+    })
+}
+
+/// Walks the blocks of a TZX file.
+pub fn parse_tzx(data: &[u8]) -> Result<Vec<TZXBlock>, Error> {
+    if data.len() < 10 || &data[..8] != TZX_SIGNATURE {
+        return Err(Error::InvalidFormat("Not a TZX file."));
+    }
+    let mut blocks = Vec::new();
+    let mut pos = 10;
+    while pos < data.len() {
+        let id = data[pos];
+        let length = block_length(id, data, pos + 1).ok_or(Error::UnexpectedEnd { offset: pos, line: None })?;
+        if pos + 1 + length > data.len() {
+            return Err(Error::UnexpectedEnd { offset: pos, line: None });
+        }
+        blocks.push(TZXBlock { id, offset: pos, length });
+        pos += 1 + length;
+    }
+    Ok(blocks)
+}
+
+/// Extracts the data bytes of a block 0x19 with a two symbol data alphabet.
+fn generalised_data(block: &[u8]) -> Option<Vec<u8>> {
+    // block starts just after the ID: length(4) pause(2) TOTP(4) NPP ASP TOTD(4) NPD ASD
+    let totp = u32_at(block, 6)?;
+    let npp = *block.get(10)? as usize;
+    let asp = match *block.get(11)? { 0 => 256, n => n as usize };
+    let totd = u32_at(block, 12)?;
+    let npd = *block.get(16)? as usize;
+    let asd = match *block.get(17)? { 0 => 256, n => n as usize };
+    if asd != 2 {
+        return None;
+    }
+
+    let mut pos = 18;
+    if totp > 0 {
+        pos += asp * (1 + 2 * npp) + totp * 3;
+    }
+    pos += asd * (1 + 2 * npd);
+    let stream = block.get(pos..pos + totd.div_ceil(8))?;
+
+    // One bit per symbol, most significant first: the stream is the data itself
+    Some(stream[..totd / 8].to_vec())
+}
+
+/// Finds the ZX Spectrum style data blocks (0x10 standard speed, 0x11 turbo speed and
+/// 0x14 pure data) of a TZX file.
+///
+/// # Arguments
+///
+/// * `data` - The complete .tzx file
+///
+/// # Returns
+///
+/// A `Result` containing, for each block, its index and the range of the file holding
+/// its flag byte, data and checksum, or an error if the file is not a valid TZX image.
+pub(crate) fn tzx_data_blocks(data: &[u8]) -> Result<Vec<(usize, Range<usize>)>, Error> {
+    let blocks = parse_tzx(data)?;
+    let mut result = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        let body = block.offset + 1;
+        // Where the length field is, and where the data starts, within the block body
+        let (length, start) = match block.id {
+            0x10 => (u16_at(data, body + 2), 4),
+            0x11 => (u24_at(data, body + 0x0F), 0x12),
+            0x14 => (u24_at(data, body + 0x07), 0x0A),
+            _ => continue,
+        };
+        if let Some(length) = length {
+            result.push((i, body + start..body + start + length));
+        }
+    }
+    Ok(result)
+}
+
+/// Extracts every ZX81 program stored in the generalised data blocks of a TZX file.
+///
+/// # Arguments
+///
+/// * `data` - The complete .tzx file
+///
+/// # Returns
+///
+/// A `Result` containing the programs (name stripped, ready for `decode_zx81_p_file`), or an error message.
+pub fn extract_zx81_programs(data: &[u8]) -> Result<Vec<TZXProgram>, Error> {
+    let blocks = parse_tzx(data)?;
+    let mut programs = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        if block.id != 0x19 {
+            continue;
+        }
+        let body = &data[block.offset + 1..block.offset + 1 + block.length];
+        if let Some(bytes) = generalised_data(body) {
+            let (name, program) = match zx81_name_length(&bytes) {
+                Some(n) => (Some(zx81_name_to_string(&bytes[..n])), bytes[n..].to_vec()),
+                None => (None, bytes),
+            };
+            programs.push(TZXProgram { block: i, name, data: program });
+        }
+    }
+    if programs.is_empty() {
+        return Err(Error::NothingFound("No ZX81 program found in the TZX file."));
+    }
+    Ok(programs)
+}
+
+/// Lists the blocks of a TZX file, one per line.
+pub fn list_tzx(data: &[u8], blocks: &[TZXBlock]) -> String {
+    let mut result = String::new();
+    for (i, block) in blocks.iter().enumerate() {
+        let kind = match block.id {
+            0x10 => "Standard speed data",
+            0x11 => "Turbo speed data",
+            0x12 => "Pure tone",
+            0x13 => "Pulse sequence",
+            0x14 => "Pure data",
+            0x15 => "Direct recording",
+            0x18 => "CSW recording",
+            0x19 => "Generalised data",
+            0x20 => "Pause",
+            0x21 => "Group start",
+            0x22 => "Group end",
+            0x30 => "Text description",
+            0x32 => "Archive info",
+            0x35 => "Custom info",
+            _ => "Other",
+        };
+        let mut line = format!("{:3}: {:06X} block {:02X} {:<20} {} bytes", i, block.offset, block.id, kind, block.length);
+        if block.id == 0x30 {
+            let text = &data[block.offset + 2..block.offset + 1 + block.length];
+            line.push_str(&format!(" \"{}\"", String::from_utf8_lossy(text)));
+        }
+        result.push_str(&line);
+        result.push('\n');
+    }
+    result
+}
+
+/// Wraps a ZX81 .P file into a TZX file using a single generalised data block.
+///
+/// # Arguments
+///
+/// * `program` - The .P file (without a name)
+/// * `name` - The program name recorded in front of the data
+///
+/// # Returns
+///
+/// The .tzx file bytes.
+pub fn zx81_p_to_tzx(program: &[u8], name: &str) -> Vec<u8> {
+    let name = if name.is_empty() { "PROGRAM" } else { name };
+    let mut data: Vec<u8> = name.chars().map(ascii_to_zx81).collect();
+    if let Some(last) = data.last_mut() {
+        *last |= 0x80;
+    }
+    data.extend_from_slice(program);
+
+    // Symbol definitions: flags, then NPD edge lengths (zero terminated when shorter).
+    // Four and nine pulses are 8 and 18 edges, then the gap.
+    const NPD: usize = 19;
+    let mut symbols = Vec::new();
+    for edges in [8usize, 18] {
+        symbols.push(0u8);
+        let mut lengths = vec![ZX81_PULSE; edges];
+        lengths.push(ZX81_GAP);
+        lengths.resize(NPD, 0);
+        for l in lengths {
+            symbols.extend_from_slice(&l.to_le_bytes());
+        }
+    }
+
+    let mut body = Vec::new();
+    body.extend_from_slice(&3000u16.to_le_bytes()); // pause after block (ms)
+    body.extend_from_slice(&0u32.to_le_bytes()); // TOTP: no pilot
+    body.push(0); // NPP
+    body.push(0); // ASP
+    body.extend_from_slice(&((data.len() * 8) as u32).to_le_bytes()); // TOTD
+    body.push(NPD as u8);
+    body.push(2); // ASD
+    body.extend_from_slice(&symbols);
+    body.extend_from_slice(&data);
+
+    let mut tzx = Vec::new();
+    tzx.extend_from_slice(TZX_SIGNATURE);
+    tzx.extend_from_slice(&[1, 20]); // TZX version 1.20
+    tzx.push(0x19);
+    tzx.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    tzx.extend_from_slice(&body);
+    tzx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::zx81_p;
+
+    fn program() -> Vec<u8> {
+        zx81_p(&[(10, "PRINT \"TZX\""), (20, "GOTO 10")])
+    }
+
+    #[test]
+    fn p_to_tzx_to_p_round_trips() {
+        let tzx = zx81_p_to_tzx(&program(), "GAME");
+        let blocks = parse_tzx(&tzx).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].id, 0x19);
+        assert_eq!(blocks[0].offset + 1 + blocks[0].length, tzx.len());
+
+        let programs = extract_zx81_programs(&tzx).unwrap();
+        assert_eq!(programs.len(), 1);
+        assert_eq!(programs[0].block, 0);
+        assert_eq!(programs[0].name.as_deref(), Some("GAME"));
+        assert_eq!(programs[0].data, program());
+    }
+
+    #[test]
+    fn an_unnamed_program_is_saved_as_program() {
+        let programs = extract_zx81_programs(&zx81_p_to_tzx(&program(), "")).unwrap();
+        assert_eq!(programs[0].name.as_deref(), Some("PROGRAM"));
+    }
+
+    #[test]
+    fn symbols_are_four_and_nine_pulses() {
+        let tzx = zx81_p_to_tzx(&program(), "GAME");
+        // After the header, ID, length, pause, TOTP, NPP, ASP, TOTD, NPD and ASD
+        let symbols = &tzx[10 + 1 + 18..];
+        let edges = |symbol: usize| {
+            let lengths = &symbols[symbol * 39 + 1..symbol * 39 + 39];
+            lengths.chunks(2).take_while(|l| l != &[0, 0]).count()
+        };
+        // Each pulse is two edges, and the last length is the gap
+        assert_eq!(edges(0), 8 + 1);
+        assert_eq!(edges(1), 18 + 1);
+    }
+
+    #[test]
+    fn other_blocks_are_walked() {
+        let mut tzx = TZX_SIGNATURE.to_vec();
+        tzx.extend([1, 20]);
+        tzx.extend([0x30, 3]);
+        tzx.extend(b"ABC");
+        tzx.extend([0x10, 0xE8, 0x03, 4, 0]);
+        tzx.extend([0xFF, 1, 2, 0xFC]);
+        let blocks = parse_tzx(&tzx).unwrap();
+        assert_eq!(blocks.iter().map(|b| b.id).collect::<Vec<_>>(), [0x30, 0x10]);
+        // The standard speed block's flag, data and checksum
+        assert_eq!(tzx_data_blocks(&tzx).unwrap(), [(1, 20..24)]);
+        assert!(list_tzx(&tzx, &blocks).contains("Text description     4 bytes \"ABC\""));
+        assert!(matches!(extract_zx81_programs(&tzx), Err(Error::NothingFound(_))));
+    }
+
+    #[test]
+    fn truncated_blocks_are_reported() {
+        let tzx = zx81_p_to_tzx(&program(), "GAME");
+        assert!(matches!(parse_tzx(&tzx[..tzx.len() - 1]), Err(Error::UnexpectedEnd { offset: 10, .. })));
+        assert!(matches!(parse_tzx(b"ZXTape!"), Err(Error::InvalidFormat(_))));
+    }
+}
@@ -0,0 +1,237 @@
+// src/wav_audio.rs
+//
+// Minimal RIFF/WAVE support for cassette images: PCM reading and pulse
+// measurement shared by the tape decoders.
+
+use crate::error::Error;
+
+/// Mono audio with samples normalised to -1.0..=1.0.
+#[derive(Debug, Clone)]
+pub struct WavAudio {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+fn read_u16_le(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+fn read_u32_le(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+/// Reads an uncompressed PCM WAV file (8, 16, 24 or 32 bit, any channel count).
+/// Only the first channel is kept.
+pub fn read_wav(bytes: &[u8]) -> Result<WavAudio, Error> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(Error::InvalidFormat("Not a RIFF/WAVE file."));
+    }
+
+    let mut pos = 12;
+    let mut format: Option<(u16, u16, u32, u16)> = None;
+    let mut data: Option<&[u8]> = None;
+
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let len = read_u32_le(bytes, pos + 4) as usize;
+        let body_start = pos + 8;
+        let body_end = body_start.saturating_add(len).min(bytes.len());
+        let body = &bytes[body_start..body_end];
+
+        if id == b"fmt " {
+            if body.len() < 16 {
+                return Err(Error::TruncatedHeader { format: "WAV fmt", expected: 16, found: body.len() });
+            }
+            format = Some((
+                read_u16_le(body, 0),
+                read_u16_le(body, 2),
+                read_u32_le(body, 4),
+                read_u16_le(body, 14),
+            ));
+        } else if id == b"data" {
+            data = Some(body);
+        }
+        // Chunks are padded to an even length
+        pos = body_start.saturating_add(len + (len & 1));
+    }
+
+    let (audio_format, channels, sample_rate, bits) = format.ok_or(Error::InvalidFormat("WAV file has no fmt chunk."))?;
+    let data = data.ok_or(Error::InvalidFormat("WAV file has no data chunk."))?;
+    // 1 = PCM, 0xFFFE = WAVE_FORMAT_EXTENSIBLE (assumed to hold PCM)
+    if audio_format != 1 && audio_format != 0xFFFE {
+        return Err(Error::InvalidFormat("Only uncompressed PCM WAV files are supported."));
+    }
+    if channels == 0 || sample_rate == 0 {
+        return Err(Error::InvalidFormat("WAV file has an invalid format."));
+    }
+
+    let width = match bits {
+        8 => 1,
+        16 => 2,
+        24 => 3,
+        32 => 4,
+        _ => return Err(Error::InvalidFormat("Unsupported WAV sample size.")),
+    };
+    let frame = width * channels as usize;
+
+    let samples = data
+        .chunks_exact(frame)
+        .map(|f| match width {
+            1 => (f[0] as f32 - 128.0) / 128.0,
+            2 => i16::from_le_bytes([f[0], f[1]]) as f32 / 32768.0,
+            3 => (i32::from_le_bytes([0, f[0], f[1], f[2]]) >> 8) as f32 / 8388608.0,
+            _ => i32::from_le_bytes([f[0], f[1], f[2], f[3]]) as f32 / 2147483648.0,
+        })
+        .collect();
+
+    Ok(WavAudio { sample_rate, samples })
+}
+
+impl WavAudio {
+    /// Measures the time in seconds between successive rising edges of the signal.
+    /// The DC offset is removed and a small hysteresis band rejects noise.
+    pub fn rising_edge_periods(&self) -> Vec<f64> {
+        self.rising_edge_times().windows(2).map(|w| w[1] - w[0]).collect()
+    }
+
+    /// Times in seconds of each rising edge of the signal.
+    pub fn rising_edge_times(&self) -> Vec<f64> {
+        self.edges(true).iter().map(|&e| e / self.sample_rate as f64).collect()
+    }
+
+    /// Sample positions (with linear interpolation) of signal edges.
+    fn edges(&self, rising_only: bool) -> Vec<f64> {
+        if self.samples.is_empty() {
+            return Vec::new();
+        }
+        let mean = self.samples.iter().map(|&s| s as f64).sum::<f64>() / self.samples.len() as f64;
+        let peak = self.samples.iter().map(|&s| (s as f64 - mean).abs()).fold(0.0, f64::max);
+        let hysteresis = peak * 0.1;
+
+        let mut edges = Vec::new();
+        let mut high: Option<bool> = None;
+        let mut previous = 0.0;
+        for (i, &s) in self.samples.iter().enumerate() {
+            let v = s as f64 - mean;
+            let now_high = if v > hysteresis {
+                Some(true)
+            } else if v < -hysteresis {
+                Some(false)
+            } else {
+                high
+            };
+            if let (Some(was), Some(is)) = (high, now_high) {
+                if was != is && (!rising_only || is) {
+                    // Interpolate the crossing of the hysteresis threshold
+                    let threshold = if is { hysteresis } else { -hysteresis };
+                    let fraction = if v != previous { (threshold - previous) / (v - previous) } else { 0.0 };
+                    edges.push(i as f64 - 1.0 + fraction.clamp(0.0, 1.0));
+                }
+            }
+            high = now_high;
+            previous = v;
+        }
+        edges
+    }
+}
+
+/// Splits pulse lengths into two clusters and returns the boundary between them.
+/// Used to tell short pulses from long ones without knowing the tape speed.
+/// Otsu's method is used as lead-in tones make the clusters very unequal in size.
+pub fn pulse_threshold(periods: &[f64]) -> Option<f64> {
+    let mut sorted: Vec<f64> = periods.iter().copied().filter(|p| *p > 0.0).collect();
+    if sorted.len() < 2 {
+        return None;
+    }
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    // Ignore the extremes, which are usually silence or clicks
+    let trim = sorted.len() / 1000;
+    let lo = sorted[trim];
+    let hi = sorted[sorted.len() - 1 - trim];
+    if hi <= lo {
+        return None;
+    }
+
+    const BINS: usize = 256;
+    let bin = |p: f64| (((p - lo) / (hi - lo)) * (BINS - 1) as f64).round() as usize;
+    let mut histogram = [0usize; BINS];
+    for &p in sorted.iter().filter(|&&p| p >= lo && p <= hi) {
+        histogram[bin(p)] += 1;
+    }
+
+    let total: usize = histogram.iter().sum();
+    let weighted_total: f64 = histogram.iter().enumerate().map(|(i, &n)| i as f64 * n as f64).sum();
+    let (mut below, mut weighted_below) = (0usize, 0.0);
+    let (mut best, mut best_variance) = (0, -1.0);
+    for (i, &n) in histogram.iter().enumerate() {
+        below += n;
+        weighted_below += i as f64 * n as f64;
+        let above = total - below;
+        if below == 0 || above == 0 {
+            continue;
+        }
+        let mean_below = weighted_below / below as f64;
+        let mean_above = (weighted_total - weighted_below) / above as f64;
+        let variance = below as f64 * above as f64 * (mean_below - mean_above).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best = i;
+        }
+    }
+    Some(lo + (best as f64 + 0.5) / (BINS - 1) as f64 * (hi - lo))
+}
+
+/// Builds a mono 8-bit PCM WAV file from unsigned samples.
+pub fn write_wav_u8(sample_rate: u32, samples: &[u8]) -> Vec<u8> {
+    let data_len = samples.len() as u32;
+    let mut wav = Vec::with_capacity(44 + samples.len() + 1);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len + (data_len & 1)).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes()); // bytes per second
+    wav.extend_from_slice(&1u16.to_le_bytes()); // block align
+    wav.extend_from_slice(&8u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    wav.extend_from_slice(samples);
+    if data_len & 1 == 1 {
+        wav.push(0x80);
+    }
+    wav
+}
+
+/// Generates square-wave audio from a list of (level, duration in seconds) pairs,
+/// carrying rounding error from one segment to the next so long recordings keep their timing.
+pub struct SquareWave {
+    sample_rate: f64,
+    samples: Vec<u8>,
+    time: f64,
+}
+
+impl SquareWave {
+    pub const HIGH: u8 = 0xE0;
+    pub const LOW: u8 = 0x20;
+    pub const SILENT: u8 = 0x80;
+
+    pub fn new(sample_rate: u32) -> Self {
+        Self { sample_rate: sample_rate as f64, samples: Vec::new(), time: 0.0 }
+    }
+
+    /// Appends `seconds` of the given sample level.
+    pub fn level(&mut self, level: u8, seconds: f64) {
+        self.time += seconds * self.sample_rate;
+        let target = self.time.round() as usize;
+        if target > self.samples.len() {
+            self.samples.resize(target, level);
+        }
+    }
+
+    /// Finishes the recording and wraps it in a WAV file.
+    pub fn into_wav(self) -> Vec<u8> {
+        write_wav_u8(self.sample_rate as u32, &self.samples)
+    }
+}
@@ -0,0 +1,17 @@
+//! Z80 disassembly of machine code files.
+//!
+//! ```no_run
+//! use rust_wasm_converter::z80;
+//!
+//! let code = [0x3E, 0x01, 0xC9];
+//! for instruction in z80::disassemble(&code, 0x1200, 0x1200) {
+//!     println!("{:04X} {}", instruction.address, instruction.text);
+//! }
+//! ```
+
+pub use crate::z80_disasm::{Instruction, Z80Disassembler};
+
+/// Disassembles `code` loaded at `load_address`, marking the instruction at `exec_address`.
+pub fn disassemble(code: &[u8], load_address: u16, exec_address: u16) -> Vec<Instruction> {
+    Z80Disassembler::new().instructions(code, load_address, exec_address)
+}
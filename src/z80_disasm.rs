@@ -1,5 +1,19 @@
 use std::char;
 
+/// One decoded Z80 instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    /// Address of the first byte of the instruction.
+    pub address: u16,
+    /// The instruction's bytes (fewer than expected if the data ended early).
+    pub bytes: Vec<u8>,
+    /// The mnemonic and operands.
+    pub text: String,
+    /// True if this is the program's execution address.
+    pub entry: bool,
+}
+
+#[derive(Default)]
 pub struct Z80Disassembler {
     pc: u16,
 }
@@ -9,46 +23,23 @@ impl Z80Disassembler {
         Self { pc: 0 }
     }
 
-    pub fn disassemble(&mut self, data: &[u8], start_address: u16, exec_address: u16) -> Vec<String> {
+    /// Decodes every instruction in `data`, which is loaded at `start_address`.
+    pub fn instructions(&mut self, data: &[u8], start_address: u16, exec_address: u16) -> Vec<Instruction> {
         let mut result = Vec::new();
         let mut pos = 0;
         self.pc = start_address;
 
         while pos < data.len() {
-            let (instruction, bytes_consumed) = self.decode_instruction(&data[pos..]);
-            
+            let (text, bytes_consumed) = self.decode_instruction(&data[pos..]);
             let end_pos = (pos + bytes_consumed).min(data.len());
-            let instruction_bytes = &data[pos..end_pos];
 
-            let hex_bytes = instruction_bytes
-                .iter()
-                .map(|b| format!("{:02X}", b))
-                .collect::<Vec<_>>()
-                .join(" ");
-            
-            let ascii = instruction_bytes
-                .iter()
-                .map(|&b| b as char)
-                .collect::<String>();
-
-            // MODIFICATION: The formatting logic below was changed to match the target ASM file.
-            let marker = if exec_address == self.pc { ">" } else { " " };
-            let marker_and_hex = format!("{} {}", marker, hex_bytes);
-            
-            // Format the final line with specific padding to align columns.
-            // - Instruction field is padded to 22 characters.
-            // - Hex bytes field is padded to 15 characters.
-
-            let line = format!(
-                "{:<22} ;{:04X} {:<15} {}",
-                instruction,
-                self.pc,
-                marker_and_hex,
-                ascii
-            );
-
-            result.push(line);
-            
+            result.push(Instruction {
+                address: self.pc,
+                bytes: data[pos..end_pos].to_vec(),
+                text,
+                entry: exec_address == self.pc,
+            });
+
             pos += bytes_consumed;
             self.pc = self.pc.wrapping_add(bytes_consumed as u16);
         }
@@ -56,9 +47,44 @@ impl Z80Disassembler {
         result
     }
 
+    pub fn disassemble(&mut self, data: &[u8], start_address: u16, exec_address: u16) -> Vec<String> {
+        self.instructions(data, start_address, exec_address)
+            .into_iter()
+            .map(|instruction| {
+                let hex_bytes = instruction
+                    .bytes
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                let ascii = instruction
+                    .bytes
+                    .iter()
+                    .map(|&b| b as char)
+                    .collect::<String>();
+
+                // MODIFICATION: The formatting logic below was changed to match the target ASM file.
+                let marker = if instruction.entry { ">" } else { " " };
+                let marker_and_hex = format!("{} {}", marker, hex_bytes);
+
+                // Format the final line with specific padding to align columns.
+                // - Instruction field is padded to 22 characters.
+                // - Hex bytes field is padded to 15 characters.
+                format!(
+                    "{:<22} ;{:04X} {:<15} {}",
+                    instruction.text,
+                    instruction.address,
+                    marker_and_hex,
+                    ascii
+                )
+            })
+            .collect()
+    }
+
     // Helper functions to read operands
-    fn read_u8(data: &[u8]) -> Option<u8> { data.get(0).copied() }
-    fn read_i8(data: &[u8]) -> Option<i8> { data.get(0).map(|&b| b as i8) }
+    fn read_u8(data: &[u8]) -> Option<u8> { data.first().copied() }
+    fn read_i8(data: &[u8]) -> Option<i8> { data.first().map(|&b| b as i8) }
     fn read_u16(data: &[u8]) -> Option<u16> {
         if data.len() < 2 {
             None
@@ -0,0 +1,51 @@
+//! Sinclair ZX80 files: .O program images and cassette recordings.
+//!
+//! ```no_run
+//! use rust_wasm_converter::zx80;
+//!
+//! let data = std::fs::read("GAME.o").unwrap();
+//! for line in zx80::decode_basic(&data, false).unwrap().lines {
+//!     println!("{} {}", line.number, line.text);
+//! }
+//! ```
+
+pub use crate::basic::{BasicItem, BasicLine, BasicListing, ItemKind};
+pub use crate::basic_analysis::{AddressUse, LineReference, ProgramAnalysis, VariableUse};
+pub use crate::basic_format::FormatOptions;
+pub use crate::basic_renumber::RenumberedProgram;
+pub use crate::error::Error;
+pub use crate::zx_tape::{decode_zx_edges, decode_zx_wav, encode_zx_wav, ZXTapeFile, ZXTapeMachine};
+
+/// Decodes the BASIC program in a ZX80 .O file.
+///
+/// # Arguments
+///
+/// * `data` - The complete .O file
+/// * `zxpand` - Decode the extra ZXpand interface keywords
+///
+/// # Returns
+///
+/// A `Result` containing the program lines, or an error if not even the first line could be read.
+/// A damaged program is listed up to the damage, with the reason in `diagnostics`.
+pub fn decode_basic(data: &[u8], zxpand: bool) -> Result<BasicListing, Error> {
+    crate::zx80_decoder::decode_zx80_listing(data, zxpand)
+}
+
+/// Renumbers the BASIC program in a ZX80 .O file, moving the system variables that
+/// follow the program when targets gain or lose digits.
+///
+/// # Arguments
+///
+/// * `data` - The complete .O file
+/// * `start` - The first new line number
+/// * `step` - The gap between new line numbers
+///
+/// # Returns
+///
+/// A `Result` containing the renumbered file, with GO TO, GO SUB, RUN and LIST targets
+/// changed to match, and notes on any computed or missing targets that were left alone.
+/// Damaged programs are refused.
+pub fn renumber_basic(data: &[u8], start: u16, step: u16) -> Result<RenumberedProgram, Error> {
+    let listing = decode_basic(data, false)?;
+    crate::basic_renumber::renumber(data, &listing, crate::basic_renumber::LineFormat::Zx80, start, step)
+}
@@ -1,3 +1,5 @@
+use crate::basic::{BasicListing, ItemKind, LineBuilder};
+use crate::error::Error;
 use std::collections::HashMap;
 
 
@@ -103,83 +105,101 @@ impl ZX80BasicDecoder {
         matches!(token_code, 236 | 251 | 247 | 230) // GOTO, GOSUB, RUN, LIST
     }
 
-    /// Decodes a single line of ZX80 BASIC.
-    pub fn decode_line(&self, line_bytes: &[u8]) -> String {
-        let mut result = String::new();
+    /// Decodes a single line of ZX80 BASIC whose statements start at `offset` in the file.
+    pub(crate) fn decode_line(&self, line_bytes: &[u8], offset: usize) -> LineBuilder {
+        let mut line = LineBuilder::default();
         let mut i = 0;
         let mut in_rem_comment = false;
+        let mut in_string = false;
 
         while i < line_bytes.len() {
             let byte = line_bytes[i];
+            let at = offset + i;
 
             if in_rem_comment {
-                result.push_str(&self.decode_character(byte));
+                line.push(ItemKind::Rem, at, &self.decode_character(byte));
             } else if let Some(token) = self.tokens.get(&byte) {
-                result.push_str(token);
+                line.push(if in_string { ItemKind::String } else { ItemKind::Keyword }, at, token);
                 if byte == 254 { // REM token
                     in_rem_comment = true;
                 } else if self.token_supports_line_number(byte) {
-                    let mut j = i + 1;
-                    let mut line_number_str = String::new();
-                    while j < line_bytes.len() {
-                        if let Some(ch) = self.zx_to_ascii.get(&line_bytes[j]) {
-                            if ch.is_ascii_digit() {
-                                line_number_str.push(*ch);
-                                j += 1;
-                            } else {
-                                break;
-                            }
-                        } else {
-                            break;
-                        }
-                    }
-                    if !line_number_str.is_empty() {
-                        result.push_str(&line_number_str);
-                        i = j - 1;
+                    let digits = self.digits(&line_bytes[i + 1..]);
+                    if !digits.is_empty() {
+                        line.number(at + 1, &digits, digits.parse().unwrap_or(0.0));
+                        i += digits.len();
                     }
                 }
+            } else if byte == 1 || in_string { // QUOTE, or inside a string
+                if byte == 1 {
+                    in_string = !in_string;
+                }
+                line.push(ItemKind::String, at, &self.decode_character(byte));
+            } else if !line_bytes[..i].last().is_some_and(|&b| (28..64).contains(&b)) && (28..38).contains(&byte) {
+                // A number, unless the digits end a variable name
+                let digits = self.digits(&line_bytes[i..]);
+                line.number(at, &digits, digits.parse().unwrap_or(0.0));
+                i += digits.len() - 1;
             } else {
-                result.push_str(&self.decode_character(byte));
+                line.push(ItemKind::Text, at, &self.decode_character(byte));
             }
             i += 1;
         }
-        result
+        line
+    }
+
+    /// The run of digits at the start of `bytes`, in ASCII.
+    fn digits(&self, bytes: &[u8]) -> String {
+        bytes
+            .iter()
+            .map_while(|b| self.zx_to_ascii.get(b).filter(|ch| ch.is_ascii_digit()))
+            .collect()
     }
 }
 
-/// Decodes a byte array representing a ZX80 program into a String.
+/// Decodes a ZX80 .O file into numbered BASIC lines.
 ///
 /// # Arguments
 ///
-/// * `bytes` - A slice of bytes containing the ZX80 program.
+/// * `bytes` - The complete .O file as a byte array
 /// * `zxpand_enabled` - A boolean to enable/disable ZXPAND specific tokens.
 ///
 /// # Returns
 ///
-/// A `Result` containing the decoded program as a `String`, or an error message.
-pub fn decode_zx80_bytes(bytes: &[u8], zxpand_enabled: bool) -> Result<String, &'static str> {
+/// A `Result` containing the program lines, with a diagnostic if the file ends before VARS,
+/// or an error if the header is damaged or not even the first line could be read.
+pub fn decode_zx80_listing(bytes: &[u8], zxpand_enabled: bool) -> Result<BasicListing, Error> {
     if bytes.len() < 40 {
-        return Err("Input byte array is too short to be a valid ZX80 file.");
+        return Err(Error::TruncatedHeader { format: "ZX80", expected: 40, found: bytes.len() });
     }
     
     let decoder = ZX80BasicDecoder::new(zxpand_enabled);
-    let mut result = String::new();
+    let mut listing = BasicListing::default();
     let mut current_pos = 40; // Skip the 40-byte header
 
-    let end_of_program = u16::from_le_bytes([bytes[8], bytes[9]]) as usize - 0x4000;
+    // VARS follows the program, so it cannot point into the system variables
+    let vars = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    let end_of_program = vars
+        .checked_sub(0x4000)
+        .filter(|&end| end >= 40)
+        .ok_or(Error::PointerOutOfRange { name: "VARS", offset: 8, value: vars })?;
 
-    while current_pos < end_of_program && current_pos + 2 < bytes.len() {
-        let high = bytes[current_pos];
+    while current_pos < end_of_program {
+        let (Some(&high), Some(&low)) = (bytes.get(current_pos), bytes.get(current_pos + 1)) else {
+            // A truncated file: VARS says the program goes on
+            return listing.partial(Error::UnexpectedEnd { offset: current_pos, line: None });
+        };
         if high == 0x80 {
             break; // End of program marker
         }
-        let low = bytes[current_pos + 1];
         let line_number = u16::from_be_bytes([high, low]);
+        let line_start = current_pos;
         current_pos += 2;
 
         let mut line_bytes = Vec::new();
-        while current_pos < bytes.len() {
-            let byte = bytes[current_pos];
+        loop {
+            let Some(&byte) = bytes.get(current_pos) else {
+                return listing.partial(Error::UnexpectedEnd { offset: current_pos, line: Some(line_number) });
+            };
             current_pos += 1;
             if byte == 0x76 { // End of line marker
                 break;
@@ -187,9 +207,84 @@ pub fn decode_zx80_bytes(bytes: &[u8], zxpand_enabled: bool) -> Result<String, &
             line_bytes.push(byte);
         }
         
-        let line_text = decoder.decode_line(&line_bytes);
-        result.push_str(&format!("{} {}\n", line_number, line_text));
+        let line = decoder.decode_line(&line_bytes, line_start + 2);
+        listing.lines.push(line.into_line(line_number, line_start, current_pos - line_start));
+    }
+
+    Ok(listing)
+}
+
+/// Decodes a byte array representing a ZX80 program into a String.
+///
+/// # Arguments
+///
+/// * `bytes` - A slice of bytes containing the ZX80 program.
+/// * `zxpand_enabled` - A boolean to enable/disable ZXPAND specific tokens.
+///
+/// # Returns
+///
+/// A `Result` containing the decoded program as a `String`, or an error.
+pub fn decode_zx80_bytes(bytes: &[u8], zxpand_enabled: bool) -> Result<String, Error> {
+    Ok(decode_zx80_listing(bytes, zxpand_enabled)?.to_text(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{zx80_image, zx80_o};
+
+    #[test]
+    fn the_fixture_is_cls() {
+        let listing = decode_zx80_listing(&zx80_o(), false).unwrap();
+        assert_eq!(listing.to_text(0), "10  CLS \n");
     }
 
-    Ok(result)
-}
\ No newline at end of file
+    #[test]
+    fn lines_are_listed() {
+        let image = zx80_image(&[
+            // PRINT "HI";A1
+            (10, &[244, 1, 45, 46, 1, 215, 38, 29]),
+            // LET B=-12
+            (20, &[240, 39, 227, 220, 29, 30]),
+            // GO TO 10
+            (30, &[236, 29, 28]),
+            // REM %A
+            (40, &[254, 38 | 0x80]),
+        ]);
+        let listing = decode_zx80_listing(&image, false).unwrap();
+        assert!(listing.diagnostics.is_empty());
+        assert_eq!(
+            listing.lines.iter().map(|line| line.text.as_str()).collect::<Vec<_>>(),
+            [" PRINT \"HI\";A1", " LET B=-12", " GO TO 10", " REM %A"]
+        );
+        let numbers: Vec<f64> = listing
+            .lines
+            .iter()
+            .flat_map(|line| &line.items)
+            .filter(|item| item.kind == ItemKind::Number)
+            .filter_map(|item| item.value)
+            .collect();
+        // The 1 of A1 is part of the name
+        assert_eq!(numbers, [12.0, 10.0]);
+        assert_eq!((listing.lines[1].offset, listing.lines[1].length), (51, 9));
+    }
+
+    #[test]
+    fn zxpand_tokens_need_the_option() {
+        let image = zx80_image(&[(10, &[255])]);
+        assert_eq!(decode_zx80_listing(&image, false).unwrap().lines[0].text, "?");
+        assert_eq!(decode_zx80_listing(&image, true).unwrap().lines[0].text, " CAT ");
+    }
+
+    #[test]
+    fn damaged_images_are_reported() {
+        let image = zx80_o();
+        // The file ends after the line number
+        let error = decode_zx80_listing(&image[..42], false).unwrap_err();
+        assert!(matches!(error, Error::UnexpectedEnd { offset: 42, line: Some(10) }));
+        let mut image = zx80_o();
+        image[9] = 0x30;
+        assert!(matches!(decode_zx80_listing(&image, false), Err(Error::PointerOutOfRange { name: "VARS", .. })));
+        assert!(matches!(decode_zx80_listing(&image[..20], false), Err(Error::TruncatedHeader { .. })));
+    }
+}
@@ -0,0 +1,60 @@
+//! Sinclair ZX81 files: .P/.P81 program images, TZX tapes and cassette
+//! recordings, plus an interpreter that runs a program to show its screen.
+//!
+//! ```no_run
+//! use rust_wasm_converter::zx81;
+//!
+//! let data = std::fs::read("GAME.p").unwrap();
+//! let listing = zx81::decode_basic(&data).unwrap();
+//! print!("{}", listing.to_text(4));
+//!
+//! let run = zx81::run_zx81_p_file(&data, zx81::ZX81RunConfig::default()).unwrap();
+//! println!("{}", run.display_lines().join("\n"));
+//! ```
+
+pub use crate::basic::{BasicItem, BasicLine, BasicListing, ItemKind};
+pub use crate::basic_analysis::{AddressUse, LineReference, ProgramAnalysis, VariableUse};
+pub use crate::basic_format::FormatOptions;
+pub use crate::basic_renumber::RenumberedProgram;
+pub use crate::error::Error;
+pub use crate::tzx_file::{extract_zx81_programs, parse_tzx, zx81_p_to_tzx, TZXBlock, TZXProgram};
+pub use crate::zx81_decoder::{split_zx81_name, zx81_name_length, zx81_name_to_string, OutputStyle};
+pub use crate::zx81_interpreter::{
+    ascii_to_zx81, run_zx81_p_file, zx81_number_string, ZX81Report, ZX81RunConfig, ZX81RunResult,
+};
+pub use crate::zx_tape::{decode_zx_edges, decode_zx_wav, encode_zx_wav, ZXTapeFile, ZXTapeMachine};
+
+/// Decodes the BASIC program in a ZX81 .P, .81 or .P81 file.
+///
+/// # Arguments
+///
+/// * `data` - The complete file; a leading program name (.P81) is recognised and returned
+///
+/// # Returns
+///
+/// A `Result` containing the program name and lines, or an error if not even the first line could be read.
+/// A damaged program is listed up to the damage, with the reason in `diagnostics`.
+pub fn decode_basic(data: &[u8]) -> Result<BasicListing, Error> {
+    crate::zx81_decoder::decode_zx81_listing(data, OutputStyle::Readable)
+}
+
+/// Renumbers the BASIC program in a ZX81 .P, .81 or .P81 file. Targets are rewritten
+/// with their hidden 5-byte numbers, and line lengths and the system variables that
+/// follow the program are moved when they gain or lose digits.
+///
+/// # Arguments
+///
+/// * `data` - The complete file
+/// * `start` - The first new line number
+/// * `step` - The gap between new line numbers
+///
+/// # Returns
+///
+/// A `Result` containing the renumbered file, with GOTO, GOSUB, RUN and LIST targets
+/// changed to match, and notes on any computed or missing targets that were left alone.
+/// Damaged programs are refused.
+pub fn renumber_basic(data: &[u8], start: u16, step: u16) -> Result<RenumberedProgram, Error> {
+    let listing = decode_basic(data)?;
+    let base = data.len() - split_zx81_name(data).1.len();
+    crate::basic_renumber::renumber(data, &listing, crate::basic_renumber::LineFormat::Zx81 { base }, start, step)
+}
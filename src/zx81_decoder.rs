@@ -1,6 +1,10 @@
 // Adapted from 1993 codebase via
 //  https://github.com/ryangray/zx81-utils
 
+use crate::basic::{BasicListing, ItemKind, LineBuilder};
+use crate::error::Error;
+use crate::zx81_interpreter::zx81_display_char;
+
 /// Output styles for ZX81 BASIC decoding
 #[derive(Debug, Clone, Copy)]
 pub enum OutputStyle {
@@ -82,14 +86,14 @@ impl ZX81BasicDecoder {
         ]
     }
 
-    /// Translates a ZX81 program line into readable text
-    fn translate_line(&self, line_bytes: &[u8]) -> String {
-        let mut result = String::new();
+    /// Translates a ZX81 program line, whose bytes start at `offset` in the file, into readable text
+    fn translate_line(&self, line_bytes: &[u8], offset: usize) -> LineBuilder {
+        let mut line = LineBuilder::default();
         let mut in_quotes = false;
         let line_len = line_bytes.len();
         
         if line_len == 0 {
-            return result;
+            return line;
         }
 
         let keyword = line_bytes[0];
@@ -97,109 +101,329 @@ impl ZX81BasicDecoder {
 
         while i < line_len - 1 {
             let c = usize::from(line_bytes[i]);
-            let x = if c < self.charset.len() {
-                self.charset[c]
-            } else {
-                "#"
-            };
+            let x = self.char_text(line_bytes[i]);
+            let at = offset + i;
+
+            if keyword == 234 && i > 0 { // REM body
+                line.push(ItemKind::Rem, at, x);
+                i += 1;
+                continue;
+            }
 
             // Handle quotes (toggle in_quotes state)
-            if keyword != 234 && c == 11 { // Not REM and is QUOTE
-                in_quotes = !in_quotes;
+            if c == 11 || in_quotes { // QUOTE, or inside a string
+                if c == 11 {
+                    in_quotes = !in_quotes;
+                }
+                line.push(ItemKind::String, at, x);
+                i += 1;
+                continue;
             }
 
-            // Skip inline floating point numbers (except in REM)
-            if keyword != 234 && c == 126 { // Not REM and is NUM_code
-                i += 5; // Skip the 5-byte floating point number
+            // Numbers are followed by a hidden 5-byte floating point copy (NUM_code 126)
+            if let Some(marker) = hidden_number_marker(line_bytes, i) {
+                let text: String = line_bytes[i..marker].iter().map(|&b| self.char_text(b)).collect();
+                let value = zx81_float(line_bytes.get(marker + 1..marker + 6).unwrap_or(&[]));
+                line.number(at, &text, value);
+                i = marker + 6;
+                continue;
+            }
+            if c == 126 { // A hidden number with no digits before it
+                i += 6;
                 continue;
             }
 
-            result.push_str(x);
+            let kind = if c >= 192 || (64..=66).contains(&c) { ItemKind::Keyword } else { ItemKind::Text };
+            line.push(kind, at, x);
 
             i += 1;
         }
 
-        result
+        line
+    }
+
+    /// The listed form of a character or token code.
+    fn char_text(&self, code: u8) -> &'static str {
+        self.charset.get(usize::from(code)).copied().unwrap_or("#")
     }
 }
 
-/// Reads a ZX81 program line from the byte stream
-fn read_zx81_line(bytes: &[u8], pos: &mut usize, remaining: &mut i32) -> Option<(u16, Vec<u8>)> {
-    if *remaining < 4 {
+/// If a number literal (digits, '.', and an exponent) starts at `start` and is followed by
+/// the hidden number marker, returns the marker's position.
+fn hidden_number_marker(bytes: &[u8], start: usize) -> Option<usize> {
+    if !(27..=37).contains(&bytes[start]) {
         return None;
     }
+    for j in start + 1..bytes.len() {
+        match bytes[j] {
+            27..=37 | 42 => {}               // digits, '.' and 'E'
+            21 | 22 if bytes[j - 1] == 42 => {} // exponent sign
+            126 => return Some(j),
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Converts a ZX81 5-byte floating point number (as stored after the hidden
+/// number marker 0x7E) into an `f64`.
+pub(crate) fn zx81_float(fp: &[u8]) -> f64 {
+    if fp.len() < 5 || fp[0] == 0 {
+        return 0.0;
+    }
+    let exponent = fp[0] as i32 - 128;
+    let sign = if fp[1] & 0x80 != 0 { -1.0 } else { 1.0 };
+    let mantissa = u32::from_be_bytes([fp[1] | 0x80, fp[2], fp[3], fp[4]]) as f64 / 4294967296.0;
+    sign * mantissa * 2.0_f64.powi(exponent)
+}
+
+/// Converts a number to the ZX81's 5-byte floating point form, the inverse of `zx81_float`.
+pub(crate) fn zx81_float_bytes(value: f64) -> [u8; 5] {
+    if value == 0.0 || !value.is_finite() {
+        return [0; 5];
+    }
+    // The mantissa is in [0.5, 1), with its top bit replaced by the sign
+    let mut exponent = value.abs().log2().floor() as i32 + 1;
+    let mut mantissa = (value.abs() / 2.0_f64.powi(exponent) * 4294967296.0).round() as u64;
+    if mantissa >= 1 << 32 {
+        mantissa >>= 1;
+        exponent += 1;
+    }
+    let m = (mantissa as u32).to_be_bytes();
+    let sign = if value < 0.0 { 0x80 } else { 0 };
+    [(exponent + 128).clamp(1, 255) as u8, (m[0] & 0x7F) | sign, m[1], m[2], m[3]]
+}
+
+/// Length of a ZX81 name prefix (up to and including the character with bit 7 set), if plausible.
+pub fn zx81_name_length(data: &[u8]) -> Option<usize> {
+    let end = data.iter().take(128).position(|&b| b & 0x80 != 0)?;
+    let name_ok = data[..end].iter().all(|&b| b < 64) && (data[end] & 0x7F) < 64;
+    name_ok.then_some(end + 1)
+}
+
+/// Translates a ZX81 program name to ASCII.
+pub fn zx81_name_to_string(name: &[u8]) -> String {
+    name.iter().map(|&b| zx81_display_char(b & 0x7F)).collect()
+}
+
+/// Checks that the system variables at the start of a .P image point at a
+/// sensible display file, variables area and edit line.
+pub(crate) fn plausible_p_image(bytes: &[u8]) -> bool {
+    if bytes.len() < 116 {
+        return false;
+    }
+    let word = |pos: usize| (bytes[pos] as u16) | ((bytes[pos + 1] as u16) << 8);
+    let (d_file, vars, e_line) = (word(3), word(7), word(11));
+    (16509..0x8000).contains(&d_file) && vars > d_file && e_line > vars
+}
+
+/// Splits a ZX81 image into its program name and the .P data.
+///
+/// Plain `.P` and `.81` images start directly with the system variables and have no name.
+/// `.P81` images (and raw tape data) start with the name, its last character in inverse.
+pub fn split_zx81_name(bytes: &[u8]) -> (Option<String>, &[u8]) {
+    if !plausible_p_image(bytes) {
+        if let Some(n) = zx81_name_length(bytes) {
+            if plausible_p_image(&bytes[n..]) {
+                return (Some(zx81_name_to_string(&bytes[..n])), &bytes[n..]);
+            }
+        }
+    }
+    (None, bytes)
+}
+
+/// A line number and the tokenised bytes of its line.
+type ProgramLine = (u16, Vec<u8>);
 
-    // Read line number (big-endian)
-    let line_num = ((bytes[*pos] as u16) << 8) | (bytes[*pos + 1] as u16);
-    *pos += 2;
-    *remaining -= 2;
+/// Reads a ZX81 program line from the byte stream
+fn read_zx81_line(bytes: &[u8], pos: &mut usize, remaining: &mut i32) -> Result<Option<ProgramLine>, Error> {
+    if *remaining < 4 {
+        return Ok(None);
+    }
+    let start = *pos;
+    let header = bytes.get(start..start + 4).ok_or(Error::UnexpectedEnd { offset: start, line: None })?;
 
-    // Read line length (little-endian)
-    let line_len = (bytes[*pos] as u16) | ((bytes[*pos + 1] as u16) << 8);
-    *pos += 2;
-    *remaining -= 2;
+    // Line number (big-endian) and line length (little-endian)
+    let line_num = ((header[0] as u16) << 8) | (header[1] as u16);
+    let line_len = (header[2] as usize) | ((header[3] as usize) << 8);
+    *pos += 4;
+    *remaining -= 4;
 
     if *remaining < line_len as i32 {
-        return None;
+        return Err(Error::BadLineLink { offset: start, line: line_num, link: line_len });
     }
 
     // Read the line content
-    let line_bytes = bytes[*pos..*pos + line_len as usize].to_vec();
-    *pos += line_len as usize;
+    let line_bytes = bytes
+        .get(*pos..*pos + line_len)
+        .ok_or(Error::UnexpectedEnd { offset: bytes.len(), line: Some(line_num) })?
+        .to_vec();
+    *pos += line_len;
     *remaining -= line_len as i32;
 
-    Some((line_num, line_bytes))
+    Ok(Some((line_num, line_bytes)))
 }
 
-/// Decodes a ZX81 .P file into readable BASIC text
+/// Splits the BASIC area of an unnamed .P image into `(line number, line bytes)` pairs.
+/// Reading stops at the first damaged line; the error is returned alongside the lines before it.
+fn read_program_lines(bytes: &[u8]) -> Result<(Vec<ProgramLine>, Option<Error>), Error> {
+    if bytes.len() < 116 {
+        return Err(Error::TruncatedHeader { format: "ZX81", expected: 116, found: bytes.len() });
+    }
+
+    // The program runs from 16509 (offset 116) up to the display file. A D_FILE beyond
+    // the end of a truncated file is read as far as the data goes.
+    let d_file = (bytes[3] as usize) | ((bytes[4] as usize) << 8);
+    if d_file < 16509 {
+        return Err(Error::PointerOutOfRange { name: "D_FILE", offset: 3, value: d_file });
+    }
+    let mut pos = 116;
+    let mut total = (d_file as i32) - 16509;
+    let mut lines = Vec::new();
+
+    loop {
+        match read_zx81_line(bytes, &mut pos, &mut total) {
+            Ok(Some(line)) => lines.push(line),
+            Ok(None) => return Ok((lines, None)),
+            Err(e) => return Ok((lines, Some(e))),
+        }
+    }
+}
+
+/// Splits the BASIC area of a ZX81 .P file into `(line number, line bytes)` pairs.
+/// The line bytes include the trailing NEWLINE (0x76). A damaged program is cut short
+/// at the damage.
+pub(crate) fn zx81_program_lines(bytes: &[u8]) -> Result<Vec<ProgramLine>, Error> {
+    let (_, bytes) = split_zx81_name(bytes);
+    match read_program_lines(bytes)? {
+        (lines, Some(e)) if lines.is_empty() => Err(e),
+        (lines, _) => Ok(lines),
+    }
+}
+
+/// Decodes a ZX81 .P file into its program name and numbered BASIC lines.
+///
+/// `.P81` files, which start with the program name, are recognised automatically.
 ///
 /// # Arguments
 ///
-/// * `bytes` - The complete .P file as a byte array
+/// * `bytes` - The complete .P, .81 or .P81 file as a byte array
 /// * `style` - The output style to use
 ///
 /// # Returns
 ///
-/// A `Result` containing the decoded program as a `String`, or an error message.
-pub fn decode_zx81_p_file(bytes: &[u8], style: OutputStyle) -> Result<String, &'static str> {
-    if bytes.len() < 116 {
-        return Err("Input byte array is too short to be a valid ZX81 .P file.");
-    }
-
+/// A `Result` containing the decoded listing, with a diagnostic if a damaged line stopped
+/// decoding early, or an error if not even the first line could be read.
+pub fn decode_zx81_listing(bytes: &[u8], style: OutputStyle) -> Result<BasicListing, Error> {
+    let (name, program) = split_zx81_name(bytes);
     let decoder = ZX81BasicDecoder::new(style);
-    let mut result = String::new();
-    
-    // Skip first 3 bytes of system variables
-    let mut pos = 3;
-    
-    // Read d_file (address of display file)
-    let d_file = (bytes[pos] as u16) | ((bytes[pos + 1] as u16) << 8);
-    pos += 2;
-    
-    // Skip remaining system variables (111 bytes)
-    pos += 111;
-    
-    // Calculate total program size
-    let mut total = (d_file as i32) - 16509;
-    
-    // Process lines
-    while total >= 0 {
-        if let Some((line_num, line_bytes)) = read_zx81_line(bytes, &mut pos, &mut total) {
-            let line_text = decoder.translate_line(&line_bytes);
-            result.push_str(&format!("{:4} {}\n", line_num, line_text));
-        } else {
-            break;
-        }
+    let (lines, stopped) = read_program_lines(program)?;
+    // Lines follow each other from offset 116, after the name if there is one
+    let mut offset = bytes.len() - program.len() + 116;
+    let mut listing = BasicListing { name, ..Default::default() };
+    for (number, line_bytes) in lines {
+        let line = decoder.translate_line(&line_bytes, offset + 4);
+        listing.lines.push(line.into_line(number, offset, line_bytes.len() + 4));
+        offset += line_bytes.len() + 4;
     }
     
-    if result.is_empty() {
-        return Err("No valid BASIC program found in the file.");
+    match stopped {
+        Some(e) => listing.partial(e),
+        None if listing.lines.is_empty() => Err(Error::NothingFound("No valid BASIC program found in the file.")),
+        None => Ok(listing),
     }
-    
-    Ok(result)
+}
+
+/// Decodes a ZX81 .P file into readable BASIC text
+///
+/// `.P81` files, which start with the program name, are recognised automatically;
+/// the name is reported on the first line of the output and removed before decoding.
+///
+/// # Arguments
+///
+/// * `bytes` - The complete .P, .81 or .P81 file as a byte array
+/// * `style` - The output style to use
+///
+/// # Returns
+///
+/// A `Result` containing the decoded program as a `String`, or an error.
+pub fn decode_zx81_p_file(bytes: &[u8], style: OutputStyle) -> Result<String, Error> {
+    Ok(decode_zx81_listing(bytes, style)?.to_text(4))
 }
 
 /// Convenience function for decoding with readable output (default)
-pub fn decode_zx81_bytes(bytes: &[u8]) -> Result<String, &'static str> {
+pub fn decode_zx81_bytes(bytes: &[u8]) -> Result<String, Error> {
     decode_zx81_p_file(bytes, OutputStyle::Readable)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::zx81_p;
+
+    fn program() -> Vec<u8> {
+        zx81_p(&[(10, "PRINT \"HI\";AT 1,2"), (20, "IF A<=3 THEN GOTO 10")])
+    }
+
+    fn texts(listing: &BasicListing) -> Vec<(u16, String)> {
+        listing.lines.iter().map(|line| (line.number, line.text.clone())).collect()
+    }
+
+    #[test]
+    fn p_files_decode() {
+        let listing = decode_zx81_listing(&program(), OutputStyle::Readable).unwrap();
+        assert_eq!(listing.name, None);
+        assert_eq!(
+            texts(&listing),
+            [(10, " PRINT \"HI\";AT 1,2".to_string()), (20, " IF A<=3 THEN GOTO 10".to_string())]
+        );
+        assert_eq!(listing.lines[0].offset, 116);
+    }
+
+    #[test]
+    fn p81_files_have_their_name_removed() {
+        // "GAME", the last character inverse, in front of the .P image
+        let mut p81 = vec![0x2C, 0x26, 0x32, 0x2A | 0x80];
+        p81.extend(program());
+        assert_eq!(split_zx81_name(&p81), (Some("GAME".to_string()), &program()[..]));
+        assert_eq!(split_zx81_name(&program()), (None, &program()[..]));
+
+        let listing = decode_zx81_listing(&p81, OutputStyle::Readable).unwrap();
+        assert_eq!(listing.name.as_deref(), Some("GAME"));
+        assert_eq!(listing.lines[0].offset, 4 + 116);
+        assert_eq!(texts(&listing), texts(&decode_zx81_listing(&program(), OutputStyle::Readable).unwrap()));
+        assert!(decode_zx81_p_file(&p81, OutputStyle::Readable).unwrap().starts_with("Program: \"GAME\"\n"));
+    }
+
+    #[test]
+    fn numbers_carry_their_hidden_value() {
+        let listing = decode_zx81_listing(&program(), OutputStyle::Readable).unwrap();
+        let numbers: Vec<f64> =
+            listing.lines[1].items.iter().filter(|item| item.kind == ItemKind::Number).filter_map(|item| item.value).collect();
+        assert_eq!(numbers, [3.0, 10.0]);
+    }
+
+    #[test]
+    fn floats_round_trip() {
+        for value in [1.0, -1.0, 0.5, 10.0, 1.0 / 3.0, 65535.0, 1e-30, 1.7e38] {
+            let back = zx81_float(&zx81_float_bytes(value));
+            assert!((back - value).abs() <= value.abs() * 1e-9, "{} came back as {}", value, back);
+        }
+        // 1 is 0.5 * 2^1, stored with the mantissa's top bit replaced by the sign
+        assert_eq!(zx81_float_bytes(1.0), [0x81, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(zx81_float_bytes(-1.0), [0x81, 0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(zx81_float_bytes(0.0), [0; 5]);
+    }
+
+    #[test]
+    fn damaged_programs_decode_up_to_the_damage() {
+        let mut damaged = program();
+        // Give line 20 a length running past the display file
+        let second = 116 + 4 + u16::from_le_bytes([damaged[118], damaged[119]]) as usize;
+        damaged[second + 2] = 0xFF;
+        let listing = decode_zx81_listing(&damaged, OutputStyle::Readable).unwrap();
+        assert_eq!(listing.lines.len(), 1);
+        assert_eq!(listing.diagnostics.len(), 1);
+        assert!(decode_zx81_listing(&damaged[..100], OutputStyle::Readable).is_err());
+    }
+}
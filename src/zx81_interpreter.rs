@@ -0,0 +1,1343 @@
+// src/zx81_interpreter.rs
+//
+// A small ZX81 BASIC interpreter that runs the program held in a .P file and
+// reports what was left on the 32x24 display, so decoded programs can be
+// checked against the real machine.
+
+use std::collections::HashMap;
+
+use crate::basic_number::{format_number, NumberStyle};
+use crate::error::Error;
+use crate::zx81_decoder::{zx81_float, zx81_program_lines};
+
+const SCREEN_WIDTH: usize = 32;
+const SCREEN_HEIGHT: usize = 24;
+/// Lines 22 and 23 belong to the lower screen; PRINT may only use 0-21.
+const PRINT_LINES: usize = 22;
+/// RAM of a ZX81 with the 16K pack, the most any array can take.
+const RAM_SIZE: usize = 16 * 1024;
+
+// ZX81 character codes used by the interpreter
+const SPACE: u8 = 0;
+const QUOTE: u8 = 11;
+const DOLLAR: u8 = 13;
+const OPEN: u8 = 16;
+const CLOSE: u8 = 17;
+const GREATER: u8 = 18;
+const LESS: u8 = 19;
+const EQUALS: u8 = 20;
+const PLUS: u8 = 21;
+const MINUS: u8 = 22;
+const STAR: u8 = 23;
+const SLASH: u8 = 24;
+const SEMICOLON: u8 = 25;
+const COMMA: u8 = 26;
+const DOT: u8 = 27;
+const ZERO: u8 = 28;
+const NINE: u8 = 37;
+const LETTER_A: u8 = 38;
+const LETTER_E: u8 = 42;
+const LETTER_Z: u8 = 63;
+const NEWLINE: u8 = 0x76;
+const NUMBER: u8 = 0x7E;
+
+// ZX81 token codes
+const TK_RND: u8 = 64;
+const TK_INKEY: u8 = 65;
+const TK_PI: u8 = 66;
+const TK_QUOTE_IMAGE: u8 = 192;
+const TK_AT: u8 = 193;
+const TK_TAB: u8 = 194;
+const TK_CODE: u8 = 196;
+const TK_USR: u8 = 212;
+const TK_STR: u8 = 213;
+const TK_CHR: u8 = 214;
+const TK_NOT: u8 = 215;
+const TK_POWER: u8 = 216;
+const TK_OR: u8 = 217;
+const TK_AND: u8 = 218;
+const TK_LE: u8 = 219;
+const TK_GE: u8 = 220;
+const TK_NE: u8 = 221;
+const TK_THEN: u8 = 222;
+const TK_TO: u8 = 223;
+const TK_STEP: u8 = 224;
+const TK_LPRINT: u8 = 225;
+const TK_LLIST: u8 = 226;
+const TK_STOP: u8 = 227;
+const TK_SLOW: u8 = 228;
+const TK_FAST: u8 = 229;
+const TK_NEW: u8 = 230;
+const TK_SCROLL: u8 = 231;
+const TK_CONT: u8 = 232;
+const TK_DIM: u8 = 233;
+const TK_REM: u8 = 234;
+const TK_FOR: u8 = 235;
+const TK_GOTO: u8 = 236;
+const TK_GOSUB: u8 = 237;
+const TK_INPUT: u8 = 238;
+const TK_LOAD: u8 = 239;
+const TK_LIST: u8 = 240;
+const TK_LET: u8 = 241;
+const TK_PAUSE: u8 = 242;
+const TK_NEXT: u8 = 243;
+const TK_POKE: u8 = 244;
+const TK_PRINT: u8 = 245;
+const TK_PLOT: u8 = 246;
+const TK_RUN: u8 = 247;
+const TK_SAVE: u8 = 248;
+const TK_RAND: u8 = 249;
+const TK_IF: u8 = 250;
+const TK_CLS: u8 = 251;
+const TK_UNPLOT: u8 = 252;
+const TK_CLEAR: u8 = 253;
+const TK_RETURN: u8 = 254;
+const TK_COPY: u8 = 255;
+
+/// Settings for a single run of a ZX81 program.
+#[derive(Debug, Clone)]
+pub struct ZX81RunConfig {
+    /// Maximum number of statements executed before the run is stopped with report D.
+    pub max_steps: usize,
+    /// Successive results of `INKEY$`; once exhausted `INKEY$` returns `""`.
+    pub inkey_script: Vec<String>,
+    /// Successive lines typed in response to `INPUT`.
+    pub input_script: Vec<String>,
+    /// Value of the FRAMES system variable, used to seed `RAND 0`.
+    pub frames: u16,
+}
+
+impl Default for ZX81RunConfig {
+    fn default() -> Self {
+        Self {
+            max_steps: 100_000,
+            inkey_script: Vec::new(),
+            input_script: Vec::new(),
+            frames: 0,
+        }
+    }
+}
+
+/// A ZX81 report, as shown in the bottom line of the screen when a program stops.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZX81Report {
+    /// Report code: '0'-'9' or 'A'-'D'.
+    pub code: char,
+    /// Line in which the program stopped.
+    pub line: u16,
+    /// Human readable explanation of the report code.
+    pub message: &'static str,
+}
+
+/// The state of the machine after a program has run.
+#[derive(Debug, Clone)]
+pub struct ZX81RunResult {
+    /// Why the program stopped.
+    pub report: ZX81Report,
+    /// The 32x24 display as ZX81 character codes, including the report in the bottom line.
+    pub display: Vec<[u8; SCREEN_WIDTH]>,
+    /// Number of statements executed.
+    pub steps: usize,
+}
+
+impl ZX81RunResult {
+    /// Renders the display as plain text, one `String` per screen line.
+    /// Inverse characters are shown as their normal form; block graphics use Unicode quadrants.
+    pub fn display_lines(&self) -> Vec<String> {
+        self.display
+            .iter()
+            .map(|row| row.iter().map(|&c| zx81_display_char(c)).collect())
+            .collect()
+    }
+}
+
+/// A BASIC value: ZX81 strings are kept as ZX81 character codes.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Num(f64),
+    Str(Vec<u8>),
+}
+
+/// A runtime error carrying the ZX81 report code.
+#[derive(Debug, Clone, Copy)]
+struct Stop {
+    code: char,
+    message: &'static str,
+}
+
+const fn stop(code: char, message: &'static str) -> Stop {
+    Stop { code, message }
+}
+
+const NEXT_WITHOUT_FOR: Stop = stop('1', "NEXT without FOR");
+const VARIABLE_NOT_FOUND: Stop = stop('2', "Variable not found");
+const SUBSCRIPT_WRONG: Stop = stop('3', "Subscript wrong");
+const NO_ROOM: Stop = stop('4', "No room for line");
+const SCREEN_FULL: Stop = stop('5', "No more room on the screen");
+const OVERFLOW: Stop = stop('6', "Arithmetic overflow");
+const RETURN_WITHOUT_GOSUB: Stop = stop('7', "RETURN without GOSUB");
+const STOPPED: Stop = stop('9', "STOP statement executed");
+const INVALID_ARGUMENT: Stop = stop('A', "Invalid argument");
+const OUT_OF_RANGE: Stop = stop('B', "Integer out of range");
+const BAD_VAL: Stop = stop('C', "Text of VAL is not a valid expression");
+const BREAK: Stop = stop('D', "BREAK or end of input script");
+const NONSENSE: Stop = stop('C', "Nonsense in BASIC");
+
+/// What the interpreter should do after a statement.
+enum Flow {
+    Next,
+    Jump(usize),
+    End,
+}
+
+struct ForLoop {
+    limit: f64,
+    step: f64,
+    body: usize,
+}
+
+struct NumArray {
+    dims: Vec<usize>,
+    data: Vec<f64>,
+}
+
+struct StrArray {
+    dims: Vec<usize>,
+    data: Vec<u8>,
+}
+
+/// Position inside the bytes of the line being executed.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn peek(&mut self) -> u8 {
+        while self.pos < self.bytes.len() && self.bytes[self.pos] == SPACE {
+            self.pos += 1;
+        }
+        self.bytes.get(self.pos).copied().unwrap_or(NEWLINE)
+    }
+
+    fn next(&mut self) -> u8 {
+        let b = self.peek();
+        self.pos += 1;
+        b
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == byte {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Stop> {
+        if self.eat(byte) { Ok(()) } else { Err(NONSENSE) }
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.peek() == NEWLINE
+    }
+}
+
+/// The interpreter: program, variables, display and RNG state.
+pub struct ZX81Interpreter {
+    lines: Vec<(u16, Vec<u8>)>,
+    config: ZX81RunConfig,
+    numbers: HashMap<Vec<u8>, f64>,
+    strings: HashMap<u8, Vec<u8>>,
+    num_arrays: HashMap<u8, NumArray>,
+    str_arrays: HashMap<u8, StrArray>,
+    for_loops: HashMap<u8, ForLoop>,
+    gosub_stack: Vec<usize>,
+    memory: HashMap<u16, u8>,
+    screen: Vec<[u8; SCREEN_WIDTH]>,
+    row: usize,
+    col: usize,
+    seed: u16,
+    inkey_pos: usize,
+    input_pos: usize,
+}
+
+impl ZX81Interpreter {
+    /// Creates an interpreter for the given program lines (as produced from a .P file).
+    pub fn new(lines: Vec<(u16, Vec<u8>)>, config: ZX81RunConfig) -> Self {
+        Self {
+            lines,
+            config,
+            numbers: HashMap::new(),
+            strings: HashMap::new(),
+            num_arrays: HashMap::new(),
+            str_arrays: HashMap::new(),
+            for_loops: HashMap::new(),
+            gosub_stack: Vec::new(),
+            memory: HashMap::new(),
+            screen: vec![[SPACE; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            row: 0,
+            col: 0,
+            seed: 0,
+            inkey_pos: 0,
+            input_pos: 0,
+        }
+    }
+
+    /// Runs the program from its first line, as `RUN` would.
+    pub fn run(mut self) -> ZX81RunResult {
+        let mut index = 0;
+        let mut steps = 0;
+        let mut last_line = 0;
+
+        let outcome = loop {
+            if index >= self.lines.len() {
+                break stop('0', "OK");
+            }
+            if steps >= self.config.max_steps {
+                break BREAK;
+            }
+            steps += 1;
+            last_line = self.lines[index].0;
+
+            let bytes = self.lines[index].1.clone();
+            let mut cursor = Cursor { bytes: &bytes, pos: 0 };
+            match self.statement(&mut cursor, index) {
+                Ok(Flow::Next) => index += 1,
+                Ok(Flow::Jump(target)) => index = target,
+                Ok(Flow::End) => break stop('0', "OK"),
+                Err(e) => break e,
+            }
+        };
+
+        let report = ZX81Report { code: outcome.code, line: last_line, message: outcome.message };
+        let text = format!("{}/{}", report.code, report.line);
+        let mut bottom = [SPACE; SCREEN_WIDTH];
+        for (cell, ch) in bottom.iter_mut().zip(text.chars()) {
+            *cell = ascii_to_zx81(ch);
+        }
+        self.screen[SCREEN_HEIGHT - 1] = bottom;
+
+        ZX81RunResult { report, display: self.screen, steps }
+    }
+
+    /// Index of the first line whose number is at least `line`.
+    fn find_line(&self, line: f64) -> Result<usize, Stop> {
+        let line = line.round();
+        if !(0.0..=65535.0).contains(&line) {
+            return Err(OUT_OF_RANGE);
+        }
+        Ok(self
+            .lines
+            .iter()
+            .position(|(n, _)| *n as f64 >= line)
+            .unwrap_or(self.lines.len()))
+    }
+
+    fn clear_variables(&mut self) {
+        self.numbers.clear();
+        self.strings.clear();
+        self.num_arrays.clear();
+        self.str_arrays.clear();
+        self.for_loops.clear();
+        self.gosub_stack.clear();
+    }
+
+    /// Executes the single statement on a line (the ZX81 has one statement per line).
+    fn statement(&mut self, c: &mut Cursor, index: usize) -> Result<Flow, Stop> {
+        let keyword = c.next();
+        match keyword {
+            TK_REM | TK_SLOW | TK_FAST | TK_LIST | TK_LLIST | TK_COPY | TK_SAVE | TK_LOAD => Ok(Flow::Next),
+            TK_STOP => Err(STOPPED),
+            TK_NEW => Ok(Flow::End),
+            TK_CONT => Ok(Flow::Next),
+            TK_PAUSE | TK_LPRINT => {
+                while !c.at_end() {
+                    if !c.eat(SEMICOLON) && !c.eat(COMMA) {
+                        self.expression(c)?;
+                    }
+                }
+                Ok(Flow::Next)
+            }
+            TK_LET => {
+                self.assignment(c)?;
+                Ok(Flow::Next)
+            }
+            TK_PRINT => {
+                self.print(c)?;
+                Ok(Flow::Next)
+            }
+            TK_CLS => {
+                self.cls();
+                Ok(Flow::Next)
+            }
+            TK_SCROLL => {
+                self.screen.remove(0);
+                self.screen.insert(PRINT_LINES - 1, [SPACE; SCREEN_WIDTH]);
+                self.row = PRINT_LINES - 1;
+                self.col = 0;
+                Ok(Flow::Next)
+            }
+            TK_PLOT | TK_UNPLOT => {
+                let x = self.number(c)?;
+                c.expect(COMMA)?;
+                let y = self.number(c)?;
+                self.plot(x, y, keyword == TK_PLOT)?;
+                Ok(Flow::Next)
+            }
+            TK_GOTO => {
+                let line = self.number(c)?;
+                Ok(Flow::Jump(self.find_line(line)?))
+            }
+            TK_GOSUB => {
+                let line = self.number(c)?;
+                self.gosub_stack.push(index + 1);
+                Ok(Flow::Jump(self.find_line(line)?))
+            }
+            TK_RETURN => self.gosub_stack.pop().map(Flow::Jump).ok_or(RETURN_WITHOUT_GOSUB),
+            TK_RUN => {
+                let target = if c.at_end() {
+                    0
+                } else {
+                    let line = self.number(c)?;
+                    self.find_line(line)?
+                };
+                self.clear_variables();
+                self.cls();
+                Ok(Flow::Jump(target))
+            }
+            TK_CLEAR => {
+                self.clear_variables();
+                Ok(Flow::Next)
+            }
+            TK_RAND => {
+                let n = if c.at_end() { 0.0 } else { self.number(c)? };
+                let n = to_u16(n)?;
+                self.seed = if n == 0 { self.config.frames } else { n };
+                Ok(Flow::Next)
+            }
+            TK_POKE => {
+                let address = self.number(c)?;
+                c.expect(COMMA)?;
+                let value = self.number(c)?;
+                let value = value.round();
+                if !(-255.0..=255.0).contains(&value) {
+                    return Err(OUT_OF_RANGE);
+                }
+                self.memory.insert(to_u16(address)?, value as i32 as u8);
+                Ok(Flow::Next)
+            }
+            TK_IF => {
+                let condition = self.number(c)?;
+                c.expect(TK_THEN)?;
+                if condition != 0.0 {
+                    self.statement(c, index)
+                } else {
+                    Ok(Flow::Next)
+                }
+            }
+            TK_FOR => self.for_statement(c, index),
+            TK_NEXT => self.next_statement(c),
+            TK_DIM => {
+                self.dim(c)?;
+                Ok(Flow::Next)
+            }
+            TK_INPUT => {
+                self.input(c)?;
+                Ok(Flow::Next)
+            }
+            _ => Err(NONSENSE),
+        }
+    }
+
+    fn cls(&mut self) {
+        for row in self.screen.iter_mut() {
+            *row = [SPACE; SCREEN_WIDTH];
+        }
+        self.row = 0;
+        self.col = 0;
+    }
+
+    /// Sets or clears one pixel on the 64x44 block graphics grid. (0,0) is bottom left.
+    fn plot(&mut self, x: f64, y: f64, set: bool) -> Result<(), Stop> {
+        let (x, y) = (x.round(), y.round());
+        if !(0.0..64.0).contains(&x) || !(0.0..44.0).contains(&y) {
+            return Err(OUT_OF_RANGE);
+        }
+        let (x, y) = (x as usize, y as usize);
+        let row = PRINT_LINES - 1 - y / 2;
+        let col = x / 2;
+        let bit = match (x % 2, y % 2) {
+            (0, 1) => 1, // top left
+            (1, 1) => 2, // top right
+            (0, _) => 4, // bottom left
+            _ => 8,      // bottom right
+        };
+
+        let current = self.screen[row][col];
+        let mut pattern = match current {
+            0..=7 => current,
+            0x80..=0x87 => 8 | (!current & 7),
+            _ => 0,
+        };
+        if set { pattern |= bit } else { pattern &= !bit }
+        self.screen[row][col] = if pattern & 8 != 0 { 0x80 | (!pattern & 7) } else { pattern };
+        Ok(())
+    }
+
+    /// Writes one character at the print position, moving to the next line at the right edge.
+    fn put_char(&mut self, ch: u8) -> Result<(), Stop> {
+        if self.col >= SCREEN_WIDTH {
+            self.newline()?;
+        }
+        if self.row >= PRINT_LINES {
+            return Err(SCREEN_FULL);
+        }
+        self.screen[self.row][self.col] = ch;
+        self.col += 1;
+        Ok(())
+    }
+
+    fn newline(&mut self) -> Result<(), Stop> {
+        if self.row + 1 >= PRINT_LINES {
+            return Err(SCREEN_FULL);
+        }
+        self.row += 1;
+        self.col = 0;
+        Ok(())
+    }
+
+    fn print(&mut self, c: &mut Cursor) -> Result<(), Stop> {
+        let mut separated = false;
+        loop {
+            match c.peek() {
+                NEWLINE => break,
+                SEMICOLON => {
+                    c.pos += 1;
+                    separated = true;
+                }
+                COMMA => {
+                    c.pos += 1;
+                    separated = true;
+                    let target = if self.col < 16 { 16 } else { 32 };
+                    while self.col < target {
+                        self.put_char(SPACE)?;
+                    }
+                }
+                TK_AT => {
+                    c.pos += 1;
+                    let row = self.number(c)?.round();
+                    c.expect(COMMA)?;
+                    let col = self.number(c)?.round();
+                    if !(0.0..PRINT_LINES as f64).contains(&row) || !(0.0..SCREEN_WIDTH as f64).contains(&col) {
+                        return Err(OUT_OF_RANGE);
+                    }
+                    self.row = row as usize;
+                    self.col = col as usize;
+                    separated = false;
+                }
+                TK_TAB => {
+                    c.pos += 1;
+                    let col = to_u16(self.number(c)?)? as usize % SCREEN_WIDTH;
+                    if col < self.col {
+                        self.newline()?;
+                    }
+                    while self.col < col {
+                        self.put_char(SPACE)?;
+                    }
+                    separated = false;
+                }
+                _ => {
+                    let text = match self.expression(c)? {
+                        Value::Num(n) => number_to_zx81(n),
+                        Value::Str(s) => s,
+                    };
+                    for ch in text {
+                        self.put_char(ch)?;
+                    }
+                    separated = false;
+                }
+            }
+        }
+        if !separated {
+            self.newline()?;
+        }
+        Ok(())
+    }
+
+    fn for_statement(&mut self, c: &mut Cursor, index: usize) -> Result<Flow, Stop> {
+        let var = c.next();
+        if !is_letter(var) {
+            return Err(NONSENSE);
+        }
+        c.expect(EQUALS)?;
+        let start = self.number(c)?;
+        c.expect(TK_TO)?;
+        let limit = self.number(c)?;
+        let step = if c.eat(TK_STEP) { self.number(c)? } else { 1.0 };
+
+        self.numbers.insert(vec![var], start);
+        self.for_loops.insert(var, ForLoop { limit, step, body: index + 1 });
+
+        if (step >= 0.0 && start > limit) || (step < 0.0 && start < limit) {
+            // Skip the loop body: continue after the matching NEXT.
+            for (i, (_, bytes)) in self.lines.iter().enumerate().skip(index + 1) {
+                let mut rest = Cursor { bytes, pos: 0 };
+                if rest.next() == TK_NEXT && rest.next() == var {
+                    return Ok(Flow::Jump(i + 1));
+                }
+            }
+            return Ok(Flow::End);
+        }
+        Ok(Flow::Next)
+    }
+
+    fn next_statement(&mut self, c: &mut Cursor) -> Result<Flow, Stop> {
+        let var = c.next();
+        let lp = self.for_loops.get(&var).ok_or(NEXT_WITHOUT_FOR)?;
+        let (limit, step, body) = (lp.limit, lp.step, lp.body);
+        let value = self.numbers.get(&vec![var]).copied().ok_or(NEXT_WITHOUT_FOR)? + step;
+        self.numbers.insert(vec![var], value);
+        if (step >= 0.0 && value > limit) || (step < 0.0 && value < limit) {
+            Ok(Flow::Next)
+        } else {
+            Ok(Flow::Jump(body))
+        }
+    }
+
+    fn dim(&mut self, c: &mut Cursor) -> Result<(), Stop> {
+        let name = c.next();
+        if !is_letter(name) {
+            return Err(NONSENSE);
+        }
+        let is_string = c.eat(DOLLAR);
+        c.expect(OPEN)?;
+        let mut dims = Vec::new();
+        loop {
+            let d = to_u16(self.number(c)?)? as usize;
+            if d == 0 {
+                return Err(SUBSCRIPT_WRONG);
+            }
+            dims.push(d);
+            if !c.eat(COMMA) {
+                break;
+            }
+        }
+        c.expect(CLOSE)?;
+
+        // Each element takes 5 bytes (1 for a string array), after the name, the length and
+        // 2 bytes per dimension; an array bigger than the RAM cannot be made
+        let element = if is_string { 1 } else { 5 };
+        let size = dims.iter().try_fold(1usize, |size, &d| size.checked_mul(d)).ok_or(NO_ROOM)?;
+        if size.checked_mul(element).is_none_or(|bytes| bytes + 4 + 2 * dims.len() > RAM_SIZE) {
+            return Err(NO_ROOM);
+        }
+        if is_string {
+            self.str_arrays.insert(name, StrArray { dims, data: vec![SPACE; size] });
+        } else {
+            self.num_arrays.insert(name, NumArray { dims, data: vec![0.0; size] });
+        }
+        Ok(())
+    }
+
+    fn input(&mut self, c: &mut Cursor) -> Result<(), Stop> {
+        let text = self.config.input_script.get(self.input_pos).cloned().ok_or(BREAK)?;
+        self.input_pos += 1;
+        let typed: Vec<u8> = text.chars().map(ascii_to_zx81).collect();
+
+        let start = c.pos;
+        let name = c.next();
+        if !is_letter(name) {
+            return Err(NONSENSE);
+        }
+        let value = if c.peek() == DOLLAR {
+            Value::Str(typed)
+        } else {
+            let mut typed = typed;
+            typed.push(NEWLINE);
+            let mut tc = Cursor { bytes: &typed, pos: 0 };
+            Value::Num(self.number(&mut tc).map_err(|_| BAD_VAL)?)
+        };
+        c.pos = start;
+        self.store(c, value)
+    }
+
+    /// `LET`: parses the target and stores the value of the expression after `=`.
+    fn assignment(&mut self, c: &mut Cursor) -> Result<(), Stop> {
+        let start = c.pos;
+        // Find the value first so that the target expression is evaluated afterwards
+        let mut depth = 0;
+        let mut in_string = false;
+        let mut eq = None;
+        let mut i = start;
+        while i < c.bytes.len() {
+            match c.bytes[i] {
+                QUOTE => in_string = !in_string,
+                OPEN if !in_string => depth += 1,
+                CLOSE if !in_string => depth -= 1,
+                NUMBER if !in_string => i += 5,
+                EQUALS if !in_string && depth == 0 => {
+                    eq = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        let eq = eq.ok_or(NONSENSE)?;
+        c.pos = eq + 1;
+        let value = self.expression(c)?;
+        let end = c.pos;
+        c.pos = start;
+        self.store(c, value)?;
+        c.pos = end;
+        Ok(())
+    }
+
+    /// Stores a value in the variable named at the cursor.
+    fn store(&mut self, c: &mut Cursor, value: Value) -> Result<(), Stop> {
+        let first = c.next();
+        if !is_letter(first) {
+            return Err(NONSENSE);
+        }
+
+        if c.eat(DOLLAR) {
+            let Value::Str(s) = value else { return Err(NONSENSE) };
+            if c.peek() != OPEN {
+                self.strings.insert(first, s);
+                return Ok(());
+            }
+            c.pos += 1;
+            if let Some(array) = self.str_arrays.get(&first) {
+                let dims = array.dims.clone();
+                let (offset, len) = self.str_array_element(c, &dims)?;
+                let array = self.str_arrays.get_mut(&first).ok_or(VARIABLE_NOT_FOUND)?;
+                procrustean(&mut array.data[offset..offset + len], &s);
+                return Ok(());
+            }
+            // Assignment to a slice of a simple string
+            let target = self.strings.get(&first).cloned().ok_or(VARIABLE_NOT_FOUND)?;
+            let (from, to) = self.slice_bounds(c, target.len())?;
+            let entry = self.strings.get_mut(&first).ok_or(VARIABLE_NOT_FOUND)?;
+            if from <= to {
+                procrustean(&mut entry[from - 1..to], &s);
+            }
+            return Ok(());
+        }
+
+        let Value::Num(n) = value else { return Err(NONSENSE) };
+        if c.peek() == OPEN {
+            c.pos += 1;
+            let array = self.num_arrays.get(&first).ok_or(VARIABLE_NOT_FOUND)?;
+            let dims = array.dims.clone();
+            let offset = self.array_offset(c, &dims)?;
+            c.expect(CLOSE)?;
+            self.num_arrays.get_mut(&first).ok_or(VARIABLE_NOT_FOUND)?.data[offset] = n;
+            return Ok(());
+        }
+
+        let mut name = vec![first];
+        while is_letter(c.peek()) || is_digit(c.peek()) {
+            name.push(c.next());
+        }
+        self.numbers.insert(name, n);
+        Ok(())
+    }
+
+    /// Reads comma separated subscripts (after the opening bracket) and returns the flat offset.
+    fn array_offset(&mut self, c: &mut Cursor, dims: &[usize]) -> Result<usize, Stop> {
+        let mut offset = 0;
+        for (i, &d) in dims.iter().enumerate() {
+            if i > 0 {
+                c.expect(COMMA)?;
+            }
+            let n = to_u16(self.number(c)?)? as usize;
+            if n == 0 || n > d {
+                return Err(SUBSCRIPT_WRONG);
+            }
+            offset = offset * d + (n - 1);
+        }
+        Ok(offset)
+    }
+
+    /// Subscripts of a string array element: the final dimension is the string length.
+    /// Returns the offset and length of the fixed-length string, consuming the closing bracket.
+    fn str_array_element(&mut self, c: &mut Cursor, dims: &[usize]) -> Result<(usize, usize), Stop> {
+        let (rows, len) = dims.split_at(dims.len() - 1);
+        let len = len[0];
+        let offset = if rows.is_empty() { 0 } else { self.array_offset(c, rows)? * len };
+        c.expect(CLOSE)?;
+        Ok((offset, len))
+    }
+
+    /// Reads `a TO b` / `a` / `TO b` / `a TO` up to and including the closing bracket.
+    /// Returns 1-based inclusive bounds.
+    fn slice_bounds(&mut self, c: &mut Cursor, len: usize) -> Result<(usize, usize), Stop> {
+        let from = if c.peek() == TK_TO { 1 } else { to_u16(self.number(c)?)? as usize };
+        let to = if c.eat(TK_TO) {
+            if c.peek() == CLOSE { len } else { to_u16(self.number(c)?)? as usize }
+        } else {
+            from
+        };
+        c.expect(CLOSE)?;
+        if from <= to && (from == 0 || to > len) {
+            return Err(SUBSCRIPT_WRONG);
+        }
+        Ok((from, to))
+    }
+
+    /// Applies any number of `(...)` slices to a string value.
+    fn slices(&mut self, c: &mut Cursor, mut s: Vec<u8>) -> Result<Vec<u8>, Stop> {
+        while c.eat(OPEN) {
+            let (from, to) = self.slice_bounds(c, s.len())?;
+            s = if from > to { Vec::new() } else { s[from - 1..to].to_vec() };
+        }
+        Ok(s)
+    }
+
+    fn number(&mut self, c: &mut Cursor) -> Result<f64, Stop> {
+        match self.expression(c)? {
+            Value::Num(n) => Ok(n),
+            Value::Str(_) => Err(NONSENSE),
+        }
+    }
+
+    fn expression(&mut self, c: &mut Cursor) -> Result<Value, Stop> {
+        self.binary(c, 0)
+    }
+
+    /// Precedence climbing over the ZX81 operator priorities.
+    fn binary(&mut self, c: &mut Cursor, min_priority: u8) -> Result<Value, Stop> {
+        let mut lhs = self.unary(c)?;
+        loop {
+            let op = c.peek();
+            let priority = match op {
+                TK_OR => 2,
+                TK_AND => 3,
+                EQUALS | GREATER | LESS | TK_LE | TK_GE | TK_NE => 5,
+                PLUS | MINUS => 6,
+                STAR | SLASH => 8,
+                TK_POWER => 10,
+                _ => return Ok(lhs),
+            };
+            if priority < min_priority {
+                return Ok(lhs);
+            }
+            c.pos += 1;
+            let rhs = self.binary(c, priority + 1)?;
+            lhs = apply_operator(op, lhs, rhs)?;
+        }
+    }
+
+    fn unary(&mut self, c: &mut Cursor) -> Result<Value, Stop> {
+        match c.peek() {
+            MINUS => {
+                c.pos += 1;
+                Ok(Value::Num(-self.binary_number(c, 9)?))
+            }
+            PLUS => {
+                c.pos += 1;
+                Ok(Value::Num(self.binary_number(c, 9)?))
+            }
+            TK_NOT => {
+                c.pos += 1;
+                Ok(Value::Num(if self.binary_number(c, 5)? == 0.0 { 1.0 } else { 0.0 }))
+            }
+            _ => self.primary(c),
+        }
+    }
+
+    fn binary_number(&mut self, c: &mut Cursor, min_priority: u8) -> Result<f64, Stop> {
+        match self.binary(c, min_priority)? {
+            Value::Num(n) => Ok(n),
+            Value::Str(_) => Err(NONSENSE),
+        }
+    }
+
+    fn primary(&mut self, c: &mut Cursor) -> Result<Value, Stop> {
+        let b = c.next();
+        match b {
+            ZERO..=NINE | DOT => {
+                c.pos -= 1;
+                Ok(Value::Num(read_number(c)?))
+            }
+            QUOTE => {
+                let mut s = Vec::new();
+                loop {
+                    match c.bytes.get(c.pos).copied() {
+                        Some(QUOTE) => break,
+                        Some(TK_QUOTE_IMAGE) => s.push(QUOTE),
+                        Some(NEWLINE) | None => return Err(NONSENSE),
+                        Some(ch) => s.push(ch),
+                    }
+                    c.pos += 1;
+                }
+                c.pos += 1;
+                Ok(Value::Str(self.slices(c, s)?))
+            }
+            OPEN => {
+                let v = self.expression(c)?;
+                c.expect(CLOSE)?;
+                match v {
+                    Value::Str(s) => Ok(Value::Str(self.slices(c, s)?)),
+                    n => Ok(n),
+                }
+            }
+            TK_RND => {
+                self.seed = ((75 * (self.seed as u32 + 1)) % 65537).wrapping_sub(1) as u16;
+                Ok(Value::Num(self.seed as f64 / 65536.0))
+            }
+            TK_PI => Ok(Value::Num(std::f64::consts::PI)),
+            TK_INKEY => {
+                let key = self.config.inkey_script.get(self.inkey_pos).cloned().unwrap_or_default();
+                self.inkey_pos += 1;
+                Ok(Value::Str(key.chars().take(1).map(ascii_to_zx81).collect()))
+            }
+            TK_CODE..=TK_CHR => self.function(c, b),
+            LETTER_A..=LETTER_Z => self.variable(c, b),
+            _ => Err(NONSENSE),
+        }
+    }
+
+    fn function(&mut self, c: &mut Cursor, token: u8) -> Result<Value, Stop> {
+        let arg = self.unary(c)?;
+        let num = |v: &Value| match v {
+            Value::Num(n) => Ok(*n),
+            Value::Str(_) => Err(NONSENSE),
+        };
+        let result = match (token, &arg) {
+            (TK_CODE, Value::Str(s)) => Value::Num(s.first().copied().unwrap_or(0) as f64),
+            (197, Value::Str(s)) => {
+                // VAL
+                let mut text = s.clone();
+                text.push(NEWLINE);
+                let mut vc = Cursor { bytes: &text, pos: 0 };
+                let v = self.number(&mut vc).map_err(|_| BAD_VAL)?;
+                if !vc.at_end() {
+                    return Err(BAD_VAL);
+                }
+                Value::Num(v)
+            }
+            (198, Value::Str(s)) => Value::Num(s.len() as f64),
+            (TK_STR, Value::Num(n)) => Value::Str(number_to_zx81(*n)),
+            (TK_CHR, Value::Num(n)) => {
+                let n = n.round();
+                if !(0.0..256.0).contains(&n) {
+                    return Err(OUT_OF_RANGE);
+                }
+                Value::Str(vec![n as u8])
+            }
+            (211, Value::Num(n)) => Value::Num(self.memory.get(&to_u16(*n)?).copied().unwrap_or(0) as f64),
+            (TK_USR, _) => return Err(INVALID_ARGUMENT),
+            _ => {
+                let x = num(&arg)?;
+                let v = match token {
+                    199 => x.sin(),
+                    200 => x.cos(),
+                    201 => x.tan(),
+                    202 if (-1.0..=1.0).contains(&x) => x.asin(),
+                    203 if (-1.0..=1.0).contains(&x) => x.acos(),
+                    204 => x.atan(),
+                    205 if x > 0.0 => x.ln(),
+                    206 => x.exp(),
+                    207 => x.floor(),
+                    208 if x >= 0.0 => x.sqrt(),
+                    209 => x.signum() * (x != 0.0) as i32 as f64,
+                    210 => x.abs(),
+                    _ => return Err(INVALID_ARGUMENT),
+                };
+                Value::Num(v)
+            }
+        };
+        if let Value::Num(n) = result {
+            if !n.is_finite() {
+                return Err(OVERFLOW);
+            }
+        }
+        Ok(result)
+    }
+
+    fn variable(&mut self, c: &mut Cursor, first: u8) -> Result<Value, Stop> {
+        if c.eat(DOLLAR) {
+            if c.peek() == OPEN && self.str_arrays.contains_key(&first) {
+                c.pos += 1;
+                let dims = self.str_arrays[&first].dims.clone();
+                let (offset, len) = self.str_array_element(c, &dims)?;
+                let s = self.str_arrays[&first].data[offset..offset + len].to_vec();
+                return Ok(Value::Str(self.slices(c, s)?));
+            }
+            let s = self.strings.get(&first).cloned().ok_or(VARIABLE_NOT_FOUND)?;
+            return Ok(Value::Str(self.slices(c, s)?));
+        }
+
+        if c.peek() == OPEN {
+            c.pos += 1;
+            let dims = self.num_arrays.get(&first).ok_or(VARIABLE_NOT_FOUND)?.dims.clone();
+            let offset = self.array_offset(c, &dims)?;
+            c.expect(CLOSE)?;
+            return Ok(Value::Num(self.num_arrays[&first].data[offset]));
+        }
+
+        let mut name = vec![first];
+        while is_letter(c.peek()) || is_digit(c.peek()) {
+            name.push(c.next());
+        }
+        self.numbers.get(&name).copied().map(Value::Num).ok_or(VARIABLE_NOT_FOUND)
+    }
+}
+
+fn is_letter(b: u8) -> bool {
+    (LETTER_A..=LETTER_Z).contains(&b)
+}
+
+fn is_digit(b: u8) -> bool {
+    (ZERO..=NINE).contains(&b)
+}
+
+/// Rounds to an integer in 0..=65535, as the ROM's FP-TO-BC does.
+fn to_u16(n: f64) -> Result<u16, Stop> {
+    let n = n.round();
+    if (0.0..=65535.0).contains(&n) { Ok(n as u16) } else { Err(OUT_OF_RANGE) }
+}
+
+/// Copies `source` into `target`, padding with spaces or truncating to fit.
+fn procrustean(target: &mut [u8], source: &[u8]) {
+    for (i, cell) in target.iter_mut().enumerate() {
+        *cell = source.get(i).copied().unwrap_or(SPACE);
+    }
+}
+
+/// Reads a numeric literal. In a program line the digits are followed by the hidden
+/// 5-byte floating point form, which is the value the ZX81 itself uses.
+fn read_number(c: &mut Cursor) -> Result<f64, Stop> {
+    let mut text = String::new();
+    while let Some(&b) = c.bytes.get(c.pos) {
+        match b {
+            ZERO..=NINE => text.push((b'0' + b - ZERO) as char),
+            DOT => text.push('.'),
+            LETTER_E => text.push('E'),
+            PLUS | MINUS if text.ends_with('E') => text.push(if b == PLUS { '+' } else { '-' }),
+            _ => break,
+        }
+        c.pos += 1;
+    }
+    if c.bytes.get(c.pos) == Some(&NUMBER) && c.pos + 6 <= c.bytes.len() {
+        let value = zx81_float(&c.bytes[c.pos + 1..c.pos + 6]);
+        c.pos += 6;
+        return Ok(value);
+    }
+    text.parse::<f64>().map_err(|_| NONSENSE)
+}
+
+fn apply_operator(op: u8, lhs: Value, rhs: Value) -> Result<Value, Stop> {
+    let truth = |b: bool| Value::Num(if b { 1.0 } else { 0.0 });
+    let result = match (lhs, rhs) {
+        (Value::Num(a), Value::Num(b)) => match op {
+            PLUS => Value::Num(a + b),
+            MINUS => Value::Num(a - b),
+            STAR => Value::Num(a * b),
+            SLASH if b == 0.0 => return Err(OVERFLOW),
+            SLASH => Value::Num(a / b),
+            TK_POWER if a < 0.0 => return Err(INVALID_ARGUMENT),
+            TK_POWER => Value::Num(a.powf(b)),
+            EQUALS => truth(a == b),
+            GREATER => truth(a > b),
+            LESS => truth(a < b),
+            TK_LE => truth(a <= b),
+            TK_GE => truth(a >= b),
+            TK_NE => truth(a != b),
+            TK_AND => Value::Num(if b != 0.0 { a } else { 0.0 }),
+            TK_OR => Value::Num(if b != 0.0 { 1.0 } else { a }),
+            _ => return Err(NONSENSE),
+        },
+        (Value::Str(a), Value::Str(b)) => match op {
+            PLUS => Value::Str([a, b].concat()),
+            EQUALS => truth(a == b),
+            GREATER => truth(a > b),
+            LESS => truth(a < b),
+            TK_LE => truth(a <= b),
+            TK_GE => truth(a >= b),
+            TK_NE => truth(a != b),
+            _ => return Err(NONSENSE),
+        },
+        (Value::Str(a), Value::Num(b)) if op == TK_AND => Value::Str(if b != 0.0 { a } else { Vec::new() }),
+        _ => return Err(NONSENSE),
+    };
+    if let Value::Num(n) = result {
+        if !n.is_finite() {
+            return Err(OVERFLOW);
+        }
+    }
+    Ok(result)
+}
+
+/// How the ZX81 prints numbers: 8 significant digits, and E notation with the exponent
+/// unpadded outside 1E-5 up to, but not including, 1E8.
+const ZX81_NUMBERS: NumberStyle = NumberStyle { digits: 8, fixed_exponents: -5..8, exponent_digits: 1 };
+
+/// Formats a number the way the ZX81 PRINTs it: up to 8 significant digits,
+/// no leading zero before the point, and `E+n` / `E-n` outside 1E-5..1E8.
+pub fn zx81_number_string(n: f64) -> String {
+    format_number(n, &ZX81_NUMBERS)
+}
+
+fn number_to_zx81(n: f64) -> Vec<u8> {
+    zx81_number_string(n).chars().map(ascii_to_zx81).collect()
+}
+
+/// Converts an ASCII character to the ZX81 character set (lower case is treated as upper case).
+pub fn ascii_to_zx81(ch: char) -> u8 {
+    match ch.to_ascii_uppercase() {
+        '0'..='9' => ZERO + (ch as u8 - b'0'),
+        c @ 'A'..='Z' => LETTER_A + (c as u8 - b'A'),
+        '"' => QUOTE,
+        '£' => 12,
+        '$' => DOLLAR,
+        ':' => 14,
+        '?' => 15,
+        '(' => OPEN,
+        ')' => CLOSE,
+        '>' => GREATER,
+        '<' => LESS,
+        '=' => EQUALS,
+        '+' => PLUS,
+        '-' => MINUS,
+        '*' => STAR,
+        '/' => SLASH,
+        ';' => SEMICOLON,
+        ',' => COMMA,
+        '.' => DOT,
+        _ => SPACE,
+    }
+}
+
+/// Plain text form of a ZX81 display character.
+pub(crate) fn zx81_display_char(code: u8) -> char {
+    const GRAPHICS: [char; 8] = [' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛'];
+    const INVERSE_GRAPHICS: [char; 8] = ['█', '▟', '▙', '▄', '▜', '▐', '▚', '▗'];
+    const TEXT: &str = "\"£$:?()><=+-*/;,.0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    match code {
+        0..=7 => GRAPHICS[code as usize],
+        8..=10 => '▒',
+        11..=63 => TEXT.chars().nth((code - 11) as usize).unwrap_or('?'),
+        0x80..=0x87 => INVERSE_GRAPHICS[(code - 0x80) as usize],
+        0x88..=0x8A => '▒',
+        0x8B..=0xBF => TEXT.chars().nth((code - 0x8B) as usize).unwrap_or('?'),
+        _ => '?',
+    }
+}
+
+/// Runs the BASIC program in a ZX81 .P file and returns the final machine state.
+///
+/// # Arguments
+///
+/// * `bytes` - The complete .P file as a byte array
+/// * `config` - Step limit, keyboard script and FRAMES value for the run
+///
+/// # Returns
+///
+/// A `Result` containing the final display and report, or an error message if no program could be read.
+pub fn run_zx81_p_file(bytes: &[u8], config: ZX81RunConfig) -> Result<ZX81RunResult, Error> {
+    let lines = zx81_program_lines(bytes)?;
+    if lines.is_empty() {
+        return Err(Error::NothingFound("No valid BASIC program found in the file."));
+    }
+    Ok(ZX81Interpreter::new(lines, config).run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{zx81_line, zx81_p};
+    use crate::zx81_decoder::zx81_float_bytes;
+
+    fn run_with(lines: &[(u16, &str)], config: ZX81RunConfig) -> ZX81RunResult {
+        run_zx81_p_file(&zx81_p(lines), config).unwrap()
+    }
+
+    fn run(lines: &[(u16, &str)]) -> ZX81RunResult {
+        run_with(lines, ZX81RunConfig::default())
+    }
+
+    /// The display lines with trailing spaces removed.
+    fn screen(result: &ZX81RunResult) -> Vec<String> {
+        result.display_lines().iter().map(|line| line.trim_end().to_string()).collect()
+    }
+
+    fn report(result: &ZX81RunResult) -> (char, u16) {
+        (result.report.code, result.report.line)
+    }
+
+    #[test]
+    fn print_at_places_text() {
+        let result = run(&[(10, "PRINT AT 5,3;\"HI\";AT 0,30;\"XYZ\""), (20, "PRINT \"NEXT\"")]);
+        let screen = screen(&result);
+        assert_eq!(screen[5], "   HI");
+        // Text reaching the right edge carries on at the start of the next line
+        assert_eq!(screen[0], format!("{}XY", " ".repeat(30)));
+        assert_eq!(screen[1], "Z");
+        assert_eq!(screen[2], "NEXT");
+        assert_eq!(screen[23], "0/20");
+        assert_eq!(report(&result), ('0', 20));
+    }
+
+    #[test]
+    fn print_at_off_the_screen_is_report_b() {
+        let result = run(&[(10, "PRINT AT 22,0;\"X\"")]);
+        assert_eq!(report(&result), ('B', 10));
+    }
+
+    #[test]
+    fn plot_and_unplot_set_quarter_squares() {
+        let result = run(&[
+            (10, "PLOT 0,0"),
+            (20, "PLOT 1,1"),
+            (30, "PLOT 2,0"),
+            (40, "UNPLOT 2,0"),
+            (50, "PLOT 63,43"),
+            (60, "PLOT 10,10"),
+            (70, "PLOT 11,10"),
+            (80, "PLOT 10,11"),
+            (90, "PLOT 11,11"),
+        ]);
+        assert_eq!(result.display[21][0], 6, "bottom left and top right");
+        assert_eq!(result.display[21][1], SPACE, "unplotted again");
+        assert_eq!(result.display[0][31], 2, "top right of the top right square");
+        assert_eq!(result.display[16][5], 0x80, "all four quarters");
+        assert_eq!(report(&result), ('0', 90));
+    }
+
+    #[test]
+    fn plot_outside_the_grid_is_report_b() {
+        assert_eq!(report(&run(&[(10, "PLOT 64,0")])), ('B', 10));
+        assert_eq!(report(&run(&[(10, "PLOT 0,44")])), ('B', 10));
+    }
+
+    #[test]
+    fn scroll_moves_the_screen_up() {
+        let result = run(&[(10, "PRINT \"A\""), (20, "PRINT \"B\""), (30, "SCROLL"), (40, "PRINT \"C\"")]);
+        let screen = screen(&result);
+        assert_eq!(screen[0], "B");
+        assert!(screen[1..21].iter().all(|line| line.is_empty()));
+        assert_eq!(screen[21], "C");
+    }
+
+    #[test]
+    fn printing_past_the_bottom_is_report_5() {
+        let result = run(&[(10, "FOR I=1 TO 30"), (20, "PRINT I"), (30, "NEXT I")]);
+        assert_eq!(report(&result), ('5', 20));
+        assert_eq!(screen(&result)[21], "22");
+    }
+
+    #[test]
+    fn rnd_follows_the_rom_generator() {
+        // seed = (75 * (seed + 1)) mod 65537 - 1, and RND is seed / 65536
+        let result = run(&[(10, "RAND 1"), (20, "PRINT RND"), (30, "PRINT RND"), (40, "RAND 1"), (50, "PRINT RND")]);
+        let screen = screen(&result);
+        assert_eq!(screen[0], ".0022735596");
+        assert_eq!(screen[1], ".17164612");
+        assert_eq!(screen[2], ".0022735596");
+    }
+
+    #[test]
+    fn rand_0_uses_frames() {
+        let config = ZX81RunConfig { frames: 1, ..Default::default() };
+        let result = run_with(&[(10, "RAND 0"), (20, "PRINT RND")], config);
+        assert_eq!(screen(&result)[0], ".0022735596");
+    }
+
+    #[test]
+    fn dim_and_arrays() {
+        let result = run(&[
+            (10, "DIM A(3,4)"),
+            (20, "LET A(3,4)=7"),
+            (30, "PRINT A(3,4);A(1,1)"),
+            (40, "DIM B$(2,3)"),
+            (50, "LET B$(2)=\"HELLO\""),
+            (60, "PRINT B$(2);\"/\";B$(1)"),
+        ]);
+        let screen = screen(&result);
+        assert_eq!(screen[0], "70");
+        assert_eq!(screen[1], "HEL/");
+        assert_eq!(report(&run(&[(10, "DIM A(3)"), (20, "PRINT A(4)")])), ('3', 20));
+        assert_eq!(report(&run(&[(10, "DIM A(0)")])), ('3', 10));
+    }
+
+    #[test]
+    fn arrays_larger_than_memory_are_report_4() {
+        assert_eq!(report(&run(&[(10, "DIM A(60000,60000)")])), ('4', 10));
+        // The product of the dimensions would overflow
+        assert_eq!(report(&run(&[(10, "DIM A(65535,65535,65535,65535,65535)")])), ('4', 10));
+        assert_eq!(report(&run(&[(10, "DIM A(3300)")])), ('4', 10));
+        assert_eq!(report(&run(&[(10, "DIM A(3200)"), (20, "DIM B$(16000)")])), ('0', 20));
+    }
+
+    #[test]
+    fn strings_and_slices() {
+        let result = run(&[
+            (10, "LET A$=\"ABCDEF\""),
+            (20, "PRINT A$(2 TO 4);A$(5);A$( TO 2);LEN A$"),
+            (30, "LET A$(1 TO 2)=\"XYZ\""),
+            (40, "PRINT A$;CODE \"A\";CHR$ 38;STR$ 12"),
+        ]);
+        let screen = screen(&result);
+        assert_eq!(screen[0], "BCDEAB6");
+        assert_eq!(screen[1], "XYCDEF38A12");
+    }
+
+    #[test]
+    fn inkey_and_input_read_the_scripts() {
+        let config = ZX81RunConfig {
+            inkey_script: vec!["Y".to_string()],
+            input_script: vec!["42".to_string(), "BOB".to_string()],
+            ..Default::default()
+        };
+        let result = run_with(
+            &[(10, "IF INKEY$=\"Y\" THEN PRINT \"YES\""), (20, "INPUT N"), (30, "INPUT N$"), (40, "PRINT N+1;N$;INKEY$;\"/\"")],
+            config,
+        );
+        let screen = screen(&result);
+        assert_eq!(screen[0], "YES");
+        assert_eq!(screen[1], "43BOB/");
+        // An exhausted INPUT script stops the program
+        assert_eq!(report(&run(&[(10, "INPUT N")])), ('D', 10));
+    }
+
+    #[test]
+    fn gosub_return_and_for_next() {
+        let result = run(&[
+            (10, "FOR I=3 TO 1 STEP -1"),
+            (20, "GOSUB 100"),
+            (30, "NEXT I"),
+            (40, "STOP"),
+            (100, "PRINT I;"),
+            (110, "RETURN"),
+        ]);
+        assert_eq!(screen(&result)[0], "321");
+        assert_eq!(report(&result), ('9', 40));
+    }
+
+    #[test]
+    fn line_numbers_come_from_the_hidden_number() {
+        // GOTO 20, with the hidden number changed to 30 as some listings are protected
+        let mut goto = zx81_line("GOTO 20");
+        goto[4..9].copy_from_slice(&zx81_float_bytes(30.0));
+        let lines = vec![(10, goto), (20, zx81_line("PRINT \"TWENTY\"")), (30, zx81_line("PRINT \"THIRTY\""))];
+        let result = ZX81Interpreter::new(lines, ZX81RunConfig::default()).run();
+        assert_eq!(result.display_lines()[0].trim_end(), "THIRTY");
+    }
+
+    #[test]
+    fn runtime_errors_give_their_reports() {
+        assert_eq!(report(&run(&[(10, "PRINT X")])), ('2', 10));
+        assert_eq!(report(&run(&[(10, "PRINT 1/0")])), ('6', 10));
+        assert_eq!(report(&run(&[(10, "RETURN")])), ('7', 10));
+        assert_eq!(report(&run(&[(10, "NEXT I")])), ('1', 10));
+        assert_eq!(report(&run(&[(10, "GOTO 70000")])), ('B', 10));
+        assert_eq!(report(&run(&[(10, "PRINT CHR$ 256")])), ('B', 10));
+        assert_eq!(report(&run(&[(10, "PRINT SQR -1")])), ('A', 10));
+        assert_eq!(report(&run(&[(10, "PRINT VAL \"1+\"")])), ('C', 10));
+        assert_eq!(report(&run(&[(10, "GOTO 10")])), ('D', 10));
+    }
+}
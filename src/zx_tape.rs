@@ -0,0 +1,309 @@
+// src/zx_tape.rs
+//
+// ZX80 and ZX81 cassette format.
+//
+// Each bit is a burst of pulses followed by a silent gap: four pulses for a 0
+// and nine pulses for a 1, most significant bit first. There is no leader tone
+// or checksum; loading simply stops when the E_LINE system variable is reached.
+// The ZX81 sends the program name first (ZX81 characters, the last one with
+// bit 7 set) followed by the memory from 0x4009 - exactly a .P file.
+// The ZX80 has no name and sends the memory from 0x4000 - exactly a .O file.
+
+use crate::error::Error;
+use crate::wav_audio::{self, SquareWave};
+use crate::zx81_decoder::{split_zx81_name, zx81_name_length, zx81_name_to_string};
+use crate::zx81_interpreter::ascii_to_zx81;
+
+// Signal timings (seconds)
+const PULSE_HIGH: f64 = 150e-6;
+const PULSE_LOW: f64 = 150e-6;
+const BIT_GAP: f64 = 1300e-6;
+
+/// Pulse counts above this are read as a 1 (0 = 4 pulses, 1 = 9 pulses).
+const ONE_THRESHOLD: usize = 6;
+
+/// Sinclair machine a tape file was recorded by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZXTapeMachine {
+    ZX80,
+    ZX81,
+}
+
+/// A program recovered from a ZX80/ZX81 tape recording.
+#[derive(Debug, Clone)]
+pub struct ZXTapeFile {
+    /// Which machine the recording appears to come from.
+    pub machine: ZXTapeMachine,
+    /// The ZX81 program name, translated to ASCII.
+    pub name: Option<String>,
+    /// The program image: a .P file for the ZX81 or a .O file for the ZX80.
+    pub data: Vec<u8>,
+    /// True if the data reaches the end given by the E_LINE system variable.
+    pub complete: bool,
+    /// Problems found while reading.
+    pub diagnostics: Vec<String>,
+}
+
+/// Shifts one bit (given by the burst's pulse count) into the byte being assembled.
+fn finish_byte(pulses: usize, byte: &mut u8, bits: &mut i32, bytes: &mut Vec<u8>) {
+    *byte = (*byte << 1) | (pulses > ONE_THRESHOLD) as u8;
+    *bits += 1;
+    if *bits == 8 {
+        bytes.push(*byte);
+        *byte = 0;
+        *bits = 0;
+    }
+}
+
+/// Groups rising edges into bursts, bursts into bytes, and silences into file boundaries.
+fn edges_to_recordings(edges: &[f64]) -> Vec<(Vec<u8>, Vec<String>)> {
+    let periods: Vec<f64> = edges.windows(2).map(|w| w[1] - w[0]).collect();
+    if periods.is_empty() {
+        return Vec::new();
+    }
+    let mut sorted = periods.clone();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let pulse_period = sorted[sorted.len() / 4];
+    let bit_boundary = pulse_period * 2.5;
+    let file_boundary = (PULSE_HIGH + PULSE_LOW + BIT_GAP) * 6.0;
+
+    let mut recordings = Vec::new();
+    let mut bytes = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut byte = 0u8;
+    let mut bits = 0;
+    let mut pulses = 1;
+
+    for (i, &p) in periods.iter().enumerate() {
+        if p < bit_boundary {
+            pulses += 1;
+            continue;
+        }
+        if pulses >= 2 {
+            finish_byte(pulses, &mut byte, &mut bits, &mut bytes);
+        }
+        pulses = 1;
+        if p > file_boundary && !bytes.is_empty() {
+            if bits != 0 {
+                diagnostics.push(format!("{} stray bits at {:.3}s", bits, edges[i]));
+            }
+            recordings.push((std::mem::take(&mut bytes), std::mem::take(&mut diagnostics)));
+            byte = 0;
+            bits = 0;
+        }
+    }
+    if pulses >= 2 {
+        finish_byte(pulses, &mut byte, &mut bits, &mut bytes);
+    }
+    if !bytes.is_empty() {
+        if bits != 0 {
+            diagnostics.push(format!("{} stray bits at end of recording", bits));
+        }
+        recordings.push((bytes, diagnostics));
+    }
+    recordings
+}
+
+/// Length of the saved image according to its E_LINE system variable.
+fn expected_length(data: &[u8], machine: ZXTapeMachine) -> Option<usize> {
+    let (offset, base) = match machine {
+        ZXTapeMachine::ZX81 => (0x4014 - 0x4009, 0x4009),
+        ZXTapeMachine::ZX80 => (0x400A - 0x4000, 0x4000),
+    };
+    let e_line = u16::from_le_bytes([*data.get(offset)?, *data.get(offset + 1)?]) as usize;
+    e_line.checked_sub(base)
+}
+
+/// Splits a raw recording into machine, name and program image.
+fn identify(raw: Vec<u8>, mut diagnostics: Vec<String>) -> ZXTapeFile {
+    // A ZX81 recording starts with a name and its E_LINE agrees with the data length
+    if let Some(name_len) = zx81_name_length(&raw) {
+        let program = &raw[name_len..];
+        if let Some(len) = expected_length(program, ZXTapeMachine::ZX81) {
+            if len >= 116 && len <= program.len() + 256 {
+                let complete = program.len() >= len;
+                if !complete {
+                    diagnostics.push(format!("Recording ends after {} of {} bytes", program.len(), len));
+                }
+                return ZXTapeFile {
+                    machine: ZXTapeMachine::ZX81,
+                    name: Some(zx81_name_to_string(&raw[..name_len])),
+                    data: program[..len.min(program.len())].to_vec(),
+                    complete,
+                    diagnostics,
+                };
+            }
+        }
+    }
+
+    let len = expected_length(&raw, ZXTapeMachine::ZX80);
+    let complete = len.is_some_and(|len| raw.len() >= len);
+    if !complete {
+        diagnostics.push(match len {
+            Some(len) => format!("Recording ends after {} of {} bytes", raw.len(), len),
+            None => "Recording is too short to hold system variables".to_string(),
+        });
+    }
+    let keep = len.map_or(raw.len(), |len| len.min(raw.len()));
+    ZXTapeFile {
+        machine: ZXTapeMachine::ZX80,
+        name: None,
+        data: raw[..keep].to_vec(),
+        complete,
+        diagnostics,
+    }
+}
+
+/// Decodes ZX80/ZX81 programs from the rising edge times (in seconds) of a recording.
+pub fn decode_zx_edges(edges: &[f64]) -> Vec<ZXTapeFile> {
+    edges_to_recordings(edges)
+        .into_iter()
+        .filter(|(raw, _)| raw.len() > 8)
+        .map(|(raw, diagnostics)| identify(raw, diagnostics))
+        .collect()
+}
+
+/// Decodes the programs recorded in a ZX80 or ZX81 cassette WAV.
+///
+/// # Arguments
+///
+/// * `bytes` - The complete PCM WAV file
+///
+/// # Returns
+///
+/// A `Result` containing each program found (as .P or .O bytes), or an error message.
+pub fn decode_zx_wav(bytes: &[u8]) -> Result<Vec<ZXTapeFile>, Error> {
+    let audio = wav_audio::read_wav(bytes)?;
+    let files = decode_zx_edges(&audio.rising_edge_times());
+    if files.is_empty() {
+        return Err(Error::NothingFound("No ZX80/ZX81 data found in the recording."));
+    }
+    Ok(files)
+}
+
+/// Generates a cassette recording of a ZX81 .P file or ZX80 .O file.
+///
+/// # Arguments
+///
+/// * `program` - The .P or .O file
+/// * `machine` - The machine the program is for
+/// * `name` - ZX81 program name (ignored for the ZX80). If the .P data already
+///   starts with a name (a .P81 style image), pass an empty name to keep it; an empty
+///   name for a plain .P records it as "PROGRAM", as the ZX81 cannot load a nameless file.
+/// * `sample_rate` - Output sample rate in Hz
+///
+/// # Returns
+///
+/// A `Result` containing the WAV file bytes, or an error message.
+pub fn encode_zx_wav(program: &[u8], machine: ZXTapeMachine, name: &str, sample_rate: u32) -> Result<Vec<u8>, Error> {
+    if program.is_empty() {
+        return Err(Error::NothingFound("Program is empty."));
+    }
+    if sample_rate < 11025 {
+        return Err(Error::InvalidArgument("Sample rate is too low for ZX80/ZX81 pulses.".to_string()));
+    }
+
+    let mut data = Vec::new();
+    let name = match machine {
+        ZXTapeMachine::ZX81 if name.is_empty() && split_zx81_name(program).0.is_none() => "PROGRAM",
+        _ => name,
+    };
+    if machine == ZXTapeMachine::ZX81 && !name.is_empty() {
+        let mut encoded: Vec<u8> = name.chars().map(ascii_to_zx81).collect();
+        if let Some(last) = encoded.last_mut() {
+            *last |= 0x80;
+        }
+        data.extend(encoded);
+    }
+    data.extend_from_slice(program);
+
+    let mut wave = SquareWave::new(sample_rate);
+    wave.level(SquareWave::SILENT, 1.0);
+    for byte in data {
+        for bit in (0..8).rev() {
+            let pulses = if byte & (1 << bit) != 0 { 9 } else { 4 };
+            for _ in 0..pulses {
+                wave.level(SquareWave::HIGH, PULSE_HIGH);
+                wave.level(SquareWave::LOW, PULSE_LOW);
+            }
+            wave.level(SquareWave::SILENT, BIT_GAP);
+        }
+    }
+    wave.level(SquareWave::SILENT, 1.0);
+    Ok(wave.into_wav())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{zx80_o, zx81_p};
+
+    fn zx81_program() -> Vec<u8> {
+        zx81_p(&[(10, "PRINT \"TAPE\""), (20, "GOTO 10")])
+    }
+
+    #[test]
+    fn zx81_recordings_decode_at_common_sample_rates() {
+        for sample_rate in [22050, 44100, 48000] {
+            let wav = encode_zx_wav(&zx81_program(), ZXTapeMachine::ZX81, "TAPE", sample_rate).unwrap();
+            let files = decode_zx_wav(&wav).unwrap();
+            assert_eq!(files.len(), 1, "{} Hz", sample_rate);
+            let file = &files[0];
+            assert_eq!(file.machine, ZXTapeMachine::ZX81);
+            assert_eq!(file.name.as_deref(), Some("TAPE"));
+            assert_eq!(file.data, zx81_program(), "{} Hz", sample_rate);
+            assert!(file.complete && file.diagnostics.is_empty(), "{} Hz: {:?}", sample_rate, file.diagnostics);
+        }
+    }
+
+    #[test]
+    fn zx80_recordings_decode_at_common_sample_rates() {
+        for sample_rate in [22050, 44100, 48000] {
+            let wav = encode_zx_wav(&zx80_o(), ZXTapeMachine::ZX80, "IGNORED", sample_rate).unwrap();
+            let files = decode_zx_wav(&wav).unwrap();
+            assert_eq!(files.len(), 1, "{} Hz", sample_rate);
+            assert_eq!(files[0].machine, ZXTapeMachine::ZX80);
+            assert_eq!(files[0].name, None);
+            assert_eq!(files[0].data, zx80_o(), "{} Hz", sample_rate);
+            assert!(files[0].complete);
+        }
+    }
+
+    #[test]
+    fn a_named_image_keeps_its_name() {
+        let mut named = vec![0x2B, 0x2E | 0x80]; // "FI", last character inverse
+        named.extend(zx81_program());
+        let wav = encode_zx_wav(&named, ZXTapeMachine::ZX81, "", 44100).unwrap();
+        let files = decode_zx_wav(&wav).unwrap();
+        assert_eq!(files[0].name.as_deref(), Some("FI"));
+        assert_eq!(files[0].data, zx81_program());
+    }
+
+    #[test]
+    fn a_nameless_image_is_recorded_as_program() {
+        let wav = encode_zx_wav(&zx81_program(), ZXTapeMachine::ZX81, "", 44100).unwrap();
+        let files = decode_zx_wav(&wav).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].machine, ZXTapeMachine::ZX81);
+        assert_eq!(files[0].name.as_deref(), Some("PROGRAM"));
+        assert_eq!(files[0].data, zx81_program());
+        assert!(files[0].complete && files[0].diagnostics.is_empty(), "{:?}", files[0].diagnostics);
+    }
+
+    #[test]
+    fn a_recording_cut_short_is_incomplete() {
+        let wav = encode_zx_wav(&zx81_program(), ZXTapeMachine::ZX81, "TAPE", 44100).unwrap();
+        // The data runs from 1 to about 4.8 seconds
+        let cut = &wav[..44 + 44100 * 3];
+        let files = decode_zx_wav(cut).unwrap();
+        assert!(!files[0].complete);
+        assert_eq!(files[0].data, zx81_program()[..files[0].data.len()]);
+        assert!(files[0].diagnostics.iter().any(|d| d.starts_with("Recording ends after")), "{:?}", files[0].diagnostics);
+    }
+
+    #[test]
+    fn refuses_what_it_cannot_record() {
+        assert!(matches!(encode_zx_wav(&[], ZXTapeMachine::ZX81, "", 44100), Err(Error::NothingFound(_))));
+        assert!(matches!(encode_zx_wav(&zx81_program(), ZXTapeMachine::ZX81, "", 8000), Err(Error::InvalidArgument(_))));
+    }
+}
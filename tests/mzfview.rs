@@ -0,0 +1,87 @@
+// tests/mzfview.rs
+//
+// Runs the command-line tool and checks its output and exit codes.
+// Run with `cargo test --features cli`.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// A directory of its own under the system temporary directory.
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mzfview-cli-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// An MZF file: the 128-byte header, then the body.
+fn mzf(file_type: u8, name: &str, load: u16, body: &[u8]) -> Vec<u8> {
+    let mut header = vec![0u8; 128];
+    header[0] = file_type;
+    header[1..1 + name.len()].copy_from_slice(name.as_bytes());
+    header[1 + name.len()] = 0x0D;
+    header[0x12..0x14].copy_from_slice(&(body.len() as u16).to_le_bytes());
+    header[0x14..0x16].copy_from_slice(&load.to_le_bytes());
+    header[0x16..0x18].copy_from_slice(&load.to_le_bytes());
+    header.extend(body);
+    header
+}
+
+fn mzfview(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mzfview")).args(args).output().unwrap()
+}
+
+#[test]
+fn a_decoded_file_exits_with_0() {
+    let dir = scratch("ok");
+    let file = dir.join("code.mzf");
+    fs::write(&file, mzf(0x01, "CODE", 0x1200, &[0x3E, 0x01, 0xC9])).unwrap();
+    let output = mzfview(&["-m", "Z80", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("LD A,01H") && stdout.contains("RET"), "{}", stdout);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_file_that_cannot_be_decoded_exits_with_1() {
+    let dir = scratch("decode");
+    let file = dir.join("basic.mzf");
+    // A BASIC line whose length runs past the end of the file
+    fs::write(&file, mzf(0x02, "BASIC", 0x4F88, &[0x40, 0x00, 0x0A, 0x00, 0x80, 0x88])).unwrap();
+    let output = mzfview(&["-m", "SA", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("mzfview: "));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_bad_command_line_exits_with_2() {
+    let output = mzfview(&["--frobnicate", "x.mzf"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("mzfview: unknown option '--frobnicate'") && stderr.contains("Usage:"));
+    assert_eq!(mzfview(&[]).status.code(), Some(2));
+    // Help is not an error
+    assert_eq!(mzfview(&["--help"]).status.code(), Some(0));
+}
+
+#[test]
+fn a_file_that_cannot_be_read_exits_with_3() {
+    let dir = scratch("io");
+    let output = mzfview(&[dir.join("missing.mzf").to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(3));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn the_worst_failure_sets_the_exit_code() {
+    let dir = scratch("several");
+    let good = dir.join("code.mzf");
+    fs::write(&good, mzf(0x01, "CODE", 0x1200, &[0xC9])).unwrap();
+    let output = mzfview(&["-m", "Z80", good.to_str().unwrap(), dir.join("missing.mzf").to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with(&format!("==> {} <==", good.display())));
+    fs::remove_dir_all(dir).unwrap();
+}